@@ -0,0 +1,136 @@
+/* Generated by `rust_monkey header` from src/capi.rs, do not edit. */
+
+#ifndef MONKEY_H
+#define MONKEY_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * The bytes of stack the evaluations of an interpreter may take unless it
+ * is changed with monkey_interpreter_set_max_stack, which a thread with a
+ * stack of 2 MiB holds.
+ */
+#define MONKEY_EMBEDDED_MAX_STACK 1572864
+
+/*
+ * An interpreter keeping its globals from one evaluation to the next.
+ */
+typedef struct MonkeyInterpreter MonkeyInterpreter;
+
+/*
+ * The result of an evaluation: either a value or an error.
+ */
+typedef struct MonkeyValue MonkeyValue;
+
+/*
+ * Creates an interpreter, to be freed with monkey_interpreter_free, or
+ * returns NULL if that fails.
+ */
+MonkeyInterpreter *monkey_interpreter_new(void);
+
+/*
+ * Frees interpreter, which may be NULL.
+ *
+ * interpreter must be NULL or come from monkey_interpreter_new, and not
+ * be used afterwards.
+ */
+void monkey_interpreter_free(MonkeyInterpreter *interpreter);
+
+/*
+ * Sets how many nested calls evaluations of interpreter may make, keeping
+ * its other limits, and returns true, or returns false and changes nothing
+ * if max_depth is 0.
+ *
+ * interpreter must come from monkey_interpreter_new.
+ */
+bool monkey_interpreter_set_max_depth(MonkeyInterpreter *interpreter, size_t max_depth);
+
+/*
+ * Sets how many bytes of stack evaluations of interpreter may take,
+ * keeping its other limits, and returns true, or returns false and changes
+ * nothing if max_stack is 0. Evaluations on a thread whose stack is not
+ * at least 512 KiB larger may crash the program.
+ *
+ * interpreter must come from monkey_interpreter_new.
+ */
+bool monkey_interpreter_set_max_stack(MonkeyInterpreter *interpreter, size_t max_stack);
+
+/*
+ * Evaluates the len bytes of UTF-8 source code at source. The result
+ * is never NULL and must be freed with monkey_value_free.
+ *
+ * interpreter must come from monkey_interpreter_new, and source must
+ * point to len readable bytes.
+ */
+MonkeyValue *monkey_eval(MonkeyInterpreter *interpreter, const char *source, size_t len);
+
+/*
+ * Frees value, which may be NULL.
+ *
+ * value must be NULL or come from monkey_eval, and not be used
+ * afterwards.
+ */
+void monkey_value_free(MonkeyValue *value);
+
+/*
+ * Returns whether the evaluation of value failed.
+ *
+ * value must come from monkey_eval.
+ */
+bool monkey_value_is_error(const MonkeyValue *value);
+
+/*
+ * Returns the message of the error value is, or NULL if it is none.
+ *
+ * value must come from monkey_eval.
+ */
+char *monkey_value_error(const MonkeyValue *value);
+
+/*
+ * Returns value as the REPL prints it, or NULL if it is an error.
+ *
+ * value must come from monkey_eval.
+ */
+char *monkey_value_inspect(const MonkeyValue *value);
+
+/*
+ * Returns the string value is, or NULL if it is not a string.
+ *
+ * value must come from monkey_eval.
+ */
+char *monkey_value_as_string(const MonkeyValue *value);
+
+/*
+ * Stores the integer value is in out and returns true, or returns
+ * false if it is not an integer.
+ *
+ * value must come from monkey_eval, and out must be writable.
+ */
+bool monkey_value_as_int(const MonkeyValue *value, int64_t *out);
+
+/*
+ * Stores the boolean value is in out and returns true, or returns
+ * false if it is not a boolean.
+ *
+ * value must come from monkey_eval, and out must be writable.
+ */
+bool monkey_value_as_bool(const MonkeyValue *value, bool *out);
+
+/*
+ * Frees a string returned by this library, which may be NULL.
+ *
+ * s must be NULL or come from this library, and not be used afterwards.
+ */
+void monkey_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MONKEY_H */
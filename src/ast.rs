@@ -1,3 +1,7 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
 use crate::token::*;
 
 pub trait Node {
@@ -5,11 +9,18 @@ pub trait Node {
     fn to_string(&self) -> String;
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum Statement {
     LetStatement(LetStatement),
     ReturnStatement(ReturnStatement),
     ExpressionStatement(ExpressionStatement),
+    WhileStatement(WhileStatement),
+    ForStatement(ForStatement),
+    BreakStatement(BreakStatement),
+    ContinueStatement(ContinueStatement),
+    ImportStatement(ImportStatement),
+    ThrowStatement(ThrowStatement),
 }
 
 impl Node for Statement {
@@ -18,38 +29,42 @@ impl Node for Statement {
             Statement::LetStatement(ls) => ls.token.literal.clone(),
             Statement::ReturnStatement(rs) => rs.token.literal.clone(),
             Statement::ExpressionStatement(es) => es.token.literal.clone(),
+            Statement::WhileStatement(ws) => ws.token_literal(),
+            Statement::ForStatement(fs) => fs.token_literal(),
+            Statement::BreakStatement(bs) => bs.token_literal(),
+            Statement::ContinueStatement(cs) => cs.token_literal(),
+            Statement::ImportStatement(is) => is.token_literal(),
+            Statement::ThrowStatement(ts) => ts.token_literal(),
         }
     }
 
     fn to_string(&self) -> String {
         match self {
-            Statement::LetStatement(ls) => {
-                return format!(
-                    "{} {} = {};",
-                    ls.token_literal(),
-                    ls.name.to_string(),
-                    match &ls.value {
-                        Some(v) => v.to_string(),
-                        None => "".to_string(),
-                    }
-                )
-            }
-            Statement::ReturnStatement(rs) => {
-                return format!(
-                    "{} {};",
-                    rs.token_literal(),
-                    match &rs.return_value {
-                        Some(rv) => rv.to_string(),
-                        None => "".to_string(),
-                    }
-                )
-            }
-            Statement::ExpressionStatement(es) => {
-                if es.expression.is_some() {
-                    return es.expression.as_ref().unwrap().to_string();
-                }
-                return "".to_string();
-            }
+            Statement::LetStatement(ls) => ls.to_string(),
+            Statement::ReturnStatement(rs) => rs.to_string(),
+            Statement::ExpressionStatement(es) => es.to_string(),
+            Statement::WhileStatement(ws) => ws.to_string(),
+            Statement::ForStatement(fs) => fs.to_string(),
+            Statement::BreakStatement(bs) => bs.to_string(),
+            Statement::ContinueStatement(cs) => cs.to_string(),
+            Statement::ImportStatement(is) => is.to_string(),
+            Statement::ThrowStatement(ts) => ts.to_string(),
+        }
+    }
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::LetStatement(ls) => ls.span,
+            Statement::ReturnStatement(rs) => rs.span,
+            Statement::ExpressionStatement(es) => es.span,
+            Statement::WhileStatement(ws) => ws.span,
+            Statement::ForStatement(fs) => fs.span,
+            Statement::BreakStatement(bs) => bs.span,
+            Statement::ContinueStatement(cs) => cs.span,
+            Statement::ImportStatement(is) => is.span,
+            Statement::ThrowStatement(ts) => ts.span,
         }
     }
 }
@@ -57,20 +72,88 @@ impl Node for Statement {
 #[derive(Debug, Clone)]
 pub enum Expression {
     Identifier(Identifier),
+    IntegerLiteral(IntegerLiteral),
+    Boolean(Boolean),
+    PrefixExpression(PrefixExpression),
+    InfixExpression(InfixExpression),
+    IfExpression(IfExpression),
+    FunctionLiteral(FunctionLiteral),
+    CallExpression(CallExpression),
+    StringLiteral(StringLiteral),
+    ArrayLiteral(ArrayLiteral),
+    IndexExpression(IndexExpression),
+    HashLiteral(HashLiteral),
+    AssignExpression(AssignExpression),
+    MacroLiteral(MacroLiteral),
+    MemberExpression(MemberExpression),
+    TryExpression(TryExpression),
 }
 
 impl Node for Expression {
     fn token_literal(&self) -> String {
         match self {
-            Expression::Identifier(id) => id.token.literal.clone(),
-            //_ => panic!("token_literal"),
+            Expression::Identifier(id) => id.token_literal(),
+            Expression::IntegerLiteral(il) => il.token_literal(),
+            Expression::Boolean(b) => b.token_literal(),
+            Expression::PrefixExpression(pe) => pe.token_literal(),
+            Expression::InfixExpression(ie) => ie.token_literal(),
+            Expression::IfExpression(ie) => ie.token_literal(),
+            Expression::FunctionLiteral(fl) => fl.token_literal(),
+            Expression::CallExpression(ce) => ce.token_literal(),
+            Expression::StringLiteral(sl) => sl.token_literal(),
+            Expression::ArrayLiteral(al) => al.token_literal(),
+            Expression::IndexExpression(ie) => ie.token_literal(),
+            Expression::HashLiteral(hl) => hl.token_literal(),
+            Expression::AssignExpression(ae) => ae.token_literal(),
+            Expression::MacroLiteral(ml) => ml.token_literal(),
+            Expression::MemberExpression(me) => me.token_literal(),
+            Expression::TryExpression(te) => te.token_literal(),
         }
     }
 
     fn to_string(&self) -> String {
         match self {
-            Expression::Identifier(id) => id.value.to_string(),
-            //_ => panic!("to_string"),
+            Expression::Identifier(id) => id.to_string(),
+            Expression::IntegerLiteral(il) => il.to_string(),
+            Expression::Boolean(b) => b.to_string(),
+            Expression::PrefixExpression(pe) => pe.to_string(),
+            Expression::InfixExpression(ie) => ie.to_string(),
+            Expression::IfExpression(ie) => ie.to_string(),
+            Expression::FunctionLiteral(fl) => fl.to_string(),
+            Expression::CallExpression(ce) => ce.to_string(),
+            Expression::StringLiteral(sl) => sl.to_string(),
+            Expression::ArrayLiteral(al) => al.to_string(),
+            Expression::IndexExpression(ie) => ie.to_string(),
+            Expression::HashLiteral(hl) => hl.to_string(),
+            Expression::AssignExpression(ae) => ae.to_string(),
+            Expression::MacroLiteral(ml) => ml.to_string(),
+            Expression::MemberExpression(me) => me.to_string(),
+            Expression::TryExpression(te) => te.to_string(),
+        }
+    }
+}
+
+impl Expression {
+    /// Where the expression appears in the source. Parentheses are not part
+    /// of the tree, so a grouped expression spans its contents only.
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Identifier(id) => id.span,
+            Expression::IntegerLiteral(il) => il.span,
+            Expression::Boolean(b) => b.span,
+            Expression::PrefixExpression(pe) => pe.span,
+            Expression::InfixExpression(ie) => ie.span,
+            Expression::IfExpression(ie) => ie.span,
+            Expression::FunctionLiteral(fl) => fl.span,
+            Expression::CallExpression(ce) => ce.span,
+            Expression::StringLiteral(sl) => sl.span,
+            Expression::ArrayLiteral(al) => al.span,
+            Expression::IndexExpression(ie) => ie.span,
+            Expression::HashLiteral(hl) => hl.span,
+            Expression::AssignExpression(ae) => ae.span,
+            Expression::MacroLiteral(ml) => ml.span,
+            Expression::MemberExpression(me) => me.span,
+            Expression::TryExpression(te) => te.span,
         }
     }
 }
@@ -82,9 +165,9 @@ pub struct Program {
 
 impl Node for Program {
     fn token_literal(&self) -> String {
-        if self.statements.len() > 0 {
+        if !self.statements.is_empty() {
             self.statements
-                .get(0)
+                .first()
                 .expect("no statement there")
                 .token_literal()
         } else {
@@ -102,11 +185,22 @@ impl Node for Program {
     }
 }
 
+/// A `let` or `const` binding, told apart by the type of `token`. Exported
+/// bindings are visible to modules importing this one.
 #[derive(Default, Debug, Clone)]
 pub struct LetStatement {
     pub token: Token,
+    pub span: Span,
     pub name: Identifier,
+    pub type_annotation: Option<TypeAnnotation>,
     pub value: Option<Expression>,
+    pub exported: bool,
+}
+
+impl LetStatement {
+    pub fn is_const(&self) -> bool {
+        self.token.token_type == TokenType::CONST
+    }
 }
 
 impl Node for LetStatement {
@@ -115,37 +209,69 @@ impl Node for LetStatement {
     }
 
     fn to_string(&self) -> String {
-        return format!(
-            "{} {} = {};",
+        format!(
+            "{}{} {}{} = {};",
+            if self.exported { "export " } else { "" },
             self.token_literal(),
             self.name.to_string(),
+            match &self.type_annotation {
+                Some(t) => format!(": {}", t),
+                None => "".to_string(),
+            },
             match &self.value {
                 Some(v) => v.to_string(),
                 None => "".to_string(),
             }
-        );
+        )
+    }
+}
+
+/// A type written in the source, as in `let xs: [int] = [];` or
+/// `fn(f: fn(int) -> bool) -> int`. Only the type checker reads them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeAnnotation {
+    /// `int`, `bool`, `string` or `null`
+    Named(String),
+    Array(Box<TypeAnnotation>),
+    Hash(Box<TypeAnnotation>, Box<TypeAnnotation>),
+    Function(Vec<TypeAnnotation>, Box<TypeAnnotation>),
+}
+
+impl fmt::Display for TypeAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeAnnotation::Named(name) => write!(f, "{}", name),
+            TypeAnnotation::Array(element) => write!(f, "[{}]", element),
+            TypeAnnotation::Hash(key, value) => write!(f, "{{{}: {}}}", key, value),
+            TypeAnnotation::Function(parameters, result) => {
+                let params: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), result)
+            }
+        }
     }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Identifier {
     pub token: Token,
+    pub span: Span,
     pub value: String,
 }
 
 impl Node for Identifier {
     fn token_literal(&self) -> String {
-        return self.token.literal.clone();
+        self.token.literal.clone()
     }
 
     fn to_string(&self) -> String {
-        return self.value.to_string();
+        self.value.to_string()
     }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct ReturnStatement {
     pub token: Token,
+    pub span: Span,
     pub return_value: Option<Box<Expression>>,
 }
 
@@ -159,13 +285,14 @@ impl Node for ReturnStatement {
             Some(rv) => rv.to_string(),
             None => "".to_string(),
         };
-        return format!("{} {};", self.token_literal(), return_value);
+        format!("{} {};", self.token_literal(), return_value)
     }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct ExpressionStatement {
     pub token: Token,
+    pub span: Span,
     pub expression: Option<Expression>,
 }
 
@@ -182,6 +309,510 @@ impl Node for ExpressionStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct IntegerLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub value: BigInt,
+}
+
+impl Node for IntegerLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        self.token.literal.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Boolean {
+    pub token: Token,
+    pub span: Span,
+    pub value: bool,
+}
+
+impl Node for Boolean {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        self.token.literal.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefixExpression {
+    pub token: Token,
+    pub span: Span,
+    pub operator: String,
+    pub right: Box<Expression>,
+}
+
+impl Node for PrefixExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{}{}", self.operator, self.right.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InfixExpression {
+    pub token: Token,
+    pub span: Span,
+    pub left: Box<Expression>,
+    pub operator: String,
+    pub right: Box<Expression>,
+}
+
+impl Node for InfixExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.left.to_string(),
+            self.operator,
+            self.right.to_string()
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BlockStatement {
+    pub token: Token,
+    pub span: Span,
+    pub statements: Vec<Statement>,
+}
+
+impl Node for BlockStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let statements: Vec<String> = self.statements.iter().map(|s| s.to_string()).collect();
+        format!("{{ {} }}", statements.join(" "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IfExpression {
+    pub token: Token,
+    pub span: Span,
+    pub condition: Box<Expression>,
+    pub consequence: BlockStatement,
+    pub alternative: Option<BlockStatement>,
+}
+
+impl Node for IfExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let mut out = format!(
+            "if ({}) {}",
+            self.condition.to_string(),
+            self.consequence.to_string()
+        );
+        if let Some(alt) = &self.alternative {
+            out.push_str(format!(" else {}", alt.to_string()).as_str());
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub parameters: Vec<Identifier>,
+    // one entry per parameter
+    pub parameter_types: Vec<Option<TypeAnnotation>>,
+    pub return_type: Option<TypeAnnotation>,
+    /// The body, with its calls in tail position marked, shared by the
+    /// functions the literal evaluates to.
+    pub body: Rc<BlockStatement>,
+}
+
+impl FunctionLiteral {
+    /// The parameters with their annotations, as in `a: int, b`.
+    pub fn parameters_to_string(&self) -> String {
+        let params: Vec<String> = self
+            .parameters
+            .iter()
+            .zip(&self.parameter_types)
+            .map(|(p, t)| match t {
+                Some(t) => format!("{}: {}", p.value, t),
+                None => p.value.clone(),
+            })
+            .collect();
+        params.join(", ")
+    }
+
+    /// The return type as written after the parameters, with its arrow.
+    pub fn return_type_to_string(&self) -> String {
+        match &self.return_type {
+            Some(t) => format!(" -> {}", t),
+            None => "".to_string(),
+        }
+    }
+}
+
+impl Node for FunctionLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "{}({}){} {}",
+            self.token_literal(),
+            self.parameters_to_string(),
+            self.return_type_to_string(),
+            self.body.to_string()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallExpression {
+    pub token: Token,
+    pub span: Span,
+    pub function: Box<Expression>,
+    pub arguments: Vec<Expression>,
+    /// Whether the call is in tail position in the function around it, as
+    /// `tail_calls::mark_tail_calls` finds once the function literal around
+    /// it is built.
+    pub tail: bool,
+}
+
+impl Node for CallExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let args: Vec<String> = self.arguments.iter().map(|a| a.to_string()).collect();
+        format!("{}({})", self.function.to_string(), args.join(", "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MacroLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub parameters: Vec<Identifier>,
+    pub body: BlockStatement,
+}
+
+impl Node for MacroLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let params: Vec<String> = self.parameters.iter().map(|p| p.to_string()).collect();
+        format!(
+            "{}({}) {}",
+            self.token_literal(),
+            params.join(", "),
+            self.body.to_string()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StringLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub value: String,
+}
+
+impl Node for StringLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("\"{}\"", self.value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArrayLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub elements: Vec<Expression>,
+}
+
+impl Node for ArrayLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.to_string()).collect();
+        format!("[{}]", elements.join(", "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexExpression {
+    pub token: Token,
+    pub span: Span,
+    pub left: Box<Expression>,
+    pub index: Box<Expression>,
+}
+
+impl Node for IndexExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{}[{}]", self.left.to_string(), self.index.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HashLiteral {
+    pub token: Token,
+    pub span: Span,
+    pub pairs: Vec<(Expression, Expression)>,
+}
+
+impl Node for HashLiteral {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let pairs: Vec<String> = self
+            .pairs
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k.to_string(), v.to_string()))
+            .collect();
+        format!("{{{}}}", pairs.join(", "))
+    }
+}
+
+/// `<target> = <value>` or a compound form such as `<target> += <value>`,
+/// where the target is an identifier or an index expression.
+#[derive(Debug, Clone)]
+pub struct AssignExpression {
+    pub token: Token,
+    pub span: Span,
+    pub target: Box<Expression>,
+    pub operator: String,
+    pub value: Box<Expression>,
+}
+
+impl AssignExpression {
+    /// The infix operator a compound assignment applies, e.g. `+` for `+=`.
+    pub fn infix_operator(&self) -> Option<&str> {
+        self.operator.strip_suffix('=').filter(|op| !op.is_empty())
+    }
+}
+
+impl Node for AssignExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.target.to_string(),
+            self.operator,
+            self.value.to_string()
+        )
+    }
+}
+
+/// `import "<path>" as <alias>;`
+#[derive(Debug, Clone)]
+pub struct ImportStatement {
+    pub token: Token,
+    pub span: Span,
+    pub path: String,
+    pub alias: Identifier,
+}
+
+impl Node for ImportStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "{} \"{}\" as {};",
+            self.token_literal(),
+            self.path,
+            self.alias.to_string()
+        )
+    }
+}
+
+/// `<object>.<property>`, used to reach the exports of a module.
+#[derive(Debug, Clone)]
+pub struct MemberExpression {
+    pub token: Token,
+    pub span: Span,
+    pub object: Box<Expression>,
+    pub property: Identifier,
+}
+
+impl Node for MemberExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{}.{}", self.object.to_string(), self.property.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WhileStatement {
+    pub token: Token,
+    pub span: Span,
+    pub condition: Expression,
+    pub body: BlockStatement,
+}
+
+impl Node for WhileStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "while ({}) {}",
+            self.condition.to_string(),
+            self.body.to_string()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForStatement {
+    pub token: Token,
+    pub span: Span,
+    pub variable: Identifier,
+    pub iterable: Expression,
+    pub body: BlockStatement,
+}
+
+impl Node for ForStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "for ({} in {}) {}",
+            self.variable.to_string(),
+            self.iterable.to_string(),
+            self.body.to_string()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakStatement {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl Node for BreakStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{};", self.token_literal())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContinueStatement {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl Node for ContinueStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{};", self.token_literal())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrowStatement {
+    pub token: Token,
+    pub span: Span,
+    pub value: Expression,
+}
+
+impl Node for ThrowStatement {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{} {};", self.token_literal(), self.value.to_string())
+    }
+}
+
+/// `try { } catch (e) { } finally { }`, with a `catch`, a `finally` or both.
+#[derive(Debug, Clone)]
+pub struct TryExpression {
+    pub token: Token,
+    pub span: Span,
+    pub body: BlockStatement,
+    pub catch: Option<Box<CatchClause>>,
+    pub finally: Option<BlockStatement>,
+}
+
+impl Node for TryExpression {
+    fn token_literal(&self) -> String {
+        self.token.literal.clone()
+    }
+
+    fn to_string(&self) -> String {
+        let mut out = format!("try {}", self.body.to_string());
+        if let Some(catch) = &self.catch {
+            out.push_str(&format!(
+                " catch ({}) {}",
+                catch.parameter.to_string(),
+                catch.body.to_string()
+            ));
+        }
+        if let Some(finally) = &self.finally {
+            out.push_str(&format!(" finally {}", finally.to_string()));
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatchClause {
+    pub parameter: Identifier,
+    pub body: BlockStatement,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{ast::Node, token::Token};
@@ -195,21 +826,28 @@ mod tests {
                 token: Token {
                     token_type: TokenType::LET,
                     literal: "let".to_string(),
+                    ..Default::default()
                 },
                 name: Identifier {
                     token: Token {
                         token_type: TokenType::IDENT,
                         literal: "myVar".to_string(),
+                        ..Default::default()
                     },
                     value: "myVar".to_string(),
+                    ..Default::default()
                 },
                 value: Some(Expression::Identifier(Identifier {
                     token: Token {
                         token_type: TokenType::IDENT,
                         literal: "anotherVar".to_string(),
+                        ..Default::default()
                     },
                     value: "anotherVar".to_string(),
+                    ..Default::default()
                 })),
+                exported: false,
+                ..Default::default()
             })],
         };
 
@@ -0,0 +1,48 @@
+//! What the backends compiling Monkey to other languages, `wat`, `js` and
+//! `c`, need to know about a program alike.
+//!
+//! Where the evaluator lets integers grow beyond 64 bits, only `js` follows
+//! it, with `BigInt`s. The integers of `wat` and `c` are 64 bits wide, and
+//! arithmetic that would overflow them stops the program: `wat` traps and
+//! `c` fails with "integer too large for the c target".
+
+use crate::ast::*;
+use crate::visitor::{visit, Visitor};
+
+/// Collects the `let` bindings and loop variables of a function body or of
+/// the program, without descending into functions. A name is listed once
+/// for every declaration.
+#[derive(Default)]
+pub(crate) struct Declarations {
+    pub names: Vec<String>,
+}
+
+impl Visitor for Declarations {
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        self.names.push(ls.name.value.clone());
+        visit::walk_let_statement(self, ls);
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.names.push(fs.variable.value.clone());
+        visit::walk_for_statement(self, fs);
+    }
+
+    fn visit_function_literal(&mut self, _fl: &FunctionLiteral) {}
+}
+
+/// Splits an assignment target such as `a[i][j]` into the identifier it
+/// assigns to and the indices leading into it.
+pub(crate) fn element(target: &Expression) -> Option<(&Identifier, Vec<&Expression>)> {
+    match target {
+        Expression::IndexExpression(ie) => {
+            let (root, mut path) = match ie.left.as_ref() {
+                Expression::Identifier(id) => (id, Vec::new()),
+                left => element(left)?,
+            };
+            path.push(&ie.index);
+            Some((root, path))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,471 @@
+//! Arbitrary-precision integers.
+//!
+//! Integers are `i64`s as long as they fit, and the evaluator switches to a
+//! `BigInt` when an operation overflows, in `Object::BigInteger`. Integer
+//! literals are parsed into one, so that those beyond 64 bits keep their
+//! value.
+//!
+//! A `BigInt` is a sign and a magnitude of 32-bit digits, least significant
+//! first and without leading zeros, so that zero has no digits and equal
+//! values have equal representations.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The bytes the digits take up.
+    pub fn size(&self) -> usize {
+        self.magnitude.len() * size_of::<u32>()
+    }
+
+    /// The number of 32-bit digits.
+    pub fn digits(&self) -> usize {
+        self.magnitude.len()
+    }
+
+    /// The number of bits of the magnitude, without leading zeros.
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(most) => self.magnitude.len() as u64 * 32 - most.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &digit| (acc << 32) | digit as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    /// The quotient and the remainder of the division by `divisor`, rounded
+    /// towards zero as for `i64`, unless `divisor` is zero.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &divisor.magnitude);
+        Some((
+            BigInt::new(self.negative != divisor.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// `self * 2^shift`.
+    pub fn shl(&self, shift: u32) -> BigInt {
+        let (digits, bits) = ((shift / 32) as usize, shift % 32);
+        let mut magnitude = vec![0; digits];
+        let mut carry = 0u32;
+        for &digit in &self.magnitude {
+            let wide = ((digit as u64) << bits) | carry as u64;
+            magnitude.push(wide as u32);
+            carry = (wide >> 32) as u32;
+        }
+        magnitude.push(carry);
+        BigInt::new(self.negative, magnitude)
+    }
+
+    /// `self / 2^shift`, rounded down like `>>` on an `i64`.
+    pub fn shr(&self, shift: u32) -> BigInt {
+        let divisor = BigInt::from(1).shl(shift);
+        let (quotient, remainder) = self.div_rem(&divisor).expect("a power of two");
+        if remainder.is_negative() {
+            &quotient - &BigInt::from(1)
+        } else {
+            quotient
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl PartialEq<i64> for BigInt {
+    fn eq(&self, other: &i64) -> bool {
+        self.to_i64() == Some(*other)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+        match compare_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::new(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut product = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let wide = a as u64 * b as u64 + product[i + j] as u64 + carry;
+                product[i + j] = wide as u32;
+                carry = wide >> 32;
+            }
+            product[i + other.magnitude.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, product)
+    }
+}
+
+/// The error of parsing something that is not a decimal integer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseBigIntError;
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    /// Parses decimal digits, after an optional `-`.
+    fn from_str(s: &str) -> Result<BigInt, ParseBigIntError> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+
+        // nine digits at a time, the most that fit in a `u32`
+        let mut magnitude = Vec::new();
+        let first = digits.len() % 9;
+        let chunks = std::iter::once(&digits[..first])
+            .chain(
+                digits.as_bytes()[first..]
+                    .chunks(9)
+                    .map(|chunk| std::str::from_utf8(chunk).expect("ASCII digits")),
+            )
+            .filter(|chunk| !chunk.is_empty());
+        for chunk in chunks {
+            let scale = 10u64.pow(chunk.len() as u32);
+            let mut carry: u64 = chunk.parse().expect("at most nine digits");
+            for digit in magnitude.iter_mut() {
+                let wide = *digit as u64 * scale + carry;
+                *digit = wide as u32;
+                carry = wide >> 32;
+            }
+            if carry > 0 {
+                magnitude.push(carry as u32);
+            }
+        }
+        Ok(BigInt::new(negative, magnitude))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // nine digits at a time, least significant first
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = div_rem_digit(&magnitude, 1_000_000_000);
+            chunks.push(remainder);
+            magnitude = quotient;
+            while magnitude.last() == Some(&0) {
+                magnitude.pop();
+            }
+        }
+
+        let mut out = String::from(if self.negative { "-" } else { "" });
+        match chunks.split_last() {
+            None => out.push('0'),
+            Some((most, rest)) => {
+                out += &most.to_string();
+                for chunk in rest.iter().rev() {
+                    out += &format!("{:09}", chunk);
+                }
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+fn compare_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let wide = digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(wide as u32);
+        carry = wide >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// `a - b`, where `a` is at least `b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let mut wide = digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if wide < 0 {
+            wide += 1 << 32;
+            borrow = 1;
+        }
+        difference.push(wide as u32);
+    }
+    difference
+}
+
+fn div_rem_digit(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (i, &digit) in a.iter().enumerate().rev() {
+        let wide = (remainder << 32) | digit as u64;
+        quotient[i] = (wide / divisor as u64) as u32;
+        remainder = wide % divisor as u64;
+    }
+    (quotient, remainder as u32)
+}
+
+// long division one bit at a time, but for single digit divisors
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let (quotient, remainder) = div_rem_digit(a, *divisor);
+        return (quotient, vec![remainder]);
+    }
+    if compare_magnitude(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for bit in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + the next bit of `a`
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for digit in remainder.iter_mut() {
+            let next = *digit >> 31;
+            *digit = (*digit << 1) | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            remainder.push(carry);
+        }
+
+        if compare_magnitude(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitude(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let tests = [
+            "0",
+            "7",
+            "-7",
+            "4294967296",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "123456789012345678901234567890",
+            "-1000000000000000000000000000000000001",
+        ];
+        for s in tests {
+            assert_eq!(big(s).to_string(), s);
+        }
+        assert_eq!(big("-0"), BigInt::default());
+        assert_eq!(big("000123").to_string(), "123");
+        assert_eq!("".parse::<BigInt>(), Err(ParseBigIntError));
+        assert_eq!("12a".parse::<BigInt>(), Err(ParseBigIntError));
+    }
+
+    #[test]
+    fn test_to_i64() {
+        for i in [0, 1, -1, i64::MAX, i64::MIN, 1 << 32, -(1 << 40)] {
+            assert_eq!(BigInt::from(i).to_i64(), Some(i));
+            assert_eq!(BigInt::from(i).to_string(), i.to_string());
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+    }
+
+    #[test]
+    fn test_digits_and_bits() {
+        let tests = [
+            ("0", 0, 0),
+            ("1", 1, 1),
+            ("-255", 1, 8),
+            ("4294967296", 2, 33),
+        ];
+        for (s, digits, bits) in tests {
+            assert_eq!((big(s).digits(), big(s).bits()), (digits, bits), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!(&(&a - &a), &BigInt::default());
+
+        let (q, r) = b.div_rem(&a).unwrap();
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            ("-8".to_string(), "-9000000000900000000090".to_string())
+        );
+        let (q, r) = a.div_rem(&BigInt::from(-7)).unwrap();
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            (
+                "-17636684144620811271604938270".to_string(),
+                "0".to_string()
+            )
+        );
+        assert_eq!(a.div_rem(&BigInt::default()), None);
+
+        // the same as `i64` where it fits
+        for (x, y) in [(7i64, 2i64), (-7, 2), (7, -2), (-7, -2), (1 << 40, 3)] {
+            let (q, r) = BigInt::from(x).div_rem(&BigInt::from(y)).unwrap();
+            assert_eq!((q, r), (BigInt::from(x / y), BigInt::from(x % y)));
+            assert_eq!(BigInt::from(x).shr(1), BigInt::from(x >> 1));
+        }
+        assert!(big("-99999999999999999999") < big("-1"));
+        assert!(big("99999999999999999999") > big("9223372036854775807"));
+    }
+
+    #[test]
+    fn test_pow_and_shifts() {
+        assert_eq!(
+            BigInt::from(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(BigInt::from(-3).pow(3), BigInt::from(-27));
+        assert_eq!(BigInt::from(5).pow(0), BigInt::from(1));
+        assert_eq!(BigInt::from(1).shl(100), BigInt::from(2).pow(100));
+        assert_eq!(BigInt::from(-3).shl(70).shr(70), BigInt::from(-3));
+        assert_eq!(BigInt::from(-3).shr(70), BigInt::from(-1));
+    }
+}
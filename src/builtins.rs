@@ -0,0 +1,228 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::limits::Cost;
+use crate::object::*;
+
+thread_local! {
+    // output of `puts` on this thread while it is being captured
+    static CAPTURED_OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, collecting what `puts` prints on this thread instead of writing
+/// it to stdout.
+#[cfg(test)]
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let previous = CAPTURED_OUTPUT.with(|out| out.replace(Some(String::new())));
+    let result = f();
+    let output = CAPTURED_OUTPUT.with(|out| out.replace(previous));
+    (result, output.unwrap_or_default())
+}
+
+type BuiltinFn = fn(Vec<Object>) -> Object;
+
+const BUILTINS: &[(&str, BuiltinFn, Option<BuiltinCost>)] = &[
+    ("len", len, None),
+    ("first", first, Some(first_cost)),
+    ("last", last, Some(last_cost)),
+    ("rest", rest, Some(rest_cost)),
+    ("push", push, Some(push_cost)),
+    ("puts", puts, None),
+    ("gc", gc, None),
+    ("pow", pow, Some(pow_cost)),
+];
+
+pub fn lookup_builtin(name: &str) -> Option<Object> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|(name, func, cost)| {
+            Object::Builtin(Builtin {
+                name: name.to_string(),
+                func: Rc::new(*func),
+                cost: *cost,
+            })
+        })
+}
+
+fn wrong_number_of_arguments(got: usize, want: usize) -> Object {
+    Object::Error(format!(
+        "wrong number of arguments. got={}, want={}",
+        got, want
+    ))
+}
+
+fn len(args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return wrong_number_of_arguments(args.len(), 1);
+    }
+
+    match &args[0] {
+        Object::String(s) => Object::Integer(s.chars().count() as i64),
+        Object::Array(elements) => Object::Integer(elements.len() as i64),
+        Object::Hash(pairs) => Object::Integer(pairs.len() as i64),
+        arg => Object::Error(format!(
+            "argument to `len` not supported, got {}",
+            arg.object_type()
+        )),
+    }
+}
+
+fn first(args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return wrong_number_of_arguments(args.len(), 1);
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.first().cloned().unwrap_or(Object::Null),
+        arg => Object::Error(format!(
+            "argument to `first` must be ARRAY, got {}",
+            arg.object_type()
+        )),
+    }
+}
+
+fn first_cost(args: &[Object]) -> Cost {
+    match args {
+        [Object::Array(elements)] => Cost::of_copy(elements.first()),
+        _ => Cost::default(),
+    }
+}
+
+fn last(args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return wrong_number_of_arguments(args.len(), 1);
+    }
+
+    match &args[0] {
+        Object::Array(elements) => elements.last().cloned().unwrap_or(Object::Null),
+        arg => Object::Error(format!(
+            "argument to `last` must be ARRAY, got {}",
+            arg.object_type()
+        )),
+    }
+}
+
+fn last_cost(args: &[Object]) -> Cost {
+    match args {
+        [Object::Array(elements)] => Cost::of_copy(elements.last()),
+        _ => Cost::default(),
+    }
+}
+
+fn rest(args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return wrong_number_of_arguments(args.len(), 1);
+    }
+
+    match &args[0] {
+        Object::Array(elements) if elements.is_empty() => Object::Null,
+        Object::Array(elements) => Object::Array(elements[1..].to_vec()),
+        arg => Object::Error(format!(
+            "argument to `rest` must be ARRAY, got {}",
+            arg.object_type()
+        )),
+    }
+}
+
+fn rest_cost(args: &[Object]) -> Cost {
+    match args {
+        [Object::Array(elements)] => Cost::of_copy(elements.iter().skip(1)),
+        _ => Cost::default(),
+    }
+}
+
+fn push(args: Vec<Object>) -> Object {
+    if args.len() != 2 {
+        return wrong_number_of_arguments(args.len(), 2);
+    }
+
+    match &args[0] {
+        Object::Array(elements) => {
+            let mut elements = elements.clone();
+            elements.push(args[1].clone());
+            Object::Array(elements)
+        }
+        arg => Object::Error(format!(
+            "argument to `push` must be ARRAY, got {}",
+            arg.object_type()
+        )),
+    }
+}
+
+fn push_cost(args: &[Object]) -> Cost {
+    match args {
+        [Object::Array(_), _] => Cost::of_copy(args),
+        _ => Cost::default(),
+    }
+}
+
+fn puts(args: Vec<Object>) -> Object {
+    CAPTURED_OUTPUT.with(|out| match out.borrow_mut().as_mut() {
+        Some(captured) => {
+            for arg in &args {
+                captured.push_str(&arg.inspect());
+                captured.push('\n');
+            }
+        }
+        None => {
+            for arg in &args {
+                println!("{}", arg.inspect());
+            }
+        }
+    });
+
+    Object::Null
+}
+
+/// Runs the collector of the `gc` module, returning its counters.
+fn gc(args: Vec<Object>) -> Object {
+    if !args.is_empty() {
+        return wrong_number_of_arguments(args.len(), 0);
+    }
+
+    crate::gc::collect();
+    crate::gc::stats().to_object()
+}
+
+/// Raises an integer to a power, growing beyond 64 bits as needed.
+fn pow(args: Vec<Object>) -> Object {
+    if args.len() != 2 {
+        return wrong_number_of_arguments(args.len(), 2);
+    }
+
+    let (base, exponent) = match (args[0].to_big_int(), args[1].to_big_int()) {
+        (Some(base), Some(exponent)) => (base, exponent),
+        _ => {
+            let arg = args.iter().find(|arg| arg.to_big_int().is_none()).unwrap();
+            return Object::Error(format!(
+                "argument to `pow` must be INTEGER, got {}",
+                arg.object_type()
+            ));
+        }
+    };
+    if exponent.is_negative() {
+        return Object::Error(format!("negative exponent: {}", exponent));
+    }
+    match exponent.to_i64().and_then(|e| u32::try_from(e).ok()) {
+        Some(e) => match &args[0] {
+            Object::Integer(b) => b.checked_pow(e).map(Object::Integer),
+            _ => None,
+        }
+        .unwrap_or_else(|| Object::integer(base.pow(e))),
+        None => Object::Error(format!("exponent too large: {}", exponent)),
+    }
+}
+
+fn pow_cost(args: &[Object]) -> Cost {
+    match args {
+        [base, exponent] => match (base.to_big_int(), exponent.to_big_int()) {
+            (Some(base), Some(exponent)) => match exponent.to_i64() {
+                Some(e) if e > 0 => Cost::of_pow(&base, e as u64),
+                _ => Cost::default(),
+            },
+            _ => Cost::default(),
+        },
+        _ => Cost::default(),
+    }
+}
@@ -0,0 +1,969 @@
+//! A backend compiling Monkey programs to C, run by `compile --target c`
+//! and `compile --target exe`.
+//!
+//! The output is a standalone C11 program: the runtime in `c_runtime.c`,
+//! followed by a C function for every function literal and `main` for the
+//! statements of the program. Values are tagged and reference counted, see
+//! the runtime for the details. Variables of the program are static
+//! globals and those of a function are locals of its C function, except for
+//! the ones a nested function refers to, which live in cells shared by the
+//! closures capturing them.
+//!
+//! The program behaves like it does in the evaluator, errors included,
+//! which the compiled program prints and exits with status 1 on. A few
+//! corners differ: a function refers to the variables of the functions
+//! around it even before they are bound rather than to globals of the same
+//! name, reference cycles are never freed, and integers are 64 bits wide:
+//! arithmetic that would grow beyond them fails with "integer too large for
+//! the c target". There are no limits but one on the stack: calls in tail
+//! position take stack like any other, and calls nested deeper than 4 MiB
+//! of it fail with "limit exceeded", where the evaluator may go on.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::ast::*;
+use crate::backend::{element, Declarations};
+use crate::builtins::lookup_builtin;
+use crate::token::Span;
+use crate::visitor::Visitor;
+use crate::wat::CompileError;
+
+/// The runtime every compiled program starts with.
+pub const RUNTIME: &str = include_str!("c_runtime.c");
+
+/// Compiles `program` to C, or returns every error found.
+pub fn compile(program: &Program) -> Result<String, Vec<CompileError>> {
+    let mut compiler = Compiler::default();
+    let main = compiler.program(program);
+    if !compiler.errors.is_empty() {
+        compiler.errors.sort_by_key(|error| error.span.start);
+        return Err(compiler.errors);
+    }
+
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+    let mut globals: Vec<&String> = compiler.globals.iter().collect();
+    globals.sort();
+    for name in globals {
+        out += &format!("static Value g_{};\n", c_name(name));
+    }
+    out.push('\n');
+    for prototype in &compiler.prototypes {
+        out += &format!("{};\n", prototype);
+    }
+    for function in &compiler.functions {
+        out.push('\n');
+        out += function;
+    }
+    out.push('\n');
+    out += &main;
+    Ok(out)
+}
+
+/// A Monkey name as part of a C identifier. Letters other than ASCII ones
+/// are spelled out by their code point.
+fn c_name(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            out.push(c);
+        } else {
+            out += &format!("_x{:x}_", c as u32);
+        }
+    }
+    out
+}
+
+/// `s` as a C string literal.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            _ => out += &format!("\\{:03o}", byte),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The runtime constant for an operator.
+fn operator(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "+" => "RT_ADD",
+        "-" => "RT_SUB",
+        "*" => "RT_MUL",
+        "/" => "RT_DIV",
+        "%" => "RT_MOD",
+        "&" => "RT_BIT_AND",
+        "|" => "RT_BIT_OR",
+        "^" => "RT_BIT_XOR",
+        "<<" => "RT_SHL",
+        ">>" => "RT_SHR",
+        "<" => "RT_LT",
+        ">" => "RT_GT",
+        "<=" => "RT_LE",
+        ">=" => "RT_GE",
+        "==" => "RT_EQ",
+        "!=" => "RT_NE",
+        _ => return None,
+    })
+}
+
+/// The variables of a function, its parameters first, each listed once.
+fn variables(parameters: &[Identifier], statements: &[Statement]) -> Vec<String> {
+    let mut declarations = Declarations::default();
+    for statement in statements {
+        declarations.visit_statement(statement);
+    }
+
+    let mut names = Vec::new();
+    let parameters = parameters.iter().map(|p| &p.value);
+    for name in parameters.chain(&declarations.names) {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Collects the names a function refers to without binding them.
+struct FreeVariables {
+    bound: HashSet<String>,
+    free: BTreeSet<String>,
+}
+
+impl Visitor for FreeVariables {
+    fn visit_identifier(&mut self, id: &Identifier) {
+        if !self.bound.contains(&id.value) {
+            self.free.insert(id.value.clone());
+        }
+    }
+
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        for name in free_variables(fl) {
+            if !self.bound.contains(&name) {
+                self.free.insert(name);
+            }
+        }
+    }
+}
+
+fn free_variables(fl: &FunctionLiteral) -> BTreeSet<String> {
+    let mut visitor = FreeVariables {
+        bound: variables(&fl.parameters, &fl.body.statements)
+            .into_iter()
+            .collect(),
+        free: BTreeSet::new(),
+    };
+    visitor.visit_block_statement(&fl.body);
+    visitor.free
+}
+
+/// Collects the names the functions in a body refer to without binding
+/// them, which the variables of that name are captured by.
+#[derive(Default)]
+struct Captures {
+    names: HashSet<String>,
+}
+
+impl Visitor for Captures {
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        self.names.extend(free_variables(fl));
+    }
+}
+
+/// The variables of a function being compiled.
+struct Frame {
+    variables: Vec<String>,
+    // the variables captured by functions within
+    cells: HashSet<String>,
+    // the variables of the functions around it that it captures, in the
+    // order of its cells
+    captured: Vec<String>,
+}
+
+/// What a name refers to where it is used.
+enum Variable {
+    Global(String),
+    Local(String),
+    Cell(String),
+    Captured(usize),
+    Builtin(String),
+    Undefined,
+}
+
+impl Variable {
+    /// The `Value` holding the variable, unless there is none.
+    fn slot(&self) -> Option<String> {
+        match self {
+            Variable::Global(name) => Some(format!("g_{}", name)),
+            Variable::Local(name) => Some(format!("l_{}", name)),
+            Variable::Cell(name) => Some(format!("c_{}->value", name)),
+            Variable::Captured(i) => Some(format!("self->cells[{}]->value", i)),
+            Variable::Builtin(_) | Variable::Undefined => None,
+        }
+    }
+}
+
+/// Where the value of a block goes.
+enum Tail {
+    Discard,
+    Assign(String),
+}
+
+/// The state of the C function being generated.
+#[derive(Default)]
+struct Body {
+    code: String,
+    indent: usize,
+    temps: usize,
+    // the variables holding the items of `for` loops
+    items: usize,
+    // whether a `return` jumps to the end
+    returns: bool,
+}
+
+#[derive(Default)]
+struct Compiler {
+    globals: HashSet<String>,
+    // the functions being compiled, innermost last
+    frames: Vec<Frame>,
+    body: Body,
+    prototypes: Vec<String>,
+    functions: Vec<String>,
+    errors: Vec<CompileError>,
+}
+
+impl Compiler {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.body.indent {
+            self.body.code += "    ";
+        }
+        self.body.code += text;
+        self.body.code.push('\n');
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str) {
+        self.errors.push(CompileError {
+            span,
+            message: format!("{} not supported by the c target", what),
+        });
+    }
+
+    /// Stores the value of `code` in a new temporary, which it names.
+    fn temp(&mut self, code: &str) -> String {
+        self.body.temps += 1;
+        let temp = format!("t{}", self.body.temps);
+        self.line(&format!("Value {} = {};", temp, code));
+        temp
+    }
+
+    fn resolve(&self, name: &str) -> Variable {
+        if let Some(frame) = self.frames.last() {
+            if frame.cells.contains(name) {
+                return Variable::Cell(c_name(name));
+            }
+            if frame.variables.iter().any(|v| v == name) {
+                return Variable::Local(c_name(name));
+            }
+            if let Some(i) = frame.captured.iter().position(|v| v == name) {
+                return Variable::Captured(i);
+            }
+        }
+        if self.globals.contains(name) {
+            Variable::Global(c_name(name))
+        } else if lookup_builtin(name).is_some() {
+            Variable::Builtin(name.to_uppercase())
+        } else {
+            Variable::Undefined
+        }
+    }
+
+    fn program(&mut self, program: &Program) -> String {
+        self.globals = variables(&[], &program.statements).into_iter().collect();
+        self.body.indent = 1;
+        self.statements(&program.statements, &Tail::Discard);
+
+        let body = std::mem::take(&mut self.body);
+        let mut out = String::from("int main(void)\n{\n");
+        out += &items(&body);
+        out += &body.code;
+        if body.returns {
+            out += "out:\n";
+        }
+        out += "    return 0;\n}\n";
+        out
+    }
+
+    fn statements(&mut self, statements: &[Statement], tail: &Tail) {
+        for (i, statement) in statements.iter().enumerate() {
+            match statement {
+                Statement::ExpressionStatement(ExpressionStatement {
+                    expression: Some(expression),
+                    ..
+                }) if i + 1 == statements.len() => self.tail(expression, tail),
+                _ => self.statement(statement),
+            }
+        }
+    }
+
+    fn tail(&mut self, expression: &Expression, tail: &Tail) {
+        match (expression, tail) {
+            (Expression::IfExpression(ie), _) => self.if_statement(ie, tail),
+            (_, Tail::Discard) => {
+                let code = self.expression(expression);
+                self.line(&format!("rt_release({});", code));
+            }
+            (_, Tail::Assign(target)) => {
+                let code = self.expression(expression);
+                self.line(&format!("{} = {};", target, code));
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => {
+                let value = match &ls.value {
+                    Some(Expression::FunctionLiteral(fl)) => self.function(fl, Some(&ls.name)),
+                    Some(value) => self.expression(value),
+                    None => "RT_NULL".to_string(),
+                };
+                let slot = self
+                    .resolve(&ls.name.value)
+                    .slot()
+                    .expect("a declared name");
+                self.line(&format!(
+                    "rt_define(&{}, {}, {});",
+                    slot,
+                    value,
+                    ls.is_const()
+                ));
+            }
+            Statement::ReturnStatement(rs) => {
+                let value = match &rs.return_value {
+                    Some(value) => self.expression(value),
+                    None => "RT_NULL".to_string(),
+                };
+                if self.frames.is_empty() {
+                    self.line(&format!("rt_release({});", value));
+                } else {
+                    self.line(&format!("result = {};", value));
+                }
+                self.line("goto out;");
+                self.body.returns = true;
+            }
+            Statement::ExpressionStatement(es) => {
+                if let Some(expression) = &es.expression {
+                    self.tail(expression, &Tail::Discard);
+                }
+            }
+            Statement::WhileStatement(ws) => {
+                self.line("while (1) {");
+                self.body.indent += 1;
+                let condition = self.expression(&ws.condition);
+                self.line(&format!("if (!rt_truthy({})) {{", condition));
+                self.line("    break;");
+                self.line("}");
+                self.statements(&ws.body.statements, &Tail::Discard);
+                self.body.indent -= 1;
+                self.line("}");
+            }
+            Statement::ForStatement(fs) => {
+                let iterable = self.expression(&fs.iterable);
+                self.body.items += 1;
+                let items = format!("it{}", self.body.items);
+                self.line(&format!("{} = rt_iter({});", items, iterable));
+                self.line(&format!(
+                    "for (size_t i{} = 0; i{} < AS_ARRAY({})->len; i{}++) {{",
+                    self.body.items, self.body.items, items, self.body.items
+                ));
+                self.body.indent += 1;
+                let slot = self
+                    .resolve(&fs.variable.value)
+                    .slot()
+                    .expect("a declared name");
+                self.line(&format!(
+                    "rt_define(&{}, rt_retain(AS_ARRAY({})->items[i{}]), false);",
+                    slot, items, self.body.items
+                ));
+                self.statements(&fs.body.statements, &Tail::Discard);
+                self.body.indent -= 1;
+                self.line("}");
+                self.line(&format!("rt_release({});", items));
+                self.line(&format!("{} = RT_UNSET;", items));
+            }
+            Statement::BreakStatement(_) => self.line("break;"),
+            Statement::ContinueStatement(_) => self.line("continue;"),
+            Statement::ImportStatement(is) => self.unsupported(is.span, "imports are"),
+            Statement::ThrowStatement(ts) => self.unsupported(ts.span, "exceptions are"),
+        }
+    }
+
+    fn if_statement(&mut self, ie: &IfExpression, tail: &Tail) {
+        let condition = self.expression(&ie.condition);
+        self.line(&format!("if (rt_truthy({})) {{", condition));
+        self.body.indent += 1;
+        self.statements(&ie.consequence.statements, tail);
+        self.body.indent -= 1;
+        if let Some(alternative) = &ie.alternative {
+            self.line("} else {");
+            self.body.indent += 1;
+            self.statements(&alternative.statements, tail);
+            self.body.indent -= 1;
+        }
+        self.line("}");
+    }
+
+    /// Compiles `expression` to a C expression giving its value, after the
+    /// statements it needs.
+    fn expression(&mut self, expression: &Expression) -> String {
+        match expression {
+            Expression::Identifier(id) => match self.resolve(&id.value) {
+                Variable::Builtin(name) => format!("rt_builtin(B_{})", name),
+                Variable::Undefined => format!(
+                    "rt_fail({})",
+                    c_string(&format!("identifier not found: {}", id.value))
+                ),
+                variable => format!(
+                    "rt_get({}, {})",
+                    variable.slot().unwrap(),
+                    c_string(&id.value)
+                ),
+            },
+            Expression::IntegerLiteral(il) => match il.value.to_i64() {
+                Some(i64::MIN) => "rt_int(INT64_MIN)".to_string(),
+                Some(i) => format!("rt_int(INT64_C({}))", i),
+                None => {
+                    self.unsupported(il.span, "integers beyond 64 bits are");
+                    "RT_NULL".to_string()
+                }
+            },
+            Expression::Boolean(b) => format!("rt_bool({})", b.value),
+            Expression::StringLiteral(sl) => {
+                format!("rt_string({}, {})", c_string(&sl.value), sl.value.len())
+            }
+            Expression::PrefixExpression(pe) => {
+                let right = self.expression(&pe.right);
+                match pe.operator.as_str() {
+                    "!" => format!("rt_not({})", right),
+                    "-" => format!("rt_neg({})", right),
+                    operator => {
+                        self.unsupported(pe.span, &format!("the operator {} is", operator));
+                        right
+                    }
+                }
+            }
+            Expression::InfixExpression(ie) if matches!(ie.operator.as_str(), "&&" | "||") => {
+                // the value of the right operand as a boolean, if it is
+                // evaluated, and the one of the left operand otherwise
+                let and = ie.operator == "&&";
+                let left = self.expression(&ie.left);
+                let temp = self.temp(&format!("rt_bool({})", !and));
+                let negation = if and { "" } else { "!" };
+                self.line(&format!("if ({}rt_truthy({})) {{", negation, left));
+                self.body.indent += 1;
+                let right = self.expression(&ie.right);
+                self.line(&format!("{} = rt_bool(rt_truthy({}));", temp, right));
+                self.body.indent -= 1;
+                self.line("}");
+                temp
+            }
+            Expression::InfixExpression(ie) => {
+                let operands = self.operands(&[&ie.left, &ie.right]);
+                match operator(&ie.operator) {
+                    Some(op) => format!("rt_binary({}, {}, {})", op, operands[0], operands[1]),
+                    None => {
+                        let what = format!("the operator {} is", ie.operator);
+                        self.unsupported(ie.span, &what);
+                        "RT_NULL".to_string()
+                    }
+                }
+            }
+            Expression::IfExpression(ie) => {
+                let temp = self.temp("RT_NULL");
+                self.if_statement(ie, &Tail::Assign(temp.clone()));
+                temp
+            }
+            Expression::FunctionLiteral(fl) => self.function(fl, None),
+            Expression::CallExpression(ce) => {
+                if matches!(ce.function.as_ref(), Expression::Identifier(id) if id.value == "quote")
+                {
+                    self.unsupported(ce.span, "quote is");
+                }
+                let mut expressions = vec![ce.function.as_ref()];
+                expressions.extend(&ce.arguments);
+                let operands = self.operands(&expressions);
+                format!(
+                    "rt_call({}, {}, {})",
+                    operands[0],
+                    ce.arguments.len(),
+                    values(&operands[1..])
+                )
+            }
+            Expression::ArrayLiteral(al) => {
+                let expressions: Vec<&Expression> = al.elements.iter().collect();
+                let operands = self.operands(&expressions);
+                format!("rt_array({}, {})", operands.len(), values(&operands))
+            }
+            Expression::IndexExpression(ie) => {
+                let operands = self.operands(&[&ie.left, &ie.index]);
+                format!("rt_index({}, {})", operands[0], operands[1])
+            }
+            Expression::HashLiteral(hl) => {
+                let expressions: Vec<&Expression> =
+                    hl.pairs.iter().flat_map(|(k, v)| [k, v]).collect();
+                let operands = self.operands(&expressions);
+                format!("rt_hash({}, {})", hl.pairs.len(), values(&operands))
+            }
+            Expression::AssignExpression(ae) => self.assignment(ae),
+            Expression::MacroLiteral(ml) => {
+                self.unsupported(ml.span, "macros are");
+                "RT_NULL".to_string()
+            }
+            Expression::MemberExpression(me) => {
+                self.unsupported(me.span, "modules are");
+                "RT_NULL".to_string()
+            }
+            Expression::TryExpression(te) => {
+                self.unsupported(te.span, "exceptions are");
+                "RT_NULL".to_string()
+            }
+        }
+    }
+
+    /// Compiles `expressions`, which are evaluated from left to right, to C
+    /// expressions that may be evaluated in any order: all but literals and
+    /// the last operand with effects are stored in temporaries.
+    fn operands(&mut self, expressions: &[&Expression]) -> Vec<String> {
+        let mut operands = Vec::new();
+        for (i, expression) in expressions.iter().enumerate() {
+            let code = self.expression(expression);
+            if is_literal(expression) || expressions[i + 1..].iter().all(|e| is_literal(e)) {
+                operands.push(code);
+            } else {
+                operands.push(self.temp(&code));
+            }
+        }
+        operands
+    }
+
+    fn assignment(&mut self, ae: &AssignExpression) -> String {
+        let op = match ae.infix_operator() {
+            None => "RT_NONE",
+            Some(infix) => match operator(infix) {
+                Some(op) => op,
+                None => {
+                    self.unsupported(ae.span, &format!("the operator {} is", ae.operator));
+                    return "RT_NULL".to_string();
+                }
+            },
+        };
+
+        let (root, path) = match ae.target.as_ref() {
+            Expression::Identifier(id) => (id, Vec::new()),
+            target => match element(target) {
+                Some(element) => element,
+                None => {
+                    self.errors.push(CompileError {
+                        span: target.span(),
+                        message: format!("invalid assignment target: {}", target.to_string()),
+                    });
+                    return "RT_NULL".to_string();
+                }
+            },
+        };
+
+        let mut expressions = path.clone();
+        expressions.push(&ae.value);
+        let operands = self.operands(&expressions);
+        let name = c_string(&root.value);
+        let Some(slot) = self.resolve(&root.value).slot() else {
+            for operand in &operands {
+                self.line(&format!("rt_release({});", operand));
+            }
+            return format!(
+                "rt_fail({})",
+                c_string(&format!(
+                    "cannot assign to undeclared identifier: {}",
+                    root.value
+                ))
+            );
+        };
+
+        match (path.len(), op) {
+            (0, "RT_NONE") => format!("rt_assign(&{}, {}, {})", slot, operands[0], name),
+            (0, _) => format!("rt_update(&{}, {}, {}, {})", slot, op, operands[0], name),
+            (len, _) => format!(
+                "rt_assign_path(&{}, {}, {}, {}, {})",
+                slot,
+                name,
+                op,
+                len,
+                values(&operands)
+            ),
+        }
+    }
+
+    /// Compiles `fl` to a C function, returning the expression creating a
+    /// closure of it. `name` is the variable it is bound to, if any.
+    fn function(&mut self, fl: &FunctionLiteral, name: Option<&Identifier>) -> String {
+        let variables = variables(&fl.parameters, &fl.body.statements);
+        let mut captures = Captures::default();
+        for statement in &fl.body.statements {
+            captures.visit_statement(statement);
+        }
+        let cells = variables
+            .iter()
+            .filter(|v| captures.names.contains(*v))
+            .cloned()
+            .collect();
+        let captured: Vec<String> = free_variables(fl)
+            .into_iter()
+            .filter(|name| {
+                self.frames
+                    .iter()
+                    .any(|frame| frame.variables.contains(name))
+            })
+            .collect();
+        let cell_arguments: Vec<String> = captured
+            .iter()
+            .map(|name| match self.resolve(name) {
+                Variable::Cell(name) => format!(", c_{}", name),
+                Variable::Captured(i) => format!(", self->cells[{}]", i),
+                _ => unreachable!("a captured variable is in a cell"),
+            })
+            .collect();
+
+        let c_function = match name {
+            Some(name) => format!("fn{}_{}", self.prototypes.len() + 1, c_name(&name.value)),
+            None => format!("fn{}", self.prototypes.len() + 1),
+        };
+        let signature = format!("static Value {}(Function *self, Value *args)", c_function);
+        self.prototypes.push(signature.clone());
+
+        let outer = std::mem::take(&mut self.body);
+        self.frames.push(Frame {
+            variables,
+            cells,
+            captured: captured.clone(),
+        });
+        self.body.indent = 1;
+
+        let parameters: Vec<&str> = fl.parameters.iter().map(|p| p.value.as_str()).collect();
+        let frame = self.frames.last().unwrap();
+        let mut prologue = Vec::new();
+        let mut epilogue = Vec::new();
+        for variable in &frame.variables {
+            let c = c_name(variable);
+            // the last parameter of a name is the one bound
+            let argument = parameters.iter().rposition(|p| p == variable);
+            if frame.cells.contains(variable) {
+                prologue.push(format!("Cell *c_{} = rt_cell();", c));
+                if let Some(i) = argument {
+                    prologue.push(format!("c_{}->value = args[{}];", c, i));
+                }
+                epilogue.push(format!("rt_release_cell(c_{});", c));
+            } else {
+                let value = match argument {
+                    Some(i) => format!("args[{}]", i),
+                    None => "RT_UNSET".to_string(),
+                };
+                prologue.push(format!("Value l_{} = {};", c, value));
+                epilogue.push(format!("rt_release(l_{});", c));
+            }
+        }
+        // the arguments of the parameters bound twice
+        for (i, parameter) in parameters.iter().enumerate() {
+            if parameters[i + 1..].contains(parameter) {
+                prologue.push(format!("rt_release(args[{}]);", i));
+            }
+        }
+        prologue.push("Value result = RT_NULL;".to_string());
+        for line in &prologue {
+            self.line(line);
+        }
+
+        self.statements(&fl.body.statements, &Tail::Assign("result".to_string()));
+
+        let body = std::mem::replace(&mut self.body, outer);
+        self.frames.pop();
+        let mut out = format!("{}\n{{\n", signature);
+        out += &items(&body);
+        out += &body.code;
+        if body.returns {
+            out += "out:\n";
+        }
+        for line in epilogue {
+            out += &format!("    {}\n", line);
+        }
+        for i in 1..=body.items {
+            out += &format!("    rt_release(it{});\n", i);
+        }
+        out += "    return result;\n}\n";
+        self.functions.push(out);
+
+        let source = format!("fn({}) {}", parameters.join(", "), fl.body.to_string());
+        format!(
+            "rt_closure({}, {}, {}, {}{})",
+            c_function,
+            fl.parameters.len(),
+            c_string(&source),
+            captured.len(),
+            cell_arguments.concat()
+        )
+    }
+}
+
+/// The declarations of the variables holding the items of `for` loops.
+fn items(body: &Body) -> String {
+    (1..=body.items)
+        .map(|i| format!("    Value it{} = RT_UNSET;\n", i))
+        .collect()
+}
+
+/// `operands` as an array of values, or `NULL` when there are none.
+fn values(operands: &[String]) -> String {
+    if operands.is_empty() {
+        "NULL".to_string()
+    } else {
+        format!("(Value[]){{{}}}", operands.join(", "))
+    }
+}
+
+/// Whether `expression` is a literal, whose value does not depend on when
+/// it is evaluated.
+fn is_literal(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::IntegerLiteral(_) | Expression::Boolean(_) | Expression::StringLiteral(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::*;
+    use crate::bigint::BigInt;
+    use crate::builtins::capture_output;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+
+    use crate::object::Object;
+    use crate::parser::parse_ok;
+
+    /// Builds `code` with cc and runs it, returning its exit status, stdout
+    /// and stderr. `name` tells apart the files of tests running at once.
+    fn run_cc(code: &str, name: &str) -> (i32, String, String) {
+        let base = std::env::temp_dir().join(format!("monkey_c_{}_{}", name, std::process::id()));
+        let source = base.with_extension("c");
+        std::fs::write(&source, code).unwrap();
+        let status = Command::new("cc")
+            .arg(&source)
+            .args(["-std=c11", "-pedantic", "-Wall", "-Werror", "-o"])
+            .arg(&base)
+            .status()
+            .expect("could not run cc");
+        let _ = std::fs::remove_file(&source);
+        assert!(status.success(), "cc failed on:\n{}", code);
+
+        let output = Command::new(&base).output().unwrap();
+        let _ = std::fs::remove_file(&base);
+        (
+            output.status.code().unwrap_or(-1),
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    }
+
+    /// Checks that the compiled `program` prints what the evaluator does and
+    /// ends with the same error, if any.
+    fn assert_same_behavior(program: &Program, name: &str, input: &str) {
+        let code = compile(program).unwrap_or_else(|errors| {
+            panic!("could not compile {}: {:?}", input, errors);
+        });
+        let (result, expected) =
+            capture_output(|| Evaluator::new().eval_program(program, &Environment::new()));
+        let (status, stdout, stderr) = run_cc(&code, name);
+
+        // integers the evaluator grows beyond 64 bits stop the program
+        if stderr == "ERROR: integer too large for the c target\n" {
+            assert_eq!(status, 1, "input: {}", input);
+            let rest = expected.strip_prefix(&stdout).unwrap_or_else(|| {
+                panic!(
+                    "input: {}\n{:?} is not the start of {:?}",
+                    input, stdout, expected
+                )
+            });
+            let too_large = rest
+                .split(|c: char| !c.is_ascii_digit() && c != '-')
+                .any(|word| word.parse::<i64>().is_err() && word.parse::<BigInt>().is_ok());
+            assert!(too_large, "input: {}\nno big integer in {:?}", input, rest);
+            return;
+        }
+
+        assert_eq!(stdout, expected, "input: {}", input);
+        match result {
+            Object::Error(msg) => {
+                assert_eq!(status, 1, "input: {}", input);
+                assert_eq!(stderr, format!("ERROR: {}\n", msg), "input: {}", input);
+            }
+            _ => assert_eq!((status, stderr.as_str()), (0, ""), "input: {}", input),
+        }
+    }
+
+    #[test]
+    fn test_compiled_programs() {
+        let tests = [
+            "let fib = fn(n) { if (n < 2) { return n; } fib(n - 1) + fib(n - 2) }; puts(fib(20));",
+            "let nothing = puts(); puts(if (0) { 1 } else { 2 }, if (nothing) { 1 }, !0, !nothing);",
+            "puts(7 / 2, -7 / 2, -7 % 3, 1 << 62, 5 & 3 | 8, 6 ^ 3, -16 >> 2, -1 >> 63);",
+            "puts(9223372036854775807 + 1, -9223372036854775807 - 1, (-9223372036854775807 - 1) / -1);",
+            "puts(-9223372036854775807 - 1, (-9223372036854775807 - 1) % -1, -1 << 63);",
+            "puts(1); puts(-(-9223372036854775807 - 1));",
+            "puts(4294967296 * -2147483648); puts(4294967296 * 2147483648);",
+            "puts(-9223372036854775807 - 2);",
+            "puts(1 << 63);",
+            "puts(pow(3, 4), pow(-2, 5), pow(7, 0), pow(2, 62), pow(-2, 63));",
+            "puts(pow(2, 63));",
+            "puts(pow(3, 40));",
+            "puts(pow(2, -1));",
+            "puts(pow(2, true));",
+            "puts(1 < 2 == true, !(1 > 2) && 3 || false, false && 1 / 0, true || 1 / 0);",
+            r#"puts("a" + "b" == "ab", len("héllo"), "x" != "y", "a\b??=c", "two
+lines");"#,
+            r#"let h = {"b": 1, 2: true, "a": [1], false: 0, 1: 1, true: 2, "b": 3};
+               puts(h, len(h), h["a"][0], h[3]);
+               for (k in h) { puts(k); }"#,
+            "let xs = [1, 2, 3]; let ys = xs; ys[0] = 10; puts(xs, ys);",
+            r#"let m = {"a": {"b": [1, 2]}}; let n = m; m["a"]["b"][1] *= 21; m["c"] = m; puts(m, n);"#,
+            "let xs = [0, 0]; let v = (xs[1] = 5) + 1; puts(xs, v, xs[0] += 2, xs);",
+            "let total = 0; let i = 0;
+             while (i < 10) { i += 1; if (i % 3 == 0) { continue; } total += i; }
+             puts(total, i);",
+            "for (c in \"añb\") { puts(c); } puts(c);",
+            "for (x in [1, 2, 3, 4]) { if (x == 3) { break; } puts(x); }",
+            "let f = fn() { let a = 1; if (true) { let a = 2; } a }; puts(f());",
+            "let g = fn(x) { let y = if (x > 0) { let d = x * 2; d + 1 } else { 0 }; y }; puts(g(3), g(-3));",
+            "let counter = fn() { let n = 0; fn() { n += 1; n } }; let c = counter(); c(); puts(c());",
+            "let adders = fn(n) { let xs = []; for (i in [1, 2]) { xs = push(xs, fn(x) { x + i + n }); } xs };
+             let fs = adders(10); puts(fs[0](1), fs[1](1));",
+            "let outer = fn(a) { fn(b) { fn(c) { a + b + c } } }; puts(outer(1)(2)(3));",
+            "let len = fn(x) { 42 }; puts(len([1]));",
+            "let x = 5; x /= 2; puts(x, x = 7, x);",
+            "puts(first([1, 2]), last([1, 2]), rest([1, 2]), rest([]), first([]), push([], 1));",
+            "let f = fn(n) { while (true) { if (n > 3) { return n; } n += 1; } }; puts(f(0));",
+            "let f = fn(xs) { for (x in xs) { if (x > 1) { return x; } } 0 }; puts(f([1, 2, 3]), f([]));",
+            "let compose = fn(f, g) { fn(x) { g(f(x)) } }; puts(compose(fn(x) { x + 1 }, fn(x) { x * 2 })(5));",
+            "let empty = fn() { }; puts(empty(), [empty()], empty, len, fn(a, b) { a });",
+            "let twice = fn(x, x) { x }; puts(twice(1, 2));",
+            "let héllo = 1; let wörld = fn() { héllo }; puts(wörld());",
+            "let i = 0; let next = fn() { i += 1; i }; puts([next(), next()], next() - next(), {next(): next()});",
+            "const answer = 42; puts(answer); return answer; puts(0);",
+            "puts(1); 1 + true",
+            "let xs = [1]; xs[1] = 2;",
+            "puts({}[[]])",
+            "puts(1[0])",
+            "for (x in 1) { }",
+            "puts(rest(1))",
+            "puts(len(1, 2))",
+            "fn(x) { x }(1, 2)",
+            "let f = 1; f()",
+            "puts(-true)",
+            "puts(1 << 64)",
+            "puts(1 % 0)",
+            "puts(x)",
+            "y = 1",
+            "let f = fn() { z[0] += 1 }; f()",
+            "let f = fn() { let later = fn() { v }; let r = later(); let v = 1; r }; f()",
+        ];
+
+        for (i, input) in tests.iter().enumerate() {
+            assert_same_behavior(&parse_ok(input), &i.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_golden_programs() {
+        // the ones with top-level quotes, imports, exceptions or parser
+        // errors do not compile
+        let skipped = ["exceptions", "macros", "modules", "parse_errors"];
+
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden");
+        let mut paths: Vec<_> = std::fs::read_dir(&golden)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|e| e == "monkey"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            if skipped.contains(&name.as_str()) {
+                continue;
+            }
+            let program = Evaluator::new().parse_file(&path).unwrap();
+            assert_same_behavior(&program, &name, &name);
+        }
+    }
+
+    #[test]
+    fn test_deep_recursion_fails() {
+        let tests = [
+            "let f = fn(n, acc) { if (n == 0) { acc } else { f(n - 1, acc + 1) } }; puts(f(1000000, 0));",
+            "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; puts(f(100000000));",
+        ];
+
+        for (i, input) in tests.iter().enumerate() {
+            let code = compile(&parse_ok(input)).unwrap();
+            let (status, stdout, stderr) = run_cc(&code, &format!("deep_{}", i));
+
+            assert_eq!((status, stdout.as_str()), (1, ""), "input: {}", input);
+            assert!(
+                stderr.starts_with("ERROR: limit exceeded: more than ")
+                    && stderr.ends_with(" nested calls\n"),
+                "input: {}\nstderr: {}",
+                input,
+                stderr
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let tests = [
+            (
+                "import \"lib.monkey\" as lib;",
+                "1:1: imports are not supported by the c target",
+            ),
+            (
+                "quote(1 + 2)",
+                "1:1: quote is not supported by the c target",
+            ),
+            (
+                "let m = macro(x) { x };",
+                "1:9: macros are not supported by the c target",
+            ),
+            (
+                "puts(99999999999999999999)",
+                "1:6: integers beyond 64 bits are not supported by the c target",
+            ),
+            (
+                "try { 1 } catch (e) { throw e; }",
+                "1:1: exceptions are not supported by the c target",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let errors = compile(&parse_ok(input)).unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+}
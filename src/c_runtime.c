@@ -0,0 +1,1012 @@
+/*
+ * The runtime of a program compiled from Monkey by the c backend, which
+ * copies this file to the start of every program it compiles.
+ *
+ * A value is a tagged union. Strings, arrays, hashes and functions live on
+ * the heap and are reference counted; every function taking a `Value`
+ * takes over that reference, and every function returning one hands over a
+ * reference to the caller. Arrays and hashes are values in Monkey, so they
+ * are copied before a change unless nothing else refers to them. Cycles,
+ * such as a function stored in a variable it captures, are never freed.
+ *
+ * Runtime errors print the message of the evaluator and exit with status 1.
+ */
+
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#if defined(__GNUC__)
+#define RT static __attribute__((unused))
+#else
+#define RT static
+#endif
+
+typedef enum {
+    /* a variable that has not been bound yet */
+    T_UNSET,
+    T_NULL,
+    T_INTEGER,
+    T_BOOLEAN,
+    T_STRING,
+    T_ARRAY,
+    T_HASH,
+    T_FUNCTION,
+    T_BUILTIN,
+} Tag;
+
+typedef struct {
+    size_t refs;
+} Obj;
+
+typedef struct {
+    uint8_t tag;
+    /* set on variables bound by `const` */
+    bool constant;
+    union {
+        int64_t i;
+        bool b;
+        Obj *o;
+    } as;
+} Value;
+
+typedef struct {
+    Obj obj;
+    size_t len;
+    char bytes[];
+} String;
+
+typedef struct {
+    Obj obj;
+    size_t len;
+    Value items[];
+} Array;
+
+typedef struct {
+    Value key;
+    Value value;
+} Pair;
+
+/* the pairs are sorted by key, in the order of the evaluator */
+typedef struct {
+    Obj obj;
+    size_t len;
+    Pair pairs[];
+} Hash;
+
+/* a variable captured by a function */
+typedef struct {
+    Obj obj;
+    Value value;
+} Cell;
+
+typedef struct Function Function;
+
+struct Function {
+    Obj obj;
+    Value (*code)(Function *self, Value *args);
+    size_t arity;
+    /* the function as the evaluator prints it */
+    const char *source;
+    size_t ncells;
+    Cell *cells[];
+};
+
+typedef enum {
+    RT_ADD,
+    RT_SUB,
+    RT_MUL,
+    RT_DIV,
+    RT_MOD,
+    RT_BIT_AND,
+    RT_BIT_OR,
+    RT_BIT_XOR,
+    RT_SHL,
+    RT_SHR,
+    RT_LT,
+    RT_GT,
+    RT_LE,
+    RT_GE,
+    RT_EQ,
+    RT_NE,
+    /* a plain assignment */
+    RT_NONE,
+} Operator;
+
+static const char *rt_operators[] = {
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "<", ">", "<=", ">=", "==", "!=",
+};
+
+#define RT_UNSET ((Value){.tag = T_UNSET})
+#define RT_NULL ((Value){.tag = T_NULL})
+
+#define AS_STRING(v) ((String *)(v).as.o)
+#define AS_ARRAY(v) ((Array *)(v).as.o)
+#define AS_HASH(v) ((Hash *)(v).as.o)
+#define AS_FUNCTION(v) ((Function *)(v).as.o)
+
+RT _Noreturn void rt_error(const char *format, ...)
+{
+    va_list args;
+    va_start(args, format);
+    fflush(stdout);
+    fputs("ERROR: ", stderr);
+    vfprintf(stderr, format, args);
+    fputc('\n', stderr);
+    va_end(args);
+    exit(1);
+}
+
+/* for errors in expressions */
+RT Value rt_fail(const char *message)
+{
+    rt_error("%s", message);
+}
+
+/* the objects allocated and freed so far, for `gc` */
+static size_t rt_allocated, rt_freed;
+
+RT void *rt_alloc(size_t size)
+{
+    Obj *obj = malloc(size);
+    if (obj == NULL) {
+        rt_error("out of memory");
+    }
+    obj->refs = 1;
+    rt_allocated++;
+    return obj;
+}
+
+RT bool rt_is_heap(Value v)
+{
+    return v.tag >= T_STRING && v.tag <= T_FUNCTION;
+}
+
+RT Value rt_heap(Tag tag, void *obj)
+{
+    Value v = {.tag = tag};
+    v.as.o = obj;
+    return v;
+}
+
+RT Value rt_int(int64_t i)
+{
+    Value v = {.tag = T_INTEGER};
+    v.as.i = i;
+    return v;
+}
+
+RT Value rt_bool(bool b)
+{
+    Value v = {.tag = T_BOOLEAN};
+    v.as.b = b;
+    return v;
+}
+
+RT Value rt_retain(Value v)
+{
+    if (rt_is_heap(v)) {
+        v.as.o->refs++;
+    }
+    return v;
+}
+
+RT void rt_release_cell(Cell *cell);
+
+RT void rt_release(Value v)
+{
+    if (!rt_is_heap(v) || --v.as.o->refs > 0) {
+        return;
+    }
+    switch (v.tag) {
+    case T_ARRAY:
+        for (size_t i = 0; i < AS_ARRAY(v)->len; i++) {
+            rt_release(AS_ARRAY(v)->items[i]);
+        }
+        break;
+    case T_HASH:
+        for (size_t i = 0; i < AS_HASH(v)->len; i++) {
+            rt_release(AS_HASH(v)->pairs[i].key);
+            rt_release(AS_HASH(v)->pairs[i].value);
+        }
+        break;
+    case T_FUNCTION:
+        for (size_t i = 0; i < AS_FUNCTION(v)->ncells; i++) {
+            rt_release_cell(AS_FUNCTION(v)->cells[i]);
+        }
+        break;
+    }
+    free(v.as.o);
+    rt_freed++;
+}
+
+RT Cell *rt_cell(void)
+{
+    Cell *cell = rt_alloc(sizeof(Cell));
+    cell->value = RT_UNSET;
+    return cell;
+}
+
+RT void rt_release_cell(Cell *cell)
+{
+    if (--cell->obj.refs == 0) {
+        rt_release(cell->value);
+        free(cell);
+        rt_freed++;
+    }
+}
+
+RT const char *rt_type(Value v)
+{
+    switch (v.tag) {
+    case T_INTEGER:
+        return "INTEGER";
+    case T_BOOLEAN:
+        return "BOOLEAN";
+    case T_STRING:
+        return "STRING";
+    case T_ARRAY:
+        return "ARRAY";
+    case T_HASH:
+        return "HASH";
+    case T_FUNCTION:
+        return "FUNCTION";
+    case T_BUILTIN:
+        return "BUILTIN";
+    default:
+        return "NULL";
+    }
+}
+
+RT bool rt_truthy(Value v)
+{
+    bool truthy = !(v.tag == T_NULL || (v.tag == T_BOOLEAN && !v.as.b));
+    rt_release(v);
+    return truthy;
+}
+
+RT Value rt_binary(Operator op, Value left, Value right);
+
+/* variables */
+
+RT Value rt_get(Value v, const char *name)
+{
+    if (v.tag == T_UNSET) {
+        rt_error("identifier not found: %s", name);
+    }
+    v.constant = false;
+    return rt_retain(v);
+}
+
+RT void rt_define(Value *slot, Value v, bool constant)
+{
+    rt_release(*slot);
+    v.constant = constant;
+    *slot = v;
+}
+
+RT void rt_check_assign(Value *slot, const char *name)
+{
+    if (slot->tag == T_UNSET) {
+        rt_error("cannot assign to undeclared identifier: %s", name);
+    }
+}
+
+RT Value rt_assign(Value *slot, Value v, const char *name)
+{
+    rt_check_assign(slot, name);
+    if (slot->constant) {
+        rt_error("cannot assign to constant: %s", name);
+    }
+    rt_release(*slot);
+    *slot = v;
+    return rt_retain(v);
+}
+
+/* `name op= v` */
+RT Value rt_update(Value *slot, Operator op, Value v, const char *name)
+{
+    rt_check_assign(slot, name);
+    return rt_assign(slot, rt_binary(op, rt_get(*slot, name), v), name);
+}
+
+/* strings */
+
+RT Value rt_string(const char *bytes, size_t len)
+{
+    String *s = rt_alloc(sizeof(String) + len + 1);
+    s->len = len;
+    memcpy(s->bytes, bytes, len);
+    s->bytes[len] = '\0';
+    return rt_heap(T_STRING, s);
+}
+
+/* the length of the UTF-8 sequence starting with `byte` */
+RT size_t rt_char_len(unsigned char byte)
+{
+    return byte < 0xC0 ? 1 : byte < 0xE0 ? 2 : byte < 0xF0 ? 3 : 4;
+}
+
+typedef struct {
+    char *data;
+    size_t len;
+    size_t cap;
+} Buffer;
+
+RT void rt_append(Buffer *b, const char *s, size_t n)
+{
+    if (b->len + n + 1 > b->cap) {
+        b->cap = (b->len + n + 1) * 2;
+        b->data = realloc(b->data, b->cap);
+        if (b->data == NULL) {
+            rt_error("out of memory");
+        }
+    }
+    memcpy(b->data + b->len, s, n);
+    b->len += n;
+    b->data[b->len] = '\0';
+}
+
+RT void rt_append_str(Buffer *b, const char *s)
+{
+    rt_append(b, s, strlen(s));
+}
+
+/* appends `v` as `puts` prints it, without taking it over */
+RT void rt_inspect(Buffer *b, Value v)
+{
+    char number[32];
+
+    switch (v.tag) {
+    case T_INTEGER:
+        snprintf(number, sizeof(number), "%" PRId64, v.as.i);
+        rt_append_str(b, number);
+        break;
+    case T_BOOLEAN:
+        rt_append_str(b, v.as.b ? "true" : "false");
+        break;
+    case T_STRING:
+        rt_append(b, AS_STRING(v)->bytes, AS_STRING(v)->len);
+        break;
+    case T_ARRAY:
+        rt_append_str(b, "[");
+        for (size_t i = 0; i < AS_ARRAY(v)->len; i++) {
+            if (i > 0) {
+                rt_append_str(b, ", ");
+            }
+            rt_inspect(b, AS_ARRAY(v)->items[i]);
+        }
+        rt_append_str(b, "]");
+        break;
+    case T_HASH:
+        rt_append_str(b, "{");
+        for (size_t i = 0; i < AS_HASH(v)->len; i++) {
+            if (i > 0) {
+                rt_append_str(b, ", ");
+            }
+            rt_inspect(b, AS_HASH(v)->pairs[i].key);
+            rt_append_str(b, ": ");
+            rt_inspect(b, AS_HASH(v)->pairs[i].value);
+        }
+        rt_append_str(b, "}");
+        break;
+    case T_FUNCTION:
+        rt_append_str(b, AS_FUNCTION(v)->source);
+        break;
+    case T_BUILTIN:
+        rt_append_str(b, "builtin function");
+        break;
+    default:
+        rt_append_str(b, "null");
+        break;
+    }
+}
+
+/* arrays and hashes */
+
+RT Value rt_array(size_t len, const Value *items)
+{
+    Array *a = rt_alloc(sizeof(Array) + len * sizeof(Value));
+    a->len = len;
+    if (items != NULL) {
+        memcpy(a->items, items, len * sizeof(Value));
+    }
+    return rt_heap(T_ARRAY, a);
+}
+
+RT bool rt_hashable(Value key)
+{
+    return key.tag == T_INTEGER || key.tag == T_BOOLEAN || key.tag == T_STRING;
+}
+
+/* integers, then booleans, then strings */
+RT int rt_compare_keys(Value a, Value b)
+{
+    if (a.tag != b.tag) {
+        int rank_a = a.tag == T_INTEGER ? 0 : a.tag == T_BOOLEAN ? 1 : 2;
+        int rank_b = b.tag == T_INTEGER ? 0 : b.tag == T_BOOLEAN ? 1 : 2;
+        return rank_a - rank_b;
+    }
+    switch (a.tag) {
+    case T_INTEGER:
+        return (a.as.i > b.as.i) - (a.as.i < b.as.i);
+    case T_BOOLEAN:
+        return (int)a.as.b - (int)b.as.b;
+    default: {
+        String *s = AS_STRING(a), *t = AS_STRING(b);
+        int c = memcmp(s->bytes, t->bytes, s->len < t->len ? s->len : t->len);
+        return c != 0 ? c : (s->len > t->len) - (s->len < t->len);
+    }
+    }
+}
+
+/* whether `hash` has `key`, and where it is or would go */
+RT bool rt_find(Hash *hash, Value key, size_t *at)
+{
+    size_t low = 0, high = hash->len;
+    while (low < high) {
+        size_t middle = low + (high - low) / 2;
+        int c = rt_compare_keys(hash->pairs[middle].key, key);
+        if (c == 0) {
+            *at = middle;
+            return true;
+        }
+        if (c < 0) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    *at = low;
+    return false;
+}
+
+/* `v`, copied unless nothing else refers to it */
+RT Value rt_own(Value v)
+{
+    if (v.as.o->refs == 1) {
+        return v;
+    }
+    Value copy;
+    if (v.tag == T_ARRAY) {
+        copy = rt_array(AS_ARRAY(v)->len, AS_ARRAY(v)->items);
+        for (size_t i = 0; i < AS_ARRAY(copy)->len; i++) {
+            rt_retain(AS_ARRAY(copy)->items[i]);
+        }
+    } else {
+        size_t len = AS_HASH(v)->len;
+        Hash *h = rt_alloc(sizeof(Hash) + len * sizeof(Pair));
+        h->len = len;
+        for (size_t i = 0; i < len; i++) {
+            h->pairs[i].key = rt_retain(AS_HASH(v)->pairs[i].key);
+            h->pairs[i].value = rt_retain(AS_HASH(v)->pairs[i].value);
+        }
+        copy = rt_heap(T_HASH, h);
+    }
+    rt_release(v);
+    return copy;
+}
+
+/* sets `key` of the hash `h` to `value`, taking over all three */
+RT Value rt_hash_set(Value h, Value key, Value value)
+{
+    if (!rt_hashable(key)) {
+        rt_error("unusable as hash key: %s", rt_type(key));
+    }
+    h = rt_own(h);
+    Hash *hash = AS_HASH(h);
+    size_t at;
+    if (rt_find(hash, key, &at)) {
+        rt_release(hash->pairs[at].key);
+        rt_release(hash->pairs[at].value);
+    } else {
+        hash = realloc(hash, sizeof(Hash) + (hash->len + 1) * sizeof(Pair));
+        if (hash == NULL) {
+            rt_error("out of memory");
+        }
+        memmove(&hash->pairs[at + 1], &hash->pairs[at], (hash->len - at) * sizeof(Pair));
+        hash->len++;
+        h.as.o = &hash->obj;
+    }
+    hash->pairs[at].key = key;
+    hash->pairs[at].value = value;
+    return h;
+}
+
+/* a hash of `len` pairs, given as keys followed by their values */
+RT Value rt_hash(size_t len, const Value *keys_and_values)
+{
+    Hash *hash = rt_alloc(sizeof(Hash));
+    hash->len = 0;
+    Value h = rt_heap(T_HASH, hash);
+    for (size_t i = 0; i < len; i++) {
+        h = rt_hash_set(h, keys_and_values[2 * i], keys_and_values[2 * i + 1]);
+    }
+    return h;
+}
+
+RT Value rt_index(Value left, Value index)
+{
+    Value result = RT_NULL;
+    if (left.tag == T_ARRAY && index.tag == T_INTEGER) {
+        if (index.as.i >= 0 && (uint64_t)index.as.i < AS_ARRAY(left)->len) {
+            result = rt_retain(AS_ARRAY(left)->items[index.as.i]);
+        }
+    } else if (left.tag == T_HASH) {
+        if (!rt_hashable(index)) {
+            rt_error("unusable as hash key: %s", rt_type(index));
+        }
+        size_t at;
+        if (rt_find(AS_HASH(left), index, &at)) {
+            result = rt_retain(AS_HASH(left)->pairs[at].value);
+        }
+    } else {
+        rt_error("index operator not supported: %s", rt_type(left));
+    }
+    rt_release(left);
+    rt_release(index);
+    return result;
+}
+
+/* a copy of `container` with `index` set to `value` */
+RT Value rt_set_index(Value container, Value index, Value value)
+{
+    if (container.tag == T_ARRAY && index.tag == T_INTEGER) {
+        if (index.as.i < 0 || (uint64_t)index.as.i >= AS_ARRAY(container)->len) {
+            rt_error("index out of range: %" PRId64, index.as.i);
+        }
+        container = rt_own(container);
+        rt_release(AS_ARRAY(container)->items[index.as.i]);
+        AS_ARRAY(container)->items[index.as.i] = value;
+        return container;
+    }
+    if (container.tag == T_HASH) {
+        return rt_hash_set(container, index, value);
+    }
+    rt_error("index assignment not supported: %s", rt_type(container));
+}
+
+/*
+ * A copy of `container` with the element at the end of `path` set to
+ * `value`. A container nothing else refers to is changed in place, and so
+ * are the ones along the path, which are taken out of it meanwhile.
+ */
+RT Value rt_set_path(Value container, Value *path, size_t len, Value value)
+{
+    if (len == 0) {
+        rt_release(container);
+        return value;
+    }
+
+    Value child = RT_NULL;
+    if (len > 1) {
+        child = rt_index(rt_retain(container), rt_retain(path[0]));
+        bool unique = rt_is_heap(container) && container.as.o->refs == 1;
+        if (unique && rt_is_heap(child) && child.as.o->refs == 2) {
+            container = rt_set_index(container, rt_retain(path[0]), RT_NULL);
+        }
+    }
+    child = rt_set_path(child, path + 1, len - 1, value);
+    return rt_set_index(container, path[0], child);
+}
+
+/*
+ * `name[path] = value`, or with an operator such as `name[path] += value`,
+ * taking over the path and the value that follows it.
+ */
+RT Value rt_assign_path(Value *slot, const char *name, Operator op, size_t len, Value *path)
+{
+    Value value = path[len];
+    rt_check_assign(slot, name);
+    if (op != RT_NONE) {
+        Value current = rt_retain(*slot);
+        for (size_t i = 0; i < len; i++) {
+            current = rt_index(current, rt_retain(path[i]));
+        }
+        value = rt_binary(op, current, value);
+    }
+
+    Value root = *slot;
+    if (slot->constant) {
+        rt_retain(root);
+    } else {
+        *slot = RT_NULL;
+    }
+    Value updated = rt_set_path(root, path, len, rt_retain(value));
+    if (slot->constant) {
+        rt_error("cannot assign to constant: %s", name);
+    }
+    *slot = updated;
+    return value;
+}
+
+/* the elements a `for` loop goes through */
+RT Value rt_iter(Value v)
+{
+    Value items;
+    if (v.tag == T_ARRAY) {
+        return v;
+    } else if (v.tag == T_STRING) {
+        String *s = AS_STRING(v);
+        items = rt_array(0, NULL);
+        for (size_t i = 0; i < s->len;) {
+            size_t n = rt_char_len((unsigned char)s->bytes[i]);
+            Value c = rt_string(s->bytes + i, n);
+            Array *a = realloc(AS_ARRAY(items), sizeof(Array) + (AS_ARRAY(items)->len + 1) * sizeof(Value));
+            if (a == NULL) {
+                rt_error("out of memory");
+            }
+            a->items[a->len++] = c;
+            items.as.o = &a->obj;
+            i += n;
+        }
+    } else if (v.tag == T_HASH) {
+        items = rt_array(AS_HASH(v)->len, NULL);
+        for (size_t i = 0; i < AS_HASH(v)->len; i++) {
+            AS_ARRAY(items)->items[i] = rt_retain(AS_HASH(v)->pairs[i].key);
+        }
+    } else {
+        rt_error("cannot iterate over %s", rt_type(v));
+    }
+    rt_release(v);
+    return items;
+}
+
+/* operators */
+
+RT Value rt_not(Value v)
+{
+    return rt_bool(!rt_truthy(v));
+}
+
+/* integers are 64 bits wide, where the evaluator goes on with bigger ones */
+RT _Noreturn void rt_too_large(void)
+{
+    rt_error("integer too large for the c target");
+}
+
+RT int64_t rt_add(int64_t a, int64_t b)
+{
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+        rt_too_large();
+    }
+    return a + b;
+}
+
+RT int64_t rt_sub(int64_t a, int64_t b)
+{
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+        rt_too_large();
+    }
+    return a - b;
+}
+
+RT int64_t rt_mul(int64_t a, int64_t b)
+{
+    if ((a == -1 && b == INT64_MIN) || (b == -1 && a == INT64_MIN)) {
+        rt_too_large();
+    }
+    /* the product wrapped around unless dividing it gives back a factor */
+    int64_t product = (int64_t)((uint64_t)a * (uint64_t)b);
+    if (b != 0 && product / b != a) {
+        rt_too_large();
+    }
+    return product;
+}
+
+RT Value rt_neg(Value v)
+{
+    if (v.tag != T_INTEGER) {
+        rt_error("unknown operator: -%s", rt_type(v));
+    }
+    if (v.as.i == INT64_MIN) {
+        rt_too_large();
+    }
+    return rt_int(-v.as.i);
+}
+
+RT Value rt_integer_op(Operator op, int64_t a, int64_t b)
+{
+    int64_t result;
+
+    switch (op) {
+    case RT_ADD:
+        return rt_int(rt_add(a, b));
+    case RT_SUB:
+        return rt_int(rt_sub(a, b));
+    case RT_MUL:
+        return rt_int(rt_mul(a, b));
+    case RT_DIV:
+    case RT_MOD:
+        if (b == 0) {
+            rt_error("division by zero");
+        }
+        if (b == -1) {
+            if (op == RT_DIV && a == INT64_MIN) {
+                rt_too_large();
+            }
+            return rt_int(op == RT_DIV ? -a : 0);
+        }
+        return rt_int(op == RT_DIV ? a / b : a % b);
+    case RT_BIT_AND:
+        return rt_int(a & b);
+    case RT_BIT_OR:
+        return rt_int(a | b);
+    case RT_BIT_XOR:
+        return rt_int(a ^ b);
+    case RT_SHL:
+    case RT_SHR:
+        if (b < 0 || b >= 64) {
+            rt_error("shift amount out of range: %" PRId64, b);
+        }
+        if (op == RT_SHL) {
+            result = (int64_t)((uint64_t)a << b);
+            if ((result >> b) != a) {
+                rt_too_large();
+            }
+            return rt_int(result);
+        }
+        return rt_int(a >= 0 ? a >> b : ~(~a >> b));
+    case RT_LT:
+        return rt_bool(a < b);
+    case RT_GT:
+        return rt_bool(a > b);
+    case RT_LE:
+        return rt_bool(a <= b);
+    case RT_GE:
+        return rt_bool(a >= b);
+    case RT_EQ:
+        return rt_bool(a == b);
+    case RT_NE:
+        return rt_bool(a != b);
+    default:
+        rt_error("unknown operator: INTEGER %s INTEGER", rt_operators[op]);
+    }
+}
+
+RT Value rt_binary(Operator op, Value left, Value right)
+{
+    Value result;
+
+    if (left.tag == T_INTEGER && right.tag == T_INTEGER) {
+        return rt_integer_op(op, left.as.i, right.as.i);
+    } else if (left.tag == T_STRING && right.tag == T_STRING && op == RT_ADD) {
+        String *l = AS_STRING(left), *r = AS_STRING(right);
+        String *s = rt_alloc(sizeof(String) + l->len + r->len + 1);
+        s->len = l->len + r->len;
+        memcpy(s->bytes, l->bytes, l->len);
+        memcpy(s->bytes + l->len, r->bytes, r->len);
+        s->bytes[s->len] = '\0';
+        result = rt_heap(T_STRING, s);
+    } else if (left.tag == T_STRING && right.tag == T_STRING && (op == RT_EQ || op == RT_NE)) {
+        String *l = AS_STRING(left), *r = AS_STRING(right);
+        bool equal = l->len == r->len && memcmp(l->bytes, r->bytes, l->len) == 0;
+        result = rt_bool(op == RT_EQ ? equal : !equal);
+    } else if (left.tag == T_BOOLEAN && right.tag == T_BOOLEAN && (op == RT_EQ || op == RT_NE)) {
+        result = rt_bool(op == RT_EQ ? left.as.b == right.as.b : left.as.b != right.as.b);
+    } else if (strcmp(rt_type(left), rt_type(right)) != 0) {
+        rt_error("type mismatch: %s %s %s", rt_type(left), rt_operators[op], rt_type(right));
+    } else {
+        rt_error("unknown operator: %s %s %s", rt_type(left), rt_operators[op], rt_type(right));
+    }
+    rt_release(left);
+    rt_release(right);
+    return result;
+}
+
+/* functions */
+
+RT Value rt_closure(Value (*code)(Function *, Value *), size_t arity, const char *source, size_t ncells, ...)
+{
+    Function *f = rt_alloc(sizeof(Function) + ncells * sizeof(Cell *));
+    f->code = code;
+    f->arity = arity;
+    f->source = source;
+    f->ncells = ncells;
+
+    va_list cells;
+    va_start(cells, ncells);
+    for (size_t i = 0; i < ncells; i++) {
+        f->cells[i] = va_arg(cells, Cell *);
+        f->cells[i]->obj.refs++;
+    }
+    va_end(cells);
+    return rt_heap(T_FUNCTION, f);
+}
+
+typedef Value (*BuiltinFn)(size_t argc, Value *args);
+
+/* builtins, which leave their arguments to the caller */
+
+RT void rt_arity(size_t argc, size_t want)
+{
+    if (argc != want) {
+        rt_error("wrong number of arguments. got=%zu, want=%zu", argc, want);
+    }
+}
+
+RT Array *rt_array_argument(Value v, const char *builtin)
+{
+    if (v.tag != T_ARRAY) {
+        rt_error("argument to `%s` must be ARRAY, got %s", builtin, rt_type(v));
+    }
+    return AS_ARRAY(v);
+}
+
+RT Value rt_len(size_t argc, Value *args)
+{
+    rt_arity(argc, 1);
+    switch (args[0].tag) {
+    case T_STRING: {
+        int64_t len = 0;
+        for (size_t i = 0; i < AS_STRING(args[0])->len; i++) {
+            len += ((unsigned char)AS_STRING(args[0])->bytes[i] & 0xC0) != 0x80;
+        }
+        return rt_int(len);
+    }
+    case T_ARRAY:
+        return rt_int((int64_t)AS_ARRAY(args[0])->len);
+    case T_HASH:
+        return rt_int((int64_t)AS_HASH(args[0])->len);
+    default:
+        rt_error("argument to `len` not supported, got %s", rt_type(args[0]));
+    }
+}
+
+RT Value rt_first(size_t argc, Value *args)
+{
+    rt_arity(argc, 1);
+    Array *a = rt_array_argument(args[0], "first");
+    return a->len > 0 ? rt_retain(a->items[0]) : RT_NULL;
+}
+
+RT Value rt_last(size_t argc, Value *args)
+{
+    rt_arity(argc, 1);
+    Array *a = rt_array_argument(args[0], "last");
+    return a->len > 0 ? rt_retain(a->items[a->len - 1]) : RT_NULL;
+}
+
+RT Value rt_rest(size_t argc, Value *args)
+{
+    rt_arity(argc, 1);
+    Array *a = rt_array_argument(args[0], "rest");
+    if (a->len == 0) {
+        return RT_NULL;
+    }
+    Value rest = rt_array(a->len - 1, a->items + 1);
+    for (size_t i = 0; i < a->len - 1; i++) {
+        rt_retain(AS_ARRAY(rest)->items[i]);
+    }
+    return rest;
+}
+
+RT Value rt_push(size_t argc, Value *args)
+{
+    rt_arity(argc, 2);
+    Array *a = rt_array_argument(args[0], "push");
+    Value pushed = rt_array(a->len + 1, NULL);
+    for (size_t i = 0; i < a->len; i++) {
+        AS_ARRAY(pushed)->items[i] = rt_retain(a->items[i]);
+    }
+    AS_ARRAY(pushed)->items[a->len] = rt_retain(args[1]);
+    return pushed;
+}
+
+RT Value rt_puts(size_t argc, Value *args)
+{
+    Buffer b = {NULL, 0, 0};
+    for (size_t i = 0; i < argc; i++) {
+        b.len = 0;
+        rt_inspect(&b, args[i]);
+        rt_append_str(&b, "\n");
+        fwrite(b.data, 1, b.len, stdout);
+    }
+    free(b.data);
+    return RT_NULL;
+}
+
+/* objects are freed as soon as they are unused, except for cycles */
+RT Value rt_gc(size_t argc, Value *args)
+{
+    rt_arity(argc, 0);
+    const char *names[] = {"allocated", "collected", "collections", "live"};
+    size_t counts[] = {rt_allocated, rt_freed, 0, rt_allocated - rt_freed};
+    Value pairs[8];
+    for (size_t i = 0; i < 4; i++) {
+        pairs[2 * i] = rt_string(names[i], strlen(names[i]));
+        pairs[2 * i + 1] = rt_int((int64_t)counts[i]);
+    }
+    return rt_hash(4, pairs);
+}
+
+RT Value rt_pow(size_t argc, Value *args)
+{
+    rt_arity(argc, 2);
+    for (size_t i = 0; i < 2; i++) {
+        if (args[i].tag != T_INTEGER) {
+            rt_error("argument to `pow` must be INTEGER, got %s", rt_type(args[i]));
+        }
+    }
+    if (args[1].as.i < 0) {
+        rt_error("negative exponent: %" PRId64, args[1].as.i);
+    }
+    int64_t base = args[0].as.i, result = 1;
+    for (int64_t exponent = args[1].as.i; exponent > 0; exponent >>= 1) {
+        if (exponent & 1) {
+            result = rt_mul(result, base);
+        }
+        /* the square is only needed for a later bit of the exponent */
+        if (exponent > 1) {
+            base = rt_mul(base, base);
+        }
+    }
+    return rt_int(result);
+}
+
+enum { B_LEN, B_FIRST, B_LAST, B_REST, B_PUSH, B_PUTS, B_GC, B_POW };
+
+static const BuiltinFn rt_builtins[] = {
+    [B_LEN] = rt_len,
+    [B_FIRST] = rt_first,
+    [B_LAST] = rt_last,
+    [B_REST] = rt_rest,
+    [B_PUSH] = rt_push,
+    [B_PUTS] = rt_puts,
+    [B_GC] = rt_gc,
+    [B_POW] = rt_pow,
+};
+
+RT Value rt_builtin(int index)
+{
+    Value v = {.tag = T_BUILTIN};
+    v.as.i = index;
+    return v;
+}
+
+/*
+ * The stack calls may take, half of the 8 MiB a main thread usually has.
+ * Calls never run in place of their caller, so recursion in tail position
+ * takes stack as any other does.
+ */
+#define RT_STACK_LIMIT ((uintptr_t)4 << 20)
+
+/* the calls in progress, and where the stack was at the outermost one */
+static size_t rt_depth;
+static uintptr_t rt_stack_base;
+
+/* calls `f`, which takes over the arguments */
+RT Value rt_call(Value f, size_t argc, Value *args)
+{
+    Value result;
+
+    if (f.tag == T_FUNCTION) {
+        if (AS_FUNCTION(f)->arity != argc) {
+            rt_error("wrong number of arguments: want=%zu, got=%zu", AS_FUNCTION(f)->arity, argc);
+        }
+        /* the stack grows down on the machines the c target is built for */
+        char here;
+        if (rt_depth == 0) {
+            rt_stack_base = (uintptr_t)&here;
+        } else if (rt_stack_base - (uintptr_t)&here > RT_STACK_LIMIT) {
+            rt_error("limit exceeded: more than %zu nested calls", rt_depth);
+        }
+        rt_depth++;
+        result = AS_FUNCTION(f)->code(AS_FUNCTION(f), args);
+        rt_depth--;
+    } else if (f.tag == T_BUILTIN) {
+        result = rt_builtins[f.as.i](argc, args);
+        for (size_t i = 0; i < argc; i++) {
+            rt_release(args[i]);
+        }
+    } else {
+        rt_error("not a function: %s", rt_type(f));
+    }
+    rt_release(f);
+    return result;
+}
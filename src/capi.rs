@@ -0,0 +1,534 @@
+//! A C interface to `Interpreter`, for embedding Monkey in programs written
+//! in C or in any language that can call C, such as Python through `ctypes`.
+//!
+//! The crate builds as a `cdylib` exporting the functions below, which are
+//! declared in `include/monkey.h`. That header is generated from this file
+//! by `header`, with `rust_monkey header > include/monkey.h`.
+//!
+//! Interpreters and values are handles that must be freed by their free
+//! functions, and every string returned must be freed with
+//! `monkey_string_free`. An interpreter and its values belong to the thread
+//! that created them. No panic unwinds into the caller: a function that
+//! panics returns NULL, false or an error value instead.
+
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::interpreter::*;
+use crate::limits::{self, *};
+use crate::object::*;
+
+/// The bytes of stack the evaluations of an interpreter may take unless it
+/// is changed with `monkey_interpreter_set_max_stack`, which a thread with a
+/// stack of 2 MiB holds.
+pub const EMBEDDED_MAX_STACK: usize = limits::EMBEDDED_MAX_STACK;
+
+/// An interpreter keeping its globals from one evaluation to the next.
+pub struct MonkeyInterpreter {
+    interpreter: Interpreter,
+}
+
+/// The result of an evaluation: either a value or an error.
+pub struct MonkeyValue {
+    result: Result<Object, Error>,
+}
+
+/// Creates an interpreter, to be freed with `monkey_interpreter_free`, or
+/// returns NULL if that fails.
+#[no_mangle]
+pub extern "C" fn monkey_interpreter_new() -> *mut MonkeyInterpreter {
+    guard(ptr::null_mut(), || {
+        let interpreter = Interpreter::new();
+        Box::into_raw(Box::new(MonkeyInterpreter { interpreter }))
+    })
+}
+
+/// Frees `interpreter`, which may be NULL.
+///
+/// # Safety
+///
+/// `interpreter` must be NULL or come from `monkey_interpreter_new`, and not
+/// be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_interpreter_free(interpreter: *mut MonkeyInterpreter) {
+    guard((), || {
+        if !interpreter.is_null() {
+            drop(Box::from_raw(interpreter));
+        }
+    })
+}
+
+/// Sets how many nested calls evaluations of `interpreter` may make, keeping
+/// its other limits, and returns true, or returns false and changes nothing
+/// if `max_depth` is 0.
+///
+/// # Safety
+///
+/// `interpreter` must come from `monkey_interpreter_new`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_interpreter_set_max_depth(
+    interpreter: *mut MonkeyInterpreter,
+    max_depth: usize,
+) -> bool {
+    let interpreter = &mut (*interpreter).interpreter;
+    guard(false, || {
+        if max_depth == 0 {
+            return false;
+        }
+        interpreter.set_limits(Limits {
+            max_depth: Some(max_depth),
+            ..interpreter.limits().clone()
+        });
+        true
+    })
+}
+
+/// Sets how many bytes of stack evaluations of `interpreter` may take,
+/// keeping its other limits, and returns true, or returns false and changes
+/// nothing if `max_stack` is 0. Evaluations on a thread whose stack is not
+/// at least 512 KiB larger may crash the program.
+///
+/// # Safety
+///
+/// `interpreter` must come from `monkey_interpreter_new`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_interpreter_set_max_stack(
+    interpreter: *mut MonkeyInterpreter,
+    max_stack: usize,
+) -> bool {
+    let interpreter = &mut (*interpreter).interpreter;
+    guard(false, || {
+        if max_stack == 0 {
+            return false;
+        }
+        interpreter.set_limits(Limits {
+            max_stack: Some(max_stack),
+            ..interpreter.limits().clone()
+        });
+        true
+    })
+}
+
+/// Evaluates the `len` bytes of UTF-8 source code at `source`. The result
+/// is never NULL and must be freed with `monkey_value_free`.
+///
+/// # Safety
+///
+/// `interpreter` must come from `monkey_interpreter_new`, and `source` must
+/// point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_eval(
+    interpreter: *mut MonkeyInterpreter,
+    source: *const c_char,
+    len: usize,
+) -> *mut MonkeyValue {
+    let interpreter = &mut (*interpreter).interpreter;
+    let bytes = match len {
+        0 => &[],
+        len => std::slice::from_raw_parts(source as *const u8, len),
+    };
+
+    let result = match std::str::from_utf8(bytes) {
+        Ok(source) => match panic::catch_unwind(AssertUnwindSafe(|| interpreter.eval(source))) {
+            Ok(result) => result,
+            Err(_) => {
+                // the evaluation it left running is metered afresh
+                interpreter.set_limits(interpreter.limits().clone());
+                Err(Error::Runtime(
+                    "internal error: the interpreter panicked".to_string(),
+                ))
+            }
+        },
+        Err(err) => Err(Error::Runtime(format!(
+            "source is not valid UTF-8: {}",
+            err
+        ))),
+    };
+    Box::into_raw(Box::new(MonkeyValue { result }))
+}
+
+/// Frees `value`, which may be NULL.
+///
+/// # Safety
+///
+/// `value` must be NULL or come from `monkey_eval`, and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_free(value: *mut MonkeyValue) {
+    guard((), || {
+        if !value.is_null() {
+            drop(Box::from_raw(value));
+        }
+    })
+}
+
+/// Returns whether the evaluation of `value` failed.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_is_error(value: *const MonkeyValue) -> bool {
+    guard(false, || (*value).result.is_err())
+}
+
+/// Returns the message of the error `value` is, or NULL if it is none.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_error(value: *const MonkeyValue) -> *mut c_char {
+    guard(ptr::null_mut(), || match &(*value).result {
+        Err(err) => into_c_string(err.to_string()),
+        Ok(_) => ptr::null_mut(),
+    })
+}
+
+/// Returns `value` as the REPL prints it, or NULL if it is an error.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_inspect(value: *const MonkeyValue) -> *mut c_char {
+    guard(ptr::null_mut(), || match &(*value).result {
+        Ok(obj) => into_c_string(obj.inspect()),
+        Err(_) => ptr::null_mut(),
+    })
+}
+
+/// Returns the string `value` is, or NULL if it is not a string.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_as_string(value: *const MonkeyValue) -> *mut c_char {
+    guard(ptr::null_mut(), || match &(*value).result {
+        Ok(Object::String(s)) => into_c_string(s.clone()),
+        _ => ptr::null_mut(),
+    })
+}
+
+/// Stores the integer `value` is in `out` and returns true, or returns
+/// false if it is not an integer.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`, and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_as_int(value: *const MonkeyValue, out: *mut i64) -> bool {
+    guard(false, || match (*value).result {
+        Ok(Object::Integer(i)) => {
+            *out = i;
+            true
+        }
+        _ => false,
+    })
+}
+
+/// Stores the boolean `value` is in `out` and returns true, or returns
+/// false if it is not a boolean.
+///
+/// # Safety
+///
+/// `value` must come from `monkey_eval`, and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_value_as_bool(value: *const MonkeyValue, out: *mut bool) -> bool {
+    guard(false, || match (*value).result {
+        Ok(Object::Boolean(b)) => {
+            *out = b;
+            true
+        }
+        _ => false,
+    })
+}
+
+/// Frees a string returned by this library, which may be NULL.
+///
+/// # Safety
+///
+/// `s` must be NULL or come from this library, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_string_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+// Unwinding into C is undefined, so a panic in `f` is caught and `failed`
+// returned instead.
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+// the values of the constants of this module, which are computed rather
+// than written out
+fn const_value(name: &str) -> usize {
+    match name {
+        "EMBEDDED_MAX_STACK" => EMBEDDED_MAX_STACK,
+        _ => unreachable!("no value for the constant {}", name),
+    }
+}
+
+// C strings end at the first NUL, so those inside `s` are replaced
+fn into_c_string(s: String) -> *mut c_char {
+    let s = CString::new(s.replace('\0', "\u{fffd}")).unwrap();
+    s.into_raw()
+}
+
+/// Generates the C header declaring the types and functions of this
+/// module, with their documentation.
+pub fn header() -> String {
+    let mut out = String::from(
+        "/* Generated by `rust_monkey header` from src/capi.rs, do not edit. */\n\n\
+         #ifndef MONKEY_H\n#define MONKEY_H\n\n\
+         #include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n\n\
+         #ifdef __cplusplus\nextern \"C\" {\n#endif\n",
+    );
+
+    let mut docs: Vec<&str> = vec![];
+    let mut lines = include_str!("capi.rs").lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(doc.strip_prefix(' ').unwrap_or(doc));
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix("pub struct ")
+            .and_then(|s| s.strip_suffix(" {"))
+        {
+            out += &format!("\n{}typedef struct {} {};\n", c_comment(&docs), name, name);
+        } else if let Some(name) = line.strip_prefix("pub const ") {
+            let name = name.split_once(": ").unwrap().0;
+            out += &format!(
+                "\n{}#define MONKEY_{} {}\n",
+                c_comment(&docs),
+                name,
+                const_value(name)
+            );
+        } else if line.starts_with("pub extern \"C\" fn ")
+            || line.starts_with("pub unsafe extern \"C\" fn ")
+        {
+            let mut signature = line.to_string();
+            while !signature.ends_with('{') {
+                signature += lines.next().unwrap().trim();
+            }
+            out += &format!("\n{}{};\n", c_comment(&docs), c_declaration(&signature));
+        }
+
+        if !line.starts_with("#[") {
+            docs.clear();
+        }
+    }
+
+    out + "\n#ifdef __cplusplus\n}\n#endif\n\n#endif /* MONKEY_H */\n"
+}
+
+// the doc comment `docs` as a C comment, without its Rust specific parts
+fn c_comment(docs: &[&str]) -> String {
+    let mut comment = String::new();
+    for doc in docs {
+        if *doc == "# Safety" {
+            continue;
+        }
+        let doc = doc.replace('`', "");
+        match doc.as_str() {
+            "" => comment += " *\n",
+            doc => comment += &format!(" * {}\n", doc),
+        }
+    }
+
+    // the blank line before a dropped heading, or a trailing one
+    let comment = comment.replace(" *\n *\n", " *\n");
+    let comment = comment.strip_suffix(" *\n").unwrap_or(&comment);
+    format!("/*\n{} */\n", comment)
+}
+
+// `pub [unsafe] extern "C" fn name(arg: Type, ...) [-> Type] {` in C
+fn c_declaration(signature: &str) -> String {
+    let (_, rest) = signature.split_once(" fn ").unwrap();
+    let (name, rest) = rest.split_once('(').unwrap();
+    let (params, rest) = rest.rsplit_once(')').unwrap();
+
+    let params: Vec<String> = params
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, ty) = param.split_once(": ").unwrap();
+            c_declarator(ty, name)
+        })
+        .collect();
+    let params = match params.is_empty() {
+        true => "void".to_string(),
+        false => params.join(", "),
+    };
+
+    let declarator = format!("{}({})", name, params);
+    match rest.trim().trim_end_matches('{').trim().strip_prefix("-> ") {
+        Some(ty) => c_declarator(ty, &declarator),
+        None => format!("void {}", declarator),
+    }
+}
+
+// a declaration of `name` with the Rust type `ty` in C
+fn c_declarator(ty: &str, name: &str) -> String {
+    if let Some(ty) = ty.strip_prefix("*mut ") {
+        return c_declarator(ty, &format!("*{}", name));
+    }
+    if let Some(ty) = ty.strip_prefix("*const ") {
+        return format!("const {}", c_declarator(ty, &format!("*{}", name)));
+    }
+
+    let ty = match ty {
+        "c_char" => "char",
+        "bool" => "bool",
+        "i64" => "int64_t",
+        "usize" => "size_t",
+        ty => ty,
+    };
+    format!("{} {}", ty, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    #[test]
+    fn test_header_is_current() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/monkey.h");
+        let header_file = std::fs::read_to_string(&path).unwrap_or_default();
+
+        assert!(
+            header_file == header(),
+            "{} is out of date, regenerate it with `cargo run -- header > include/monkey.h`",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_c_declarations() {
+        let tests = [
+            (
+                "pub extern \"C\" fn monkey_interpreter_new() -> *mut MonkeyInterpreter {",
+                "MonkeyInterpreter *monkey_interpreter_new(void)",
+            ),
+            (
+                "pub unsafe extern \"C\" fn f(value: *const MonkeyValue, out: *mut i64) -> bool {",
+                "bool f(const MonkeyValue *value, int64_t *out)",
+            ),
+            (
+                "pub unsafe extern \"C\" fn f(s: *mut c_char, len: usize,) {",
+                "void f(char *s, size_t len)",
+            ),
+        ];
+
+        for (signature, expected) in tests {
+            assert_eq!(c_declaration(signature), expected);
+        }
+    }
+
+    #[test]
+    fn test_setting_limits_keeps_the_others() {
+        let interpreter = monkey_interpreter_new();
+        unsafe {
+            (*interpreter).interpreter.set_limits(Limits {
+                fuel: Some(1000),
+                ..Limits::default()
+            });
+            assert!(monkey_interpreter_set_max_depth(interpreter, 50));
+            assert!(!monkey_interpreter_set_max_depth(interpreter, 0));
+            assert!(monkey_interpreter_set_max_stack(interpreter, 1 << 20));
+            assert!(!monkey_interpreter_set_max_stack(interpreter, 0));
+
+            let limits = (*interpreter).interpreter.limits().clone();
+            monkey_interpreter_free(interpreter);
+            assert_eq!(
+                limits,
+                Limits {
+                    fuel: Some(1000),
+                    max_depth: Some(50),
+                    max_stack: Some(1 << 20),
+                    ..Limits::default()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_panics_do_not_unwind() {
+        let interpreter = monkey_interpreter_new();
+        unsafe {
+            (*interpreter)
+                .interpreter
+                .register("boom", || -> i64 { panic!("boom") });
+            let eval = |source: &str| {
+                let value =
+                    monkey_eval(interpreter, source.as_ptr() as *const c_char, source.len());
+                let s = match monkey_value_is_error(value) {
+                    true => monkey_value_error(value),
+                    false => monkey_value_inspect(value),
+                };
+                let result = std::ffi::CStr::from_ptr(s).to_str().unwrap().to_string();
+                monkey_string_free(s);
+                monkey_value_free(value);
+                result
+            };
+
+            assert_eq!(eval("boom()"), "internal error: the interpreter panicked");
+            // the interpreter still works afterwards
+            assert_eq!(eval("1 + 1"), "2");
+            monkey_interpreter_free(interpreter);
+        }
+    }
+
+    // the library the tests were built with, next to the test binary
+    fn library_dir() -> PathBuf {
+        let exe = std::env::current_exe().unwrap();
+        let deps = exe.parent().unwrap();
+        let dir = deps.parent().unwrap().to_path_buf();
+        assert!(
+            dir.join("librust_monkey.so").exists(),
+            "no shared library in {}, build it with `cargo build`",
+            dir.display()
+        );
+        dir
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_c_program() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let lib = library_dir();
+        let exe = std::env::temp_dir().join(format!("monkey_embed_{}", std::process::id()));
+
+        let status = Command::new("cc")
+            .arg(root.join("testdata/capi/embed.c"))
+            .arg("-I")
+            .arg(root.join("include"))
+            .arg("-L")
+            .arg(&lib)
+            .arg(format!("-Wl,-rpath,{}", lib.display()))
+            .args(["-lrust_monkey", "-Wall", "-Werror", "-o"])
+            .arg(&exe)
+            .status()
+            .expect("could not run cc");
+        assert!(status.success(), "could not compile embed.c");
+
+        let output = Command::new(&exe).output().unwrap();
+        let _ = std::fs::remove_file(&exe);
+
+        let expected = std::fs::read_to_string(root.join("testdata/capi/embed.out")).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        assert!(output.status.success(), "embed failed: {:?}", output);
+    }
+}
@@ -0,0 +1,579 @@
+//! Static checks over a parsed program, run by the `check` subcommand.
+//!
+//! Every diagnostic carries a stable code:
+//!
+//! | code | lint                                            |
+//! |------|-------------------------------------------------|
+//! | C001 | an identifier that is not defined anywhere      |
+//! | C002 | a `let` or `const` binding that is never used   |
+//! | C003 | a parameter that is never used                  |
+//! | C004 | a binding that shadows a builtin                |
+//! | C005 | statements after `return`, `break`, `continue`, |
+//! |      | `throw`                                         |
+//! | C006 | a parameter listed twice                        |
+//!
+//! A file silences lints with a comment such as `// check: allow(C002, C003)`
+//! on a line of its own.
+//!
+//! Only functions and catch bodies introduce scopes, as in the evaluator, and
+//! a name bound anywhere in a function counts as defined in all of it. Using a name
+//! before its `let` therefore goes unreported, but a function may refer to
+//! bindings that come after it. Exported bindings are never unused. Names
+//! inside `quote` may refer to bindings where the quoted code ends up, so
+//! outside of `unquote` they count as uses but are never undefined.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::builtins::lookup_builtin;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::Span;
+use crate::visitor::{visit, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    UndefinedIdentifier,
+    UnusedLet,
+    UnusedParameter,
+    ShadowedBuiltin,
+    UnreachableCode,
+    DuplicateParameter,
+}
+
+const LINTS: &[Lint] = &[
+    Lint::UndefinedIdentifier,
+    Lint::UnusedLet,
+    Lint::UnusedParameter,
+    Lint::ShadowedBuiltin,
+    Lint::UnreachableCode,
+    Lint::DuplicateParameter,
+];
+
+impl Lint {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UndefinedIdentifier => "C001",
+            Lint::UnusedLet => "C002",
+            Lint::UnusedParameter => "C003",
+            Lint::ShadowedBuiltin => "C004",
+            Lint::UnreachableCode => "C005",
+            Lint::DuplicateParameter => "C006",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Lint> {
+        LINTS.iter().copied().find(|lint| lint.code() == code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub lint: Lint,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {} {}",
+            self.span.start.line,
+            self.span.start.column,
+            self.lint.code(),
+            self.message
+        )
+    }
+}
+
+/// Parses and checks `source`, returning the diagnostics in source order,
+/// or the parser errors and malformed `check:` comments if there are any.
+pub fn check_source(source: &str) -> Result<Vec<Diagnostic>, Vec<String>> {
+    let allowed = allowed_lints(source)?;
+
+    let mut p = Parser::new(Lexer::new(source));
+    let program = p.parse_program();
+    if !p.errors().is_empty() {
+        return Err(p.errors());
+    }
+
+    Ok(check_program(&program)
+        .into_iter()
+        .filter(|d| !allowed.contains(&d.lint))
+        .collect())
+}
+
+/// Returns the lints silenced by `// check: allow(...)` comments in `source`.
+fn allowed_lints(source: &str) -> Result<Vec<Lint>, Vec<String>> {
+    let mut allowed = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let directive = match line.trim().strip_prefix("//") {
+            Some(comment) => match comment.trim().strip_prefix("check:") {
+                Some(directive) => directive.trim(),
+                None => continue,
+            },
+            None => continue,
+        };
+
+        let codes = match directive
+            .strip_prefix("allow(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            Some(codes) => codes,
+            None => {
+                errors.push(format!(
+                    "line {}: expected check: allow(<codes>), got check: {}",
+                    i + 1,
+                    directive
+                ));
+                continue;
+            }
+        };
+
+        for code in codes.split(',').map(str::trim) {
+            match Lint::from_code(code) {
+                Some(lint) => allowed.push(lint),
+                None => errors.push(format!("line {}: unknown lint code {:?}", i + 1, code)),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(allowed)
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn check_program(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+    checker.visit_program(program);
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+fn is_builtin(name: &str) -> bool {
+    lookup_builtin(name).is_some() || name == "quote" || name == "unquote"
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BindingKind {
+    Let,
+    Parameter,
+    // `for` variables, import aliases and exported bindings, which are not
+    // reported when unused
+    Other,
+}
+
+struct Binding {
+    kind: BindingKind,
+    span: Span,
+    used: bool,
+}
+
+/// The bindings of one function or catch body, or of the program.
+#[derive(Default)]
+struct Scope {
+    bindings: HashMap<String, Binding>,
+}
+
+impl Scope {
+    fn bind(&mut self, id: &Identifier, kind: BindingKind) {
+        self.bindings.entry(id.value.clone()).or_insert(Binding {
+            kind,
+            span: id.span,
+            used: false,
+        });
+    }
+}
+
+/// Collects the names bound in a function body, without descending into
+/// nested functions.
+#[derive(Default)]
+struct Declarations {
+    scope: Scope,
+}
+
+impl Visitor for Declarations {
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        let kind = if ls.exported {
+            BindingKind::Other
+        } else {
+            BindingKind::Let
+        };
+        self.scope.bind(&ls.name, kind);
+        visit::walk_let_statement(self, ls);
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.scope.bind(&fs.variable, BindingKind::Other);
+        visit::walk_for_statement(self, fs);
+    }
+
+    fn visit_import_statement(&mut self, is: &ImportStatement) {
+        self.scope.bind(&is.alias, BindingKind::Other);
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        // the catch body declares into a scope of its own
+        self.visit_block_statement(&te.body);
+        if let Some(finally) = &te.finally {
+            self.visit_block_statement(finally);
+        }
+    }
+
+    fn visit_function_literal(&mut self, _fl: &FunctionLiteral) {}
+
+    fn visit_macro_literal(&mut self, _ml: &MacroLiteral) {}
+
+    fn visit_call_expression(&mut self, ce: &CallExpression) {
+        if !is_call_to(ce, "quote") {
+            visit::walk_call_expression(self, ce);
+        }
+    }
+}
+
+fn is_call_to(ce: &CallExpression, name: &str) -> bool {
+    matches!(ce.function.as_ref(), Expression::Identifier(id) if id.value == name)
+}
+
+#[derive(Default)]
+struct Checker {
+    scopes: Vec<Scope>,
+    diagnostics: Vec<Diagnostic>,
+    // inside `quote` but not `unquote`, where code is data
+    quoted: bool,
+}
+
+impl Checker {
+    fn report(&mut self, lint: Lint, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            lint,
+            span,
+            message,
+        });
+    }
+
+    fn check_shadowing(&mut self, id: &Identifier) {
+        if !self.quoted && is_builtin(&id.value) {
+            self.report(
+                Lint::ShadowedBuiltin,
+                id.span,
+                format!("'{}' shadows a builtin", id.value),
+            );
+        }
+    }
+
+    /// Checks `body` in a new scope holding `parameters` and the bindings of
+    /// the body itself.
+    fn function(&mut self, parameters: &[Identifier], body: &BlockStatement) {
+        if self.quoted {
+            return visit::walk_block_statement(self, body);
+        }
+
+        let mut scope = Scope::default();
+        for (i, param) in parameters.iter().enumerate() {
+            self.check_shadowing(param);
+            if parameters[..i].iter().any(|p| p.value == param.value) {
+                self.report(
+                    Lint::DuplicateParameter,
+                    param.span,
+                    format!("duplicate parameter '{}'", param.value),
+                );
+            }
+            scope.bind(param, BindingKind::Parameter);
+        }
+
+        self.scoped(
+            scope,
+            |checker| checker.visit_block_statement(body),
+            |v| v.visit_block_statement(body),
+        );
+    }
+
+    /// Runs `check` in `scope` extended by the declarations `declare` finds,
+    /// then reports the bindings of the scope that went unused.
+    fn scoped(
+        &mut self,
+        mut scope: Scope,
+        check: impl FnOnce(&mut Self),
+        declare: impl FnOnce(&mut Declarations),
+    ) {
+        let mut declarations = Declarations::default();
+        declare(&mut declarations);
+        for (name, binding) in declarations.scope.bindings {
+            scope.bindings.entry(name).or_insert(binding);
+        }
+
+        self.scopes.push(scope);
+        check(self);
+        let scope = self.scopes.pop().unwrap();
+
+        for (name, binding) in scope.bindings {
+            if binding.used {
+                continue;
+            }
+            match binding.kind {
+                BindingKind::Let => self.report(
+                    Lint::UnusedLet,
+                    binding.span,
+                    format!("unused let binding '{}'", name),
+                ),
+                BindingKind::Parameter => self.report(
+                    Lint::UnusedParameter,
+                    binding.span,
+                    format!("unused parameter '{}'", name),
+                ),
+                BindingKind::Other => {}
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        let exit = statements.iter().position(|s| {
+            matches!(
+                s,
+                Statement::ReturnStatement(_)
+                    | Statement::BreakStatement(_)
+                    | Statement::ContinueStatement(_)
+                    | Statement::ThrowStatement(_)
+            )
+        });
+
+        if let Some(i) = exit {
+            if let (Some(first), Some(last)) = (statements.get(i + 1), statements.last()) {
+                let keyword = match &statements[i] {
+                    Statement::ReturnStatement(_) => "return",
+                    Statement::BreakStatement(_) => "break",
+                    Statement::ContinueStatement(_) => "continue",
+                    _ => "throw",
+                };
+                self.report(
+                    Lint::UnreachableCode,
+                    Span {
+                        start: first.span().start,
+                        end: last.span().end,
+                    },
+                    format!("unreachable code after '{}'", keyword),
+                );
+            }
+        }
+
+        for s in statements {
+            self.visit_statement(s);
+        }
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_program(&mut self, program: &Program) {
+        self.scoped(
+            Scope::default(),
+            |checker| checker.statements(&program.statements),
+            |declarations| declarations.visit_program(program),
+        );
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.statements(&block.statements);
+    }
+
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        self.check_shadowing(&ls.name);
+        visit::walk_let_statement(self, ls);
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.check_shadowing(&fs.variable);
+        visit::walk_for_statement(self, fs);
+    }
+
+    fn visit_import_statement(&mut self, is: &ImportStatement) {
+        self.check_shadowing(&is.alias);
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        self.visit_block_statement(&te.body);
+        if let Some(catch) = &te.catch {
+            self.check_shadowing(&catch.parameter);
+            let mut scope = Scope::default();
+            scope.bind(&catch.parameter, BindingKind::Other);
+            self.scoped(
+                scope,
+                |checker| checker.visit_block_statement(&catch.body),
+                |declarations| declarations.visit_block_statement(&catch.body),
+            );
+        }
+        if let Some(finally) = &te.finally {
+            self.visit_block_statement(finally);
+        }
+    }
+
+    fn visit_identifier(&mut self, id: &Identifier) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.bindings.get_mut(&id.value));
+
+        match binding {
+            Some(binding) => binding.used = true,
+            None if self.quoted || is_builtin(&id.value) => {}
+            None => self.report(
+                Lint::UndefinedIdentifier,
+                id.span,
+                format!("undefined identifier '{}'", id.value),
+            ),
+        }
+    }
+
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        self.function(&fl.parameters, &fl.body);
+    }
+
+    fn visit_macro_literal(&mut self, ml: &MacroLiteral) {
+        self.function(&ml.parameters, &ml.body);
+    }
+
+    fn visit_call_expression(&mut self, ce: &CallExpression) {
+        let quoted = self.quoted;
+
+        if is_call_to(ce, "quote") {
+            self.quoted = true;
+        } else if is_call_to(ce, "unquote") {
+            self.quoted = false;
+        }
+        visit::walk_call_expression(self, ce);
+
+        self.quoted = quoted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostics(input: &str) -> Vec<String> {
+        check_source(input)
+            .unwrap_or_else(|errors| panic!("check errors: {:?}", errors))
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_lints() {
+        let tests: Vec<(&str, Vec<&str>)> = vec![
+            ("let x = 1; puts(x);", vec![]),
+            ("puts(y);", vec!["1:6: C001 undefined identifier 'y'"]),
+            ("let x = 1;", vec!["1:5: C002 unused let binding 'x'"]),
+            ("export let x = 1;", vec![]),
+            (
+                "let f = fn(a, b) { a }; f(1, 2);",
+                vec!["1:15: C003 unused parameter 'b'"],
+            ),
+            (
+                "let len = fn(puts) { puts }; len(1);",
+                vec![
+                    "1:5: C004 'len' shadows a builtin",
+                    "1:14: C004 'puts' shadows a builtin",
+                ],
+            ),
+            (
+                "let f = fn() { return 1; puts(2); 3 }; f();",
+                vec!["1:26: C005 unreachable code after 'return'"],
+            ),
+            (
+                "while (true) { break; puts(1); }",
+                vec!["1:23: C005 unreachable code after 'break'"],
+            ),
+            (
+                "let f = fn(a, b, a) { a + b }; f(1, 2, 3);",
+                vec!["1:18: C006 duplicate parameter 'a'"],
+            ),
+            // functions may refer to bindings that come after them, and to
+            // bindings of the functions around them
+            (
+                "let f = fn(n) { g(n) }; let g = fn(m) { fn() { m } }; f(1);",
+                vec![],
+            ),
+            // blocks do not introduce scopes
+            ("if (true) { let x = 1; } puts(x);", vec![]),
+            (
+                "for (x in [1]) { let y = x; } puts(z);",
+                vec![
+                    "1:22: C002 unused let binding 'y'",
+                    "1:36: C001 undefined identifier 'z'",
+                ],
+            ),
+            (r#"import "lib.monkey" as lib; puts(lib.value);"#, vec![]),
+            (
+                "try { throw 1; puts(2); } catch (e) { puts(e, f); }",
+                vec![
+                    "1:16: C005 unreachable code after 'throw'",
+                    "1:47: C001 undefined identifier 'f'",
+                ],
+            ),
+            // a catch body is a scope of its own
+            (
+                "try { 1 } catch (e) { let y = e; } puts(e);",
+                vec![
+                    "1:27: C002 unused let binding 'y'",
+                    "1:41: C001 undefined identifier 'e'",
+                ],
+            ),
+            (
+                "let unless = macro(c, a) { quote(if (!(unquote(c))) { b }) }; unless(1, 2);",
+                vec!["1:23: C003 unused parameter 'a'"],
+            ),
+            (
+                "let double = macro(x) { quote(unquote(x) * 2) }; quote(double(y));",
+                vec![],
+            ),
+            (
+                "let x = 1; x = 2; y += 1;",
+                vec!["1:19: C001 undefined identifier 'y'"],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(diagnostics(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_allow_comments() {
+        let input = "// check: allow(C001, C002)\nlet x = y;\nlet f = fn(a) { 1 };";
+        assert_eq!(diagnostics(input), vec!["3:12: C003 unused parameter 'a'"]);
+
+        let tests = [
+            (
+                "// check: deny(C001)\n",
+                vec!["line 1: expected check: allow(<codes>), got check: deny(C001)"],
+            ),
+            (
+                "let x = 1;\n  //check:allow(C002, C999)",
+                vec!["line 2: unknown lint code \"C999\""],
+            ),
+            (
+                "let x = ;",
+                vec!["no prefix parse function for SEMICOLON found"],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                check_source(input).unwrap_err(),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+}
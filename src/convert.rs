@@ -0,0 +1,404 @@
+//! Conversions between Rust values and Monkey objects, so that a host
+//! application can pass its own values to scripts and register plain Rust
+//! closures as Monkey functions.
+//!
+//! | Rust                        | Monkey                      |
+//! |-----------------------------|-----------------------------|
+//! | `i64`, `i32`, `usize`       | integer                     |
+//! | `bool`                      | boolean                     |
+//! | `String`, `&str`            | string                      |
+//! | `()`                        | `null`                      |
+//! | `Option<T>`                 | `T`, or `null` for `None`   |
+//! | `Vec<T>`                    | array                       |
+//! | `HashMap<String, T>`        | hash with string keys       |
+//! | `Object`                    | any value, unconverted      |
+//!
+//! A closure whose arguments convert from objects and whose result converts
+//! into one is a `HostFunction`. When it returns a `Result`, an `Err`
+//! becomes a Monkey error.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use crate::object::*;
+
+pub trait IntoObject {
+    fn into_object(self) -> Object;
+}
+
+pub trait FromObject: Sized {
+    /// The type of object converted from, as `Object::object_type` names
+    /// it, for error messages.
+    fn expected() -> String;
+
+    /// Converts `obj`, or returns `None` if it has another type.
+    fn from_object(obj: Object) -> Option<Self>;
+
+    /// Whether `obj` has the type converted from but a value `Self` cannot
+    /// hold, as a big integer for an `i64`.
+    fn out_of_range(_obj: &Object) -> bool {
+        false
+    }
+}
+
+impl IntoObject for Object {
+    fn into_object(self) -> Object {
+        self
+    }
+}
+
+impl FromObject for Object {
+    fn expected() -> String {
+        "any value".to_string()
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        Some(obj)
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {
+        $(
+            impl IntoObject for $int {
+                fn into_object(self) -> Object {
+                    Object::Integer(self as i64)
+                }
+            }
+
+            impl FromObject for $int {
+                fn expected() -> String {
+                    INTEGER_OBJ.to_string()
+                }
+
+                fn from_object(obj: Object) -> Option<Self> {
+                    match obj {
+                        Object::Integer(i) => <$int>::try_from(i).ok(),
+                        _ => None,
+                    }
+                }
+
+                fn out_of_range(obj: &Object) -> bool {
+                    match obj {
+                        Object::Integer(i) => <$int>::try_from(*i).is_err(),
+                        Object::BigInteger(_) => true,
+                        _ => false,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(i64, i32, usize);
+
+impl IntoObject for bool {
+    fn into_object(self) -> Object {
+        Object::Boolean(self)
+    }
+}
+
+impl FromObject for bool {
+    fn expected() -> String {
+        BOOLEAN_OBJ.to_string()
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl IntoObject for String {
+    fn into_object(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl IntoObject for &str {
+    fn into_object(self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl FromObject for String {
+    fn expected() -> String {
+        STRING_OBJ.to_string()
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl IntoObject for () {
+    fn into_object(self) -> Object {
+        Object::Null
+    }
+}
+
+impl FromObject for () {
+    fn expected() -> String {
+        NULL_OBJ.to_string()
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::Null => Some(()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: IntoObject> IntoObject for Option<T> {
+    fn into_object(self) -> Object {
+        match self {
+            Some(value) => value.into_object(),
+            None => Object::Null,
+        }
+    }
+}
+
+impl<T: FromObject> FromObject for Option<T> {
+    fn expected() -> String {
+        format!("{} or {}", T::expected(), NULL_OBJ)
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::Null => Some(None),
+            obj => T::from_object(obj).map(Some),
+        }
+    }
+
+    fn out_of_range(obj: &Object) -> bool {
+        T::out_of_range(obj)
+    }
+}
+
+impl<T: IntoObject> IntoObject for Vec<T> {
+    fn into_object(self) -> Object {
+        Object::Array(self.into_iter().map(IntoObject::into_object).collect())
+    }
+}
+
+impl<T: FromObject> FromObject for Vec<T> {
+    fn expected() -> String {
+        format!("{} of {}", ARRAY_OBJ, T::expected())
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::Array(elements) => elements.into_iter().map(T::from_object).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: IntoObject> IntoObject for HashMap<String, T> {
+    fn into_object(self) -> Object {
+        let pairs: BTreeMap<HashKey, HashPair> = self
+            .into_iter()
+            .map(|(key, value)| {
+                let pair = HashPair {
+                    key: Object::String(key.clone()),
+                    value: value.into_object(),
+                };
+                (HashKey::String(key), pair)
+            })
+            .collect();
+        Object::Hash(pairs)
+    }
+}
+
+impl<T: FromObject> FromObject for HashMap<String, T> {
+    fn expected() -> String {
+        format!("{} of {} to {}", HASH_OBJ, STRING_OBJ, T::expected())
+    }
+
+    fn from_object(obj: Object) -> Option<Self> {
+        match obj {
+            Object::Hash(pairs) => pairs
+                .into_values()
+                .map(|pair| match pair.key {
+                    Object::String(key) => T::from_object(pair.value).map(|value| (key, value)),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: IntoObject, E: Display> IntoObject for Result<T, E> {
+    fn into_object(self) -> Object {
+        match self {
+            Ok(value) => value.into_object(),
+            Err(err) => Object::Error(err.to_string()),
+        }
+    }
+}
+
+/// A Rust closure callable from Monkey, taking arguments of the types in
+/// the tuple `Args`.
+pub trait HostFunction<Args>: 'static {
+    /// Calls the closure, registered as `name`, with `args`.
+    fn call(&self, name: &str, args: Vec<Object>) -> Object;
+}
+
+/// Arguments for a call of a Monkey function from Rust.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Object>;
+}
+
+impl IntoArgs for Vec<Object> {
+    fn into_args(self) -> Vec<Object> {
+        self
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($(($($arg:ident),*)),*) => {
+        $(
+            impl<F, R, $($arg),*> HostFunction<($($arg,)*)> for F
+            where
+                F: Fn($($arg),*) -> R + 'static,
+                R: IntoObject,
+                $($arg: FromObject),*
+            {
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn call(&self, name: &str, args: Vec<Object>) -> Object {
+                    let want = <[&str]>::len(&[$(stringify!($arg)),*]);
+                    if args.len() != want {
+                        return Object::Error(format!(
+                            "wrong number of arguments. got={}, want={}",
+                            args.len(),
+                            want
+                        ));
+                    }
+
+                    let mut args = args.into_iter().enumerate();
+                    $(
+                        let (i, arg) = args.next().unwrap();
+                        if $arg::out_of_range(&arg) {
+                            return Object::Error(format!(
+                                "argument {} to `{}` out of range: {}",
+                                i + 1,
+                                name,
+                                arg.inspect()
+                            ));
+                        }
+                        let got = arg.object_type();
+                        let $arg = match $arg::from_object(arg) {
+                            Some(value) => value,
+                            None => {
+                                return Object::Error(format!(
+                                    "argument {} to `{}` must be {}, got {}",
+                                    i + 1,
+                                    name,
+                                    $arg::expected(),
+                                    got
+                                ))
+                            }
+                        };
+                    )*
+                    self($($arg),*).into_object()
+                }
+            }
+
+            impl<$($arg: IntoObject),*> IntoArgs for ($($arg,)*) {
+                #[allow(non_snake_case)]
+                fn into_args(self) -> Vec<Object> {
+                    let ($($arg,)*) = self;
+                    vec![$($arg.into_object()),*]
+                }
+            }
+        )*
+    };
+}
+
+tuple_conversions!(
+    (),
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, G)
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bigint::BigInt;
+
+    fn roundtrip<T: IntoObject + FromObject + PartialEq + std::fmt::Debug + Clone>(value: T) {
+        let obj = value.clone().into_object();
+        assert_eq!(T::from_object(obj), Some(value));
+    }
+
+    #[test]
+    fn test_conversions() {
+        roundtrip(42i64);
+        roundtrip(-7i32);
+        roundtrip(3usize);
+        roundtrip(true);
+        roundtrip("monkey".to_string());
+        roundtrip(());
+        roundtrip(Some(1i64));
+        roundtrip(None::<i64>);
+        roundtrip(vec![vec![1i64], vec![]]);
+        roundtrip(HashMap::from([("a".to_string(), vec![true])]));
+
+        assert_eq!(usize::from_object(Object::Integer(-1)), None);
+        assert!(usize::out_of_range(&Object::Integer(-1)));
+        assert!(Option::<i64>::out_of_range(&Object::BigInteger(
+            BigInt::from(1).shl(63)
+        )));
+        assert!(!i32::out_of_range(&"1".into_object()));
+        assert_eq!(i64::from_object(Object::Boolean(true)), None);
+        assert_eq!(Vec::<i64>::from_object("s".into_object()), None);
+        assert_eq!(Vec::<i64>::expected(), "ARRAY of INTEGER");
+        assert_eq!(Err::<i64, _>("no").into_object().inspect(), "ERROR: no");
+    }
+
+    #[test]
+    fn test_host_functions() {
+        fn call<Args>(f: impl HostFunction<Args>, args: Vec<Object>) -> String {
+            f.call("f", args).inspect()
+        }
+
+        let add = |a: i64, b: i64| a + b;
+        assert_eq!(call(add, (1, 2).into_args()), "3");
+        assert_eq!(
+            call(add, (1,).into_args()),
+            "ERROR: wrong number of arguments. got=1, want=2"
+        );
+        assert_eq!(
+            call(add, (1, "2").into_args()),
+            "ERROR: argument 2 to `f` must be INTEGER, got STRING"
+        );
+        let shorten = |n: i32| n;
+        assert_eq!(
+            call(shorten, vec![Object::Integer(1 << 40)]),
+            "ERROR: argument 1 to `f` out of range: 1099511627776"
+        );
+
+        let join = |parts: Vec<String>, separator: Option<String>| {
+            parts.join(&separator.unwrap_or_default())
+        };
+        assert_eq!(call(join, (vec!["a", "b"], "-").into_args()), "a-b");
+        assert_eq!(call(join, (vec!["a", "b"], ()).into_args()), "ab");
+
+        let nothing = || ();
+        assert_eq!(call(nothing, ().into_args()), "null");
+
+        let checked = |n: i64| if n < 0 { Err("negative") } else { Ok(n) };
+        assert_eq!(call(checked, vec![Object::Integer(-1)]), "ERROR: negative");
+    }
+}
@@ -0,0 +1,525 @@
+//! A step debugger for the evaluator.
+//!
+//! An `Evaluator` given a `Debugger` asks it before each statement of a file
+//! whether to pause there. Paused, the debugger reads commands, one per
+//! line, until one of them resumes the program:
+//!
+//! | command                     | does                                               |
+//! |-----------------------------|----------------------------------------------------|
+//! | `step`, `s`                 | pauses at the next line, entering calls            |
+//! | `next`, `n`                 | pauses at the next line, stepping over calls       |
+//! | `out`, `o`                  | pauses once the current function returns           |
+//! | `continue`, `c`             | runs until a breakpoint                            |
+//! | `break`, `b` `[file:]line`  | sets a breakpoint, in the debugged file by default |
+//! | `delete`, `d` `[file:]line` | removes a breakpoint                               |
+//! | `backtrace`, `bt`           | shows the calls in progress, innermost first       |
+//! | `locals`                    | shows the variables of the current scope           |
+//! | `captured`                  | shows the variables of the enclosing scopes        |
+//! | `print`, `p` `expr`         | evaluates `expr` in the current scope              |
+//! | `list`, `l`                 | shows the source around the current line           |
+//! | `quit`, `q`                 | stops the program                                  |
+//!
+//! The debugger pauses before the first statement. Commands come from any
+//! reader, so a script of them can drive it as well as a terminal can; once
+//! they run out the program runs to its end.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::environment::*;
+use crate::evaluator::*;
+use crate::lexer::*;
+use crate::object::*;
+use crate::parser::*;
+use crate::stack_trace::*;
+use crate::token::Position;
+
+const PROMPT: &str = "(debug) ";
+
+// the lines of source around the current one `list` shows on either side
+const LIST_CONTEXT: usize = 3;
+
+const HELP: &str = "\
+step, s                pause at the next line, entering calls
+next, n                pause at the next line, stepping over calls
+out, o                 pause once the current function returns
+continue, c            run until a breakpoint
+break, b [file:]line   set a breakpoint
+delete, d [file:]line  remove a breakpoint
+backtrace, bt          show the calls in progress
+locals                 show the variables of the current scope
+captured               show the variables of the enclosing scopes
+print, p expr          evaluate expr in the current scope
+list, l                show the source around the current line
+quit, q                stop the program";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    // stepping over the calls made at a depth
+    Next(usize),
+    Out(usize),
+    Continue,
+    // the commands ran out
+    Detached,
+    Quit,
+}
+
+// where a statement is about to run
+#[derive(Debug, Clone, PartialEq)]
+struct Location {
+    file: Rc<Path>,
+    position: Position,
+    depth: usize,
+    // the address of the statement, telling a loop coming round again
+    // apart from the next statement on the same line
+    statement: usize,
+}
+
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    // the debugged file, which breakpoints without a file are in
+    main: PathBuf,
+    breakpoints: BTreeMap<PathBuf, BTreeSet<usize>>,
+    mode: Mode,
+    last: Option<Location>,
+}
+
+impl Debugger {
+    /// A debugger for the program at `main`, reading commands from `input`
+    /// and writing what they show to `output`.
+    pub fn new(main: &Path, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Debugger {
+        Debugger {
+            input,
+            output,
+            main: fs::canonicalize(main).unwrap_or_else(|_| main.to_path_buf()),
+            breakpoints: BTreeMap::new(),
+            mode: Mode::Step,
+            last: None,
+        }
+    }
+
+    /// Called by `evaluator` before it runs `statement` in `env`: pauses if
+    /// the statement starts a line a step or breakpoint stops at. Breaks
+    /// once the program is to stop, which it then does with `Object::Quit`.
+    pub fn before_statement(
+        &mut self,
+        evaluator: &mut Evaluator,
+        statement: &Statement,
+        env: &Rc<RefCell<Environment>>,
+    ) -> ControlFlow<()> {
+        match self.mode {
+            Mode::Quit => return ControlFlow::Break(()),
+            Mode::Detached => return ControlFlow::Continue(()),
+            _ => {}
+        }
+        // statements of macros and code not from a file have no line to
+        // show
+        let Some(file) = evaluator.current_file() else {
+            return ControlFlow::Continue(());
+        };
+
+        let location = Location {
+            file,
+            position: statement.span().start,
+            depth: evaluator.frames().len(),
+            statement: statement as *const Statement as usize,
+        };
+        let arrived = match &self.last {
+            Some(last) => {
+                last.file != location.file
+                    || last.position.line != location.position.line
+                    || last.depth != location.depth
+                    || last.statement == location.statement
+            }
+            None => true,
+        };
+        self.last = Some(location.clone());
+        if !arrived {
+            return ControlFlow::Continue(());
+        }
+
+        let stepped = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => location.depth <= depth,
+            Mode::Out(depth) => location.depth < depth,
+            _ => false,
+        };
+        let breakpoint = self
+            .breakpoints
+            .get(location.file.as_ref())
+            .is_some_and(|lines| lines.contains(&location.position.line));
+        if !stepped && !breakpoint {
+            return ControlFlow::Continue(());
+        }
+
+        self.pause(evaluator, &location, env)
+    }
+
+    // reads commands until one resumes the program
+    fn pause(
+        &mut self,
+        evaluator: &mut Evaluator,
+        location: &Location,
+        env: &Rc<RefCell<Environment>>,
+    ) -> ControlFlow<()> {
+        self.print(&format!(
+            "paused at {}:{}:{}",
+            display_path(&location.file),
+            location.position.line,
+            location.position.column
+        ));
+        self.list(location, 0);
+
+        loop {
+            let _ = self.output.write_all(PROMPT.as_bytes());
+            let _ = self.output.flush();
+
+            let mut line = String::new();
+            if !matches!(self.input.read_line(&mut line), Ok(n) if n > 0) {
+                self.print("");
+                self.mode = Mode::Detached;
+                return ControlFlow::Continue(());
+            }
+            let line = line.trim();
+            let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+            let argument = argument.trim();
+
+            let resume = match command {
+                "step" | "s" => Some(Mode::Step),
+                "next" | "n" => Some(Mode::Next(location.depth)),
+                "out" | "o" => Some(Mode::Out(location.depth)),
+                "continue" | "c" => Some(Mode::Continue),
+                _ => None,
+            };
+            if let Some(mode) = resume {
+                self.mode = mode;
+                return ControlFlow::Continue(());
+            }
+
+            match command {
+                "quit" | "q" => {
+                    self.mode = Mode::Quit;
+                    return ControlFlow::Break(());
+                }
+                "break" | "b" => self.set_breakpoint(argument, true),
+                "delete" | "d" => self.set_breakpoint(argument, false),
+                "backtrace" | "bt" => self.backtrace(evaluator, location),
+                "locals" => self.show_bindings(&env.borrow().bindings()),
+                "captured" => self.show_bindings(&captured(env)),
+                "print" | "p" => self.evaluate(evaluator, argument, env),
+                "list" | "l" => self.list(location, LIST_CONTEXT),
+                "help" | "h" => self.print(HELP),
+                "" => {}
+                _ => self.print(&format!("unknown command: {}, see help", command)),
+            }
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    // sets the breakpoint at `[file:]line`, or deletes it
+    fn set_breakpoint(&mut self, argument: &str, set: bool) {
+        let (file, line) = match argument.rsplit_once(':') {
+            Some((file, line)) => (self.resolve(file), line),
+            None => (Ok(self.main.clone()), argument),
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(msg) => return self.print(&msg),
+        };
+        let line = match line.parse::<usize>() {
+            Ok(line) if line > 0 => line,
+            _ => return self.print(&format!("expected a line number, got {:?}", line)),
+        };
+
+        let place = format!("{}:{}", display_path(&file), line);
+        if set {
+            self.breakpoints.entry(file).or_default().insert(line);
+            self.print(&format!("breakpoint at {}", place));
+        } else if self
+            .breakpoints
+            .get_mut(&file)
+            .is_some_and(|lines| lines.remove(&line))
+        {
+            self.print(&format!("deleted breakpoint at {}", place));
+        } else {
+            self.print(&format!("no breakpoint at {}", place));
+        }
+    }
+
+    // finds `file` relative to the directory of the debugged file
+    fn resolve(&self, file: &str) -> Result<PathBuf, String> {
+        let path = match self.main.parent() {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        fs::canonicalize(&path).map_err(|err| format!("could not find {}: {}", file, err))
+    }
+
+    // `#0 f at main.monkey:2:3` for the current statement, then the call of
+    // each function in progress, in the function that made it
+    fn backtrace(&mut self, evaluator: &Evaluator, location: &Location) {
+        let file = Some(Rc::clone(&location.file));
+        let entries = backtrace(evaluator.frames(), file, location.position);
+        let text: Vec<String> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| format!("#{} {}", i, entry))
+            .collect();
+        self.print(&text.join("\n"));
+    }
+
+    fn show_bindings(&mut self, bindings: &[(String, Object)]) {
+        for (name, value) in bindings {
+            self.print(&format!("{} = {}", name, value.inspect()));
+        }
+    }
+
+    fn evaluate(&mut self, evaluator: &mut Evaluator, input: &str, env: &Rc<RefCell<Environment>>) {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            return self.print(&format!(
+                "could not parse {:?}:\n\t{}",
+                input,
+                parser.errors().join("\n\t")
+            ));
+        }
+
+        let result = evaluator.eval_paused(&program, env);
+        self.print(&result.inspect());
+    }
+
+    // shows the current line, and `context` lines on either side of it
+    fn list(&mut self, location: &Location, context: usize) {
+        let Ok(source) = fs::read_to_string(&location.file) else {
+            return;
+        };
+        let current = location.position.line;
+        let first = current.saturating_sub(context).max(1);
+        let last = current + context;
+        let width = last.to_string().len();
+
+        for (number, text) in source.lines().enumerate().map(|(i, text)| (i + 1, text)) {
+            if (first..=last).contains(&number) {
+                let marker = if number == current { '>' } else { ' ' };
+                self.print(&format!("{} {:>width$} | {}", marker, number, text));
+            }
+        }
+    }
+}
+
+// the bindings of the scopes enclosing `env`, leaving out those shadowed by
+// nearer ones, ordered by name
+fn captured(env: &Rc<RefCell<Environment>>) -> Vec<(String, Object)> {
+    let mut seen: BTreeSet<String> = env
+        .borrow()
+        .bindings()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let mut captured = BTreeMap::new();
+
+    let mut scope = env.borrow().outer();
+    while let Some(current) = scope {
+        for (name, value) in current.borrow().bindings() {
+            if seen.insert(name.clone()) {
+                captured.insert(name, value);
+            }
+        }
+        scope = current.borrow().outer();
+    }
+    captured.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // output the test can read after the debugger took it
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const PROGRAM: &str = "let base = 10;
+let add = fn(x) {
+  let y = x + base;
+  y * 2
+};
+let twice = fn(f, v) {
+  f(v) + f(v)
+};
+let total = twice(add, 1);
+let i = 0;
+while (i < 2) {
+  i += 1;
+}
+";
+
+    // debugs `PROGRAM` with the commands of `script`, returning what the
+    // program ended with, the debugger's output with `$ ` for prompts and
+    // the file as the output shows it
+    fn debug(name: &str, script: &str) -> (Object, String, String) {
+        let dir =
+            std::env::temp_dir().join(format!("rust_monkey_debug_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.monkey");
+        fs::write(&path, PROGRAM).unwrap();
+
+        let output = Output::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_debugger(Debugger::new(
+            &path,
+            Box::new(Cursor::new(script.to_string())),
+            Box::new(output.clone()),
+        ));
+        let result = evaluator.eval_file(&path, &Environment::new());
+
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let file = display_path(&fs::canonicalize(&path).unwrap());
+        (result, text.replace(PROMPT, "$ "), file)
+    }
+
+    #[test]
+    fn test_stepping() {
+        let (result, output, file) = debug("stepping", "n\nn\nn\ns\ns\nbt\ns\no\nq\n");
+
+        assert!(matches!(result, Object::Quit), "{}", result.inspect());
+        assert_eq!(
+            output,
+            format!(
+                "paused at {file}:1:1
+> 1 | let base = 10;
+$ paused at {file}:2:1
+> 2 | let add = fn(x) {{
+$ paused at {file}:6:1
+> 6 | let twice = fn(f, v) {{
+$ paused at {file}:9:1
+> 9 | let total = twice(add, 1);
+$ paused at {file}:7:3
+> 7 |   f(v) + f(v)
+$ paused at {file}:3:3
+> 3 |   let y = x + base;
+$ #0 add at {file}:3:3
+#1 twice at {file}:7:3
+#2 <top level> at {file}:9:13
+$ paused at {file}:4:3
+> 4 |   y * 2
+$ paused at {file}:10:1
+> 10 | let i = 0;
+$ "
+            )
+        );
+    }
+
+    #[test]
+    fn test_quit_is_not_caught() {
+        let dir =
+            std::env::temp_dir().join(format!("rust_monkey_debug_quit_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.monkey");
+        let program =
+            "let x = 0;\ntry {\n  x = 1;\n} catch (e) {\n  x = 2;\n} finally {\n  x = 3;\n}\n";
+        fs::write(&path, program).unwrap();
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_debugger(Debugger::new(
+            &path,
+            // quits inside the try
+            Box::new(Cursor::new("s\ns\nq\n")),
+            Box::new(Output::default()),
+        ));
+        let env = Environment::new();
+        let result = evaluator.eval_file(&path, &env);
+
+        assert!(matches!(result, Object::Quit), "{}", result.inspect());
+        assert_eq!(env.borrow().get("x").unwrap().inspect(), "0");
+    }
+
+    #[test]
+    fn test_breakpoints_and_inspection() {
+        let script = "b 12\nb 3\nb 0\nd 3\nc\np i * 10\nlocals\nc\np i\nd 12\nc\n";
+        let (result, output, file) = debug("breakpoints", script);
+
+        assert_eq!(result.inspect(), "null");
+        assert_eq!(
+            output,
+            format!(
+                "paused at {file}:1:1
+> 1 | let base = 10;
+$ breakpoint at {file}:12
+$ breakpoint at {file}:3
+$ expected a line number, got \"0\"
+$ deleted breakpoint at {file}:3
+$ paused at {file}:12:3
+> 12 |   i += 1;
+$ 0
+$ add = fn(x) {{ let y = x + base; y * 2 }}
+base = 10
+i = 0
+total = 44
+twice = fn(f, v) {{ f(v) + f(v) }}
+$ paused at {file}:12:3
+> 12 |   i += 1;
+$ 1
+$ deleted breakpoint at {file}:12
+$ "
+            )
+        );
+    }
+
+    #[test]
+    fn test_captured_variables() {
+        let script = "b 4\nc\nlocals\ncaptured\np y = 0\nl\nc\n";
+        let (result, output, file) = debug("captured", script);
+
+        assert_eq!(result.inspect(), "null");
+        let output: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            output[3..],
+            [
+                "$ paused at {file}:4:3".replace("{file}", &file),
+                "> 4 |   y * 2".to_string(),
+                "$ x = 1".to_string(),
+                "y = 11".to_string(),
+                "$ add = fn(x) { let y = x + base; y * 2 }".to_string(),
+                "base = 10".to_string(),
+                "twice = fn(f, v) { f(v) + f(v) }".to_string(),
+                // assigning in the paused scope changes what the function
+                // goes on with
+                "$ 0".to_string(),
+                "$   1 | let base = 10;".to_string(),
+                "  2 | let add = fn(x) {".to_string(),
+                "  3 |   let y = x + base;".to_string(),
+                "> 4 |   y * 2".to_string(),
+                "  5 | };".to_string(),
+                "  6 | let twice = fn(f, v) {".to_string(),
+                "  7 |   f(v) + f(v)".to_string(),
+                "$ paused at {file}:4:3".replace("{file}", &file),
+                "> 4 |   y * 2".to_string(),
+                "$ ".to_string(),
+            ]
+        );
+    }
+}
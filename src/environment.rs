@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::gc;
+use crate::object::*;
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    store: HashMap<String, Object>,
+    // the constants of this scope, each with the number of constants
+    // declared in it before
+    constants: HashMap<String, usize>,
+    // how many constants have been declared in this scope
+    declarations: usize,
+    outer: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment::default()));
+        gc::track(&env);
+        env
+    }
+
+    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment {
+            outer: Some(outer),
+            ..Default::default()
+        }));
+        gc::track(&env);
+        env
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(obj) => Some(obj.clone()),
+            None => match &self.outer {
+                Some(outer) => outer.borrow().get(name),
+                None => None,
+            },
+        }
+    }
+
+    pub fn set(&mut self, name: String, val: Object) -> Object {
+        self.constants.remove(&name);
+        self.store.insert(name, val.clone());
+        val
+    }
+
+    /// Binds `name` the way a `let`, a `for` variable or an import alias
+    /// does, which may not replace a constant of this scope.
+    pub fn rebind(&mut self, name: String, val: Object) -> Result<(), String> {
+        if self.constants.contains_key(&name) {
+            return Err(format!("cannot assign to constant: {}", name));
+        }
+        self.store.insert(name, val);
+        Ok(())
+    }
+
+    /// Binds `name` as a constant, unless it is one in this scope already.
+    pub fn set_const(&mut self, name: String, val: Object) -> Result<(), String> {
+        self.rebind(name.clone(), val)?;
+        self.constants.insert(name, self.declarations);
+        self.declarations += 1;
+        Ok(())
+    }
+
+    /// How many constants have been declared in this scope, to tell the ones
+    /// declared after from the ones before.
+    pub fn declarations(&self) -> usize {
+        self.declarations
+    }
+
+    /// Lets the constants declared in this scope since it had `declarations`
+    /// be declared again, as those of the body of a loop are each time round.
+    pub fn release_constants(&mut self, declarations: usize) {
+        if self.declarations > declarations {
+            self.constants
+                .retain(|_, declared| *declared < declarations);
+        }
+    }
+
+    /// Rebinds an existing name in the scope that declared it.
+    pub fn assign(&mut self, name: &str, val: Object) -> Result<(), String> {
+        if self.store.contains_key(name) {
+            if self.constants.contains_key(name) {
+                return Err(format!("cannot assign to constant: {}", name));
+            }
+            self.store.insert(name.to_string(), val);
+            return Ok(());
+        }
+
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign(name, val),
+            None => Err(format!("cannot assign to undeclared identifier: {}", name)),
+        }
+    }
+
+    /// The names bound in this scope itself, not its outer ones, with their
+    /// values, ordered by name.
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        let mut bindings: Vec<(String, Object)> = self
+            .store
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    /// Whether this scope itself binds no names.
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    pub fn outer(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.outer.clone()
+    }
+
+    /// Calls `f` with every environment this one refers to, through its
+    /// outer environment and the functions and macros it binds.
+    pub(crate) fn for_each_reference(&self, f: &mut dyn FnMut(&Rc<RefCell<Environment>>)) {
+        if let Some(outer) = &self.outer {
+            f(outer);
+        }
+        for value in self.store.values() {
+            object_references(value, f);
+        }
+    }
+
+    /// Removes the bindings and the outer environment, returning them to be
+    /// dropped.
+    pub(crate) fn clear(&mut self) -> (HashMap<String, Object>, Option<Rc<RefCell<Environment>>>) {
+        self.constants.clear();
+        (std::mem::take(&mut self.store), self.outer.take())
+    }
+}
+
+// Modules are left out: the environments their exports refer to count as
+// referred to from outside.
+fn object_references(object: &Object, f: &mut dyn FnMut(&Rc<RefCell<Environment>>)) {
+    match object {
+        Object::Function(function) => f(&function.env),
+        Object::Macro(m) => f(&m.env),
+        Object::ReturnValue(value) | Object::Thrown(value) => object_references(value, f),
+        Object::Array(elements) => {
+            for element in elements {
+                object_references(element, f);
+            }
+        }
+        Object::Hash(pairs) => {
+            for pair in pairs.values() {
+                object_references(&pair.value, f);
+            }
+        }
+        _ => {}
+    }
+}
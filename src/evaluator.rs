@@ -0,0 +1,2061 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::bigint::BigInt;
+use crate::builtins::*;
+use crate::debugger::*;
+use crate::environment::*;
+use crate::exception::*;
+use crate::lexer::*;
+use crate::limits::*;
+use crate::macro_expansion::*;
+use crate::modify::*;
+use crate::module::*;
+use crate::object::*;
+use crate::optimizer::*;
+use crate::parser::*;
+use crate::stack_trace::*;
+use crate::token::*;
+
+#[derive(Default)]
+pub struct Evaluator {
+    modules: ModuleLoader,
+    opt_level: u8,
+    meter: Meter,
+    // the calls in progress, innermost last
+    frames: Vec<Frame>,
+    // the frames a runtime error left while on its way to a `try`
+    unwound: Option<Vec<Frame>>,
+    // the file and position of the statement or expression that error
+    // arose in
+    failed_at: Option<(Option<Rc<Path>>, Position)>,
+    // the file of the code being evaluated
+    file: Option<Rc<Path>>,
+    // taken out while it has the evaluation paused
+    debugger: Option<Box<Debugger>>,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::default()
+    }
+
+    /// An evaluator that optimizes the files it parses at `opt_level`, see
+    /// `optimizer::optimize`.
+    pub fn with_opt_level(opt_level: u8) -> Evaluator {
+        Evaluator {
+            opt_level,
+            ..Evaluator::default()
+        }
+    }
+
+    /// Sets the limits of the evaluations that start after this, see the
+    /// `limits` module.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.meter = Meter::new(limits);
+    }
+
+    pub fn limits(&self) -> &Limits {
+        self.meter.limits()
+    }
+
+    /// Lets `debugger` pause the evaluations that start after this before
+    /// the statements of files, see the `debugger` module.
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    /// Evaluates the source file at `path` in `env`. Imports inside of it
+    /// are resolved relative to the file.
+    pub fn eval_file(&mut self, path: &Path, env: &Rc<RefCell<Environment>>) -> Object {
+        let started = self.start();
+        let result = self.eval_file_metered(path, env);
+        self.stop(started, result)
+    }
+
+    fn eval_file_metered(&mut self, path: &Path, env: &Rc<RefCell<Environment>>) -> Object {
+        let path = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(err) => {
+                return Object::Error(format!("could not read {}: {}", path.display(), err))
+            }
+        };
+
+        if let Err(msg) = self.modules.enter(path.clone()) {
+            return Object::Error(msg);
+        }
+        let result = match self.parse_file(&path) {
+            Ok(program) => self.eval_program_in_file(&program, env, &path),
+            Err(msg) => Object::Error(msg),
+        };
+        self.modules.leave();
+
+        result
+    }
+
+    /// Parses the source file at `path`, expands the macros in it and
+    /// optimizes it, the way `eval_file` does before evaluating it.
+    pub fn parse_file(&mut self, path: &Path) -> Result<Program, String> {
+        let input = fs::read_to_string(path).map_err(|err| {
+            format!(
+                "could not read {}: {}",
+                self.modules.display_path(path),
+                err
+            )
+        })?;
+
+        let mut parser = Parser::new(Lexer::new(&input));
+        let mut program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            return Err(format!(
+                "could not parse {}:\n\t{}",
+                self.modules.display_path(path),
+                parser.errors().join("\n\t")
+            ));
+        }
+
+        let macro_env = Environment::new();
+        define_macros(&mut program, &macro_env).map_err(|msg| {
+            format!(
+                "could not parse {}:\n\t{}",
+                self.modules.display_path(path),
+                msg
+            )
+        })?;
+        let program = self.expand_macros(program, &macro_env)?;
+        Ok(optimize(program, self.opt_level))
+    }
+
+    /// Expands the macros defined in `macro_env` in `program`, see
+    /// `macro_expansion::expand_macros`, metered as an evaluation of its own
+    /// unless one is running already.
+    pub fn expand_macros(
+        &mut self,
+        program: Program,
+        macro_env: &Rc<RefCell<Environment>>,
+    ) -> Result<Program, String> {
+        let started = self.start();
+        let expanded = expand_macros(program, macro_env, self);
+        if started {
+            self.meter.stop();
+        }
+        expanded
+    }
+
+    /// Makes sure the stack taken so far is within the limit of the
+    /// evaluation running, see `Meter::check_stack`.
+    pub fn check_stack(&self) -> Result<(), LimitExceeded> {
+        self.meter.check_stack()
+    }
+
+    /// Counts a syntax tree of `nodes` nodes against the limits of the
+    /// evaluation running, see `Meter::build`.
+    pub fn build_nodes(&mut self, nodes: u64) -> Result<(), LimitExceeded> {
+        self.meter.build(nodes)
+    }
+
+    fn eval_import_statement(
+        &mut self,
+        is: &ImportStatement,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        match self.load_module(&is.path) {
+            Ok(module) => match env
+                .borrow_mut()
+                .rebind(is.alias.value.clone(), Object::Module(module))
+            {
+                Ok(()) => Object::Null,
+                Err(msg) => Object::Error(msg),
+            },
+            Err(err) => err,
+        }
+    }
+
+    /// Loads the module at `path`, or returns the error that stopped it.
+    fn load_module(&mut self, path: &str) -> Result<Rc<Module>, Object> {
+        let resolved = self.modules.resolve(path);
+        let path = fs::canonicalize(&resolved).map_err(|err| {
+            Object::Error(format!(
+                "could not load module {}: {}",
+                self.modules.display_path(&resolved),
+                err
+            ))
+        })?;
+
+        if let Some(module) = self.modules.cached(&path) {
+            return Ok(module);
+        }
+
+        self.modules.enter(path.clone()).map_err(Object::Error)?;
+        let program = self.parse_file(&path);
+        let env = Environment::new();
+        let result = match &program {
+            Ok(program) => self.eval_program_in_file(program, &env, &path),
+            Err(msg) => Object::Error(msg.clone()),
+        };
+        self.modules.leave();
+
+        if result.is_error() {
+            return Err(result);
+        }
+
+        let mut exports = BTreeMap::new();
+        for statement in &program.unwrap().statements {
+            if let Statement::LetStatement(ls) = statement {
+                if let (true, Some(value)) = (ls.exported, env.borrow().get(&ls.name.value)) {
+                    exports.insert(ls.name.value.clone(), value);
+                }
+            }
+        }
+
+        let module = Rc::new(Module { path, exports });
+        self.modules.insert(Rc::clone(&module));
+        Ok(module)
+    }
+
+    pub fn eval_program(&mut self, program: &Program, env: &Rc<RefCell<Environment>>) -> Object {
+        let started = self.start();
+        let result = self.eval_program_metered(program, env);
+        self.stop(started, result)
+    }
+
+    fn eval_program_in_file(
+        &mut self,
+        program: &Program,
+        env: &Rc<RefCell<Environment>>,
+        path: &Path,
+    ) -> Object {
+        let outer = self.file.replace(Rc::from(path));
+        let result = self.eval_program(program, env);
+        self.file = outer;
+        result
+    }
+
+    /// Calls `function` with `args` as a call expression in a program would.
+    pub fn call_function(&mut self, function: Object, args: Vec<Object>) -> Object {
+        let started = self.start();
+        let frame = Frame {
+            function: "<host>".to_string(),
+            file: None,
+            position: Position::default(),
+        };
+        let result = self.apply_function(function, args, frame);
+        self.stop(started, result)
+    }
+
+    /// Evaluates `program` in `env` while another evaluation is paused, as
+    /// the debugger does, leaving the error that one may be unwinding alone.
+    pub fn eval_paused(&mut self, program: &Program, env: &Rc<RefCell<Environment>>) -> Object {
+        let unwound = (self.unwound.take(), self.failed_at.take());
+        let result = match self.eval_program(program, env) {
+            Object::Thrown(value) => Object::Error(uncaught_message(&value)),
+            result => result,
+        };
+        (self.unwound, self.failed_at) = unwound;
+        result
+    }
+
+    /// The calls in progress, innermost last.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The file of the code being evaluated, `None` for code evaluated from
+    /// a string.
+    pub fn current_file(&self) -> Option<Rc<Path>> {
+        self.file.clone()
+    }
+
+    /// The functions the last evaluation was in when it failed with an
+    /// error it did not catch, innermost first, with where each was: the
+    /// innermost where the error arose. See `stack_trace::backtrace`.
+    pub fn stack_trace(&self) -> Vec<Frame> {
+        match &self.failed_at {
+            Some((file, position)) => {
+                let calls = self.unwound.as_deref().unwrap_or_default();
+                backtrace(calls, file.clone(), *position)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // starts an outermost evaluation, unless one is running already
+    fn start(&mut self) -> bool {
+        let started = self.meter.start();
+        if started {
+            self.unwound = None;
+            self.failed_at = None;
+        }
+        started
+    }
+
+    // ends what `start` started, turning a value thrown and never caught
+    // into an error
+    fn stop(&mut self, started: bool, result: Object) -> Object {
+        if !started {
+            return result;
+        }
+        self.meter.stop();
+        match result {
+            Object::Thrown(value) => Object::Error(uncaught_message(&value)),
+            result => result,
+        }
+    }
+
+    fn eval_program_metered(
+        &mut self,
+        program: &Program,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let mut result = Object::Null;
+
+        for statement in &program.statements {
+            result = self.eval_statement(statement, env);
+
+            match result {
+                Object::ReturnValue(rv) => return *rv,
+                Object::Error(_) | Object::LimitExceeded(_) | Object::Thrown(_) | Object::Quit => {
+                    return result
+                }
+                Object::Break | Object::Continue => {
+                    return Object::Error(format!("'{}' outside of a loop", result.inspect()))
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    pub fn eval_block_statement(
+        &mut self,
+        block: &BlockStatement,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let mut result = Object::Null;
+
+        for statement in &block.statements {
+            result = self.eval_statement(statement, env);
+
+            if matches!(
+                result,
+                Object::ReturnValue(_)
+                    | Object::TailCall(..)
+                    | Object::Error(_)
+                    | Object::LimitExceeded(_)
+                    | Object::Thrown(_)
+                    | Object::Quit
+                    | Object::Break
+                    | Object::Continue
+            ) {
+                return result;
+            }
+        }
+
+        result
+    }
+
+    fn eval_statement(&mut self, statement: &Statement, env: &Rc<RefCell<Environment>>) -> Object {
+        if let Err(limit) = self.meter.step() {
+            return Object::LimitExceeded(limit);
+        }
+        if let Some(mut debugger) = self.debugger.take() {
+            let paused = debugger.before_statement(self, statement, env);
+            self.debugger = Some(debugger);
+            if paused.is_break() {
+                return Object::Quit;
+            }
+        }
+
+        if let Err(limit) = self.meter.check_stack() {
+            return Object::LimitExceeded(limit);
+        }
+        let result = self.eval_statement_kind(statement, env);
+        self.note_failure(&result, statement.span().start);
+        result
+    }
+
+    // notes where an error arose, the first time it passes a statement or
+    // expression on its way out
+    fn note_failure(&mut self, result: &Object, position: Position) {
+        if self.failed_at.is_none() && matches!(result, Object::Error(_) | Object::Thrown(_)) {
+            self.failed_at = Some((self.file.clone(), position));
+        }
+    }
+
+    fn eval_statement_kind(
+        &mut self,
+        statement: &Statement,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        match statement {
+            Statement::ExpressionStatement(es) => match &es.expression {
+                Some(e) => self.eval_expression(e, env),
+                None => Object::Null,
+            },
+            Statement::ReturnStatement(rs) => {
+                let val = match &rs.return_value {
+                    Some(rv) => self.eval_expression(rv, env),
+                    None => Object::Null,
+                };
+                if val.is_error() || matches!(val, Object::TailCall(..)) {
+                    return val;
+                }
+                Object::ReturnValue(Box::new(val))
+            }
+            Statement::LetStatement(ls) => {
+                let mut val = match &ls.value {
+                    Some(v) => self.eval_expression(v, env),
+                    None => Object::Null,
+                };
+                if val.is_error() {
+                    return val;
+                }
+                if let (Some(Expression::FunctionLiteral(_)), Object::Function(f)) =
+                    (&ls.value, &mut val)
+                {
+                    f.name = Some(ls.name.value.clone());
+                }
+                let name = ls.name.value.clone();
+                let declared = if ls.is_const() {
+                    env.borrow_mut().set_const(name, val)
+                } else {
+                    env.borrow_mut().rebind(name, val)
+                };
+                match declared {
+                    Ok(()) => Object::Null,
+                    Err(msg) => Object::Error(msg),
+                }
+            }
+            Statement::WhileStatement(ws) => self.eval_while_statement(ws, env),
+            Statement::ForStatement(fs) => self.eval_for_statement(fs, env),
+            Statement::ImportStatement(is) => self.eval_import_statement(is, env),
+            Statement::BreakStatement(_) => Object::Break,
+            Statement::ContinueStatement(_) => Object::Continue,
+            Statement::ThrowStatement(ts) => {
+                let val = self.eval_expression(&ts.value, env);
+                if val.is_error() {
+                    return val;
+                }
+                Object::Thrown(Box::new(val))
+            }
+        }
+    }
+
+    fn eval_while_statement(
+        &mut self,
+        ws: &WhileStatement,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let declarations = env.borrow().declarations();
+        loop {
+            let condition = self.eval_expression(&ws.condition, env);
+            if condition.is_error() {
+                return condition;
+            }
+            if !is_truthy(&condition) {
+                break;
+            }
+
+            // the constants of the body are new each time round
+            env.borrow_mut().release_constants(declarations);
+            match self.eval_block_statement(&ws.body, env) {
+                Object::Break => break,
+                result @ (Object::ReturnValue(_)
+                | Object::TailCall(..)
+                | Object::Error(_)
+                | Object::LimitExceeded(_)
+                | Object::Thrown(_)
+                | Object::Quit) => return result,
+                _ => {}
+            }
+        }
+
+        Object::Null
+    }
+
+    fn eval_for_statement(&mut self, fs: &ForStatement, env: &Rc<RefCell<Environment>>) -> Object {
+        let iterable = self.eval_expression(&fs.iterable, env);
+        let items = match iterable {
+            Object::Array(elements) => elements,
+            Object::String(s) => s.chars().map(|c| Object::String(c.to_string())).collect(),
+            Object::Hash(pairs) => pairs.into_values().map(|pair| pair.key).collect(),
+            Object::Error(_) | Object::LimitExceeded(_) | Object::Thrown(_) | Object::Quit => {
+                return iterable
+            }
+            _ => return Object::Error(format!("cannot iterate over {}", iterable.object_type())),
+        };
+
+        let declarations = env.borrow().declarations();
+        for item in items {
+            if let Err(msg) = env.borrow_mut().rebind(fs.variable.value.clone(), item) {
+                return Object::Error(msg);
+            }
+
+            // the constants of the body are new each time round
+            env.borrow_mut().release_constants(declarations);
+            match self.eval_block_statement(&fs.body, env) {
+                Object::Break => break,
+                result @ (Object::ReturnValue(_)
+                | Object::TailCall(..)
+                | Object::Error(_)
+                | Object::LimitExceeded(_)
+                | Object::Thrown(_)
+                | Object::Quit) => return result,
+                _ => {}
+            }
+        }
+
+        Object::Null
+    }
+
+    fn eval_expression(
+        &mut self,
+        expression: &Expression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        if let Err(limit) = self.meter.step() {
+            return Object::LimitExceeded(limit);
+        }
+
+        if let Err(limit) = self.meter.check_stack() {
+            return Object::LimitExceeded(limit);
+        }
+        let result = self.eval_expression_kind(expression, env);
+        self.note_failure(&result, expression.span().start);
+
+        // the expressions that create objects rather than pass them on
+        if matches!(
+            expression,
+            Expression::StringLiteral(_)
+                | Expression::ArrayLiteral(_)
+                | Expression::HashLiteral(_)
+                | Expression::FunctionLiteral(_)
+                | Expression::InfixExpression(_)
+        ) {
+            if let Err(limit) = self.meter.allocate(&result) {
+                return Object::LimitExceeded(limit);
+            }
+        }
+        result
+    }
+
+    // every level of nesting takes a frame of this, so the cases needing
+    // more than a few locals are functions of their own
+    fn eval_expression_kind(
+        &mut self,
+        expression: &Expression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        match expression {
+            Expression::IntegerLiteral(il) => Object::integer(il.value.clone()),
+            Expression::Boolean(b) => Object::Boolean(b.value),
+            Expression::Identifier(id) => eval_identifier(id, env),
+            Expression::PrefixExpression(pe) => {
+                let right = self.eval_expression(&pe.right, env);
+                if right.is_error() {
+                    return right;
+                }
+                eval_prefix_expression(&pe.operator, right)
+            }
+            Expression::InfixExpression(ie) => match ie.operator.as_str() {
+                "&&" | "||" => self.eval_logical_expression(ie, env),
+                _ => self.eval_infix_operands(ie, env),
+            },
+            Expression::IfExpression(ie) => self.eval_if_expression(ie, env),
+            Expression::FunctionLiteral(fl) => self.eval_function_literal(fl, env),
+            Expression::CallExpression(ce) => self.eval_call_expression(ce, env),
+            Expression::StringLiteral(sl) => Object::String(sl.value.clone()),
+            Expression::ArrayLiteral(al) => match self.eval_expressions(&al.elements, env) {
+                Ok(elements) => Object::Array(elements),
+                Err(err) => err,
+            },
+            Expression::IndexExpression(ie) => self.eval_index_operands(ie, env),
+            Expression::HashLiteral(hl) => self.eval_hash_literal(hl, env),
+            Expression::AssignExpression(ae) => self.eval_assign_expression(ae, env),
+            Expression::MemberExpression(me) => {
+                let object = self.eval_expression(&me.object, env);
+                if object.is_error() {
+                    return object;
+                }
+                self.eval_member_expression(object, &me.property)
+            }
+            Expression::MacroLiteral(ml) => Object::Macro(Macro {
+                parameters: ml.parameters.clone(),
+                body: Rc::new(ml.body.clone()),
+                env: Rc::clone(env),
+            }),
+            Expression::TryExpression(te) => self.eval_try_expression(te, env),
+        }
+    }
+
+    fn eval_function_literal(
+        &mut self,
+        fl: &FunctionLiteral,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        Object::Function(Function {
+            parameters: fl.parameters.clone(),
+            body: Rc::clone(&fl.body),
+            env: Rc::clone(env),
+            name: None,
+            file: self.file.clone(),
+        })
+    }
+
+    fn eval_call_expression(
+        &mut self,
+        ce: &CallExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        if let Expression::Identifier(id) = ce.function.as_ref() {
+            if id.value == "quote" {
+                if ce.arguments.len() != 1 {
+                    return Object::Error(format!(
+                        "wrong number of arguments to quote: want=1, got={}",
+                        ce.arguments.len()
+                    ));
+                }
+                return self.quote(ce.arguments[0].clone(), env);
+            }
+        }
+
+        let function = self.eval_expression(&ce.function, env);
+        if function.is_error() {
+            return function;
+        }
+        let args = match self.eval_expressions(&ce.arguments, env) {
+            Ok(args) => args,
+            Err(err) => return err,
+        };
+        let name = match (&function, ce.function.as_ref()) {
+            (
+                Object::Function(Function {
+                    name: Some(name), ..
+                }),
+                _,
+            ) => name.clone(),
+            (_, Expression::FunctionLiteral(_)) => "<anonymous>".to_string(),
+            (_, callee) => callee.to_string(),
+        };
+        if ce.tail {
+            return Object::TailCall(Box::new(function), args, name);
+        }
+        let frame = Frame {
+            function: name,
+            file: self.file.clone(),
+            position: ce.span.start,
+        };
+        self.apply_function(function, args, frame)
+    }
+
+    /// Runs the body of `te`, then the `catch` if the body raised an error
+    /// or threw a value, and then the `finally`, which takes over if it
+    /// ends abruptly itself. Exceeding a limit or quitting the debugger
+    /// cannot be caught, and skips the `finally`.
+    fn eval_try_expression(
+        &mut self,
+        te: &TryExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let mut result = self.eval_block_statement(&te.body, env);
+
+        if let Some(catch) = &te.catch {
+            let caught = match result {
+                Object::Thrown(ref value) => {
+                    self.unwound = None;
+                    self.failed_at = None;
+                    Some(value.as_ref().clone())
+                }
+                Object::Error(ref msg) => {
+                    self.failed_at = None;
+                    let stack = self.unwound.take().unwrap_or_else(|| self.frames.clone());
+                    Some(error_object(msg, &stack))
+                }
+                _ => None,
+            };
+            if let Some(value) = caught {
+                let catch_env = Environment::new_enclosed(Rc::clone(env));
+                catch_env
+                    .borrow_mut()
+                    .set(catch.parameter.value.clone(), value);
+                result = self.eval_block_statement(&catch.body, &catch_env);
+            }
+        }
+
+        let stopped = matches!(result, Object::LimitExceeded(_) | Object::Quit);
+        if let (Some(finally), false) = (&te.finally, stopped) {
+            // the frames of an error still on its way
+            let unwound = (self.unwound.take(), self.failed_at.take());
+            let outcome = self.eval_block_statement(finally, env);
+            if matches!(
+                outcome,
+                Object::ReturnValue(_)
+                    | Object::Error(_)
+                    | Object::LimitExceeded(_)
+                    | Object::Thrown(_)
+                    | Object::Quit
+                    | Object::Break
+                    | Object::Continue
+            ) {
+                return outcome;
+            }
+            (self.unwound, self.failed_at) = unwound;
+        }
+
+        result
+    }
+
+    fn eval_member_expression(&self, object: Object, property: &Identifier) -> Object {
+        match object {
+            Object::Module(module) => match module.exports.get(&property.value) {
+                Some(value) => value.clone(),
+                None => Object::Error(format!(
+                    "module {} has no export named {}",
+                    self.modules.display_path(&module.path),
+                    property.value
+                )),
+            },
+            _ => Object::Error(format!(
+                "member access not supported: {}",
+                object.object_type()
+            )),
+        }
+    }
+
+    /// Turns `node` into a quote, evaluating `unquote(...)` calls inside of it
+    /// and splicing their results into the tree.
+    fn quote(&mut self, node: Expression, env: &Rc<RefCell<Environment>>) -> Object {
+        let mut eval_unquote_calls = |exp: Expression| {
+            let ce = match &exp {
+                Expression::CallExpression(ce) if is_unquote_call(ce) => ce,
+                _ => return exp,
+            };
+
+            let unquoted = self.eval_expression(&ce.arguments[0], env);
+            match convert_object_to_ast_node(&unquoted, ce.span) {
+                Some(node) => node,
+                None => exp,
+            }
+        };
+
+        let quoted = modify_expression(node, &mut eval_unquote_calls);
+        if let Err(limit) = self.meter.build(count_nodes(&quoted)) {
+            return Object::LimitExceeded(limit);
+        }
+        Object::Quote(Box::new(quoted))
+    }
+
+    fn eval_assign_expression(
+        &mut self,
+        ae: &AssignExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let (name, path) = match self.resolve_assign_target(&ae.target, env) {
+            Ok(resolved) => resolved,
+            Err(err) => return err,
+        };
+
+        let mut value = self.eval_expression(&ae.value, env);
+        if value.is_error() {
+            return value;
+        }
+
+        let root = match env.borrow().get(&name) {
+            Some(root) => root,
+            None => {
+                return Object::Error(format!("cannot assign to undeclared identifier: {}", name))
+            }
+        };
+
+        if let Some(operator) = ae.infix_operator() {
+            value = self.eval_compound_assignment(operator, &root, &path, value);
+            if value.is_error() {
+                return value;
+            }
+        }
+
+        let updated = eval_path_assignment(root, &path, value.clone());
+        if updated.is_error() {
+            return updated;
+        }
+        // the containers along the path are copies
+        if !path.is_empty() {
+            if let Err(limit) = self.meter.allocate(&updated) {
+                return Object::LimitExceeded(limit);
+            }
+        }
+
+        match env.borrow_mut().assign(&name, updated) {
+            Ok(()) => value,
+            Err(msg) => Object::Error(msg),
+        }
+    }
+
+    /// The value `operator` makes of what `path` leads to in `root` and
+    /// `value`, for `root[path] operator= value`.
+    fn eval_compound_assignment(
+        &mut self,
+        operator: &str,
+        root: &Object,
+        path: &[Object],
+        value: Object,
+    ) -> Object {
+        let mut current = root.clone();
+        for index in path {
+            current = eval_index_expression(current, index.clone());
+            if current.is_error() {
+                return current;
+            }
+        }
+        let cost = Cost::of_infix(operator, &current, &value);
+        if let Err(limit) = self.meter.reserve(cost) {
+            return Object::LimitExceeded(limit);
+        }
+        let value = eval_infix_expression(operator, current, value);
+        if value.is_error() {
+            return value;
+        }
+        match self.meter.allocate(&value) {
+            Ok(()) => value,
+            Err(limit) => Object::LimitExceeded(limit),
+        }
+    }
+
+    /// Splits an assignment target such as `a[i][j]` into the name of the
+    /// binding and the evaluated indices leading into it, so every index
+    /// expression is evaluated exactly once.
+    fn resolve_assign_target(
+        &mut self,
+        target: &Expression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(String, Vec<Object>), Object> {
+        match target {
+            Expression::Identifier(id) => Ok((id.value.clone(), Vec::new())),
+            Expression::IndexExpression(ie) => {
+                let (name, mut path) = self.resolve_assign_target(&ie.left, env)?;
+                let index = self.eval_expression(&ie.index, env);
+                if index.is_error() {
+                    return Err(index);
+                }
+                path.push(index);
+                Ok((name, path))
+            }
+            _ => Err(Object::Error(format!(
+                "invalid assignment target: {}",
+                target.to_string()
+            ))),
+        }
+    }
+
+    fn eval_expressions(
+        &mut self,
+        expressions: &[Expression],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Vec<Object>, Object> {
+        let mut result = Vec::new();
+
+        for e in expressions {
+            let evaluated = self.eval_expression(e, env);
+            if evaluated.is_error() {
+                return Err(evaluated);
+            }
+            result.push(evaluated);
+        }
+
+        Ok(result)
+    }
+
+    fn eval_infix_operands(
+        &mut self,
+        ie: &InfixExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let left = self.eval_expression(&ie.left, env);
+        if left.is_error() {
+            return left;
+        }
+        let right = self.eval_expression(&ie.right, env);
+        if right.is_error() {
+            return right;
+        }
+        let cost = Cost::of_infix(&ie.operator, &left, &right);
+        if let Err(limit) = self.meter.reserve(cost) {
+            return Object::LimitExceeded(limit);
+        }
+        eval_infix_expression(&ie.operator, left, right)
+    }
+
+    fn eval_index_operands(
+        &mut self,
+        ie: &IndexExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let left = self.eval_expression(&ie.left, env);
+        if left.is_error() {
+            return left;
+        }
+        let index = self.eval_expression(&ie.index, env);
+        if index.is_error() {
+            return index;
+        }
+        eval_index_expression(left, index)
+    }
+
+    /// `&&` and `||` only evaluate their right operand when the left one does not
+    /// already decide the result.
+    fn eval_logical_expression(
+        &mut self,
+        ie: &InfixExpression,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Object {
+        let left = self.eval_expression(&ie.left, env);
+        if left.is_error() {
+            return left;
+        }
+
+        match (ie.operator.as_str(), is_truthy(&left)) {
+            ("&&", false) => return Object::Boolean(false),
+            ("||", true) => return Object::Boolean(true),
+            _ => {}
+        }
+
+        let right = self.eval_expression(&ie.right, env);
+        if right.is_error() {
+            return right;
+        }
+        Object::Boolean(is_truthy(&right))
+    }
+
+    fn eval_hash_literal(&mut self, hl: &HashLiteral, env: &Rc<RefCell<Environment>>) -> Object {
+        let mut pairs = BTreeMap::new();
+
+        for (key_node, value_node) in &hl.pairs {
+            let key = self.eval_expression(key_node, env);
+            if key.is_error() {
+                return key;
+            }
+
+            let hash_key = match key.hash_key() {
+                Some(hash_key) => hash_key,
+                None => {
+                    return Object::Error(format!("unusable as hash key: {}", key.object_type()))
+                }
+            };
+
+            let value = self.eval_expression(value_node, env);
+            if value.is_error() {
+                return value;
+            }
+
+            pairs.insert(hash_key, HashPair { key, value });
+        }
+
+        Object::Hash(pairs)
+    }
+
+    fn eval_if_expression(&mut self, ie: &IfExpression, env: &Rc<RefCell<Environment>>) -> Object {
+        let condition = self.eval_expression(&ie.condition, env);
+        if condition.is_error() {
+            return condition;
+        }
+
+        if is_truthy(&condition) {
+            self.eval_block_statement(&ie.consequence, env)
+        } else if let Some(alt) = &ie.alternative {
+            self.eval_block_statement(alt, env)
+        } else {
+            Object::Null
+        }
+    }
+
+    /// Calls `function` in `frame`, and then the calls in tail position it
+    /// leaves behind in its place, see `tail_calls`.
+    fn apply_function(&mut self, function: Object, args: Vec<Object>, frame: Frame) -> Object {
+        self.frames.push(frame);
+        let result = self.apply_function_in_frame(function, args);
+        if let (Object::Error(_) | Object::Thrown(_), None) = (&result, &self.unwound) {
+            self.unwound = Some(self.frames.clone());
+        }
+        self.frames.pop();
+        result
+    }
+
+    fn apply_function_in_frame(&mut self, mut function: Object, mut args: Vec<Object>) -> Object {
+        loop {
+            let f = match function {
+                Object::Function(f) => f,
+                Object::Builtin(builtin) => {
+                    if let Some(cost) = builtin.cost {
+                        if let Err(limit) = self.meter.reserve(cost(&args)) {
+                            return Object::LimitExceeded(limit);
+                        }
+                    }
+                    let result = (builtin.func)(args);
+                    // what builtins return is new or copied from their arguments
+                    return match self.meter.allocate_copy(&result) {
+                        Ok(()) => result,
+                        Err(limit) => Object::LimitExceeded(limit),
+                    };
+                }
+                _ => return Object::Error(format!("not a function: {}", function.object_type())),
+            };
+
+            if f.parameters.len() != args.len() {
+                return Object::Error(format!(
+                    "wrong number of arguments: want={}, got={}",
+                    f.parameters.len(),
+                    args.len()
+                ));
+            }
+
+            if let Err(limit) = self.meter.enter() {
+                return Object::LimitExceeded(limit);
+            }
+
+            let env = Environment::new_enclosed(Rc::clone(&f.env));
+            for (param, arg) in f.parameters.iter().zip(args) {
+                env.borrow_mut().set(param.value.clone(), arg);
+            }
+
+            let caller = std::mem::replace(&mut self.file, f.file.clone());
+            let result = self.eval_block_statement(&f.body, &env);
+            self.file = caller;
+            self.meter.leave();
+            match result {
+                Object::TailCall(next, next_args, name) => {
+                    (function, args) = (*next, next_args);
+                    // the caller is still waiting where it made the call
+                    self.frames.last_mut().unwrap().function = name;
+                }
+                Object::ReturnValue(rv) => return *rv,
+                obj => return obj,
+            }
+        }
+    }
+}
+
+fn is_unquote_call(ce: &CallExpression) -> bool {
+    match ce.function.as_ref() {
+        Expression::Identifier(id) => id.value == "unquote" && ce.arguments.len() == 1,
+        _ => false,
+    }
+}
+
+/// Builds the literal for `obj`, placing it at the `unquote` call it
+/// replaces.
+pub fn convert_object_to_ast_node(obj: &Object, span: Span) -> Option<Expression> {
+    match obj {
+        Object::Integer(_) | Object::BigInteger(_) => {
+            let value = obj.to_big_int()?;
+            Some(Expression::IntegerLiteral(IntegerLiteral {
+                token: Token {
+                    token_type: TokenType::INT,
+                    literal: value.to_string(),
+                    span,
+                },
+                span,
+                value,
+            }))
+        }
+        Object::Boolean(b) => Some(Expression::Boolean(Boolean {
+            token: Token {
+                token_type: if *b {
+                    TokenType::TRUE
+                } else {
+                    TokenType::FALSE
+                },
+                literal: b.to_string(),
+                span,
+            },
+            span,
+            value: *b,
+        })),
+        Object::String(s) => Some(Expression::StringLiteral(StringLiteral {
+            token: Token {
+                token_type: TokenType::STRING,
+                literal: s.clone(),
+                span,
+            },
+            span,
+            value: s.clone(),
+        })),
+        Object::Array(elements) => {
+            let elements = elements
+                .iter()
+                .map(|element| convert_object_to_ast_node(element, span))
+                .collect::<Option<Vec<_>>>()?;
+            Some(Expression::ArrayLiteral(ArrayLiteral {
+                token: Token {
+                    token_type: TokenType::LBRACKET,
+                    literal: "[".to_string(),
+                    span,
+                },
+                span,
+                elements,
+            }))
+        }
+        Object::Quote(node) => Some(node.as_ref().clone()),
+        _ => None,
+    }
+}
+
+/// Returns a copy of `container` with the element at the end of `path`
+/// replaced by `value`. Arrays and hashes are values, so every container
+/// along the path is rebuilt.
+fn eval_path_assignment(container: Object, path: &[Object], value: Object) -> Object {
+    match path.split_first() {
+        None => value,
+        Some((index, rest)) => {
+            let child = if rest.is_empty() {
+                Object::Null
+            } else {
+                eval_index_expression(container.clone(), index.clone())
+            };
+            if child.is_error() {
+                return child;
+            }
+            let child = eval_path_assignment(child, rest, value);
+            if child.is_error() {
+                return child;
+            }
+            eval_index_assignment(container, index.clone(), child)
+        }
+    }
+}
+
+/// Returns a copy of `container` with `index` set to `value`.
+fn eval_index_assignment(container: Object, index: Object, value: Object) -> Object {
+    match (container, &index) {
+        (Object::Array(mut elements), Object::Integer(i)) => {
+            if *i < 0 || *i as usize >= elements.len() {
+                return Object::Error(format!("index out of range: {}", i));
+            }
+            elements[*i as usize] = value;
+            Object::Array(elements)
+        }
+        (Object::Array(_), Object::BigInteger(i)) => {
+            Object::Error(format!("index out of range: {}", i))
+        }
+        (Object::Hash(mut pairs), _) => match index.hash_key() {
+            Some(key) => {
+                pairs.insert(key, HashPair { key: index, value });
+                Object::Hash(pairs)
+            }
+            None => Object::Error(format!("unusable as hash key: {}", index.object_type())),
+        },
+        (container, _) => Object::Error(format!(
+            "index assignment not supported: {}",
+            container.object_type()
+        )),
+    }
+}
+
+fn eval_identifier(id: &Identifier, env: &Rc<RefCell<Environment>>) -> Object {
+    if let Some(val) = env.borrow().get(&id.value) {
+        return val;
+    }
+
+    match lookup_builtin(&id.value) {
+        Some(builtin) => builtin,
+        None => Object::Error(format!("identifier not found: {}", id.value)),
+    }
+}
+
+pub fn eval_prefix_expression(operator: &str, right: Object) -> Object {
+    match operator {
+        "!" => Object::Boolean(!is_truthy(&right)),
+        "-" => match right {
+            Object::Integer(i) => match i.checked_neg() {
+                Some(i) => Object::Integer(i),
+                None => Object::integer(-&BigInt::from(i)),
+            },
+            Object::BigInteger(i) => Object::integer(-&i),
+            _ => Object::Error(format!("unknown operator: -{}", right.object_type())),
+        },
+        _ => Object::Error(format!(
+            "unknown operator: {}{}",
+            operator,
+            right.object_type()
+        )),
+    }
+}
+
+pub fn eval_infix_expression(operator: &str, left: Object, right: Object) -> Object {
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => eval_integer_infix_expression(operator, *l, *r),
+        (
+            Object::Integer(_) | Object::BigInteger(_),
+            Object::Integer(_) | Object::BigInteger(_),
+        ) => eval_big_integer_infix_expression(
+            operator,
+            left.to_big_int().unwrap(),
+            right.to_big_int().unwrap(),
+        ),
+        (Object::String(l), Object::String(r)) => match operator {
+            "+" => Object::String(format!("{}{}", l, r)),
+            "==" => Object::Boolean(l == r),
+            "!=" => Object::Boolean(l != r),
+            _ => Object::Error(format!(
+                "unknown operator: {} {} {}",
+                left.object_type(),
+                operator,
+                right.object_type()
+            )),
+        },
+        (Object::Boolean(l), Object::Boolean(r)) => match operator {
+            "==" => Object::Boolean(l == r),
+            "!=" => Object::Boolean(l != r),
+            _ => Object::Error(format!(
+                "unknown operator: {} {} {}",
+                left.object_type(),
+                operator,
+                right.object_type()
+            )),
+        },
+        _ if left.object_type() != right.object_type() => Object::Error(format!(
+            "type mismatch: {} {} {}",
+            left.object_type(),
+            operator,
+            right.object_type()
+        )),
+        _ => Object::Error(format!(
+            "unknown operator: {} {} {}",
+            left.object_type(),
+            operator,
+            right.object_type()
+        )),
+    }
+}
+
+// Arithmetic that overflows is redone on `BigInt`s.
+fn eval_integer_infix_expression(operator: &str, left: i64, right: i64) -> Object {
+    let result = match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Object::Error("division by zero".to_string()),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        "&" => Some(left & right),
+        "|" => Some(left | right),
+        "^" => Some(left ^ right),
+        "<<" | ">>" if !(0..64).contains(&right) => {
+            return Object::Error(format!("shift amount out of range: {}", right))
+        }
+        "<<" => left
+            .checked_mul(1 << right)
+            .filter(|_| right < 63 || left == 0),
+        ">>" => Some(left >> right),
+        "<" => return Object::Boolean(left < right),
+        ">" => return Object::Boolean(left > right),
+        "<=" => return Object::Boolean(left <= right),
+        ">=" => return Object::Boolean(left >= right),
+        "==" => return Object::Boolean(left == right),
+        "!=" => return Object::Boolean(left != right),
+        _ => return Object::Error(format!("unknown operator: INTEGER {} INTEGER", operator)),
+    };
+    match result {
+        Some(i) => Object::Integer(i),
+        None => {
+            eval_big_integer_infix_expression(operator, BigInt::from(left), BigInt::from(right))
+        }
+    }
+}
+
+// The bitwise operators stay those of 64-bit two's complement integers.
+fn eval_big_integer_infix_expression(operator: &str, left: BigInt, right: BigInt) -> Object {
+    match operator {
+        "+" => Object::integer(&left + &right),
+        "-" => Object::integer(&left - &right),
+        "*" => Object::integer(&left * &right),
+        "/" | "%" => match left.div_rem(&right) {
+            Some((quotient, _)) if operator == "/" => Object::integer(quotient),
+            Some((_, remainder)) => Object::integer(remainder),
+            None => Object::Error("division by zero".to_string()),
+        },
+        "<<" | ">>" => match right.to_i64().filter(|shift| (0..64).contains(shift)) {
+            Some(shift) if operator == "<<" => Object::integer(left.shl(shift as u32)),
+            Some(shift) => Object::integer(left.shr(shift as u32)),
+            None => Object::Error(format!("shift amount out of range: {}", right)),
+        },
+        "&" | "|" | "^" => {
+            let large = if left.to_i64().is_none() { left } else { right };
+            Object::Error(format!("integer too large for {}: {}", operator, large))
+        }
+        "<" => Object::Boolean(left < right),
+        ">" => Object::Boolean(left > right),
+        "<=" => Object::Boolean(left <= right),
+        ">=" => Object::Boolean(left >= right),
+        "==" => Object::Boolean(left == right),
+        "!=" => Object::Boolean(left != right),
+        _ => Object::Error(format!("unknown operator: INTEGER {} INTEGER", operator)),
+    }
+}
+
+fn eval_index_expression(left: Object, index: Object) -> Object {
+    match (&left, &index) {
+        (Object::Array(elements), Object::Integer(i)) => {
+            if *i < 0 || *i as usize >= elements.len() {
+                return Object::Null;
+            }
+            elements[*i as usize].clone()
+        }
+        (Object::Array(_), Object::BigInteger(_)) => Object::Null,
+        (Object::Hash(pairs), _) => match index.hash_key() {
+            Some(key) => match pairs.get(&key) {
+                Some(pair) => pair.value.clone(),
+                None => Object::Null,
+            },
+            None => Object::Error(format!("unusable as hash key: {}", index.object_type())),
+        },
+        _ => Object::Error(format!(
+            "index operator not supported: {}",
+            left.object_type()
+        )),
+    }
+}
+
+pub fn is_truthy(obj: &Object) -> bool {
+    !matches!(obj, Object::Null | Object::Boolean(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Evaluator;
+    use crate::ast::Node;
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::limits::Limits;
+    use crate::object::Object;
+    use crate::parser::{parse_ok, Parser};
+
+    fn test_eval(input: &str) -> Object {
+        Evaluator::new().eval_program(&parse_ok(input), &Environment::new())
+    }
+
+    fn test_integer_object(obj: &Object, expected: i64) {
+        match obj {
+            Object::Integer(i) => assert_eq!(*i, expected, "object has wrong value"),
+            _ => panic!("object is not Integer, got {:?}", obj),
+        }
+    }
+
+    fn test_boolean_object(obj: &Object, expected: bool) {
+        match obj {
+            Object::Boolean(b) => assert_eq!(*b, expected, "object has wrong value"),
+            _ => panic!("object is not Boolean, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let tests = [
+            ("5", 5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("50 / 2 * 2 + 10", 60),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("10 % 3", 1),
+            ("-7 % 3", -1),
+            ("2 + 10 % 4", 4),
+            ("6 & 3", 2),
+            ("6 | 3", 7),
+            ("6 ^ 3", 5),
+            ("1 << 4", 16),
+            ("-16 >> 2", -4),
+            ("1 << 2 + 1", 8),
+            ("1 | 2 ^ 3 & 4", 3),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_big_integers() {
+        let tests = [
+            ("9223372036854775807 + 1", "9223372036854775808"),
+            ("-9223372036854775807 - 2", "-9223372036854775809"),
+            ("(-9223372036854775807 - 1) / -1", "9223372036854775808"),
+            ("-(-9223372036854775807 - 1)", "9223372036854775808"),
+            ("4294967296 * 4294967296", "18446744073709551616"),
+            ("1 << 63", "9223372036854775808"),
+            (
+                "123456789012345678901234567890",
+                "123456789012345678901234567890",
+            ),
+            (
+                "-123456789012345678901234567890 / 1000000007",
+                "-123456788148148161864",
+            ),
+            ("-123456789012345678901234567890 % 1000000007", "-197434842"),
+            ("123456789012345678901234567890 >> 60", "107081695084"),
+            ("(9223372036854775807 + 1) - 1", "9223372036854775807"),
+            ("99999999999999999999 > 9223372036854775807", "true"),
+            ("-99999999999999999999 < -1", "true"),
+            ("99999999999999999999 == 99999999999999999999", "true"),
+            ("[1, 2][99999999999999999999]", "null"),
+            (
+                "{99999999999999999999: 1, -99999999999999999999: 2, 0: 3}",
+                "{-99999999999999999999: 2, 0: 3, 99999999999999999999: 1}",
+            ),
+            ("pow(2, 100)", "1267650600228229401496703205376"),
+            ("pow(-3, 3)", "-27"),
+            ("pow(99999999999999999999, 0)", "1"),
+            ("99999999999999999999 / 0", "ERROR: division by zero"),
+            (
+                "99999999999999999999 & 1",
+                "ERROR: integer too large for &: 99999999999999999999",
+            ),
+            ("pow(2, -1)", "ERROR: negative exponent: -1"),
+            (
+                "pow(2, 9999999999)",
+                "ERROR: exponent too large: 9999999999",
+            ),
+            (
+                "pow(2, true)",
+                "ERROR: argument to `pow` must be INTEGER, got BOOLEAN",
+            ),
+            (
+                "let xs = [1]; xs[99999999999999999999] = 2",
+                "ERROR: index out of range: 99999999999999999999",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+
+        // small results of big operands are `Object::Integer`s again
+        test_integer_object(&test_eval("(9223372036854775807 + 1) - 1"), i64::MAX);
+    }
+
+    #[test]
+    fn test_factorial() {
+        let input = "
+let factorial = fn(n) { if (n < 2) { 1 } else { n * factorial(n - 1) } };
+factorial(100)";
+        let mut evaluator = Evaluator::new();
+        assert_eq!(
+            evaluator
+                .eval_program(&parse_ok(input), &Environment::new())
+                .inspect(),
+            "93326215443944152681699238856266700490715968264381621468592963895217599993229915608941463976156518286253697920827223758251185210916864000000000000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // far deeper than the depth limit, if the calls took stack
+        let limits = Limits {
+            max_depth: Some(100),
+            ..Limits::default()
+        };
+        let tests = [
+            (
+                "let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } };
+                 sum(100000, 0)",
+                "5000050000",
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { return true; } odd(n - 1) };
+                 let odd = fn(n) { if (n == 0) { return false; } even(n - 1) };
+                 [even(100000), odd(100001), even(7)]",
+                "[true, true, false]",
+            ),
+            (
+                "let count = fn(n) { while (true) { if (n == 0) { return \"done\"; } return count(n - 1); } };
+                 count(100000)",
+                "done",
+            ),
+            (
+                "let last = fn(xs) { if (len(xs) > 1) { last(rest(xs)) } else { first(xs) } };
+                 last([1, 2, 3])",
+                "3",
+            ),
+            ("let f = fn(n) { len(n) }; f(\"abc\")", "3"),
+            (
+                "let f = fn(n) { f(n, 1) }; f(1)",
+                "ERROR: wrong number of arguments: want=1, got=2",
+            ),
+            (
+                "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(1000)",
+                "ERROR: limit exceeded: more than 100 nested calls",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut evaluator = Evaluator::new();
+            evaluator.set_limits(limits.clone());
+            let result = evaluator.eval_program(&parse_ok(input), &Environment::new());
+            assert_eq!(result.inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let tests = [
+            ("true", true),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 <= 1", true),
+            ("2 <= 1", false),
+            ("1 >= 1", true),
+            ("1 >= 2", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("true == true", true),
+            ("(1 < 2) == true", true),
+            ("!(1 > 2)", true),
+            ("true && false", false),
+            ("true && true", true),
+            ("false || true", true),
+            ("false || false", false),
+            ("1 < 2 && 2 < 3", true),
+            ("false && true || true", true),
+            ("true || false && false", true),
+        ];
+
+        for (input, expected) in tests {
+            test_boolean_object(&test_eval(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        test_boolean_object(&test_eval("false && undefined"), false);
+        test_boolean_object(&test_eval("true || undefined"), true);
+
+        match test_eval("true && undefined") {
+            Object::Error(msg) => assert_eq!(msg, "identifier not found: undefined"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = [
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("true <= false;", "unknown operator: BOOLEAN <= BOOLEAN"),
+            ("5; true & false; 5", "unknown operator: BOOLEAN & BOOLEAN"),
+            (
+                "if (10 > 1) { return true % false; }",
+                "unknown operator: BOOLEAN % BOOLEAN",
+            ),
+            ("10 % 0", "division by zero"),
+            ("10 / 0", "division by zero"),
+            ("1 << 64", "shift amount out of range: 64"),
+            ("foobar", "identifier not found: foobar"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error(msg) => assert_eq!(msg, expected),
+                obj => panic!("no error object returned for {}, got {:?}", input, obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let tests = [
+            (r#"try { throw "oops"; 1 } catch (e) { e + "!" }"#, "oops!"),
+            ("try { 1 } catch (e) { 2 }", "1"),
+            ("try { throw [1, 2]; } catch (e) { len(e) }", "2"),
+            // the innermost try catches
+            (
+                "try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { e * 10 }",
+                "20",
+            ),
+            (
+                r#"let f = fn() { throw "deep" }; let g = fn() { f() + 1 }; try { g() } catch (e) { e }"#,
+                "deep",
+            ),
+            (
+                r#"try { 1 / 0 } catch (e) { e["kind"] + ": " + e["message"] }"#,
+                "ArithmeticError: division by zero",
+            ),
+            // builtins given the wrong arguments raise catchable errors
+            (
+                r#"try { len(1) } catch (e) { e["kind"] + ": " + e["message"] }"#,
+                "TypeError: argument to `len` not supported, got INTEGER",
+            ),
+            (
+                r#"try { first([1], [2]) } catch (e) { e["kind"] }"#,
+                "ArgumentError",
+            ),
+            (r#"try { nope } catch (e) { e["kind"] }"#, "NameError"),
+            (
+                "let f = fn() { try { return 1; } catch (e) { 2 } }; f()",
+                "1",
+            ),
+            // the catch body has a scope of its own, holding the parameter
+            ("let e = 1; try { throw 3; } catch (e) { } e", "1"),
+            ("const e = 1; try { throw 2; } catch (e) { e }", "2"),
+            ("let x = 0; try { throw 3; } catch (e) { x = e; } x", "3"),
+            (
+                "try { throw 3; } catch (e) { } e",
+                "ERROR: identifier not found: e",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_stack() {
+        let input = r#"
+let inner = fn(x) { x / 0 };
+let outer = fn() {
+  inner(1) + 1
+};
+try { outer() } catch (e) { e["stack"] }
+"#;
+        assert_eq!(test_eval(input).inspect(), "[inner at 4:3, outer at 6:7]");
+
+        // calls in tail position take the place of their caller, where it
+        // was called from
+        let input = r#"
+let fail = fn() { len(1) };
+let f = fn() { fail() };
+try { f() } catch (e) { e["stack"] }
+"#;
+        assert_eq!(test_eval(input).inspect(), "[len at 4:7]");
+
+        // functions are named by the `let` that bound them
+        let input = r#"
+let fail = fn() { 1 / 0 };
+let alias = fail;
+let fs = [fn() { alias() + 1 }];
+try { fs[0]() } catch (e) { e["stack"] }
+"#;
+        assert_eq!(test_eval(input).inspect(), "[fail at 4:18, fs[0] at 5:7]");
+
+        // errors raised at the level of the try unwind nothing
+        assert_eq!(
+            test_eval(r#"try { -true } catch (e) { e["stack"] }"#).inspect(),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn test_finally() {
+        let tests = [
+            (
+                "let log = []; try { log = push(log, 1); } finally { log = push(log, 2); } log",
+                "[1, 2]",
+            ),
+            (
+                "let log = []; try { throw 0; } catch (e) { log = push(log, 1); } finally { log = push(log, 2); } log",
+                "[1, 2]",
+            ),
+            // the value of the try is that of its body or catch
+            ("try { 1 } finally { 2 }", "1"),
+            // finally runs on the way out of functions and loops
+            (
+                "let x = 0; let f = fn() { try { return 1; } finally { x = 5; } }; f() + x",
+                "6",
+            ),
+            (
+                "let n = 0; for (i in [1, 2, 3]) { try { if (i == 2) { break; } } finally { n += 1; } } n",
+                "2",
+            ),
+            (
+                "let x = 0; try { try { throw 1; } finally { x = 1; } } catch (e) { e + x }",
+                "2",
+            ),
+            // a finally ending abruptly takes over
+            (
+                "let f = fn() { try { throw 1; } finally { return 2; } }; f()",
+                "2",
+            ),
+            ("try { throw 1; } catch (e) { throw 2; } finally { throw 3; }", "ERROR: uncaught exception: 3"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(test_eval(input).inspect(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_uncaught_exceptions() {
+        let tests = [
+            (r#"throw "oops";"#, "uncaught exception: oops"),
+            (
+                r#"let f = fn() { throw {"message": "bad input"} }; f()"#,
+                "bad input",
+            ),
+            (
+                r#"try { 1 / 0 } catch (e) { throw e; }"#,
+                "division by zero",
+            ),
+            ("try { throw 1; } finally { 2 }", "uncaught exception: 1"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error(msg) => assert_eq!(msg, expected, "input: {}", input),
+                obj => panic!("no error object returned for {}, got {:?}", input, obj),
+            }
+        }
+
+        // exceeding a limit cannot be caught, and skips finally
+        let mut p = Parser::new(Lexer::new(
+            "let x = 0; try { while (true) { } } catch (e) { x = 1; } finally { x = 2; }",
+        ));
+        let program = p.parse_program();
+        let env = Environment::new();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(Limits {
+            fuel: Some(100),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            evaluator.eval_program(&program, &env),
+            Object::LimitExceeded(_)
+        ));
+        assert_eq!(env.borrow().get("x").unwrap().inspect(), "0");
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        test_integer_object(&test_eval("if (1 <= 2) { 10 }"), 10);
+        test_integer_object(&test_eval("if (1 >= 2) { 10 } else { 20 }"), 20);
+        assert!(matches!(test_eval("if (false) { 10 }"), Object::Null));
+    }
+
+    #[test]
+    fn test_let_and_return_statements() {
+        let tests = [
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; let b = a; b % 7;", 4),
+            ("return 10; 9;", 10),
+            ("if (10 > 1) { if (10 > 1) { return 10; } return 1; }", 10),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = [
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("let adder = fn(x) { fn(y) { x + y } }; adder(2)(3);", 5),
+            (
+                "let max = fn(a, b) { if (a >= b) { a } else { b } }; max(3, 9);",
+                9,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        match test_eval(r#""Hello" + " " + "World!""#) {
+            Object::String(s) => assert_eq!(s, "Hello World!"),
+            obj => panic!("object is not String, got {:?}", obj),
+        }
+        test_boolean_object(&test_eval(r#""a" == "a""#), true);
+        test_boolean_object(&test_eval(r#""a" != "a""#), false);
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = [
+            (r#"len("")"#, 0),
+            (r#"len("four")"#, 4),
+            ("len([1, 2, 3])", 3),
+            (r#"len({"a": 1})"#, 1),
+            ("first([1, 2, 3])", 1),
+            ("last([1, 2, 3])", 3),
+            ("len(rest([1, 2, 3]))", 2),
+            ("last(push([1, 2], 3))", 3),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+
+        match test_eval("len(1)") {
+            Object::Error(msg) => assert_eq!(msg, "argument to `len` not supported, got INTEGER"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+        match test_eval(r#"len("one", "two")"#) {
+            Object::Error(msg) => assert_eq!(msg, "wrong number of arguments. got=2, want=1"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        let tests = [
+            ("[1, 2, 3][0]", Some(1)),
+            ("let i = 0; [1][i];", Some(1)),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                Some(6),
+            ),
+            ("[1, 2, 3][3]", None),
+            ("[1, 2, 3][-1]", None),
+            (r#"{"foo": 5}["foo"]"#, Some(5)),
+            (r#"{"foo": 5}["bar"]"#, None),
+            (r#"let key = "foo"; {"foo": 5}[key]"#, Some(5)),
+            ("{5: 5}[5]", Some(5)),
+            ("{true: 5}[true]", Some(5)),
+        ];
+
+        for (input, expected) in tests {
+            match expected {
+                Some(i) => test_integer_object(&test_eval(input), i),
+                None => assert!(matches!(test_eval(input), Object::Null), "input: {}", input),
+            }
+        }
+
+        match test_eval(r#"{"name": "Monkey"}[fn(x) { x }];"#) {
+            Object::Error(msg) => assert_eq!(msg, "unusable as hash key: FUNCTION"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_while_statements() {
+        let tests = [
+            ("let i = 0; while (i < 10) { let i = i + 1; } i", 10),
+            ("let i = 0; while (true) { let i = i + 1; if (i == 7) { break; } } i", 7),
+            (
+                "let i = 0; let sum = 0; while (i < 10) { let i = i + 1; if (i % 2 == 0) { continue; } let sum = sum + i; } sum",
+                25,
+            ),
+            (
+                "let f = fn() { let i = 0; while (true) { let i = i + 1; if (i > 3) { return i * 10; } } }; f()",
+                40,
+            ),
+            ("let i = 0; while (false) { let i = 1; } i", 0),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+
+        // deep iteration must not grow the native stack
+        test_integer_object(
+            &test_eval("let i = 0; while (i < 100000) { let i = i + 1; } i"),
+            100000,
+        );
+    }
+
+    #[test]
+    fn test_for_statements() {
+        let tests = [
+            ("let sum = 0; for (x in [1, 2, 3, 4]) { let sum = sum + x; } sum", 10),
+            (
+                "let sum = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { break; } let sum = sum + x; } sum",
+                3,
+            ),
+            (
+                "let sum = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { continue; } let sum = sum + x; } sum",
+                7,
+            ),
+            (
+                r#"let n = 0; for (c in "hello") { if (c == "l") { let n = n + 1; } } n"#,
+                2,
+            ),
+            (
+                r#"let sum = 0; let h = {"a": 1, "b": 2}; for (k in h) { let sum = sum + h[k]; } sum"#,
+                3,
+            ),
+            (
+                "let total = 0; for (row in [[1, 2], [3]]) { for (x in row) { if (x == 2) { break; } let total = total + x; } } total",
+                4,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+
+        match test_eval("for (x in 5) { x }") {
+            Object::Error(msg) => assert_eq!(msg, "cannot iterate over INTEGER"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_assign_expressions() {
+        let tests = [
+            ("let x = 1; x = 5; x", 5),
+            ("let x = 1; x = x + 1; x", 2),
+            ("let x = 1; x += 4; x", 5),
+            ("let x = 10; x -= 4; x", 6),
+            ("let x = 3; x *= 4; x", 12),
+            ("let x = 12; x /= 4; x", 3),
+            ("let x = 1; let y = 2; x = y = 7; x + y", 14),
+            ("let x = 1; (x = 9) + 1", 10),
+            ("let x = 1; let f = fn() { x = 2; }; f(); x", 2),
+            ("let x = 1; let f = fn() { let x = 5; x = 6; }; f(); x", 1),
+            ("let a = [1, 2, 3]; a[1] = 5; a[1]", 5),
+            ("let a = [1, 2, 3]; a[2] += 10; a[2]", 13),
+            (
+                "let a = [[1, 2], [3, 4]]; a[1][0] *= 5; a[1][0] + a[0][0]",
+                16,
+            ),
+            (r#"let h = {"a": 1}; h["b"] = 2; h["a"] + h["b"]"#, 3),
+            (r#"let h = {"a": [1]}; h["a"][0] -= 3; h["a"][0]"#, -2),
+            ("let a = [1]; let b = a; b[0] = 2; a[0]", 1),
+            (
+                "let i = 0; let sum = 0; while (i < 5) { i += 1; sum += i; } sum",
+                15,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_integer_object(&test_eval(input), expected);
+        }
+
+        match test_eval(r#"let s = "a"; s += "b"; s"#) {
+            Object::String(s) => assert_eq!(s, "ab"),
+            obj => panic!("object is not String, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_assign_errors() {
+        let tests = [
+            ("x = 1;", "cannot assign to undeclared identifier: x"),
+            ("x += 1;", "cannot assign to undeclared identifier: x"),
+            ("a[0] = 1;", "cannot assign to undeclared identifier: a"),
+            ("const x = 1; x = 2;", "cannot assign to constant: x"),
+            ("const x = 1; x += 2;", "cannot assign to constant: x"),
+            ("const a = [1]; a[0] = 2;", "cannot assign to constant: a"),
+            (
+                "const x = 1; let f = fn() { x = 2; }; f();",
+                "cannot assign to constant: x",
+            ),
+            (
+                "const x = 1; for (x in [5, 6]) { } puts(x);",
+                "cannot assign to constant: x",
+            ),
+            ("const x = 1; let x = 2;", "cannot assign to constant: x"),
+            ("const x = 1; const x = 2;", "cannot assign to constant: x"),
+            (
+                "for (i in [1, 2]) { const c = i; c = 3; }",
+                "cannot assign to constant: c",
+            ),
+            (
+                "for (i in [1, 2]) { const c = i; const c = 3; }",
+                "cannot assign to constant: c",
+            ),
+            (
+                "for (i in [1, 2]) { const c = i; } c = 3;",
+                "cannot assign to constant: c",
+            ),
+            (
+                "const c = 1; for (i in [1, 2]) { const c = i; }",
+                "cannot assign to constant: c",
+            ),
+            ("let a = [1]; a[1] = 2;", "index out of range: 1"),
+            (
+                "let x = 1; x[0] = 2;",
+                "index assignment not supported: INTEGER",
+            ),
+            ("let x = 1; x += true;", "type mismatch: INTEGER + BOOLEAN"),
+            (
+                "let h = {}; h[fn() {}] = 1;",
+                "unusable as hash key: FUNCTION",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error(msg) => assert_eq!(msg, expected, "input: {}", input),
+                obj => panic!("no error object returned for {}, got {:?}", input, obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_const_bindings() {
+        test_integer_object(&test_eval("const x = 5; x * 2"), 10);
+        // a declaration replaces a variable of the same scope, not a constant
+        test_integer_object(&test_eval("let x = 5; const x = 6; x * 2"), 12);
+        test_integer_object(
+            &test_eval("const x = 5; let f = fn() { let x = 1; x = 2; x }; f()"),
+            2,
+        );
+        // a loop variable in a function shadows the constant outside of it
+        test_integer_object(
+            &test_eval("const x = 5; let f = fn() { for (x in [1]) { } x }; f() + x"),
+            6,
+        );
+        // a loop body declares its constants anew each time round, and
+        // the last of them stay
+        test_integer_object(&test_eval("for (i in [1, 2]) { const c = i; } c"), 2);
+        test_integer_object(
+            &test_eval("let i = 0; while (i < 3) { const c = i; i += 1; } c"),
+            2,
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let tests = [
+            ("quote(5)", "5"),
+            ("quote(5 + 8)", "5 + 8"),
+            ("quote(foobar)", "foobar"),
+            ("quote(foobar + barfoo)", "foobar + barfoo"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Quote(node) => assert_eq!(node.to_string(), expected),
+                obj => panic!("expected Quote, got {:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_quote_unquote() {
+        let tests = [
+            ("quote(unquote(4))", "4"),
+            ("quote(unquote(4 + 4))", "8"),
+            ("quote(8 + unquote(4 + 4))", "8 + 8"),
+            ("quote(unquote(4 + 4) + 8)", "8 + 8"),
+            ("let foobar = 8; quote(foobar)", "foobar"),
+            ("let foobar = 8; quote(unquote(foobar))", "8"),
+            ("quote(unquote(true))", "true"),
+            ("quote(unquote(true == false))", "false"),
+            ("quote(unquote(quote(4 + 4)))", "4 + 4"),
+            (
+                "let quotedInfixExpression = quote(4 + 4); quote(unquote(4 + 4) + unquote(quotedInfixExpression))",
+                "8 + 4 + 4",
+            ),
+            (r#"quote(unquote("a" + "b"))"#, r#""ab""#),
+            ("quote(unquote([1, 1 + 1]))", "[1, 2]"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Quote(node) => assert_eq!(node.to_string(), expected, "input: {}", input),
+                obj => panic!("expected Quote, got {:?}", obj),
+            }
+        }
+    }
+}
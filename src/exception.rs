@@ -0,0 +1,132 @@
+//! The values `try` catches.
+//!
+//! `throw` raises any value as it is. Runtime errors, including those of
+//! builtins given the wrong arguments, are caught as error objects: hashes
+//! with the `"message"` of the error, its `"kind"`, such as `"TypeError"`,
+//! and the `"stack"` of calls it unwound, innermost first.
+
+use std::collections::BTreeMap;
+
+use crate::object::*;
+use crate::stack_trace::Frame;
+
+// the kinds of runtime errors, by the start of their message
+const KINDS: &[(&str, &str)] = &[
+    ("identifier not found", "NameError"),
+    ("cannot assign to undeclared identifier", "NameError"),
+    ("wrong number of arguments", "ArgumentError"),
+    ("argument to `", "TypeError"),
+    ("type mismatch", "TypeError"),
+    ("unknown operator", "TypeError"),
+    ("not a function", "TypeError"),
+    ("unusable as hash key", "TypeError"),
+    ("index operator not supported", "TypeError"),
+    ("index assignment not supported", "TypeError"),
+    ("member access not supported", "TypeError"),
+    ("cannot iterate over", "TypeError"),
+    ("index out of range", "IndexError"),
+    ("division by zero", "ArithmeticError"),
+    ("shift amount out of range", "ArithmeticError"),
+    ("integer too large", "ArithmeticError"),
+    ("negative exponent", "ArithmeticError"),
+    ("exponent too large", "ArithmeticError"),
+    ("could not load module", "ImportError"),
+    ("module ", "ImportError"),
+];
+
+/// The kind of the runtime error with `message`, or `"Error"` for those of
+/// no particular kind.
+pub fn error_kind(message: &str) -> &'static str {
+    KINDS
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))
+        .map_or("Error", |(_, kind)| kind)
+}
+
+/// The object a runtime error with `message` is caught as, having unwound
+/// `stack`, outermost call first.
+pub fn error_object(message: &str, stack: &[Frame]) -> Object {
+    let stack = stack
+        .iter()
+        .rev()
+        .map(|frame| Object::String(frame.to_string()))
+        .collect();
+    let fields = [
+        ("kind", Object::String(error_kind(message).to_string())),
+        ("message", Object::String(message.to_string())),
+        ("stack", Object::Array(stack)),
+    ];
+
+    let mut pairs = BTreeMap::new();
+    for (key, value) in fields {
+        let key = Object::String(key.to_string());
+        pairs.insert(key.hash_key().unwrap(), HashPair { key, value });
+    }
+    Object::Hash(pairs)
+}
+
+/// The message of `value` thrown and never caught: the `"message"` of an
+/// error object, or the value itself.
+pub fn uncaught_message(value: &Object) -> String {
+    if let Object::Hash(pairs) = value {
+        let key = Object::String("message".to_string()).hash_key().unwrap();
+        if let Some(HashPair {
+            value: Object::String(message),
+            ..
+        }) = pairs.get(&key)
+        {
+            return message.clone();
+        }
+    }
+    format!("uncaught exception: {}", value.inspect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Position;
+
+    #[test]
+    fn test_error_object() {
+        let stack = [
+            Frame {
+                function: "<host>".to_string(),
+                file: None,
+                position: Position::default(),
+            },
+            Frame {
+                function: "f".to_string(),
+                file: None,
+                position: Position { line: 3, column: 5 },
+            },
+        ];
+
+        let obj = error_object("division by zero", &stack);
+
+        assert_eq!(
+            obj.inspect(),
+            "{kind: ArithmeticError, message: division by zero, stack: [f at 3:5, <host>]}"
+        );
+        assert_eq!(uncaught_message(&obj), "division by zero");
+        assert_eq!(
+            uncaught_message(&Object::Integer(1)),
+            "uncaught exception: 1"
+        );
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let tests = [
+            ("identifier not found: x", "NameError"),
+            ("argument to `len` not supported, got INTEGER", "TypeError"),
+            ("wrong number of arguments. got=2, want=1", "ArgumentError"),
+            ("index out of range: 5", "IndexError"),
+            ("module lib.monkey has no export named x", "ImportError"),
+            ("'break' outside of a loop", "Error"),
+        ];
+
+        for (message, expected) in tests {
+            assert_eq!(error_kind(message), expected, "message: {}", message);
+        }
+    }
+}
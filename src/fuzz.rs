@@ -0,0 +1,575 @@
+//! Fuzzing of the lexer and the parser without external tooling.
+//!
+//! The targets take one input each and panic when something is wrong:
+//!
+//! - `fuzz_lexer` checks that lexing reaches EOF and that tokens move forward,
+//! - `fuzz_parser` checks that parsing, and printing what parsed, completes,
+//! - `fuzz_roundtrip` checks that a program printed with `parenthesize`
+//!   parses back into the same tree.
+//!
+//! `run` feeds them random bytes, programs made up by `ProgramGenerator`
+//! from the grammar, and mutations of such programs, all derived from a
+//! seed so that a run can be repeated. It backs both the tests below and the
+//! `fuzz` subcommand.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::ast::{Node, Program};
+use crate::json::encode_program;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::printer::{parenthesize, sexp};
+use crate::token::{Position, TokenType};
+
+/// A xorshift64* generator: fast, deterministic and good enough to pick
+/// fuzzing inputs.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Pieces of Monkey source that random inputs are partly built from, so that
+/// they get past the lexer more often than plain noise would.
+const FRAGMENTS: &[&str] = &[
+    "let ",
+    "const ",
+    "fn",
+    "macro",
+    "if",
+    "else",
+    "return ",
+    "while",
+    "for",
+    " in ",
+    "break",
+    "continue",
+    "throw ",
+    "try",
+    "catch",
+    "finally",
+    "import ",
+    " as ",
+    "export ",
+    "true",
+    "false",
+    "x",
+    "foo",
+    "0",
+    "42",
+    "9223372036854775808",
+    "\"",
+    "\"s\"",
+    "(",
+    ")",
+    "{",
+    "}",
+    "[",
+    "]",
+    ",",
+    ";",
+    ":",
+    ".",
+    "=",
+    "+=",
+    "-",
+    "!",
+    "*",
+    "/",
+    "%",
+    "<",
+    ">=",
+    "==",
+    "!=",
+    "&&",
+    "||",
+    "&",
+    "|",
+    "^",
+    "<<",
+    ">>",
+    " ",
+    "\n",
+    "\t",
+    "\0",
+    "é",
+    "→",
+    "@",
+    "_",
+];
+
+const NAMES: &[&str] = &["a", "b", "x", "y", "foo", "len", "puts", "quote", "unquote"];
+const PREFIX_OPERATORS: &[&str] = &["-", "!"];
+const INFIX_OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "%", "<", ">", "<=", ">=", "==", "!=", "&&", "||", "&", "|", "^", "<<",
+    ">>",
+];
+const ASSIGN_OPERATORS: &[&str] = &["=", "+=", "-=", "*=", "/="];
+const STRING_CHARS: &[char] = &['a', 'b', 'z', '0', ' ', '\n', 'é', '→', '{', '\\'];
+
+/// How deeply `ProgramGenerator` nests statements and expressions.
+const MAX_DEPTH: usize = 4;
+
+/// Returns up to 64 bytes, mixing random bytes with `FRAGMENTS`.
+pub fn random_bytes(rng: &mut Rng) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for _ in 0..rng.below(64) {
+        if rng.chance(50) {
+            bytes.push(rng.next_u64() as u8);
+        } else {
+            bytes.extend_from_slice(rng.pick(FRAGMENTS).as_bytes());
+        }
+    }
+    bytes
+}
+
+/// Applies a few random edits to `bytes`: deleting, inserting, replacing
+/// and duplicating ranges.
+pub fn mutate(rng: &mut Rng, bytes: &mut Vec<u8>) {
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(bytes.len() + 1);
+        let end = (at + rng.below(8)).min(bytes.len());
+
+        match rng.below(4) {
+            0 => {
+                bytes.drain(at..end);
+            }
+            1 => {
+                let fragment = rng.pick(FRAGMENTS).as_bytes();
+                bytes.splice(at..at, fragment.iter().copied());
+            }
+            2 => {
+                if at < bytes.len() {
+                    bytes[at] = rng.next_u64() as u8;
+                }
+            }
+            _ => {
+                let copy = bytes[at..end].to_vec();
+                bytes.splice(at..at, copy);
+            }
+        }
+    }
+}
+
+/// Makes up syntactically valid programs by walking the grammar.
+pub struct ProgramGenerator<'a> {
+    rng: &'a mut Rng,
+    depth: usize,
+    // whether `break` and `continue` are allowed here
+    in_loop: bool,
+}
+
+impl<'a> ProgramGenerator<'a> {
+    pub fn new(rng: &'a mut Rng) -> ProgramGenerator<'a> {
+        ProgramGenerator {
+            rng,
+            depth: 0,
+            in_loop: false,
+        }
+    }
+
+    pub fn program(&mut self) -> String {
+        let statements: Vec<String> = (0..1 + self.rng.below(5))
+            .map(|_| self.statement(true))
+            .collect();
+        statements.join("\n")
+    }
+
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn name(&mut self) -> String {
+        self.rng.pick(NAMES).to_string()
+    }
+
+    fn names(&mut self) -> String {
+        let names: Vec<String> = (0..self.rng.below(3)).map(|_| self.name()).collect();
+        names.join(", ")
+    }
+
+    fn parameters(&mut self) -> String {
+        let params: Vec<String> = (0..self.rng.below(3))
+            .map(|_| format!("{}{}", self.name(), self.annotation(": ")))
+            .collect();
+        params.join(", ")
+    }
+
+    /// Returns `separator` and a type, or nothing.
+    fn annotation(&mut self, separator: &str) -> String {
+        match self.rng.chance(30) {
+            true => format!("{}{}", separator, self.type_annotation()),
+            false => "".to_string(),
+        }
+    }
+
+    fn type_annotation(&mut self) -> String {
+        if self.depth >= MAX_DEPTH || self.rng.chance(50) {
+            return self
+                .rng
+                .pick(&["int", "bool", "string", "null"])
+                .to_string();
+        }
+
+        self.nested(|g| match g.rng.below(3) {
+            0 => format!("[{}]", g.type_annotation()),
+            1 => format!("{{{}: {}}}", g.type_annotation(), g.type_annotation()),
+            _ => {
+                let params: Vec<String> =
+                    (0..g.rng.below(3)).map(|_| g.type_annotation()).collect();
+                format!("fn({}) -> {}", params.join(", "), g.type_annotation())
+            }
+        })
+    }
+
+    fn statement(&mut self, top_level: bool) -> String {
+        let choices = if top_level { 10 } else { 8 };
+
+        match self.rng.below(choices) {
+            0 => format!(
+                "{} {}{} = {};",
+                self.rng.pick(&["let", "const"]),
+                self.name(),
+                self.annotation(": "),
+                self.expression()
+            ),
+            1 => format!("return {};", self.expression()),
+            2 if self.depth < MAX_DEPTH => {
+                let condition = self.expression();
+                format!("while ({}) {}", condition, self.loop_body())
+            }
+            3 if self.depth < MAX_DEPTH => {
+                let variable = self.name();
+                let iterable = self.expression();
+                format!("for ({} in {}) {}", variable, iterable, self.loop_body())
+            }
+            4 if self.in_loop => self.rng.pick(&["break;", "continue;"]).to_string(),
+            5 => format!(
+                "{} {} {};",
+                self.name(),
+                self.rng.pick(ASSIGN_OPERATORS),
+                self.expression()
+            ),
+            7 => format!("throw {};", self.expression()),
+            8 => format!("export let {} = {};", self.name(), self.expression()),
+            9 => format!("import \"{}.monkey\" as {};", self.name(), self.name()),
+            _ => format!("{};", self.expression()),
+        }
+    }
+
+    fn block(&mut self) -> String {
+        self.nested(|g| {
+            let statements: Vec<String> = (0..g.rng.below(4)).map(|_| g.statement(false)).collect();
+            format!("{{ {} }}", statements.join(" "))
+        })
+    }
+
+    fn loop_body(&mut self) -> String {
+        let in_loop = std::mem::replace(&mut self.in_loop, true);
+        let body = self.block();
+        self.in_loop = in_loop;
+        body
+    }
+
+    fn function_body(&mut self) -> String {
+        let in_loop = std::mem::replace(&mut self.in_loop, false);
+        let body = self.block();
+        self.in_loop = in_loop;
+        body
+    }
+
+    fn expressions(&mut self, max: usize) -> String {
+        let expressions: Vec<String> = (0..self.rng.below(max + 1))
+            .map(|_| self.expression())
+            .collect();
+        expressions.join(", ")
+    }
+
+    fn expression(&mut self) -> String {
+        if self.depth >= MAX_DEPTH || self.rng.chance(30) {
+            return self.literal();
+        }
+
+        self.nested(|g| match g.rng.below(14) {
+            0 => format!("{}{}", g.rng.pick(PREFIX_OPERATORS), g.expression()),
+            1 => format!(
+                "{} {} {}",
+                g.expression(),
+                g.rng.pick(INFIX_OPERATORS),
+                g.expression()
+            ),
+            2 => format!("({})", g.expression()),
+            3 => {
+                let condition = g.expression();
+                let consequence = g.block();
+                if g.rng.chance(50) {
+                    format!("if ({}) {} else {}", condition, consequence, g.block())
+                } else {
+                    format!("if ({}) {}", condition, consequence)
+                }
+            }
+            4 => format!(
+                "fn({}){} {}",
+                g.parameters(),
+                g.annotation(" -> "),
+                g.function_body()
+            ),
+            5 => format!("macro({}) {}", g.names(), g.function_body()),
+            6 => format!("{}({})", g.expression(), g.expressions(3)),
+            7 => format!("[{}]", g.expressions(3)),
+            8 => {
+                let pairs: Vec<String> = (0..g.rng.below(3))
+                    .map(|_| format!("{}: {}", g.expression(), g.expression()))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            9 => format!("{}[{}]", g.expression(), g.expression()),
+            10 => format!("{}.{}", g.expression(), g.name()),
+            // only identifiers and index expressions can be assigned to, and
+            // the parentheses keep the target from absorbing an operator
+            // to its left
+            11 => {
+                let target = if g.rng.chance(50) {
+                    g.name()
+                } else {
+                    format!("{}[{}]", g.name(), g.expression())
+                };
+                let operator = g.rng.pick(ASSIGN_OPERATORS);
+                format!("({} {} {})", target, operator, g.expression())
+            }
+            12 => {
+                let body = g.block();
+                match g.rng.below(3) {
+                    0 => format!("try {} catch ({}) {}", body, g.name(), g.block()),
+                    1 => format!("try {} finally {}", body, g.block()),
+                    _ => {
+                        let parameter = g.name();
+                        let catch = g.block();
+                        format!(
+                            "try {} catch ({}) {} finally {}",
+                            body,
+                            parameter,
+                            catch,
+                            g.block()
+                        )
+                    }
+                }
+            }
+            _ => g.literal(),
+        })
+    }
+
+    fn literal(&mut self) -> String {
+        match self.rng.below(5) {
+            0 => self.rng.below(1000).to_string(),
+            1 => (self.rng.next_u64() >> 1).to_string(),
+            2 => self.rng.pick(&["true", "false"]).to_string(),
+            3 => {
+                let chars: String = (0..self.rng.below(6))
+                    .map(|_| *self.rng.pick(STRING_CHARS))
+                    .collect();
+                format!("\"{}\"", chars)
+            }
+            _ => self.name(),
+        }
+    }
+}
+
+pub fn fuzz_lexer(input: &str) {
+    let mut l = Lexer::new(input);
+    // every token but EOF takes up at least one character
+    let max_tokens = input.chars().count() + 1;
+    let mut last_end = Position::default();
+
+    for _ in 0..max_tokens {
+        let tok = l.next_token();
+        assert!(
+            last_end <= tok.span.start && tok.span.start <= tok.span.end,
+            "token {:?} does not follow the previous one ending at {:?}",
+            tok,
+            last_end
+        );
+        if tok.token_type == TokenType::EOF {
+            return;
+        }
+        last_end = tok.span.end;
+    }
+    panic!("lexer did not reach EOF within {} tokens", max_tokens);
+}
+
+pub fn fuzz_parser(input: &str) {
+    let mut p = Parser::new(Lexer::new(input));
+    let program = p.parse_program();
+
+    if p.errors().is_empty() {
+        print_all(&program);
+        fuzz_roundtrip(input);
+    }
+}
+
+pub fn fuzz_roundtrip(input: &str) {
+    let program = parse_valid(input, "input");
+    let printed = parenthesize(&program);
+    let reparsed = parse_valid(&printed, "printed program");
+
+    assert_eq!(
+        sexp(&program),
+        sexp(&reparsed),
+        "printed program {:?} parses into a different tree",
+        printed
+    );
+}
+
+fn parse_valid(input: &str, what: &str) -> Program {
+    let mut p = Parser::new(Lexer::new(input));
+    let program = p.parse_program();
+    assert!(
+        p.errors().is_empty(),
+        "{} {:?} does not parse:\n\t{}",
+        what,
+        input,
+        p.errors().join("\n\t")
+    );
+    program
+}
+
+fn print_all(program: &Program) {
+    program.to_string();
+    parenthesize(program);
+    sexp(program);
+    encode_program(program).to_string();
+}
+
+/// An input that made a fuzz target panic.
+#[derive(Debug)]
+pub struct Failure {
+    pub target: &'static str,
+    pub input: String,
+    pub message: String,
+}
+
+fn check(target: &'static str, f: fn(&str), input: &str) -> Result<(), Failure> {
+    panic::catch_unwind(AssertUnwindSafe(|| f(input))).map_err(|payload| Failure {
+        target,
+        input: input.to_string(),
+        message: payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "panicked".to_string()),
+    })
+}
+
+/// Runs `iterations` rounds of every target on inputs derived from `seed`,
+/// stopping at the first failure.
+pub fn run(seed: u64, iterations: usize) -> Result<(), Failure> {
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..iterations {
+        let bytes = random_bytes(&mut rng);
+        let input = String::from_utf8_lossy(&bytes);
+        check("lexer", fuzz_lexer, &input)?;
+        check("parser", fuzz_parser, &input)?;
+
+        let program = ProgramGenerator::new(&mut rng).program();
+        check("roundtrip", fuzz_roundtrip, &program)?;
+
+        let mut bytes = program.into_bytes();
+        mutate(&mut rng, &mut bytes);
+        let input = String::from_utf8_lossy(&bytes);
+        check("lexer", fuzz_lexer, &input)?;
+        check("parser", fuzz_parser, &input)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+
+        let xs: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        let ys: Vec<u64> = (0..4).map(|_| b.next_u64()).collect();
+        let zs: Vec<u64> = (0..4).map(|_| c.next_u64()).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(xs, zs);
+        assert!((0..100).all(|_| a.below(3) < 3));
+    }
+
+    #[test]
+    fn test_generated_programs_parse() {
+        let mut rng = Rng::new(1);
+
+        for _ in 0..500 {
+            let program = ProgramGenerator::new(&mut rng).program();
+            parse_valid(&program, "generated program");
+        }
+    }
+
+    #[test]
+    fn test_former_crashes() {
+        let inputs = [
+            "é",
+            "\"é",
+            "let é = 1; é→",
+            "a @ b",
+            "1\0 + 2",
+            "x → y",
+            "_",
+            "\u{fffd}(",
+            &"(".repeat(10_000),
+            &"if (a) { ".repeat(10_000),
+            &"while (a) { ".repeat(10_000),
+        ];
+
+        for input in inputs {
+            if let Err(failure) =
+                check("lexer", fuzz_lexer, input).and_then(|_| check("parser", fuzz_parser, input))
+            {
+                panic!("{:?}", failure);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz() {
+        if let Err(failure) = run(0x5eed, 2_000) {
+            panic!(
+                "{} target failed: {}\ninput: {:?}",
+                failure.target, failure.message, failure.input
+            );
+        }
+    }
+}
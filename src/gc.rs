@@ -0,0 +1,248 @@
+//! A cycle collector for environments.
+//!
+//! Environments are shared through `Rc`, and a function holds on to the
+//! environment it was created in, which usually binds the function in turn:
+//! every call of `fn() { let f = fn() { 1 }; f() }` leaves behind a cycle
+//! that reference counting never frees. Arrays and hashes are values, so
+//! every cycle goes through an environment, and tracking environments is
+//! enough to find them all.
+//!
+//! A collection subtracts from the reference count of each environment the
+//! references other environments hold to it. What remains are references
+//! from elsewhere, such as the evaluator, the host or a module, and the
+//! environments reachable from those are live. The others are cleared,
+//! which breaks their cycles and frees them. Collections run once enough
+//! environments were created since the previous one, and when a program
+//! calls the builtin `gc()`.
+//!
+//! The heap is per thread, like the environments it tracks.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::environment::Environment;
+use crate::object::{HashPair, Object};
+
+/// The environments created since the last collection that trigger the
+/// next one, at least. It doubles with the environments still alive.
+const MIN_THRESHOLD: usize = 10_000;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+#[derive(Default)]
+struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    since_collection: usize,
+    threshold: usize,
+    stats: Stats,
+}
+
+/// Counters of the collector on this thread.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Environments created so far.
+    pub allocated: usize,
+    /// Environments freed by collections.
+    pub collected: usize,
+    /// Collections run so far.
+    pub collections: usize,
+    /// Environments alive now.
+    pub live: usize,
+}
+
+impl Stats {
+    /// The counters as a Monkey hash, as `gc()` returns them.
+    pub fn to_object(self) -> Object {
+        let counters = [
+            ("allocated", self.allocated),
+            ("collected", self.collected),
+            ("collections", self.collections),
+            ("live", self.live),
+        ];
+        let pairs = counters.into_iter().map(|(name, count)| {
+            let key = Object::String(name.to_string());
+            let pair = HashPair {
+                key: key.clone(),
+                value: Object::Integer(count as i64),
+            };
+            (key.hash_key().unwrap(), pair)
+        });
+        Object::Hash(pairs.collect())
+    }
+}
+
+/// Starts tracking a new environment, collecting first if it is due.
+pub(crate) fn track(env: &Rc<RefCell<Environment>>) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(env));
+        heap.stats.allocated += 1;
+        heap.since_collection += 1;
+        heap.since_collection >= heap.threshold.max(MIN_THRESHOLD)
+    });
+    if due {
+        collect();
+    }
+}
+
+/// The counters of the collector on this thread.
+pub fn stats() -> Stats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        Stats {
+            live: heap
+                .environments
+                .iter()
+                .filter(|env| env.strong_count() > 0)
+                .count(),
+            ..heap.stats
+        }
+    })
+}
+
+/// Frees the environments only kept alive by cycles, returning how many
+/// there were.
+pub fn collect() -> usize {
+    let environments: Vec<Rc<RefCell<Environment>>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.retain(|env| env.strong_count() > 0);
+        heap.environments.iter().filter_map(Weak::upgrade).collect()
+    });
+    let index: HashMap<*const RefCell<Environment>, usize> = environments
+        .iter()
+        .enumerate()
+        .map(|(i, env)| (Rc::as_ptr(env), i))
+        .collect();
+
+    // the references from outside the tracked environments, leaving out the
+    // one in `environments`
+    let mut external: Vec<usize> = environments
+        .iter()
+        .map(|env| Rc::strong_count(env) - 1)
+        .collect();
+    let mut edges: Vec<Vec<usize>> = vec![Vec::new(); environments.len()];
+    for (i, env) in environments.iter().enumerate() {
+        match env.try_borrow() {
+            Ok(env) => env.for_each_reference(&mut |target| {
+                if let Some(&j) = index.get(&Rc::as_ptr(target)) {
+                    edges[i].push(j);
+                    external[j] -= 1;
+                }
+            }),
+            // one being changed is in use
+            Err(_) => external[i] = usize::MAX,
+        }
+    }
+
+    let mut live = vec![false; environments.len()];
+    let mut pending: Vec<usize> = (0..environments.len())
+        .filter(|&i| external[i] > 0)
+        .collect();
+    while let Some(i) = pending.pop() {
+        if !live[i] {
+            live[i] = true;
+            pending.extend(&edges[i]);
+        }
+    }
+
+    // the bindings are dropped once every cycle is broken
+    let mut cleared = Vec::new();
+    for (env, _) in environments.iter().zip(&live).filter(|(_, live)| !**live) {
+        cleared.push(env.borrow_mut().clear());
+    }
+    let collected = cleared.len();
+    let remaining = environments.len() - collected;
+    drop(cleared);
+    drop(environments);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.stats.collections += 1;
+        heap.stats.collected += collected;
+        heap.since_collection = 0;
+        heap.threshold = remaining * 2;
+    });
+    collected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+
+    use crate::parser::parse_ok;
+
+    fn eval_in(input: &str, env: &Rc<RefCell<Environment>>) -> Object {
+        Evaluator::new().eval_program(&parse_ok(input), env)
+    }
+
+    fn eval(input: &str) -> Object {
+        eval_in(input, &Environment::new())
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        // each call leaves the environment of `f` and the closure `g` bound
+        // in it referring to each other
+        let input = "
+let f = fn(x) { let g = fn() { x }; g() };
+let i = 0;
+while (i < 1000) { f(i); i += 1; }
+";
+        let env = Environment::new();
+        eval_in(input, &env);
+        let before = stats();
+        assert_eq!(collect(), 1000);
+        let after = stats();
+        assert_eq!(after.collected - before.collected, 1000);
+        assert_eq!(after.collections, before.collections + 1);
+        assert_eq!(after.live, before.live - 1000);
+    }
+
+    #[test]
+    fn test_keep_reachable_environments() {
+        let input = "
+let counter = fn() { let n = 0; let inc = fn() { n += 1; n }; inc };
+let c = counter();
+let cs = [counter(), counter()];
+gc();
+c(); c();
+[c(), cs[1](), gc()[\"collected\"] >= 0]
+";
+        assert_eq!(eval(input).inspect(), "[3, 1, true]");
+    }
+
+    #[test]
+    fn test_bounded_memory() {
+        // far more cycles than the threshold, so collections run on their
+        // own while the loop goes on
+        let input = "
+let make = fn(x) { let cycle = fn() { cycle }; cycle };
+let i = 0;
+while (i < 50000) { make(i); i += 1; }
+let stats = gc();
+[stats[\"allocated\"] > 50000, stats[\"collections\"] > 3, stats[\"live\"] < 10]
+";
+        assert_eq!(eval(input).inspect(), "[true, true, true]");
+    }
+
+    #[test]
+    fn test_gc_builtin() {
+        let input = "
+let f = fn() { let g = fn() { g }; g };
+f(); f();
+let stats = gc();
+[stats[\"collected\"], stats[\"live\"], len(stats)]
+";
+        // the closure the second call returned is still held as the value
+        // of the statement before
+        assert_eq!(eval(input).inspect(), "[1, 2, 4]");
+        assert_eq!(
+            eval("gc(1)").inspect(),
+            "ERROR: wrong number of arguments. got=1, want=0"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Golden tests over the programs in `testdata/golden`.
+//!
+//! Every `<name>.monkey` directly inside the corpus directory is paired with
+//! three snapshots next to it:
+//!
+//! - `<name>.tokens`, the tokens of the file, one per line,
+//! - `<name>.ast`, the program as an S-expression, or the parser errors,
+//! - `<name>.out`, what the program prints followed by `=> <result>`.
+//!
+//! Files in subdirectories are only there to be imported. After an intended
+//! change, regenerate the snapshots with `BLESS=1 cargo test golden` and
+//! review the diff. Every program must also give the same `.out` at each
+//! optimization level.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::builtins::capture_output;
+use crate::environment::Environment;
+use crate::evaluator::Evaluator;
+use crate::lexer::Lexer;
+use crate::object::Object;
+use crate::optimizer::MAX_OPT_LEVEL;
+use crate::parser::Parser;
+use crate::printer::sexp;
+use crate::token::TokenType;
+
+/// Renders one snapshot of the source file at the given path.
+type Render = fn(&Path, &str) -> String;
+
+const SNAPSHOTS: &[(&str, Render)] = &[
+    ("tokens", render_tokens),
+    ("ast", render_ast),
+    ("out", render_output),
+];
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden")
+}
+
+fn render_tokens(_path: &Path, source: &str) -> String {
+    let mut l = Lexer::new(source);
+    let mut out = String::new();
+
+    loop {
+        let tok = l.next_token();
+        out.push_str(&format!("{}\n", tok));
+        if tok.token_type == TokenType::EOF {
+            return out;
+        }
+    }
+}
+
+fn render_ast(_path: &Path, source: &str) -> String {
+    let mut p = Parser::new(Lexer::new(source));
+    let program = p.parse_program();
+
+    if p.errors().is_empty() {
+        format!("{}\n", sexp(&program))
+    } else {
+        format!("parser errors:\n\t{}\n", p.errors().join("\n\t"))
+    }
+}
+
+fn render_output(path: &Path, _source: &str) -> String {
+    run(path, 0)
+}
+
+fn run(path: &Path, opt_level: u8) -> String {
+    let (result, mut out) = capture_output(|| {
+        Evaluator::with_opt_level(opt_level).eval_file(path, &Environment::new())
+    });
+
+    match result {
+        Object::Error(msg) => out.push_str(&format!("ERROR: {}\n", msg)),
+        obj => out.push_str(&format!("=> {}\n", obj.inspect())),
+    }
+    out
+}
+
+fn sources() -> Vec<PathBuf> {
+    let mut sources: Vec<PathBuf> = fs::read_dir(corpus_dir())
+        .expect("could not read the golden corpus")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "monkey"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "the golden corpus is empty");
+    sources
+}
+
+/// Describes the first line where `actual` differs from `expected`.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => continue,
+            (e, a) => {
+                return format!(
+                    "line {}:\n\texpected: {}\n\tactual:   {}",
+                    line,
+                    e.unwrap_or("<end of file>"),
+                    a.unwrap_or("<end of file>")
+                )
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn test_golden_corpus() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let sources = sources();
+    let mut failures = Vec::new();
+
+    for source_path in &sources {
+        let source = fs::read_to_string(source_path).unwrap();
+
+        for (extension, render) in SNAPSHOTS {
+            let snapshot_path = source_path.with_extension(extension);
+            let actual = render(source_path, &source);
+
+            if bless {
+                if fs::read_to_string(&snapshot_path).ok().as_ref() != Some(&actual) {
+                    fs::write(&snapshot_path, &actual).unwrap();
+                }
+                continue;
+            }
+
+            match fs::read_to_string(&snapshot_path) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => failures.push(format!(
+                    "{} does not match, first difference at {}",
+                    snapshot_path.display(),
+                    first_difference(&expected, &actual)
+                )),
+                Err(_) => failures.push(format!("{} is missing", snapshot_path.display())),
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} golden snapshot(s) out of date, rerun with BLESS=1 to update them if the \
+         change is intended:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
+
+#[test]
+fn test_optimized_corpus() {
+    let mut failures = Vec::new();
+
+    for source_path in sources() {
+        let expected = run(&source_path, 0);
+
+        for opt_level in 1..=MAX_OPT_LEVEL {
+            let actual = run(&source_path, opt_level);
+            if actual != expected {
+                failures.push(format!(
+                    "{} at --opt-level {} differs at {}",
+                    source_path.display(),
+                    opt_level,
+                    first_difference(&expected, &actual)
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "optimizing changed the output of {} program(s):\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,403 @@
+//! The interface for host applications that script their behavior with
+//! Monkey.
+//!
+//! An `Interpreter` keeps its globals and macros from one `eval` to the
+//! next, the way the REPL does. Values cross between Rust and Monkey through
+//! the conversions of the `convert` module:
+//!
+//! ```
+//! use rust_monkey::Interpreter;
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.register("greet", |name: String| format!("hello, {}", name));
+//! interpreter.set_global("limit", 3);
+//!
+//! interpreter
+//!     .eval::<()>("let twice = fn(x) { [greet(x), greet(x)] };")
+//!     .unwrap();
+//!
+//! let greetings: Vec<String> = interpreter.call_function("twice", ("you",)).unwrap();
+//! assert_eq!(greetings, ["hello, you", "hello, you"]);
+//! assert_eq!(interpreter.eval::<i64>("limit * 2").unwrap(), 6);
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::convert::*;
+use crate::environment::*;
+use crate::evaluator::*;
+use crate::lexer::*;
+use crate::limits::*;
+use crate::macro_expansion::*;
+use crate::object::*;
+use crate::parser::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The source did not parse, with the parser errors.
+    Parse(Vec<String>),
+    /// Evaluation stopped with an error.
+    Runtime(String),
+    LimitExceeded(LimitExceeded),
+    /// No global has the name.
+    Undefined(String),
+    /// A value had another type than the Rust one asked for.
+    Conversion {
+        expected: String,
+        got: &'static str,
+    },
+    /// An integer did not fit the Rust type asked for, with its value.
+    OutOfRange(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(errors) => write!(f, "could not parse input:\n\t{}", errors.join("\n\t")),
+            Error::Runtime(msg) => write!(f, "{}", msg),
+            Error::LimitExceeded(limit) => write!(f, "{}", limit),
+            Error::Undefined(name) => write!(f, "identifier not found: {}", name),
+            Error::Conversion { expected, got } => write!(f, "expected {}, got {}", expected, got),
+            Error::OutOfRange(value) => write!(f, "integer out of range: {}", value),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Interpreter {
+    evaluator: Evaluator,
+    env: Rc<RefCell<Environment>>,
+    macro_env: Rc<RefCell<Environment>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter with the default `Limits`, whose evaluations
+    /// fit the stack of a thread spawned by Rust's standard library.
+    pub fn new() -> Interpreter {
+        Interpreter {
+            evaluator: Evaluator::new(),
+            env: Environment::new(),
+            macro_env: Environment::new(),
+        }
+    }
+
+    /// Sets the limits of each later `eval` and `call_function`. Hosts
+    /// raising `max_stack` above `limits::EMBEDDED_MAX_STACK` must run
+    /// scripts on a thread with a stack to match, see `Limits::for_stack`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.evaluator.set_limits(limits);
+    }
+
+    pub fn limits(&self) -> &Limits {
+        self.evaluator.limits()
+    }
+
+    /// Evaluates `source` and converts its value.
+    pub fn eval<T: FromObject>(&mut self, source: &str) -> Result<T, Error> {
+        let mut parser = Parser::new(Lexer::new(source));
+        let mut program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            return Err(Error::Parse(parser.errors().clone()));
+        }
+
+        define_macros(&mut program, &self.macro_env).map_err(Error::Runtime)?;
+        let program = self
+            .evaluator
+            .expand_macros(program, &self.macro_env)
+            .map_err(Error::Runtime)?;
+
+        convert(self.evaluator.eval_program(&program, &self.env))
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl IntoObject) {
+        self.env
+            .borrow_mut()
+            .set(name.to_string(), value.into_object());
+    }
+
+    pub fn get_global<T: FromObject>(&self, name: &str) -> Result<T, Error> {
+        let value = self.env.borrow().get(name);
+        convert(value.ok_or_else(|| Error::Undefined(name.to_string()))?)
+    }
+
+    /// Calls the global function `name` with `args`, a tuple of values
+    /// converted into objects.
+    pub fn call_function<T: FromObject>(
+        &mut self,
+        name: &str,
+        args: impl IntoArgs,
+    ) -> Result<T, Error> {
+        let function = self.get_global::<Object>(name)?;
+        convert(self.evaluator.call_function(function, args.into_args()))
+    }
+
+    /// Makes the closure `f` a global function named `name`, converting the
+    /// arguments of each call into its parameter types.
+    pub fn register<Args>(&mut self, name: &str, f: impl HostFunction<Args>) {
+        let registered = name.to_string();
+        self.register_variadic(name, move |args| f.call(&registered, args));
+    }
+
+    /// Makes `f` a global function named `name` that takes any number of
+    /// arguments as they are.
+    pub fn register_variadic(&mut self, name: &str, f: impl Fn(Vec<Object>) -> Object + 'static) {
+        let builtin = Builtin {
+            name: name.to_string(),
+            func: Rc::new(f),
+            cost: None,
+        };
+        self.set_global(name, Object::Builtin(builtin));
+    }
+}
+
+fn convert<T: FromObject>(obj: Object) -> Result<T, Error> {
+    match obj {
+        Object::Error(msg) => Err(Error::Runtime(msg)),
+        Object::LimitExceeded(limit) => Err(Error::LimitExceeded(limit)),
+        obj if T::out_of_range(&obj) => Err(Error::OutOfRange(obj.inspect())),
+        obj => {
+            let got = obj.object_type();
+            T::from_object(obj).ok_or_else(|| Error::Conversion {
+                expected: T::expected(),
+                got,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::capture_output;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_eval_keeps_globals() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter.eval::<()>("let x = 20;").unwrap();
+        assert_eq!(interpreter.eval::<i64>("x + 22"), Ok(42));
+        assert_eq!(
+            interpreter.eval::<String>(r#""a" + "b""#),
+            Ok("ab".to_string())
+        );
+        assert_eq!(interpreter.get_global::<i64>("x"), Ok(20));
+
+        let mac = "let unless = macro(c, a) { quote(if (!(unquote(c))) { unquote(a) }) };";
+        interpreter.eval::<()>(mac).unwrap();
+        assert_eq!(interpreter.eval::<i64>("unless(false, 7)"), Ok(7));
+    }
+
+    #[test]
+    fn test_globals_and_calls() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("names", vec!["ann", "bob"]);
+        interpreter.set_global("ages", HashMap::from([("ann".to_string(), 31)]));
+        interpreter
+            .eval::<()>("let age = fn(name) { ages[name] }; let count = fn() { len(names) };")
+            .unwrap();
+
+        assert_eq!(interpreter.call_function::<i64>("count", ()), Ok(2));
+        assert_eq!(
+            interpreter.call_function::<Option<i64>>("age", ("ann",)),
+            Ok(Some(31))
+        );
+        assert_eq!(
+            interpreter.call_function::<Option<i64>>("age", ("bob",)),
+            Ok(None)
+        );
+        assert_eq!(
+            interpreter.get_global::<Vec<String>>("names"),
+            Ok(vec!["ann".to_string(), "bob".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let mut interpreter = Interpreter::new();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+
+        let log = Rc::clone(&calls);
+        interpreter.register("record", move |event: String, n: i64| {
+            log.borrow_mut().push(format!("{}:{}", event, n));
+        });
+        interpreter.register("divide", |a: i64, b: i64| match b {
+            0 => Err("cannot divide by zero"),
+            b => Ok(a / b),
+        });
+        interpreter.register_variadic("count", |args| Object::Integer(args.len() as i64));
+
+        let result = interpreter.eval::<i64>(
+            r#"record("start", 1); let q = divide(10, 2); record("end", q); count(q, q, q)"#,
+        );
+        assert_eq!(result, Ok(3));
+        assert_eq!(*calls.borrow(), vec!["start:1", "end:5"]);
+
+        // registered functions are values like any other
+        let result = interpreter.eval::<Vec<i64>>("let apply = fn(f) { f(9, 3) }; [apply(divide)]");
+        assert_eq!(result, Ok(vec![3]));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("divide", |a: i64, b: i64| match b {
+            0 => Err("cannot divide by zero"),
+            b => Ok(a / b),
+        });
+        interpreter.set_limits(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+
+        let tests: [(&str, Error); 7] = [
+            (
+                "let = 1;",
+                Error::Parse(vec![
+                    "expected next token to be IDENT, got ASSIGN instead".to_string(),
+                    "no prefix parse function for ASSIGN found".to_string(),
+                ]),
+            ),
+            (
+                "divide(1, 0)",
+                Error::Runtime("cannot divide by zero".to_string()),
+            ),
+            (
+                r#"divide(1, "0")"#,
+                Error::Runtime("argument 2 to `divide` must be INTEGER, got STRING".to_string()),
+            ),
+            (
+                "undefined",
+                Error::Runtime("identifier not found: undefined".to_string()),
+            ),
+            (
+                "while (true) { }",
+                Error::LimitExceeded(LimitExceeded::Fuel(1000)),
+            ),
+            (
+                r#""not a number""#,
+                Error::Conversion {
+                    expected: "INTEGER".to_string(),
+                    got: "STRING",
+                },
+            ),
+            (
+                "9223372036854775807 + 1",
+                Error::OutOfRange("9223372036854775808".to_string()),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                interpreter.eval::<i64>(input),
+                Err(expected),
+                "input: {}",
+                input
+            );
+        }
+
+        assert_eq!(
+            interpreter.get_global::<i64>("missing"),
+            Err(Error::Undefined("missing".to_string()))
+        );
+        assert_eq!(
+            interpreter
+                .call_function::<Object>("divide", (1,))
+                .unwrap_err(),
+            Error::Runtime("wrong number of arguments. got=1, want=2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_default_limits_fit_a_spawned_thread() {
+        // other limits set over the default keep its stack
+        let run = || {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_limits(Limits {
+                fuel: Some(1_000_000),
+                ..Limits::default()
+            });
+            let ordinary = (
+                interpreter.eval::<bool>(
+                    "let factorial = fn(n) { if (n < 2) { 1 } else { n * factorial(n - 1) } };
+                     factorial(100) > 0",
+                ),
+                interpreter.eval::<i64>(&format!(
+                    "let sum = fn(xs) {{ if (len(xs) == 0) {{ 0 }} else {{ first(xs) + sum(rest(xs)) }} }};
+                     sum([{}])",
+                    vec!["1"; 60].join(", ")
+                )),
+            );
+            let nested = format!("{}g(n - 1){}", "[".repeat(20), "]".repeat(20));
+            let runaway = (
+                interpreter.eval::<i64>(
+                    "let g = fn(n) { if (n == 0) { 0 } else { 1 + g(n - 1) } }; g(9000)",
+                ),
+                interpreter.eval::<i64>(&format!(
+                    "let g = fn(n) {{ if (n == 0) {{ 0 }} else {{ 1 + len({}) }} }}; g(9000)",
+                    nested
+                )),
+            );
+            (ordinary, runaway)
+        };
+        // the size of the stack `std::thread::spawn` gives
+        let ((factorial, sum), (plain, nested)) = std::thread::Builder::new()
+            .stack_size(2 << 20)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(factorial, Ok(true));
+        assert_eq!(sum, Ok(60));
+        let stack = Err(Error::LimitExceeded(LimitExceeded::Stack(
+            EMBEDDED_MAX_STACK,
+        )));
+        assert_eq!(plain, stack);
+        assert_eq!(nested, stack);
+    }
+
+    #[test]
+    fn test_long_chains_fit_a_spawned_thread() {
+        let run = || {
+            let mut interpreter = Interpreter::new();
+            let chain = |terms: usize| vec!["1"; terms].join(" + ");
+            (
+                interpreter.eval::<i64>(&chain(300)),
+                interpreter.eval::<i64>(&chain(MAX_TREE_DEPTH - 1)),
+            )
+        };
+        let (long, longest) = std::thread::Builder::new()
+            .stack_size(EMBEDDED_STACK_SIZE)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(long, Ok(300));
+        assert_eq!(
+            longest,
+            Err(Error::LimitExceeded(LimitExceeded::Stack(
+                EMBEDDED_MAX_STACK
+            )))
+        );
+    }
+
+    #[test]
+    fn test_output_of_scripts() {
+        let mut interpreter = Interpreter::new();
+        let (result, output) = capture_output(|| interpreter.eval::<()>(r#"puts("hi", 1);"#));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "hi\n1\n");
+    }
+}
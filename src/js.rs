@@ -0,0 +1,1434 @@
+//! A backend compiling Monkey programs to JavaScript, run by
+//! `compile --target js`.
+//!
+//! The output is ES2020 code, a module when the program exports bindings,
+//! that starts with the runtime `$`: the builtins, and the operators, which
+//! check their operands as the evaluator does.
+//! Integers are `BigInt`s, arrays are arrays, hashes are `Map`s and `null`
+//! is `null`. Arrays and hashes are never changed in place, so assigning to an
+//! element copies the container as in the evaluator. Functions become arrow
+//! functions, and an `if` used as a value becomes a conditional expression,
+//! or a function called on the spot when its branches hold statements.
+//!
+//! The code behaves like the evaluator, and throws the runtime errors it
+//! reports as `Error`s with the same messages, except that calls do not
+//! check their number of arguments, functions print as their JavaScript
+//! source, and a variable read before its `let` has run is `null` or
+//! throws a `ReferenceError`. Integers grow beyond 64 bits as they do in
+//! the evaluator.
+//!
+//! `Output::source_map` maps the code back to the program, in the Source
+//! Map v3 format.
+
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::backend::{element, Declarations};
+use crate::builtins::lookup_builtin;
+use crate::json::Json;
+use crate::token::{Position, Span};
+use crate::visitor::Visitor;
+use crate::wat::CompileError;
+
+/// The runtime every compiled program starts with.
+pub const PRELUDE: &str = r#"// the runtime of a program compiled from Monkey
+const $ = {
+  // the value of the last element assignment used as a value
+  v: null,
+  fail(message) {
+    throw new Error(message);
+  },
+  type(x) {
+    if (x == null) return "NULL";
+    if (Array.isArray(x)) return "ARRAY";
+    if (x instanceof Map) return "HASH";
+    const types = { bigint: "INTEGER", boolean: "BOOLEAN", string: "STRING" };
+    return types[typeof x] ?? "FUNCTION";
+  },
+  truthy: (x) => x !== false && x != null,
+  call(f, ...args) {
+    if (typeof f !== "function") $.fail(`not a function: ${$.type(f)}`);
+    return f(...args);
+  },
+  // fails as the evaluator does unless `op` applies to `a` and `b`
+  check(a, op, b) {
+    const [l, r] = [$.type(a), $.type(b)];
+    if (l !== r) $.fail(`type mismatch: ${l} ${op} ${r}`);
+    const takes = { INTEGER: true, STRING: ["+", "==", "!="], BOOLEAN: ["==", "!="] }[l];
+    if (takes !== true && !takes?.includes(op)) $.fail(`unknown operator: ${l} ${op} ${r}`);
+  },
+  neg(x) {
+    if ($.type(x) !== "INTEGER") $.fail(`unknown operator: -${$.type(x)}`);
+    return -x;
+  },
+  add: (a, b) => ($.check(a, "+", b), a + b),
+  sub: (a, b) => ($.check(a, "-", b), a - b),
+  mul: (a, b) => ($.check(a, "*", b), a * b),
+  div(a, b) {
+    $.check(a, "/", b);
+    if (b === 0n) $.fail("division by zero");
+    return a / b;
+  },
+  rem(a, b) {
+    $.check(a, "%", b);
+    if (b === 0n) $.fail("division by zero");
+    return a % b;
+  },
+  // the bitwise operators stay those of 64-bit integers, as in the evaluator
+  bits(a, op, b) {
+    $.check(a, op, b);
+    const large = [a, b].find((n) => BigInt.asIntN(64, n) !== n);
+    if (large !== undefined) $.fail(`integer too large for ${op}: ${large}`);
+  },
+  and: (a, b) => ($.bits(a, "&", b), a & b),
+  or: (a, b) => ($.bits(a, "|", b), a | b),
+  xor: (a, b) => ($.bits(a, "^", b), a ^ b),
+  shl: (a, b) => ($.check(a, "<<", b), a << $.shift(b)),
+  shr: (a, b) => ($.check(a, ">>", b), a >> $.shift(b)),
+  lt: (a, b) => ($.check(a, "<", b), a < b),
+  gt: (a, b) => ($.check(a, ">", b), a > b),
+  le: (a, b) => ($.check(a, "<=", b), a <= b),
+  ge: (a, b) => ($.check(a, ">=", b), a >= b),
+  eq: (a, b) => ($.check(a, "==", b), a === b),
+  ne: (a, b) => ($.check(a, "!=", b), a !== b),
+  shift(b) {
+    if (b < 0n || b > 63n) $.fail(`shift amount out of range: ${b}`);
+    return b;
+  },
+  key(k) {
+    if (!["bigint", "boolean", "string"].includes(typeof k)) {
+      $.fail(`unusable as hash key: ${$.type(k)}`);
+    }
+    return k;
+  },
+  // integers, then booleans, then strings, as the evaluator orders them
+  keys(hash) {
+    const rank = (k) => ["bigint", "boolean", "string"].indexOf(typeof k);
+    const compare = (a, b) => rank(a) - rank(b) || (a < b ? -1 : a > b ? 1 : 0);
+    return [...hash.keys()].sort(compare);
+  },
+  index(x, i) {
+    if (Array.isArray(x) && typeof i === "bigint") return x[i] ?? null;
+    if (x instanceof Map) return x.get($.key(i)) ?? null;
+    $.fail(`index operator not supported: ${$.type(x)}`);
+  },
+  set(x, [i, ...path], value) {
+    if (path.length > 0) value = $.set($.index(x, i), path, value);
+    if (Array.isArray(x) && typeof i === "bigint") {
+      if (i < 0 || i >= x.length) $.fail(`index out of range: ${i}`);
+      const copy = [...x];
+      copy[i] = value;
+      return copy;
+    }
+    if (x instanceof Map) return new Map(x).set($.key(i), value);
+    $.fail(`index assignment not supported: ${$.type(x)}`);
+  },
+  update: (x, path, f) => $.set(x, path, f(path.reduce((y, i) => $.index(y, i), x))),
+  iter(x) {
+    if (Array.isArray(x)) return x;
+    if (typeof x === "string") return Array.from(x);
+    if (x instanceof Map) return $.keys(x);
+    $.fail(`cannot iterate over ${$.type(x)}`);
+  },
+  inspect(x) {
+    if (x == null) return "null";
+    if (Array.isArray(x)) return `[${x.map((e) => $.inspect(e)).join(", ")}]`;
+    if (x instanceof Map) {
+      const pairs = $.keys(x).map((k) => `${$.inspect(k)}: ${$.inspect(x.get(k))}`);
+      return `{${pairs.join(", ")}}`;
+    }
+    return String(x);
+  },
+  array(xs, name) {
+    if (!Array.isArray(xs)) $.fail(`argument to \`${name}\` must be ARRAY, got ${$.type(xs)}`);
+    return xs;
+  },
+  len(x) {
+    if (typeof x === "string") return BigInt(Array.from(x).length);
+    if (Array.isArray(x)) return BigInt(x.length);
+    if (x instanceof Map) return BigInt(x.size);
+    $.fail(`argument to \`len\` not supported, got ${$.type(x)}`);
+  },
+  first: (xs) => $.array(xs, "first")[0] ?? null,
+  last: (xs) => $.array(xs, "last")[xs.length - 1] ?? null,
+  rest: (xs) => ($.array(xs, "rest").length > 0 ? xs.slice(1) : null),
+  push: (xs, x) => [...$.array(xs, "push"), x],
+  puts(...xs) {
+    for (const x of xs) console.log($.inspect(x));
+    return null;
+  },
+  // the engine collects garbage on its own and does not tell
+  gc: () => new Map(["allocated", "collected", "collections", "live"].map((k) => [k, 0n])),
+  pow(x, y) {
+    if (y < 0n) $.fail(`negative exponent: ${y}`);
+    if (y > 0xffffffffn) $.fail(`exponent too large: ${y}`);
+    return x ** y;
+  },
+};
+"#;
+
+// names JavaScript reserves or gives a meaning that Monkey does not
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "case",
+    "catch",
+    "class",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "instanceof",
+    "interface",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "with",
+    "yield",
+    "Infinity",
+    "NaN",
+];
+
+// the precedences of the JavaScript operators in use
+const ASSIGNMENT: u8 = 2;
+const CONDITIONAL: u8 = 3;
+const OR: u8 = 4;
+const AND: u8 = 5;
+const PREFIX: u8 = 14;
+const CALL: u8 = 17;
+const PRIMARY: u8 = 18;
+
+/// A position in the generated code and the one in the program it was
+/// compiled from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub generated: Position,
+    pub original: Position,
+}
+
+/// The JavaScript compiled from a program, with the mappings of its
+/// statements and expressions.
+#[derive(Debug)]
+pub struct Output {
+    pub code: String,
+    pub mappings: Vec<Mapping>,
+}
+
+impl Output {
+    /// The source map of the code, for the code stored at `file` and the
+    /// program at `source`, both relative to the map.
+    pub fn source_map(&self, file: &str, source: &str) -> String {
+        let mut mappings = String::new();
+        let mut line = 1;
+        let mut first = true;
+        // every field is relative to the one of the previous segment, the
+        // column in the code to the previous segment on the same line
+        let (mut column, mut original_line, mut original_column) = (0, 0, 0);
+
+        for mapping in &self.mappings {
+            while line < mapping.generated.line {
+                mappings.push(';');
+                line += 1;
+                column = 0;
+                first = true;
+            }
+            if !first {
+                mappings.push(',');
+            }
+            first = false;
+
+            let segment = [
+                mapping.generated.column as i64 - 1 - column,
+                0,
+                mapping.original.line as i64 - 1 - original_line,
+                mapping.original.column as i64 - 1 - original_column,
+            ];
+            for field in segment {
+                encode_vlq(&mut mappings, field);
+            }
+            column = mapping.generated.column as i64 - 1;
+            original_line = mapping.original.line as i64 - 1;
+            original_column = mapping.original.column as i64 - 1;
+        }
+
+        Json::Object(vec![
+            ("version".to_string(), Json::Number(3)),
+            ("file".to_string(), Json::String(file.to_string())),
+            (
+                "sources".to_string(),
+                Json::Array(vec![Json::String(source.to_string())]),
+            ),
+            ("names".to_string(), Json::Array(Vec::new())),
+            ("mappings".to_string(), Json::String(mappings)),
+        ])
+        .to_string()
+    }
+}
+
+/// Appends `value` in the base 64 VLQ encoding of source maps.
+fn encode_vlq(out: &mut String, value: i64) {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // the sign goes into the lowest bit
+    let mut rest = (value.unsigned_abs() << 1) | (value < 0) as u64;
+    loop {
+        let mut digit = rest & 31;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 32;
+        }
+        out.push(DIGITS[digit as usize] as char);
+        if rest == 0 {
+            return;
+        }
+    }
+}
+
+/// Compiles `program` to JavaScript, or returns every error found.
+pub fn compile(program: &Program) -> Result<Output, Vec<CompileError>> {
+    let mut compiler = Compiler::default();
+    compiler.program(program);
+    if !compiler.errors.is_empty() {
+        compiler.errors.sort_by_key(|error| error.span.start);
+        return Err(compiler.errors);
+    }
+
+    Ok(Output {
+        code: compiler.writer.code,
+        mappings: compiler.writer.mappings,
+    })
+}
+
+fn js_name(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Code being generated, with the position it has reached.
+struct Writer {
+    code: String,
+    position: Position,
+    indent: usize,
+    mappings: Vec<Mapping>,
+}
+
+impl Default for Writer {
+    fn default() -> Writer {
+        Writer {
+            code: String::new(),
+            position: Position { line: 1, column: 1 },
+            indent: 0,
+            mappings: Vec::new(),
+        }
+    }
+}
+
+impl Writer {
+    // columns count UTF-16 code units, as source maps do
+    fn write(&mut self, text: &str) {
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else {
+                self.position.column += ch.len_utf16();
+            }
+        }
+        self.code.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.write("\n");
+        self.write(&"  ".repeat(self.indent));
+    }
+
+    /// Maps the code written next to the start of `span`.
+    fn mark(&mut self, span: Span) {
+        // code from the expansion of a macro may have no position
+        if span.start.line == 0 {
+            return;
+        }
+        if let Some(last) = self.mappings.last() {
+            if last.generated == self.position {
+                return;
+            }
+        }
+        self.mappings.push(Mapping {
+            generated: self.position,
+            original: span.start,
+        });
+    }
+}
+
+/// The names bound in a function or the program.
+#[derive(Default)]
+struct Scope {
+    names: HashSet<String>,
+    // the names declared by the only `let` binding them, a statement of the
+    // body itself; the others are declared at the start of the body
+    declared_in_place: HashSet<String>,
+}
+
+/// What the code being compiled is part of.
+#[derive(Default, Clone, Copy, PartialEq)]
+enum Context {
+    #[default]
+    Program,
+    Function,
+    // a function called on the spot for an `if` used as a value, which
+    // `return`, `break` and `continue` cannot leave
+    Expression,
+}
+
+/// Where the value of a block goes.
+enum Tail {
+    Discard,
+    Return,
+    Assign(String),
+}
+
+#[derive(Default)]
+struct Compiler {
+    writer: Writer,
+    // the scopes of the program and of the functions being compiled,
+    // innermost last
+    scopes: Vec<Scope>,
+    context: Context,
+    // the loops around the code being compiled within its context
+    loops: usize,
+    errors: Vec<CompileError>,
+}
+
+impl Compiler {
+    fn write(&mut self, text: &str) {
+        self.writer.write(text);
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str) {
+        self.errors.push(CompileError {
+            span,
+            message: format!("{} not supported by the js target", what),
+        });
+    }
+
+    fn declared(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.names.contains(name))
+    }
+
+    fn is_builtin(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Identifier(id) => {
+                !self.declared(&id.value) && lookup_builtin(&id.value).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes a call of `$.fail` with `message`, after evaluating
+    /// `operands`, as the evaluator does before it finds the error.
+    fn fail(&mut self, message: &str, operands: &[&Expression]) {
+        self.write("$.fail(");
+        self.write(&Json::String(message.to_string()).to_string());
+        for operand in operands {
+            self.write(", ");
+            self.expression(operand, ASSIGNMENT);
+        }
+        self.write(")");
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.write(PRELUDE);
+
+        let hoisted = self.enter(&[], &program.statements);
+        let exported: HashSet<&str> = program
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::LetStatement(ls) if ls.exported => Some(ls.name.value.as_str()),
+                _ => None,
+            })
+            .collect();
+        let (exports, others): (Vec<String>, Vec<String>) = hoisted
+            .into_iter()
+            .partition(|name| exported.contains(name.as_str()));
+        if !others.is_empty() {
+            self.writer.newline();
+            self.declare("let", &others);
+        }
+        if !exports.is_empty() {
+            self.writer.newline();
+            self.declare("export let", &exports);
+        }
+
+        self.statements(&program.statements, &Tail::Discard);
+        self.scopes.pop();
+        self.write("\n");
+    }
+
+    /// Enters the scope of a function with `parameters` and the body
+    /// `statements`, returning the names to declare at its start.
+    fn enter(&mut self, parameters: &[Identifier], statements: &[Statement]) -> Vec<String> {
+        let mut declarations = Declarations::default();
+        for statement in statements {
+            declarations.visit_statement(statement);
+        }
+
+        let mut scope = Scope::default();
+        scope
+            .names
+            .extend(parameters.iter().map(|p| p.value.clone()));
+        let mut hoisted = Vec::new();
+        for name in &declarations.names {
+            if scope.names.contains(name) {
+                continue;
+            }
+            let once = declarations.names.iter().filter(|n| *n == name).count() == 1;
+            let in_body = statements.iter().any(|statement| {
+                matches!(statement, Statement::LetStatement(ls) if ls.name.value == *name)
+            });
+            if once && in_body {
+                scope.declared_in_place.insert(name.clone());
+            } else {
+                hoisted.push(name.clone());
+            }
+            scope.names.insert(name.clone());
+        }
+
+        self.scopes.push(scope);
+        hoisted
+    }
+
+    fn declare(&mut self, keyword: &str, names: &[String]) {
+        let names: Vec<String> = names.iter().map(|name| js_name(name)).collect();
+        self.write(&format!("{} {};", keyword, names.join(", ")));
+    }
+
+    fn statements(&mut self, statements: &[Statement], tail: &Tail) {
+        for (i, statement) in statements.iter().enumerate() {
+            let last = i + 1 == statements.len();
+            self.writer.newline();
+            self.writer.mark(statement.span());
+            match statement {
+                Statement::ExpressionStatement(ExpressionStatement {
+                    expression: Some(expression),
+                    ..
+                }) if last => self.tail(expression, tail),
+                statement => {
+                    self.statement(statement);
+                    let jumps = matches!(
+                        statement,
+                        Statement::ReturnStatement(_)
+                            | Statement::BreakStatement(_)
+                            | Statement::ContinueStatement(_)
+                    );
+                    if last && !jumps {
+                        self.null_tail(tail);
+                    }
+                }
+            }
+        }
+        if statements.is_empty() {
+            self.null_tail(tail);
+        }
+    }
+
+    // a function returning nothing returns `undefined`, which the runtime
+    // takes for `null`
+    fn null_tail(&mut self, tail: &Tail) {
+        if let Tail::Assign(name) = tail {
+            self.writer.newline();
+            self.write(&format!("{} = null;", name));
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement, tail: &Tail) {
+        self.write("{");
+        self.writer.indent += 1;
+        self.statements(&block.statements, tail);
+        self.writer.indent -= 1;
+        self.writer.newline();
+        self.write("}");
+    }
+
+    fn tail(&mut self, expression: &Expression, tail: &Tail) {
+        match (expression, tail) {
+            (Expression::IfExpression(ie), Tail::Discard) => self.if_statement(ie, tail),
+            (Expression::IfExpression(ie), _) if !is_conditional(ie) => self.if_statement(ie, tail),
+            (Expression::AssignExpression(ae), Tail::Discard) => {
+                self.assignment(ae, false);
+                self.write(";");
+            }
+            (expression, Tail::Discard) => {
+                self.expression(expression, 0);
+                self.write(";");
+            }
+            (expression, Tail::Return) => {
+                self.write("return ");
+                self.expression(expression, 0);
+                self.write(";");
+            }
+            (expression, Tail::Assign(name)) => {
+                self.write(&format!("{} = ", name));
+                self.expression(expression, ASSIGNMENT);
+                self.write(";");
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::LetStatement(ls) => self.let_statement(ls),
+            Statement::ReturnStatement(rs) => {
+                match self.context {
+                    Context::Program => self.unsupported(rs.span, "return at the top level is"),
+                    Context::Expression => {
+                        self.unsupported(rs.span, "return inside an expression is")
+                    }
+                    Context::Function => {}
+                }
+                self.write("return");
+                if let Some(value) = &rs.return_value {
+                    self.write(" ");
+                    self.expression(value, 0);
+                }
+                self.write(";");
+            }
+            Statement::ExpressionStatement(es) => {
+                if let Some(expression) = &es.expression {
+                    self.tail(expression, &Tail::Discard);
+                }
+            }
+            Statement::WhileStatement(ws) => {
+                self.write("while (");
+                self.condition(&ws.condition, 0);
+                self.write(") ");
+                self.loop_body(&ws.body);
+            }
+            Statement::ForStatement(fs) => {
+                self.write(&format!("for ({} of $.iter(", js_name(&fs.variable.value)));
+                self.expression(&fs.iterable, ASSIGNMENT);
+                self.write(")) ");
+                self.loop_body(&fs.body);
+            }
+            Statement::BreakStatement(bs) => {
+                if self.loops == 0 {
+                    self.unsupported(bs.span, "break out of an expression is");
+                }
+                self.write("break;");
+            }
+            Statement::ContinueStatement(cs) => {
+                if self.loops == 0 {
+                    self.unsupported(cs.span, "continue out of an expression is");
+                }
+                self.write("continue;");
+            }
+            Statement::ImportStatement(is) => self.unsupported(is.span, "imports are"),
+            Statement::ThrowStatement(ts) => self.unsupported(ts.span, "exceptions are"),
+        }
+    }
+
+    fn loop_body(&mut self, body: &BlockStatement) {
+        self.loops += 1;
+        self.block(body, &Tail::Discard);
+        self.loops -= 1;
+    }
+
+    fn let_statement(&mut self, ls: &LetStatement) {
+        let name = js_name(&ls.name.value);
+        let in_place = self
+            .scopes
+            .last()
+            .is_some_and(|scope| scope.declared_in_place.contains(&ls.name.value));
+        let export = if ls.exported { "export " } else { "" };
+
+        match &ls.value {
+            Some(Expression::IfExpression(ie)) if !is_conditional(ie) => {
+                if in_place {
+                    self.write(&format!("{}let {};", export, name));
+                    self.writer.newline();
+                }
+                self.if_statement(ie, &Tail::Assign(name));
+            }
+            value => {
+                if in_place {
+                    let keyword = if ls.is_const() { "const" } else { "let" };
+                    self.write(&format!("{}{} ", export, keyword));
+                }
+                self.write(&format!("{} = ", name));
+                match value {
+                    Some(value) => self.expression(value, ASSIGNMENT),
+                    None => self.write("null"),
+                }
+                self.write(";");
+            }
+        }
+    }
+
+    fn if_statement(&mut self, ie: &IfExpression, tail: &Tail) {
+        self.write("if (");
+        self.condition(&ie.condition, 0);
+        self.write(") ");
+        self.block(&ie.consequence, tail);
+        match &ie.alternative {
+            Some(alternative) => {
+                self.write(" else ");
+                self.block(alternative, tail);
+            }
+            None if matches!(tail, Tail::Assign(_)) => {
+                self.write(" else {");
+                self.writer.indent += 1;
+                self.null_tail(tail);
+                self.writer.indent -= 1;
+                self.writer.newline();
+                self.write("}");
+            }
+            None => {}
+        }
+    }
+
+    /// Compiles `expression`, in parentheses if its operator binds less
+    /// tightly than `min`.
+    fn expression(&mut self, expression: &Expression, min: u8) {
+        let parenthesized = precedence(expression) < min;
+        if parenthesized {
+            self.write("(");
+        }
+        self.writer.mark(expression.span());
+
+        match expression {
+            Expression::Identifier(id) => {
+                if self.declared(&id.value) {
+                    self.write(&js_name(&id.value));
+                } else if lookup_builtin(&id.value).is_some() {
+                    self.write(&format!("$.{}", id.value));
+                } else {
+                    self.fail(&format!("identifier not found: {}", id.value), &[]);
+                }
+            }
+            Expression::IntegerLiteral(il) => self.write(&format!("{}n", il.value)),
+            Expression::Boolean(b) => self.write(&b.value.to_string()),
+            Expression::StringLiteral(sl) => {
+                self.write(&Json::String(sl.value.clone()).to_string())
+            }
+            Expression::PrefixExpression(pe) => match pe.operator.as_str() {
+                "!" => {
+                    self.write("!");
+                    self.condition(&pe.right, PREFIX);
+                }
+                "-" => {
+                    self.write("$.neg(");
+                    self.expression(&pe.right, ASSIGNMENT);
+                    self.write(")");
+                }
+                operator => {
+                    let what = format!("the prefix operator {} is", operator);
+                    self.unsupported(pe.span, &what);
+                }
+            },
+            Expression::InfixExpression(ie) => match ie.operator.as_str() {
+                "&&" | "||" => {
+                    let prec = precedence(expression);
+                    self.condition(&ie.left, prec);
+                    self.write(&format!(" {} ", ie.operator));
+                    self.condition(&ie.right, prec + 1);
+                }
+                operator => {
+                    let left = &ie.left;
+                    self.operation(
+                        operator,
+                        ie.span,
+                        &mut |c| {
+                            c.expression(left, binary(operator).map_or(ASSIGNMENT, |(_, p)| p))
+                        },
+                        &ie.right,
+                    );
+                }
+            },
+            Expression::IfExpression(ie) if is_conditional(ie) => {
+                self.condition(&ie.condition, CONDITIONAL + 1);
+                self.write(" ? ");
+                self.branch(Some(&ie.consequence));
+                self.write(" : ");
+                self.branch(ie.alternative.as_ref());
+            }
+            Expression::IfExpression(ie) => {
+                let outer = (self.context, self.loops);
+                self.context = Context::Expression;
+                self.loops = 0;
+                self.write("(() => {");
+                self.writer.indent += 1;
+                self.writer.newline();
+                self.if_statement(ie, &Tail::Return);
+                self.writer.indent -= 1;
+                self.writer.newline();
+                self.write("})()");
+                (self.context, self.loops) = outer;
+            }
+            Expression::FunctionLiteral(fl) => self.function(fl),
+            Expression::CallExpression(ce) => {
+                if let Expression::Identifier(id) = ce.function.as_ref() {
+                    if (id.value == "quote" || id.value == "unquote") && !self.declared(&id.value) {
+                        self.unsupported(ce.span, &format!("{} is", id.value));
+                    }
+                }
+                // builtins are functions, anything else is checked first
+                if self.is_builtin(&ce.function) {
+                    self.expression(&ce.function, CALL);
+                    self.write("(");
+                } else {
+                    self.write("$.call(");
+                    self.expression(&ce.function, ASSIGNMENT);
+                    if !ce.arguments.is_empty() {
+                        self.write(", ");
+                    }
+                }
+                self.list(&ce.arguments);
+                self.write(")");
+            }
+            Expression::ArrayLiteral(al) => {
+                self.write("[");
+                self.list(&al.elements);
+                self.write("]");
+            }
+            Expression::IndexExpression(ie) => {
+                self.write("$.index(");
+                self.expression(&ie.left, ASSIGNMENT);
+                self.write(", ");
+                self.expression(&ie.index, ASSIGNMENT);
+                self.write(")");
+            }
+            Expression::HashLiteral(hl) if hl.pairs.is_empty() => self.write("new Map()"),
+            Expression::HashLiteral(hl) => {
+                self.write("new Map([");
+                for (i, (key, value)) in hl.pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.write("[");
+                    self.expression(key, ASSIGNMENT);
+                    self.write(", ");
+                    self.expression(value, ASSIGNMENT);
+                    self.write("]");
+                }
+                self.write("])");
+            }
+            Expression::AssignExpression(ae) => self.assignment(ae, true),
+            Expression::MacroLiteral(ml) => self.unsupported(ml.span, "macros are"),
+            Expression::MemberExpression(me) => self.unsupported(me.span, "modules are"),
+            Expression::TryExpression(te) => self.unsupported(te.span, "exceptions are"),
+        }
+
+        if parenthesized {
+            self.write(")");
+        }
+    }
+
+    fn list<'e>(&mut self, expressions: impl IntoIterator<Item = &'e Expression>) {
+        for (i, expression) in expressions.into_iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expression(expression, ASSIGNMENT);
+        }
+    }
+
+    /// Compiles `expression` as a condition, which needs no conversion when
+    /// it is a boolean.
+    fn condition(&mut self, expression: &Expression, min: u8) {
+        if is_boolean(expression) {
+            self.expression(expression, min);
+        } else {
+            self.write("$.truthy(");
+            self.expression(expression, ASSIGNMENT);
+            self.write(")");
+        }
+    }
+
+    // the value of a block of a conditional expression
+    fn branch(&mut self, block: Option<&BlockStatement>) {
+        match block.and_then(block_value) {
+            Some(expression) => self.expression(expression, ASSIGNMENT),
+            None => self.write("null"),
+        }
+    }
+
+    /// Compiles the application of an infix operator to the code `left`
+    /// writes and `right`.
+    fn operation(
+        &mut self,
+        operator: &str,
+        span: Span,
+        left: &mut dyn FnMut(&mut Compiler),
+        right: &Expression,
+    ) {
+        if let Some(helper) = helper(operator) {
+            self.write(&format!("$.{}(", helper));
+            left(self);
+            self.write(", ");
+            self.expression(right, ASSIGNMENT);
+            self.write(")");
+        } else if let Some((js, prec)) = binary(operator) {
+            left(self);
+            self.write(&format!(" {} ", js));
+            self.expression(right, prec + 1);
+        } else {
+            self.unsupported(span, &format!("the operator {} is", operator));
+        }
+    }
+
+    fn assignment(&mut self, ae: &AssignExpression, value: bool) {
+        let root = match ae.target.as_ref() {
+            Expression::Identifier(id) => Some((id, Vec::new())),
+            target => element(target),
+        };
+        if let Some((root, mut operands)) = root.filter(|(id, _)| !self.declared(&id.value)) {
+            operands.push(&ae.value);
+            let message = format!("cannot assign to undeclared identifier: {}", root.value);
+            self.fail(&message, &operands);
+            return;
+        }
+
+        if let Expression::Identifier(id) = ae.target.as_ref() {
+            let name = js_name(&id.value);
+            self.write(&name);
+            match ae.infix_operator() {
+                None => {
+                    self.write(" = ");
+                    self.expression(&ae.value, ASSIGNMENT);
+                }
+                Some(operator) => {
+                    self.write(" = ");
+                    self.operation(operator, ae.span, &mut |c| c.write(&name), &ae.value);
+                }
+            }
+            return;
+        }
+
+        let Some((root, path)) = element(&ae.target) else {
+            self.errors.push(CompileError {
+                span: ae.target.span(),
+                message: format!("invalid assignment target: {}", ae.target.to_string()),
+            });
+            return;
+        };
+
+        // `xs[i] = v` is `xs = $.set(xs, [i], v)`, and `xs[i] += v` updates
+        // the element with a function of its value, `$x`
+        let name = js_name(&root.value);
+        if value {
+            self.write("(");
+        }
+        let helper = if ae.infix_operator().is_some() {
+            "update"
+        } else {
+            "set"
+        };
+        self.write(&format!("{} = $.{}({}, [", name, helper, name));
+        self.list(path);
+        self.write("], ");
+        if let Some(operator) = ae.infix_operator() {
+            self.write("($x) => ");
+            if value {
+                self.write("($.v = ");
+            }
+            self.operation(operator, ae.span, &mut |c| c.write("$x"), &ae.value);
+            if value {
+                self.write(")");
+            }
+        } else {
+            if value {
+                self.write("$.v = ");
+            }
+            self.expression(&ae.value, ASSIGNMENT);
+        }
+        self.write(")");
+        if value {
+            self.write(", $.v)");
+        }
+    }
+
+    fn function(&mut self, fl: &FunctionLiteral) {
+        let parameters: Vec<String> = fl.parameters.iter().map(|p| js_name(&p.value)).collect();
+        self.write(&format!("({}) => ", parameters.join(", ")));
+
+        let outer = (self.context, self.loops);
+        self.context = Context::Function;
+        self.loops = 0;
+        let hoisted = self.enter(&fl.parameters, &fl.body.statements);
+
+        match fl.body.statements.as_slice() {
+            [Statement::ExpressionStatement(ExpressionStatement {
+                expression: Some(expression),
+                ..
+            })] if hoisted.is_empty() && is_expression(expression) => {
+                self.expression(expression, ASSIGNMENT)
+            }
+            statements => {
+                self.write("{");
+                self.writer.indent += 1;
+                if !hoisted.is_empty() {
+                    self.writer.newline();
+                    self.declare("let", &hoisted);
+                }
+                self.statements(statements, &Tail::Return);
+                self.writer.indent -= 1;
+                self.writer.newline();
+                self.write("}");
+            }
+        }
+
+        self.scopes.pop();
+        (self.context, self.loops) = outer;
+    }
+}
+
+/// The runtime function applying an operator, which checks its operands
+/// as the evaluator does.
+fn helper(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "+" => "add",
+        "-" => "sub",
+        "*" => "mul",
+        "/" => "div",
+        "%" => "rem",
+        "&" => "and",
+        "|" => "or",
+        "^" => "xor",
+        "<<" => "shl",
+        ">>" => "shr",
+        "<" => "lt",
+        ">" => "gt",
+        "<=" => "le",
+        ">=" => "ge",
+        "==" => "eq",
+        "!=" => "ne",
+        _ => return None,
+    })
+}
+
+/// The JavaScript operator for a Monkey one, with its precedence.
+fn binary(operator: &str) -> Option<(&'static str, u8)> {
+    Some(match operator {
+        "&&" => ("&&", AND),
+        "||" => ("||", OR),
+        _ => return None,
+    })
+}
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::PrefixExpression(pe) if pe.operator == "!" => PREFIX,
+        Expression::InfixExpression(ie) => match binary(&ie.operator) {
+            Some((_, prec)) => prec,
+            None => CALL,
+        },
+        Expression::IfExpression(ie) if is_conditional(ie) => CONDITIONAL,
+        Expression::PrefixExpression(_) => CALL,
+        Expression::IfExpression(_) | Expression::CallExpression(_) => CALL,
+        Expression::IndexExpression(_) | Expression::HashLiteral(_) => CALL,
+        Expression::FunctionLiteral(_) => ASSIGNMENT,
+        Expression::AssignExpression(ae) => match ae.target.as_ref() {
+            Expression::Identifier(_) => ASSIGNMENT,
+            _ => PRIMARY,
+        },
+        _ => PRIMARY,
+    }
+}
+
+fn is_boolean(expression: &Expression) -> bool {
+    match expression {
+        Expression::Boolean(_) => true,
+        Expression::PrefixExpression(pe) => pe.operator == "!",
+        Expression::InfixExpression(ie) => matches!(
+            ie.operator.as_str(),
+            "<" | ">" | "<=" | ">=" | "==" | "!=" | "&&" | "||"
+        ),
+        _ => false,
+    }
+}
+
+/// The expression giving the value of `block`, if that is all it holds.
+/// An empty block has the value `null`.
+fn block_value(block: &BlockStatement) -> Option<&Expression> {
+    match block.statements.as_slice() {
+        [Statement::ExpressionStatement(es)] => es.expression.as_ref(),
+        _ => None,
+    }
+}
+
+// whether `expression` compiles to an expression rather than statements
+// where its value is used
+fn is_expression(expression: &Expression) -> bool {
+    match expression {
+        Expression::IfExpression(ie) => is_conditional(ie),
+        _ => true,
+    }
+}
+
+/// Whether the branches of `ie` are expressions, so that it compiles to a
+/// conditional expression.
+fn is_conditional(ie: &IfExpression) -> bool {
+    let is_value =
+        |block: &BlockStatement| block.statements.is_empty() || block_value(block).is_some();
+    is_value(&ie.consequence) && ie.alternative.as_ref().is_none_or(is_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::builtins::capture_output;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::json;
+
+    use crate::parser::parse_ok;
+
+    /// Runs `code` with node, returning what it prints, or `None` when
+    /// node is not installed, which only skips running the code.
+    fn run_node(code: &str) -> Option<String> {
+        // a file for every call, as the tests calling it run in parallel
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let call = CALLS.fetch_add(1, Ordering::Relaxed);
+        let file =
+            std::env::temp_dir().join(format!("monkey_js_{}_{}.mjs", std::process::id(), call));
+        std::fs::write(&file, code).unwrap();
+        let output = Command::new("node").arg(&file).output();
+        let _ = std::fs::remove_file(&file);
+        let output = match output {
+            Ok(output) => output,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                eprintln!("node not found, not running the compiled code");
+                return None;
+            }
+            Err(err) => panic!("could not run node: {}", err),
+        };
+        assert!(
+            output.status.success(),
+            "node failed: {:?}\n{}",
+            output,
+            code
+        );
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn test_compiled_program() {
+        let input = r#"let scores = {"ann": 3};
+let bump = fn(name) {
+    if (len(name) > 2) { scores[name] += 1; }
+    let new = scores[name];
+    if (new) { new * 2 } else { let base = 1; base }
+};
+for (k in scores) { puts(k, bump(k) / 2); }
+"#;
+        let expected = r#"
+let k;
+let scores = new Map([["ann", 3n]]);
+let bump = (name) => {
+  let base;
+  if ($.gt($.len(name), 2n)) {
+    scores = $.update(scores, [name], ($x) => $.add($x, 1n));
+  }
+  let new_ = $.index(scores, name);
+  if ($.truthy(new_)) {
+    return $.mul(new_, 2n);
+  } else {
+    base = 1n;
+    return base;
+  }
+};
+for (k of $.iter(scores)) {
+  $.puts(k, $.div($.call(bump, k), 2n));
+}
+"#;
+
+        let code = compile(&parse_ok(input)).unwrap().code;
+        assert_eq!(code.strip_prefix(PRELUDE), Some(expected), "{}", code);
+    }
+
+    #[test]
+    fn test_compiled_programs() {
+        let mut tests: Vec<String> = [
+            "let fib = fn(n) { if (n < 2) { return n; } fib(n - 1) + fib(n - 2) }; puts(fib(20));",
+            "let nothing = puts(); puts(if (0) { 1 } else { 2 }, if (nothing) { 1 }, !0, !nothing);",
+            "puts(7 / 2, -7 / 2, -7 % 3, 1 << 40, 5 & 3 | 8, 6 ^ 3, -16 >> 2);",
+            "puts(255 & -1, 1 < 2 == true, !(1 > 2) && 3 || false);",
+            "puts(pow(3, 4), pow(-2, 5), pow(7, 0));",
+            "puts(9007199254740990 + 1, -9007199254740990 - 1, 94906265 * 94906265, pow(2, 52));",
+            "let f = fn(n) { n * 2 }; puts(f(4503599627370496), pow(2, 100), 1 << 63, -pow(3, 41) / 7);",
+            "let x = 9223372036854775807; x += 1; puts(x, x % 1000, -x >> 3, x - 1 & 255, [1][x]);",
+            "puts(- -3, 2 - -3, (1 + 2) * 3, 1 + 2 * 3, 10 - (4 - 3));",
+            r#"puts("a" + "b" == "ab", len("héllo"), "x" != "y");"#,
+            r#"let h = {"b": 1, 2: true, "a": [1], false: 0, 1: 1, true: 2};
+               puts(h, len(h), h["a"][0], h[3]);
+               for (k in h) { puts(k); }"#,
+            "let xs = [1, 2, 3]; let ys = xs; ys[0] = 10; puts(xs, ys);",
+            r#"let m = {"a": {"b": [1, 2]}}; m["a"]["b"][1] *= 21; puts(m);"#,
+            "let xs = [0, 0]; let v = (xs[1] = 5) + 1; puts(xs, v, xs[0] += 2, xs);",
+            "let total = 0; let i = 0;
+             while (i < 10) { i += 1; if (i % 3 == 0) { continue; } total += i; }
+             puts(total, i);",
+            "for (c in \"abc\") { puts(c); } puts(c);",
+            "let f = fn() { let a = 1; if (true) { let a = 2; } a }; puts(f());",
+            "let g = fn(x) { let y = if (x > 0) { let d = x * 2; d + 1 } else { 0 }; y }; puts(g(3), g(-3));",
+            "let h = fn(x) { puts(if (x) { let z = 1; z + x } else { 0 }); }; h(2); h(false);",
+            "let counter = fn() { let n = 0; fn() { n += 1; n } }; let c = counter(); c(); puts(c());",
+            "let case = 1; let new = fn(this) { this + case }; puts(new(2));",
+            "let len = fn(x) { 42 }; puts(len([1]));",
+            "let x = 5; x /= 2; puts(x, x = 7, x);",
+            "puts(first([1, 2]), last([1, 2]), rest([1, 2]), rest([]), first([]), push([], 1));",
+            "let f = fn(n) { while (true) { if (n > 3) { return n; } n += 1; } }; puts(f(0));",
+            "let compose = fn(f, g) { fn(x) { g(f(x)) } }; puts(compose(fn(x) { x + 1 }, fn(x) { x * 2 })(5));",
+            "let empty = fn() { }; puts(empty(), [empty()]);",
+            "const answer = 42; puts(answer);",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        // the golden programs that end without an error, up to their value
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden");
+        for name in ["functions", "let_statements", "loops", "precedence"] {
+            let path = golden.join(format!("{}.monkey", name));
+            tests.push(std::fs::read_to_string(path).unwrap());
+        }
+
+        for input in tests {
+            let program = parse_ok(&input);
+            let output = compile(&program).unwrap_or_else(|errors| {
+                panic!("could not compile {}: {:?}", input, errors);
+            });
+
+            let (result, expected) =
+                capture_output(|| Evaluator::new().eval_program(&program, &Environment::new()));
+            assert!(!result.is_error(), "{}: {}", input, result.inspect());
+            if let Some(printed) = run_node(&output.code) {
+                assert_eq!(printed, expected, "input: {}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exports() {
+        let input = "export let a = 1; export let b = 2; let b = 3; export const c = a + b;";
+        let expected = "
+export let b;
+export let a = 1n;
+b = 2n;
+b = 3n;
+export const c = $.add(a, b);
+";
+        let code = compile(&parse_ok(input)).unwrap().code;
+        assert_eq!(code.strip_prefix(PRELUDE), Some(expected));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let tests = [
+            ("puts(1 / 0)", "division by zero"),
+            ("puts(1 << 64)", "shift amount out of range: 64"),
+            ("puts(pow(2, -1))", "negative exponent: -1"),
+            (
+                "let add = fn(a, b) { a + b }; puts(add(1, true)); puts(\"unreachable\");",
+                "type mismatch: INTEGER + BOOLEAN",
+            ),
+            ("let x = 1; x += [1];", "type mismatch: INTEGER + ARRAY"),
+            ("puts(1 == \"1\")", "type mismatch: INTEGER == STRING"),
+            ("puts(true + false)", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("puts(\"a\" < \"b\")", "unknown operator: STRING < STRING"),
+            ("puts(-true)", "unknown operator: -BOOLEAN"),
+            (
+                "puts((1 << 63) & 1)",
+                "integer too large for &: 9223372036854775808",
+            ),
+            (
+                "puts(1 | -pow(2, 64))",
+                "integer too large for |: -18446744073709551616",
+            ),
+            ("puts(pow(2, 4294967296))", "exponent too large: 4294967296"),
+            ("let xs = [1]; xs[1] = 2;", "index out of range: 1"),
+            ("puts({}[[]])", "unusable as hash key: ARRAY"),
+            ("puts(1[0])", "index operator not supported: INTEGER"),
+            ("for (x in 1) { }", "cannot iterate over INTEGER"),
+            (
+                "puts(len(1))",
+                "argument to `len` not supported, got INTEGER",
+            ),
+            (
+                "puts(rest(1))",
+                "argument to `rest` must be ARRAY, got INTEGER",
+            ),
+            ("puts(!null)", "identifier not found: null"),
+            ("let f = fn() { x }; f()", "identifier not found: x"),
+            ("puts(5(1))", "not a function: INTEGER"),
+            ("let f = fn() { }; f()()", "not a function: NULL"),
+            ("y = 1", "cannot assign to undeclared identifier: y"),
+            (
+                "let f = fn() { z[0] += 1 }; f()",
+                "cannot assign to undeclared identifier: z",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let code = compile(&parse_ok(input)).unwrap().code;
+            let code = code.replace(PRELUDE, &format!("{}try {{", PRELUDE))
+                + "} catch (e) { console.log(e.message); }\n";
+            if let Some(printed) = run_node(&code) {
+                assert_eq!(printed, format!("{}\n", expected), "input: {}", input);
+            }
+        }
+    }
+
+    // decodes the mappings of a source map into the positions they map
+    fn decode_mappings(mappings: &str) -> Vec<Mapping> {
+        let digits = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut decoded = Vec::new();
+        let mut fields = [0i64; 4];
+
+        for (line, segments) in mappings.split(';').enumerate() {
+            fields[0] = 0;
+            for segment in segments.split(',').filter(|s| !s.is_empty()) {
+                let (mut field, mut value, mut shift) = (0, 0i64, 0);
+                for ch in segment.chars() {
+                    let digit = digits.find(ch).unwrap() as i64;
+                    value |= (digit & 31) << shift;
+                    shift += 5;
+                    if digit & 32 == 0 {
+                        let delta = if value & 1 == 1 {
+                            -(value >> 1)
+                        } else {
+                            value >> 1
+                        };
+                        fields[field] += delta;
+                        (field, value, shift) = (field + 1, 0, 0);
+                    }
+                }
+                assert_eq!(field, 4, "segment {}", segment);
+                let position = |line: i64, column: i64| Position {
+                    line: line as usize + 1,
+                    column: column as usize + 1,
+                };
+                decoded.push(Mapping {
+                    generated: position(line as i64, fields[0]),
+                    original: position(fields[2], fields[3]),
+                });
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn test_source_map() {
+        let input = "let s = \"é\";\nlet f = fn(x) {\n  x + 1\n};\nputs(s, f(2));";
+        let output = compile(&parse_ok(input)).unwrap();
+        let map = json::parse(&output.source_map("out.js", "in.monkey")).unwrap();
+
+        let Json::Object(fields) = map else {
+            panic!("not an object: {}", map);
+        };
+        assert_eq!(fields[0], ("version".to_string(), Json::Number(3)));
+        assert_eq!(
+            fields[2],
+            (
+                "sources".to_string(),
+                Json::Array(vec![Json::String("in.monkey".to_string())])
+            )
+        );
+        let Json::String(mappings) = &fields[4].1 else {
+            panic!("no mappings in {:?}", fields);
+        };
+        assert_eq!(decode_mappings(mappings), output.mappings);
+
+        // the code after the prelude, by line and column
+        let lines: Vec<&str> = output.code.lines().collect();
+        let prelude = PRELUDE.lines().count();
+        let find = |text: &str| {
+            let (line, code) = lines
+                .iter()
+                .enumerate()
+                .skip(prelude)
+                .find(|(_, line)| line.contains(text))
+                .unwrap();
+            let column = code[..code.find(text).unwrap()].encode_utf16().count();
+            Position {
+                line: line + 1,
+                column: column + 1,
+            }
+        };
+        let original = |generated: Position| {
+            output
+                .mappings
+                .iter()
+                .find(|m| m.generated == generated)
+                .map(|m| m.original)
+        };
+        let position = |line, column| Some(Position { line, column });
+
+        assert_eq!(original(find("let f")), position(2, 1));
+        assert_eq!(original(find("$.add(x, 1n)")), position(3, 3));
+        assert_eq!(original(find("$.call(f, 2n)")), position(5, 9));
+        assert_eq!(original(find("$.puts")), position(5, 1));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let tests = [
+            (
+                "import \"lib.monkey\" as lib;",
+                "1:1: imports are not supported by the js target",
+            ),
+            (
+                "throw 1;",
+                "1:1: exceptions are not supported by the js target",
+            ),
+            (
+                "return 1;",
+                "1:1: return at the top level is not supported by the js target",
+            ),
+            (
+                "let f = fn(x) { puts(if (x) { return 1; } else { 2 }) };",
+                "1:31: return inside an expression is not supported by the js target",
+            ),
+            (
+                "while (true) { puts(if (true) { break; } else { 1 }); }",
+                "1:33: break out of an expression is not supported by the js target",
+            ),
+            (
+                "quote(1 + 2)",
+                "1:1: quote is not supported by the js target",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let errors = compile(&parse_ok(input)).unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+}
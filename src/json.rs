@@ -0,0 +1,1632 @@
+//! A JSON encoding of tokens and syntax trees, for tools outside of Rust.
+//!
+//! A token is encoded as
+//!
+//! ```text
+//! {"type": "IDENT", "literal": "x", "span": <span>}
+//! ```
+//!
+//! where `type` is the name of the `TokenType` variant and a span is
+//! `{"start": {"line": 1, "column": 1}, "end": {"line": 1, "column": 2}}`,
+//! `end` being the position just past the last character.
+//!
+//! Every node is an object with a `kind` and a `span` next to its fields.
+//! `Program` is `{"kind": "Program", "statements": [...]}` and has no span.
+//! The kinds and their fields are:
+//!
+//! | kind                  | fields                                                   |
+//! |-----------------------|----------------------------------------------------------|
+//! | `LetStatement`        | `name`, `type`, `value`, `const`, `exported`             |
+//! | `ReturnStatement`     | `value` (or `null`)                                      |
+//! | `ExpressionStatement` | `expression`                                             |
+//! | `WhileStatement`      | `condition`, `body`                                      |
+//! | `ForStatement`        | `variable`, `iterable`, `body`                           |
+//! | `BreakStatement`      |                                                          |
+//! | `ContinueStatement`   |                                                          |
+//! | `ImportStatement`     | `path` (string), `alias`                                 |
+//! | `ThrowStatement`      | `value`                                                  |
+//! | `BlockStatement`      | `statements`                                             |
+//! | `Identifier`          | `value` (string)                                         |
+//! | `IntegerLiteral`      | `value` (number, or a string of digits beyond 64 bits)   |
+//! | `Boolean`             | `value` (boolean)                                        |
+//! | `StringLiteral`       | `value` (string)                                         |
+//! | `PrefixExpression`    | `operator`, `right`                                      |
+//! | `InfixExpression`     | `operator`, `left`, `right`                              |
+//! | `AssignExpression`    | `operator`, `target`, `value`                            |
+//! | `IfExpression`        | `condition`, `consequence`, `alternative` (or `null`)    |
+//! | `FunctionLiteral`     | `parameters` (identifiers), `types`, `returns`, `body`   |
+//! | `MacroLiteral`        | `parameters` (identifiers), `body`                       |
+//! | `CallExpression`      | `function`, `arguments`                                  |
+//! | `ArrayLiteral`        | `elements`                                               |
+//! | `IndexExpression`     | `left`, `index`                                          |
+//! | `HashLiteral`         | `pairs`, a list of `{"key": ..., "value": ...}`          |
+//! | `MemberExpression`    | `object`, `property` (identifier)                        |
+//! | `TryExpression`       | `body`, `catch`, `finally` (or `null`)                   |
+//!
+//! The `catch` of a `TryExpression` is `{"parameter": ..., "body": ...}`, or
+//! `null` where there is none.
+//!
+//! Type annotations are strings in the syntax of the source, or `null`
+//! where there is none; `types` holds one per parameter.
+//!
+//! The tokens stored in nodes are not part of the encoding; decoding
+//! rebuilds them from the node, spanning the whole node. Decoding refuses
+//! trees nested more deeply than the parser would accept, see `MAX_NESTING`
+//! and `MAX_TREE_DEPTH`.
+
+use std::fmt;
+
+use crate::ast::*;
+use crate::bigint::BigInt;
+use crate::lexer::Lexer;
+use crate::parser::{parse_type_annotation, MAX_NESTING, MAX_TREE_DEPTH};
+use crate::tail_calls::function_body;
+use crate::token::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    // keys keep their order so that encoding is stable
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Number(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    /// The `kind` of an encoded node, or the JSON type of anything else.
+    fn kind(&self) -> String {
+        match Fields::new(self, "node").and_then(|fields| fields.string("kind")) {
+            Ok(kind) => kind.to_string(),
+            Err(_) => self.type_name().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// How deeply arrays and objects may nest in JSON text. The encoding of a
+/// syntax tree `MAX_TREE_DEPTH` levels deep takes at most three levels of
+/// JSON per level.
+pub const MAX_DEPTH: usize = 4 * MAX_TREE_DEPTH;
+
+/// Parses JSON text. Numbers must be integers that fit into an `i64`, and
+/// arrays and objects may nest at most `MAX_DEPTH` levels deep.
+pub fn parse(input: &str) -> Result<Json, String> {
+    let mut reader = Reader {
+        chars: input.chars().collect(),
+        position: 0,
+        depth: 0,
+    };
+
+    let value = reader.read_value()?;
+    reader.skip_whitespace();
+    if reader.position < reader.chars.len() {
+        return Err(reader.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Reader {
+    chars: Vec<char>,
+    position: usize,
+    // number of arrays and objects being read
+    depth: usize,
+}
+
+impl Reader {
+    fn error(&self, msg: &str) -> String {
+        format!("invalid JSON at offset {}: {}", self.position, msg)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        self.position += 1;
+        ch
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(ch) if ch == expected => Ok(()),
+            _ => {
+                self.position -= 1;
+                Err(self.error(&format!("expected '{}'", expected)))
+            }
+        }
+    }
+
+    fn read_keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            if self.next() != Some(expected) {
+                self.position -= 1;
+                return Err(self.error(&format!("expected {}", keyword)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn read_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.read_keyword("null", Json::Null),
+            Some('t') => self.read_keyword("true", Json::Bool(true)),
+            Some('f') => self.read_keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.read_string()?)),
+            Some('[') => self.read_nested(Reader::read_array),
+            Some('{') => self.read_nested(Reader::read_object),
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => self.read_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn read_nested(
+        &mut self,
+        read: fn(&mut Reader) -> Result<Json, String>,
+    ) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nested more than {} levels deep", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = read(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn read_number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        if self.peek() == Some('-') {
+            self.position += 1;
+        }
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_digit()) {
+            self.position += 1;
+        }
+        if matches!(self.peek(), Some('.' | 'e' | 'E')) {
+            return Err(self.error("only integer numbers are supported"));
+        }
+
+        let literal: String = self.chars[start..self.position].iter().collect();
+        literal
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error(&format!("could not parse {:?} as integer", literal)))
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut out = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => match self.next() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('u') => out.push(self.read_unicode_escape()?),
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some(ch) if (ch as u32) < 0x20 => {
+                    return Err(self.error("control character in string"))
+                }
+                Some(ch) => out.push(ch),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            match self.next().and_then(|ch| ch.to_digit(16)) {
+                Some(digit) => value = value * 16 + digit,
+                None => return Err(self.error("invalid unicode escape")),
+            }
+        }
+        Ok(value)
+    }
+
+    fn read_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.read_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            // a surrogate pair, the low half must follow as another escape
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error("unpaired surrogate"));
+            }
+            let low = self.read_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn read_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(elements));
+        }
+
+        loop {
+            elements.push(self.read_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(elements)),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected ',' or ']'"));
+                }
+            }
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.read_string()?;
+            self.expect(':')?;
+            members.push((key, self.read_value()?));
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(members)),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected ',' or '}'"));
+                }
+            }
+        }
+    }
+}
+
+fn encode_position(position: Position) -> Json {
+    Json::Object(vec![
+        ("line".to_string(), Json::Number(position.line as i64)),
+        ("column".to_string(), Json::Number(position.column as i64)),
+    ])
+}
+
+fn encode_span(span: Span) -> Json {
+    Json::Object(vec![
+        ("start".to_string(), encode_position(span.start)),
+        ("end".to_string(), encode_position(span.end)),
+    ])
+}
+
+pub fn encode_token(token: &Token) -> Json {
+    Json::Object(vec![
+        (
+            "type".to_string(),
+            Json::String(format!("{:?}", token.token_type)),
+        ),
+        ("literal".to_string(), Json::String(token.literal.clone())),
+        ("span".to_string(), encode_span(token.span)),
+    ])
+}
+
+fn node(kind: &str, span: Span, fields: Vec<(&str, Json)>) -> Json {
+    let mut members = vec![
+        ("kind".to_string(), Json::String(kind.to_string())),
+        ("span".to_string(), encode_span(span)),
+    ];
+    members.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+    Json::Object(members)
+}
+
+pub fn encode_program(program: &Program) -> Json {
+    Json::Object(vec![
+        ("kind".to_string(), Json::String("Program".to_string())),
+        (
+            "statements".to_string(),
+            Json::Array(program.statements.iter().map(encode_statement).collect()),
+        ),
+    ])
+}
+
+pub fn encode_statement(statement: &Statement) -> Json {
+    let (kind, fields) = match statement {
+        Statement::LetStatement(ls) => (
+            "LetStatement",
+            vec![
+                ("name", encode_identifier(&ls.name)),
+                ("type", encode_type(ls.type_annotation.as_ref())),
+                ("value", encode_optional(ls.value.as_ref())),
+                ("const", Json::Bool(ls.is_const())),
+                ("exported", Json::Bool(ls.exported)),
+            ],
+        ),
+        Statement::ReturnStatement(rs) => (
+            "ReturnStatement",
+            vec![("value", encode_optional(rs.return_value.as_deref()))],
+        ),
+        Statement::ExpressionStatement(es) => (
+            "ExpressionStatement",
+            vec![("expression", encode_optional(es.expression.as_ref()))],
+        ),
+        Statement::WhileStatement(ws) => (
+            "WhileStatement",
+            vec![
+                ("condition", encode_expression(&ws.condition)),
+                ("body", encode_block(&ws.body)),
+            ],
+        ),
+        Statement::ForStatement(fs) => (
+            "ForStatement",
+            vec![
+                ("variable", encode_identifier(&fs.variable)),
+                ("iterable", encode_expression(&fs.iterable)),
+                ("body", encode_block(&fs.body)),
+            ],
+        ),
+        Statement::BreakStatement(_) => ("BreakStatement", vec![]),
+        Statement::ContinueStatement(_) => ("ContinueStatement", vec![]),
+        Statement::ImportStatement(is) => (
+            "ImportStatement",
+            vec![
+                ("path", Json::String(is.path.clone())),
+                ("alias", encode_identifier(&is.alias)),
+            ],
+        ),
+        Statement::ThrowStatement(ts) => (
+            "ThrowStatement",
+            vec![("value", encode_expression(&ts.value))],
+        ),
+    };
+
+    node(kind, statement.span(), fields)
+}
+
+fn encode_block(block: &BlockStatement) -> Json {
+    node(
+        "BlockStatement",
+        block.span,
+        vec![(
+            "statements",
+            Json::Array(block.statements.iter().map(encode_statement).collect()),
+        )],
+    )
+}
+
+fn encode_identifier(id: &Identifier) -> Json {
+    node(
+        "Identifier",
+        id.span,
+        vec![("value", Json::String(id.value.clone()))],
+    )
+}
+
+fn encode_optional(expression: Option<&Expression>) -> Json {
+    expression.map_or(Json::Null, encode_expression)
+}
+
+fn encode_expressions(expressions: &[Expression]) -> Json {
+    Json::Array(expressions.iter().map(encode_expression).collect())
+}
+
+fn encode_parameters(parameters: &[Identifier]) -> Json {
+    Json::Array(parameters.iter().map(encode_identifier).collect())
+}
+
+fn encode_type(annotation: Option<&TypeAnnotation>) -> Json {
+    annotation.map_or(Json::Null, |t| Json::String(t.to_string()))
+}
+
+pub fn encode_expression(expression: &Expression) -> Json {
+    let (kind, fields) = match expression {
+        Expression::Identifier(id) => (
+            "Identifier",
+            vec![("value", Json::String(id.value.clone()))],
+        ),
+        Expression::IntegerLiteral(il) => {
+            let value = match il.value.to_i64() {
+                Some(i) => Json::Number(i),
+                None => Json::String(il.value.to_string()),
+            };
+            ("IntegerLiteral", vec![("value", value)])
+        }
+        Expression::Boolean(b) => ("Boolean", vec![("value", Json::Bool(b.value))]),
+        Expression::StringLiteral(sl) => (
+            "StringLiteral",
+            vec![("value", Json::String(sl.value.clone()))],
+        ),
+        Expression::PrefixExpression(pe) => (
+            "PrefixExpression",
+            vec![
+                ("operator", Json::String(pe.operator.clone())),
+                ("right", encode_expression(&pe.right)),
+            ],
+        ),
+        Expression::InfixExpression(ie) => (
+            "InfixExpression",
+            vec![
+                ("operator", Json::String(ie.operator.clone())),
+                ("left", encode_expression(&ie.left)),
+                ("right", encode_expression(&ie.right)),
+            ],
+        ),
+        Expression::AssignExpression(ae) => (
+            "AssignExpression",
+            vec![
+                ("operator", Json::String(ae.operator.clone())),
+                ("target", encode_expression(&ae.target)),
+                ("value", encode_expression(&ae.value)),
+            ],
+        ),
+        Expression::IfExpression(ie) => (
+            "IfExpression",
+            vec![
+                ("condition", encode_expression(&ie.condition)),
+                ("consequence", encode_block(&ie.consequence)),
+                (
+                    "alternative",
+                    ie.alternative.as_ref().map_or(Json::Null, encode_block),
+                ),
+            ],
+        ),
+        Expression::FunctionLiteral(fl) => (
+            "FunctionLiteral",
+            vec![
+                ("parameters", encode_parameters(&fl.parameters)),
+                (
+                    "types",
+                    Json::Array(
+                        fl.parameter_types
+                            .iter()
+                            .map(|t| encode_type(t.as_ref()))
+                            .collect(),
+                    ),
+                ),
+                ("returns", encode_type(fl.return_type.as_ref())),
+                ("body", encode_block(&fl.body)),
+            ],
+        ),
+        Expression::MacroLiteral(ml) => (
+            "MacroLiteral",
+            vec![
+                ("parameters", encode_parameters(&ml.parameters)),
+                ("body", encode_block(&ml.body)),
+            ],
+        ),
+        Expression::CallExpression(ce) => (
+            "CallExpression",
+            vec![
+                ("function", encode_expression(&ce.function)),
+                ("arguments", encode_expressions(&ce.arguments)),
+            ],
+        ),
+        Expression::ArrayLiteral(al) => (
+            "ArrayLiteral",
+            vec![("elements", encode_expressions(&al.elements))],
+        ),
+        Expression::IndexExpression(ie) => (
+            "IndexExpression",
+            vec![
+                ("left", encode_expression(&ie.left)),
+                ("index", encode_expression(&ie.index)),
+            ],
+        ),
+        Expression::HashLiteral(hl) => (
+            "HashLiteral",
+            vec![(
+                "pairs",
+                Json::Array(
+                    hl.pairs
+                        .iter()
+                        .map(|(k, v)| {
+                            Json::Object(vec![
+                                ("key".to_string(), encode_expression(k)),
+                                ("value".to_string(), encode_expression(v)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            )],
+        ),
+        Expression::MemberExpression(me) => (
+            "MemberExpression",
+            vec![
+                ("object", encode_expression(&me.object)),
+                ("property", encode_identifier(&me.property)),
+            ],
+        ),
+        Expression::TryExpression(te) => (
+            "TryExpression",
+            vec![
+                ("body", encode_block(&te.body)),
+                (
+                    "catch",
+                    te.catch.as_ref().map_or(Json::Null, |catch| {
+                        Json::Object(vec![
+                            ("parameter".to_string(), encode_identifier(&catch.parameter)),
+                            ("body".to_string(), encode_block(&catch.body)),
+                        ])
+                    }),
+                ),
+                (
+                    "finally",
+                    te.finally.as_ref().map_or(Json::Null, encode_block),
+                ),
+            ],
+        ),
+    };
+
+    node(kind, expression.span(), fields)
+}
+
+/// The members of a JSON object being decoded, `what` naming it in errors.
+struct Fields<'a> {
+    what: &'a str,
+    members: &'a [(String, Json)],
+}
+
+impl<'a> Fields<'a> {
+    fn new(json: &'a Json, what: &'a str) -> Result<Fields<'a>, String> {
+        match json {
+            Json::Object(members) => Ok(Fields { what, members }),
+            json => Err(format!(
+                "{} must be an object, got {}",
+                what,
+                json.type_name()
+            )),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<&'a Json, String> {
+        self.members
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("{} is missing field {:?}", self.what, name))
+    }
+
+    fn mismatch(&self, name: &str, expected: &str, got: &Json) -> String {
+        format!(
+            "field {:?} of {} must be {}, got {}",
+            name,
+            self.what,
+            expected,
+            got.type_name()
+        )
+    }
+
+    fn string(&self, name: &str) -> Result<&'a str, String> {
+        match self.get(name)? {
+            Json::String(s) => Ok(s),
+            json => Err(self.mismatch(name, "a string", json)),
+        }
+    }
+
+    fn number(&self, name: &str) -> Result<i64, String> {
+        match self.get(name)? {
+            Json::Number(n) => Ok(*n),
+            json => Err(self.mismatch(name, "a number", json)),
+        }
+    }
+
+    // a number, or a string of digits for those beyond 64 bits
+    fn integer(&self, name: &str) -> Result<BigInt, String> {
+        match self.get(name)? {
+            Json::Number(n) => Ok(BigInt::from(*n)),
+            json @ Json::String(s) => s
+                .parse()
+                .map_err(|_| self.mismatch(name, "an integer", json)),
+            json => Err(self.mismatch(name, "an integer", json)),
+        }
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, String> {
+        match self.get(name)? {
+            Json::Bool(b) => Ok(*b),
+            json => Err(self.mismatch(name, "a boolean", json)),
+        }
+    }
+
+    fn array(&self, name: &str) -> Result<&'a [Json], String> {
+        match self.get(name)? {
+            Json::Array(elements) => Ok(elements),
+            json => Err(self.mismatch(name, "an array", json)),
+        }
+    }
+
+    fn span(&self) -> Result<Span, String> {
+        let span = Fields::new(self.get("span")?, "span")?;
+        Ok(Span {
+            start: decode_position(span.get("start")?)?,
+            end: decode_position(span.get("end")?)?,
+        })
+    }
+
+    fn expression(&self, name: &str) -> Result<Expression, String> {
+        decode_expression(self.get(name)?)
+    }
+
+    fn optional_expression(&self, name: &str) -> Result<Option<Expression>, String> {
+        match self.get(name)? {
+            Json::Null => Ok(None),
+            json => decode_expression(json).map(Some),
+        }
+    }
+
+    fn expressions(&self, name: &str) -> Result<Vec<Expression>, String> {
+        self.array(name)?.iter().map(decode_expression).collect()
+    }
+
+    fn identifier(&self, name: &str) -> Result<Identifier, String> {
+        decode_identifier(self.get(name)?)
+    }
+
+    fn parameters(&self) -> Result<Vec<Identifier>, String> {
+        self.array("parameters")?
+            .iter()
+            .map(decode_identifier)
+            .collect()
+    }
+
+    fn block(&self, name: &str) -> Result<BlockStatement, String> {
+        decode_block(self.get(name)?)
+    }
+
+    fn optional_type(&self, name: &str) -> Result<Option<TypeAnnotation>, String> {
+        self.decode_type(name, self.get(name)?)
+    }
+
+    fn types(&self) -> Result<Vec<Option<TypeAnnotation>>, String> {
+        self.array("types")?
+            .iter()
+            .map(|json| self.decode_type("types", json))
+            .collect()
+    }
+
+    fn decode_type(&self, name: &str, json: &Json) -> Result<Option<TypeAnnotation>, String> {
+        match json {
+            Json::Null => Ok(None),
+            Json::String(s) => parse_type_annotation(s).map(Some).map_err(|err| {
+                format!(
+                    "field {:?} of {} holds an invalid type: {}",
+                    name, self.what, err
+                )
+            }),
+            json => Err(self.mismatch(name, "a type or null", json)),
+        }
+    }
+}
+
+fn decode_position(json: &Json) -> Result<Position, String> {
+    let position = Fields::new(json, "position")?;
+    let field = |name| match position.number(name)? {
+        n if n >= 0 => Ok(n as usize),
+        n => Err(format!(
+            "{} of a position must not be negative, got {}",
+            name, n
+        )),
+    };
+
+    Ok(Position {
+        line: field("line")?,
+        column: field("column")?,
+    })
+}
+
+// every token type, for looking them up by name
+const TOKEN_TYPES: &[TokenType] = &[
+    TokenType::ILLEGAL,
+    TokenType::EOF,
+    TokenType::IDENT,
+    TokenType::INT,
+    TokenType::STRING,
+    TokenType::ASSIGN,
+    TokenType::PlusAssign,
+    TokenType::MinusAssign,
+    TokenType::AsteriskAssign,
+    TokenType::SlashAssign,
+    TokenType::PLUS,
+    TokenType::MINUS,
+    TokenType::BANG,
+    TokenType::ASTERISK,
+    TokenType::SLASH,
+    TokenType::PERCENT,
+    TokenType::LT,
+    TokenType::GT,
+    TokenType::LtEq,
+    TokenType::GtEq,
+    TokenType::EQ,
+    TokenType::NotEq,
+    TokenType::AND,
+    TokenType::OR,
+    TokenType::AMPERSAND,
+    TokenType::PIPE,
+    TokenType::CARET,
+    TokenType::LShift,
+    TokenType::RShift,
+    TokenType::COMMA,
+    TokenType::SEMICOLON,
+    TokenType::COLON,
+    TokenType::DOT,
+    TokenType::ARROW,
+    TokenType::LPAREN,
+    TokenType::RPAREN,
+    TokenType::LBRACE,
+    TokenType::RBRACE,
+    TokenType::LBRACKET,
+    TokenType::RBRACKET,
+    TokenType::FUNCTION,
+    TokenType::LET,
+    TokenType::CONST,
+    TokenType::TRUE,
+    TokenType::FALSE,
+    TokenType::IF,
+    TokenType::ELSE,
+    TokenType::RETURN,
+    TokenType::WHILE,
+    TokenType::FOR,
+    TokenType::IN,
+    TokenType::BREAK,
+    TokenType::CONTINUE,
+    TokenType::MACRO,
+    TokenType::IMPORT,
+    TokenType::EXPORT,
+    TokenType::AS,
+    TokenType::THROW,
+    TokenType::TRY,
+    TokenType::CATCH,
+    TokenType::FINALLY,
+];
+
+pub fn decode_token(json: &Json) -> Result<Token, String> {
+    let fields = Fields::new(json, "token")?;
+    let name = fields.string("type")?;
+    let token_type = TOKEN_TYPES
+        .iter()
+        .find(|tt| format!("{:?}", tt) == name)
+        .cloned()
+        .ok_or_else(|| format!("unknown token type {:?}", name))?;
+
+    Ok(Token {
+        token_type,
+        literal: fields.string("literal")?.to_string(),
+        span: fields.span()?,
+    })
+}
+
+fn token(token_type: TokenType, literal: &str, span: Span) -> Token {
+    Token {
+        token_type,
+        literal: literal.to_string(),
+        span,
+    }
+}
+
+/// Rebuilds the token of an operator node by lexing `operator`.
+fn operator_token(fields: &Fields, span: Span) -> Result<Token, String> {
+    let operator = fields.string("operator")?;
+    let mut tok = Lexer::new(operator).next_token();
+
+    match tok.token_type {
+        TokenType::IDENT
+        | TokenType::INT
+        | TokenType::STRING
+        | TokenType::ILLEGAL
+        | TokenType::EOF => Err(format!(
+            "unknown operator {:?} in {}",
+            operator, fields.what
+        )),
+        _ if tok.literal != operator => Err(format!(
+            "unknown operator {:?} in {}",
+            operator, fields.what
+        )),
+        _ => {
+            tok.span = span;
+            Ok(tok)
+        }
+    }
+}
+
+fn kind<'a>(json: &'a Json, what: &'a str) -> Result<(Fields<'a>, &'a str), String> {
+    let fields = Fields::new(json, what)?;
+    let kind = fields.string("kind")?;
+    Ok((
+        Fields {
+            what: kind,
+            ..fields
+        },
+        kind,
+    ))
+}
+
+pub fn decode_program(json: &Json) -> Result<Program, String> {
+    let (fields, kind) = kind(json, "program")?;
+    if kind != "Program" {
+        return Err(format!("expected Program, got {}", kind));
+    }
+
+    // decoding recurses as deeply as the tree nests, so that is checked first
+    let (nesting, depth) = nesting(json, 0, 0);
+    for (deep, limit) in [(nesting, MAX_NESTING), (depth, MAX_TREE_DEPTH)] {
+        if deep > limit {
+            return Err(format!("program is nested more than {} levels deep", limit));
+        }
+    }
+
+    Ok(Program {
+        statements: fields
+            .array("statements")?
+            .iter()
+            .map(decode_statement)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// How deeply the encoded tree `json` found at `level` and `depth` nests, as
+/// the parser counts both: once for each statement and expression, except
+/// identifiers that are only names. An expression an operator is chained onto
+/// is at the level of the operator, though a level deeper in the tree.
+fn nesting(json: &Json, level: usize, depth: usize) -> (usize, usize) {
+    match json {
+        Json::Array(elements) => elements
+            .iter()
+            .map(|element| nesting(element, level, depth))
+            .fold((level, depth), |(a, b), (c, d)| (a.max(c), b.max(d))),
+        Json::Object(members) => {
+            let kind = match members.iter().find(|(name, _)| name == "kind") {
+                Some((_, Json::String(kind))) => kind.as_str(),
+                _ => "",
+            };
+            let counted = !matches!(kind, "" | "Program" | "BlockStatement");
+            let (inner, depth) = match counted {
+                true => (level + 1, depth + 1),
+                false => (level, depth),
+            };
+            members
+                .iter()
+                .filter(|(name, _)| {
+                    !matches!(
+                        name.as_str(),
+                        "name" | "variable" | "parameters" | "alias" | "property" | "parameter"
+                    )
+                })
+                .map(|(name, value)| {
+                    let chained = matches!(
+                        (kind, name.as_str()),
+                        ("InfixExpression", "left")
+                            | ("AssignExpression", "target")
+                            | ("CallExpression", "function")
+                            | ("IndexExpression", "left")
+                            | ("MemberExpression", "object")
+                    );
+                    nesting(value, if chained { level } else { inner }, depth)
+                })
+                .fold((inner, depth), |(a, b), (c, d)| (a.max(c), b.max(d)))
+        }
+        _ => (level, depth),
+    }
+}
+
+pub fn decode_statement(json: &Json) -> Result<Statement, String> {
+    let (fields, kind) = kind(json, "statement")?;
+    let span = fields.span()?;
+
+    let statement = match kind {
+        "LetStatement" => {
+            let (token_type, literal) = match fields.bool("const")? {
+                true => (TokenType::CONST, "const"),
+                false => (TokenType::LET, "let"),
+            };
+            Statement::LetStatement(LetStatement {
+                token: token(token_type, literal, span),
+                span,
+                name: fields.identifier("name")?,
+                type_annotation: fields.optional_type("type")?,
+                value: fields.optional_expression("value")?,
+                exported: fields.bool("exported")?,
+            })
+        }
+        "ReturnStatement" => Statement::ReturnStatement(ReturnStatement {
+            token: token(TokenType::RETURN, "return", span),
+            span,
+            return_value: fields.optional_expression("value")?.map(Box::new),
+        }),
+        "ExpressionStatement" => {
+            let expression = fields.optional_expression("expression")?;
+            let token = match &expression {
+                Some(e) => expression_token(e),
+                None => token(TokenType::ILLEGAL, "", span),
+            };
+            Statement::ExpressionStatement(ExpressionStatement {
+                token,
+                span,
+                expression,
+            })
+        }
+        "WhileStatement" => Statement::WhileStatement(WhileStatement {
+            token: token(TokenType::WHILE, "while", span),
+            span,
+            condition: fields.expression("condition")?,
+            body: fields.block("body")?,
+        }),
+        "ForStatement" => Statement::ForStatement(ForStatement {
+            token: token(TokenType::FOR, "for", span),
+            span,
+            variable: fields.identifier("variable")?,
+            iterable: fields.expression("iterable")?,
+            body: fields.block("body")?,
+        }),
+        "BreakStatement" => Statement::BreakStatement(BreakStatement {
+            token: token(TokenType::BREAK, "break", span),
+            span,
+        }),
+        "ContinueStatement" => Statement::ContinueStatement(ContinueStatement {
+            token: token(TokenType::CONTINUE, "continue", span),
+            span,
+        }),
+        "ImportStatement" => Statement::ImportStatement(ImportStatement {
+            token: token(TokenType::IMPORT, "import", span),
+            span,
+            path: fields.string("path")?.to_string(),
+            alias: fields.identifier("alias")?,
+        }),
+        "ThrowStatement" => Statement::ThrowStatement(ThrowStatement {
+            token: token(TokenType::THROW, "throw", span),
+            span,
+            value: fields.expression("value")?,
+        }),
+        kind => return Err(format!("unknown statement kind {:?}", kind)),
+    };
+
+    Ok(statement)
+}
+
+fn decode_block(json: &Json) -> Result<BlockStatement, String> {
+    let (fields, kind) = kind(json, "block")?;
+    if kind != "BlockStatement" {
+        return Err(format!("expected BlockStatement, got {}", kind));
+    }
+    let span = fields.span()?;
+
+    Ok(BlockStatement {
+        token: token(TokenType::LBRACE, "{", span),
+        span,
+        statements: fields
+            .array("statements")?
+            .iter()
+            .map(decode_statement)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn decode_identifier(json: &Json) -> Result<Identifier, String> {
+    match decode_expression(json)? {
+        Expression::Identifier(id) => Ok(id),
+        e => Err(format!(
+            "expected Identifier, got {}",
+            encode_expression(&e).kind()
+        )),
+    }
+}
+
+pub fn decode_expression(json: &Json) -> Result<Expression, String> {
+    let (fields, kind) = kind(json, "expression")?;
+    let span = fields.span()?;
+
+    let expression = match kind {
+        "Identifier" => {
+            let value = fields.string("value")?;
+            Expression::Identifier(Identifier {
+                token: token(TokenType::IDENT, value, span),
+                span,
+                value: value.to_string(),
+            })
+        }
+        "IntegerLiteral" => {
+            let value = fields.integer("value")?;
+            Expression::IntegerLiteral(IntegerLiteral {
+                token: token(TokenType::INT, &value.to_string(), span),
+                span,
+                value,
+            })
+        }
+        "Boolean" => {
+            let value = fields.bool("value")?;
+            let token_type = if value {
+                TokenType::TRUE
+            } else {
+                TokenType::FALSE
+            };
+            Expression::Boolean(Boolean {
+                token: token(token_type, &value.to_string(), span),
+                span,
+                value,
+            })
+        }
+        "StringLiteral" => {
+            let value = fields.string("value")?;
+            Expression::StringLiteral(StringLiteral {
+                token: token(TokenType::STRING, value, span),
+                span,
+                value: value.to_string(),
+            })
+        }
+        // nodes holding expressions are decoded by functions of their own
+        // so that each level of nesting takes no more of the stack than one
+        "PrefixExpression" => decode_prefix(&fields, span)?,
+        "InfixExpression" => decode_infix(&fields, span)?,
+        "AssignExpression" => decode_assign(&fields, span)?,
+        "IfExpression" => decode_if(&fields, span)?,
+        "FunctionLiteral" => decode_function(&fields, span)?,
+        "MacroLiteral" => decode_macro(&fields, span)?,
+        "CallExpression" => decode_call(&fields, span)?,
+        "ArrayLiteral" => decode_array(&fields, span)?,
+        "IndexExpression" => decode_index(&fields, span)?,
+        "HashLiteral" => decode_hash(&fields, span)?,
+        "MemberExpression" => decode_member(&fields, span)?,
+        "TryExpression" => decode_try(&fields, span)?,
+        kind => return Err(format!("unknown expression kind {:?}", kind)),
+    };
+
+    Ok(expression)
+}
+
+fn decode_prefix(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::PrefixExpression(PrefixExpression {
+        token: operator_token(fields, span)?,
+        span,
+        operator: fields.string("operator")?.to_string(),
+        right: Box::new(fields.expression("right")?),
+    }))
+}
+
+fn decode_infix(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::InfixExpression(InfixExpression {
+        token: operator_token(fields, span)?,
+        span,
+        left: Box::new(fields.expression("left")?),
+        operator: fields.string("operator")?.to_string(),
+        right: Box::new(fields.expression("right")?),
+    }))
+}
+
+fn decode_assign(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::AssignExpression(AssignExpression {
+        token: operator_token(fields, span)?,
+        span,
+        target: Box::new(fields.expression("target")?),
+        operator: fields.string("operator")?.to_string(),
+        value: Box::new(fields.expression("value")?),
+    }))
+}
+
+fn decode_if(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::IfExpression(IfExpression {
+        token: token(TokenType::IF, "if", span),
+        span,
+        condition: Box::new(fields.expression("condition")?),
+        consequence: fields.block("consequence")?,
+        alternative: match fields.get("alternative")? {
+            Json::Null => None,
+            json => Some(decode_block(json)?),
+        },
+    }))
+}
+
+fn decode_function(fields: &Fields, span: Span) -> Result<Expression, String> {
+    let parameters = fields.parameters()?;
+    let parameter_types = fields.types()?;
+    if parameter_types.len() != parameters.len() {
+        return Err(format!(
+            "FunctionLiteral has {} parameters but {} types",
+            parameters.len(),
+            parameter_types.len()
+        ));
+    }
+    Ok(Expression::FunctionLiteral(FunctionLiteral {
+        token: token(TokenType::FUNCTION, "fn", span),
+        span,
+        parameters,
+        parameter_types,
+        return_type: fields.optional_type("returns")?,
+        body: function_body(fields.block("body")?),
+    }))
+}
+
+fn decode_macro(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::MacroLiteral(MacroLiteral {
+        token: token(TokenType::MACRO, "macro", span),
+        span,
+        parameters: fields.parameters()?,
+        body: fields.block("body")?,
+    }))
+}
+
+fn decode_call(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::CallExpression(CallExpression {
+        token: token(TokenType::LPAREN, "(", span),
+        span,
+        function: Box::new(fields.expression("function")?),
+        arguments: fields.expressions("arguments")?,
+        tail: false,
+    }))
+}
+
+fn decode_array(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::ArrayLiteral(ArrayLiteral {
+        token: token(TokenType::LBRACKET, "[", span),
+        span,
+        elements: fields.expressions("elements")?,
+    }))
+}
+
+fn decode_index(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::IndexExpression(IndexExpression {
+        token: token(TokenType::LBRACKET, "[", span),
+        span,
+        left: Box::new(fields.expression("left")?),
+        index: Box::new(fields.expression("index")?),
+    }))
+}
+
+fn decode_hash(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::HashLiteral(HashLiteral {
+        token: token(TokenType::LBRACE, "{", span),
+        span,
+        pairs: fields
+            .array("pairs")?
+            .iter()
+            .map(|pair| {
+                let pair = Fields::new(pair, "hash pair")?;
+                Ok((pair.expression("key")?, pair.expression("value")?))
+            })
+            .collect::<Result<_, String>>()?,
+    }))
+}
+
+fn decode_member(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::MemberExpression(MemberExpression {
+        token: token(TokenType::DOT, ".", span),
+        span,
+        object: Box::new(fields.expression("object")?),
+        property: fields.identifier("property")?,
+    }))
+}
+
+fn decode_try(fields: &Fields, span: Span) -> Result<Expression, String> {
+    Ok(Expression::TryExpression(TryExpression {
+        token: token(TokenType::TRY, "try", span),
+        span,
+        body: fields.block("body")?,
+        catch: match fields.get("catch")? {
+            Json::Null => None,
+            json => {
+                let catch = Fields::new(json, "catch clause")?;
+                Some(Box::new(CatchClause {
+                    parameter: catch.identifier("parameter")?,
+                    body: catch.block("body")?,
+                }))
+            }
+        },
+        finally: match fields.get("finally")? {
+            Json::Null => None,
+            json => Some(decode_block(json)?),
+        },
+    }))
+}
+
+fn expression_token(expression: &Expression) -> Token {
+    match expression {
+        Expression::Identifier(id) => id.token.clone(),
+        Expression::IntegerLiteral(il) => il.token.clone(),
+        Expression::Boolean(b) => b.token.clone(),
+        Expression::PrefixExpression(pe) => pe.token.clone(),
+        Expression::InfixExpression(ie) => ie.token.clone(),
+        Expression::IfExpression(ie) => ie.token.clone(),
+        Expression::FunctionLiteral(fl) => fl.token.clone(),
+        Expression::CallExpression(ce) => ce.token.clone(),
+        Expression::StringLiteral(sl) => sl.token.clone(),
+        Expression::ArrayLiteral(al) => al.token.clone(),
+        Expression::IndexExpression(ie) => ie.token.clone(),
+        Expression::HashLiteral(hl) => hl.token.clone(),
+        Expression::AssignExpression(ae) => ae.token.clone(),
+        Expression::MacroLiteral(ml) => ml.token.clone(),
+        Expression::MemberExpression(me) => me.token.clone(),
+        Expression::TryExpression(te) => te.token.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::STACK_SIZE;
+    use crate::parser::parse_ok;
+
+    #[test]
+    fn test_json_values() {
+        let tests = [
+            ("null", Json::Null),
+            (" true ", Json::Bool(true)),
+            ("-42", Json::Number(-42)),
+            (
+                r#""a\"b\\c\né😀""#,
+                Json::String("a\"b\\c\né😀".to_string()),
+            ),
+            (
+                r#"{"a": [1, {}, []], "b": false}"#,
+                Json::Object(vec![
+                    (
+                        "a".to_string(),
+                        Json::Array(vec![
+                            Json::Number(1),
+                            Json::Object(vec![]),
+                            Json::Array(vec![]),
+                        ]),
+                    ),
+                    ("b".to_string(), Json::Bool(false)),
+                ]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let value = parse(input).unwrap();
+            assert_eq!(value, expected, "input: {}", input);
+            assert_eq!(parse(&value.to_string()).unwrap(), expected);
+        }
+
+        assert_eq!(
+            Json::String("\"\u{1}\t".to_string()).to_string(),
+            r#""\"\u0001\t""#
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let tests = [
+            ("", "invalid JSON at offset 0: unexpected end of input"),
+            ("[1,]", "invalid JSON at offset 3: unexpected character"),
+            (
+                "1.5",
+                "invalid JSON at offset 1: only integer numbers are supported",
+            ),
+            ("{\"a\" 1}", "invalid JSON at offset 5: expected ':'"),
+            ("\"abc", "invalid JSON at offset 5: unterminated string"),
+            ("nul", "invalid JSON at offset 3: expected null"),
+            (
+                "[] x",
+                "invalid JSON at offset 3: unexpected trailing characters",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse(input).unwrap_err(), expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_json_depth() {
+        // reading recurses once for each array and object, as deep as the
+        // command line's stack allows for
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| {
+                assert_eq!(
+                    parse(&"[".repeat(1_000_000)).unwrap_err(),
+                    format!(
+                        "invalid JSON at offset {}: nested more than {} levels deep",
+                        MAX_DEPTH, MAX_DEPTH
+                    )
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_encode_token() {
+        let tok = Lexer::new("  let\n foo").next_token();
+        assert_eq!(
+            encode_token(&tok).to_string(),
+            r#"{"type":"LET","literal":"let","span":{"start":{"line":1,"column":3},"end":{"line":1,"column":6}}}"#
+        );
+
+        let decoded = decode_token(&encode_token(&tok)).unwrap();
+        assert_eq!(decoded.token_type, TokenType::LET);
+        assert_eq!(decoded.literal, "let");
+        assert_eq!(decoded.span, tok.span);
+    }
+
+    #[test]
+    fn test_encode_program() {
+        let program = parse_ok("let x = -a;\nf(x)");
+
+        assert_eq!(
+            encode_program(&program).to_string(),
+            concat!(
+                r#"{"kind":"Program","statements":["#,
+                r#"{"kind":"LetStatement","span":{"start":{"line":1,"column":1},"end":{"line":1,"column":12}},"#,
+                r#""name":{"kind":"Identifier","span":{"start":{"line":1,"column":5},"end":{"line":1,"column":6}},"value":"x"},"type":null,"#,
+                r#""value":{"kind":"PrefixExpression","span":{"start":{"line":1,"column":9},"end":{"line":1,"column":11}},"operator":"-","#,
+                r#""right":{"kind":"Identifier","span":{"start":{"line":1,"column":10},"end":{"line":1,"column":11}},"value":"a"}},"#,
+                r#""const":false,"exported":false},"#,
+                r#"{"kind":"ExpressionStatement","span":{"start":{"line":2,"column":1},"end":{"line":2,"column":5}},"#,
+                r#""expression":{"kind":"CallExpression","span":{"start":{"line":2,"column":1},"end":{"line":2,"column":5}},"#,
+                r#""function":{"kind":"Identifier","span":{"start":{"line":2,"column":1},"end":{"line":2,"column":2}},"value":"f"},"#,
+                r#""arguments":[{"kind":"Identifier","span":{"start":{"line":2,"column":3},"end":{"line":2,"column":4}},"value":"x"}]}}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_node_spans() {
+        let input = "let add = fn(a, b) {\n  a + b;\n};\nxs[0] += {\"k\": true}.k;";
+        let program = parse_ok(input);
+
+        let spans: Vec<(usize, usize, usize, usize)> = program
+            .statements
+            .iter()
+            .map(|s| {
+                let span = s.span();
+                (
+                    span.start.line,
+                    span.start.column,
+                    span.end.line,
+                    span.end.column,
+                )
+            })
+            .collect();
+        assert_eq!(spans, vec![(1, 1, 3, 3), (4, 1, 4, 24)]);
+
+        let body = match &program.statements[0] {
+            Statement::LetStatement(LetStatement {
+                value: Some(Expression::FunctionLiteral(fl)),
+                ..
+            }) => &fl.body,
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        assert_eq!(
+            (body.span.start, body.span.end),
+            (
+                Position {
+                    line: 1,
+                    column: 20
+                },
+                Position { line: 3, column: 2 }
+            )
+        );
+        assert_eq!(
+            body.statements[0].span().end,
+            Position { line: 2, column: 9 }
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let input = r#"
+import "lib.monkey" as lib;
+export const a = -1 + 2 * lib.three;
+let b = [3, {"four": !true}][5];
+let f = fn(x: int, y) -> [int] { if (x > y) { return x; } else { x = y; } };
+let g: fn({string: int}, null) -> bool = fn() { true };
+let m = macro(q) { quote(unquote(q) + 1) };
+while (a < 10) { a += 1; if (a == 5) { break; } continue; }
+for (item in "str") { f(item, false)[0]; }
+puts(123456789012345678901234567890 - 9223372036854775807);
+let r = try { throw {"message": "no"}; } catch (e) { e.message } finally { puts(r) };
+try { f(1) } finally { 2 };
+"#;
+        let program = parse_ok(input);
+        let json = encode_program(&program).to_string();
+
+        let decoded = decode_program(&parse(&json).unwrap()).unwrap();
+
+        assert_eq!(decoded.to_string(), program.to_string());
+        assert_eq!(encode_program(&decoded).to_string(), json);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let span = r#""span":{"start":{"line":1,"column":1},"end":{"line":1,"column":2}}"#;
+        let tests = [
+            (r#"[]"#.to_string(), "program must be an object, got array"),
+            (
+                r#"{"kind":"Statement"}"#.to_string(),
+                "expected Program, got Statement",
+            ),
+            (
+                r#"{"kind":"Program","statements":[{"kind":"Nope"}]}"#.to_string(),
+                "Nope is missing field \"span\"",
+            ),
+            (
+                format!(
+                    r#"{{"kind":"Program","statements":[{{"kind":"Nope",{}}}]}}"#,
+                    span
+                ),
+                "unknown statement kind \"Nope\"",
+            ),
+            (
+                format!(
+                    r#"{{"kind":"Program","statements":[{{"kind":"ExpressionStatement",{},"expression":{{"kind":"InfixExpression",{},"operator":"@"}}}}]}}"#,
+                    span, span
+                ),
+                "unknown operator \"@\" in InfixExpression",
+            ),
+            (
+                format!(
+                    r#"{{"kind":"Program","statements":[{{"kind":"ExpressionStatement",{},"expression":{{"kind":"IntegerLiteral",{},"value":"one"}}}}]}}"#,
+                    span, span
+                ),
+                "field \"value\" of IntegerLiteral must be an integer, got string",
+            ),
+            (
+                format!(
+                    r#"{{"kind":"Program","statements":[{{"kind":"LetStatement",{},"name":{{"kind":"Identifier",{},"value":"x"}},"type":"[int","value":null,"const":false,"exported":false}}]}}"#,
+                    span, span
+                ),
+                "field \"type\" of LetStatement holds an invalid type: expected next token to be RBRACKET, got EOF instead",
+            ),
+            (
+                format!(
+                    r#"{{"kind":"Program","statements":[{{"kind":"ImportStatement",{},"path":"a","alias":{{"kind":"Boolean",{},"value":true}}}}]}}"#,
+                    span, span
+                ),
+                "expected Identifier, got Boolean",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let json = parse(&input).unwrap();
+            assert_eq!(
+                decode_program(&json).unwrap_err(),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_nesting() {
+        // decoding recurses like the passes over a tree do, which need the
+        // stack of the command line rather than of a test
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(check_decode_nesting)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn check_decode_nesting() {
+        let span = r#""span":{"start":{"line":1,"column":1},"end":{"line":1,"column":2}}"#;
+        let nested = |depth: usize| {
+            let mut expression = format!(r#"{{"kind":"IntegerLiteral",{},"value":1}}"#, span);
+            for _ in 1..depth {
+                expression = format!(
+                    r#"{{"kind":"PrefixExpression",{},"operator":"-","right":{}}}"#,
+                    span, expression
+                );
+            }
+            format!(
+                r#"{{"kind":"Program","statements":[{{"kind":"ExpressionStatement",{},"expression":{}}}]}}"#,
+                span, expression
+            )
+        };
+
+        // the statement takes a level too
+        assert!(decode_program(&parse(&nested(MAX_NESTING - 1)).unwrap()).is_ok());
+        assert_eq!(
+            decode_program(&parse(&nested(MAX_NESTING)).unwrap()).unwrap_err(),
+            format!("program is nested more than {} levels deep", MAX_NESTING)
+        );
+
+        // operands on the left stay at the level of their operators, but
+        // each operator chained on makes the tree a level deeper
+        let chain = |depth: usize| {
+            let literal = format!(r#"{{"kind":"IntegerLiteral",{},"value":1}}"#, span);
+            let expression = format!(
+                "{}{}{}",
+                format!(r#"{{"kind":"InfixExpression",{},"left":"#, span).repeat(depth - 1),
+                literal,
+                format!(r#","operator":"+","right":{}}}"#, literal).repeat(depth - 1)
+            );
+            format!(
+                r#"{{"kind":"Program","statements":[{{"kind":"ExpressionStatement",{},"expression":{}}}]}}"#,
+                span, expression
+            )
+        };
+        assert!(decode_program(&parse(&chain(MAX_TREE_DEPTH - 1)).unwrap()).is_ok());
+        assert_eq!(
+            decode_program(&parse(&chain(MAX_TREE_DEPTH)).unwrap()).unwrap_err(),
+            format!("program is nested more than {} levels deep", MAX_TREE_DEPTH)
+        );
+    }
+}
@@ -6,6 +6,9 @@ pub struct Lexer {
     read_position: usize,
     ch: char,
     char_list: Vec<char>,
+    // position of `ch` in the source
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
@@ -16,21 +19,26 @@ impl Lexer {
             read_position: 0,
             ch: '\0',
             char_list: Vec::new(),
+            line: 1,
+            column: 0,
         };
 
-        l.read_char();
         l.char_list = l.input.chars().collect();
+        l.read_char();
 
         l
     }
 
     pub fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = self.input.chars().nth(self.read_position).unwrap();
+            self.column += 1;
         }
 
+        self.ch = self.char_at(self.read_position);
+
         self.position = self.read_position;
         self.read_position += 1;
     }
@@ -38,46 +46,97 @@ impl Lexer {
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
 
+        let start = self.current_position();
+        let mut tok = self.read_token();
+        tok.span = Span {
+            start,
+            end: self.current_position(),
+        };
+        tok
+    }
+
+    fn current_position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         let tok = match self.ch {
+            '+' | '-' | '*' | '/' if self.peek_char() == '=' => {
+                let token_type = match self.ch {
+                    '+' => TokenType::PlusAssign,
+                    '-' => TokenType::MinusAssign,
+                    '*' => TokenType::AsteriskAssign,
+                    _ => TokenType::SlashAssign,
+                };
+                return self.new_double_token(token_type);
+            }
+            '-' if self.peek_char() == '>' => return self.new_double_token(TokenType::ARROW),
             '+' => self.new_token(TokenType::PLUS, self.ch),
             '-' => self.new_token(TokenType::MINUS, self.ch),
             '*' => self.new_token(TokenType::ASTERISK, self.ch),
             '/' => self.new_token(TokenType::SLASH, self.ch),
+            '%' => self.new_token(TokenType::PERCENT, self.ch),
+            '^' => self.new_token(TokenType::CARET, self.ch),
             '=' => {
                 if self.peek_char() == '=' {
-                    self.read_char();
-                    self.read_char();
-                    return Token {
-                        token_type: TokenType::EQ,
-                        literal: "==".to_string(),
-                    };
+                    return self.new_double_token(TokenType::EQ);
                 } else {
                     self.new_token(TokenType::ASSIGN, self.ch)
                 }
             }
-            '>' => self.new_token(TokenType::GT, self.ch),
-            '<' => self.new_token(TokenType::LT, self.ch),
+            '>' => match self.peek_char() {
+                '=' => return self.new_double_token(TokenType::GtEq),
+                '>' => return self.new_double_token(TokenType::RShift),
+                _ => self.new_token(TokenType::GT, self.ch),
+            },
+            '<' => match self.peek_char() {
+                '=' => return self.new_double_token(TokenType::LtEq),
+                '<' => return self.new_double_token(TokenType::LShift),
+                _ => self.new_token(TokenType::LT, self.ch),
+            },
+            '&' => {
+                if self.peek_char() == '&' {
+                    return self.new_double_token(TokenType::AND);
+                } else {
+                    self.new_token(TokenType::AMPERSAND, self.ch)
+                }
+            }
+            '|' => {
+                if self.peek_char() == '|' {
+                    return self.new_double_token(TokenType::OR);
+                } else {
+                    self.new_token(TokenType::PIPE, self.ch)
+                }
+            }
             ',' => self.new_token(TokenType::COMMA, self.ch),
             ';' => self.new_token(TokenType::SEMICOLON, self.ch),
+            ':' => self.new_token(TokenType::COLON, self.ch),
+            '.' => self.new_token(TokenType::DOT, self.ch),
             '(' => self.new_token(TokenType::LPAREN, self.ch),
             ')' => self.new_token(TokenType::RPAREN, self.ch),
             '!' => {
                 if self.peek_char() == '=' {
-                    self.read_char();
-                    self.read_char();
-                    return Token {
-                        token_type: TokenType::NotEq,
-                        literal: "!=".to_string(),
-                    };
+                    return self.new_double_token(TokenType::NotEq);
                 } else {
                     self.new_token(TokenType::BANG, self.ch)
                 }
             }
             '{' => self.new_token(TokenType::LBRACE, self.ch),
             '}' => self.new_token(TokenType::RBRACE, self.ch),
-            '\0' => Token {
+            '[' => self.new_token(TokenType::LBRACKET, self.ch),
+            ']' => self.new_token(TokenType::RBRACKET, self.ch),
+            '"' => Token {
+                token_type: TokenType::STRING,
+                literal: self.read_string(),
+                ..Default::default()
+            },
+            _ if self.at_end() => Token {
                 token_type: TokenType::EOF,
                 literal: "".to_string(),
+                ..Default::default()
             },
 
             _ => {
@@ -86,17 +145,16 @@ impl Lexer {
                     return Token {
                         literal: literal.clone(),
                         token_type: lookup_ident(literal),
+                        ..Default::default()
                     };
-                } else if self.ch.is_digit(10) {
+                } else if self.ch.is_ascii_digit() {
                     return Token {
                         token_type: TokenType::INT,
                         literal: self.read_number(),
+                        ..Default::default()
                     };
                 } else {
-                    return Token {
-                        token_type: TokenType::EOF,
-                        literal: self.ch.to_string(),
-                    };
+                    self.new_token(TokenType::ILLEGAL, self.ch)
                 }
             }
         };
@@ -105,12 +163,23 @@ impl Lexer {
     }
 
     pub fn new_token(&self, token_type: TokenType, ch: char) -> Token {
-        let mut buffer = [0; 1];
-        ch.encode_utf8(&mut buffer);
-        let literal = std::str::from_utf8(&buffer).unwrap().to_owned();
+        Token {
+            token_type,
+            literal: ch.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Builds a token out of the current and the next character, leaving the
+    /// lexer positioned after both.
+    pub fn new_double_token(&mut self, token_type: TokenType) -> Token {
+        let literal = format!("{}{}", self.ch, self.peek_char());
+        self.read_char();
+        self.read_char();
         Token {
             token_type,
             literal,
+            ..Default::default()
         }
     }
 
@@ -131,7 +200,7 @@ impl Lexer {
         let pos = self.position;
 
         loop {
-            if self.ch.is_digit(10) {
+            if self.ch.is_ascii_digit() {
                 self.read_char();
             } else {
                 break;
@@ -141,10 +210,28 @@ impl Lexer {
         self.get_substring(pos, self.position)
     }
 
+    pub fn read_string(&mut self) -> String {
+        let pos = self.position + 1;
+
+        loop {
+            self.read_char();
+            if self.ch == '"' || self.at_end() {
+                break;
+            }
+        }
+
+        self.get_substring(pos, self.position)
+    }
+
+    /// Skips whitespace and `//` comments, which run to the end of the line.
     pub fn skip_whitespace(&mut self) {
         loop {
             if self.ch.is_whitespace() {
                 self.read_char()
+            } else if self.ch == '/' && self.peek_char() == '/' {
+                while self.ch != '\n' && !self.at_end() {
+                    self.read_char();
+                }
             } else {
                 break;
             }
@@ -155,12 +242,19 @@ impl Lexer {
         String::from_iter(self.char_list[start..end].iter())
     }
 
-    pub fn peek_char(&mut self) -> char {
-        if self.read_position >= self.input.len() {
-            return '\0';
-        } else {
-            return self.char_list.get(self.read_position).unwrap().clone();
-        }
+    pub fn peek_char(&self) -> char {
+        self.char_at(self.read_position)
+    }
+
+    /// Returns the character at `index`, or `'\0'` past the end of the input.
+    fn char_at(&self, index: usize) -> char {
+        self.char_list.get(index).copied().unwrap_or('\0')
+    }
+
+    /// Whether `ch` is past the end of the input, as opposed to a NUL
+    /// character inside it.
+    fn at_end(&self) -> bool {
+        self.position >= self.char_list.len()
     }
 }
 
@@ -188,6 +282,23 @@ if (5 < 10) {
 
 10 == 10;
 10 != 9;
+a <= b >= c;
+a && b || c;
+10 % 3;
+a & b | c ^ d;
+1 << 2 >> 3;
+"foobar"
+"foo bar"
+[1, 2];
+{"foo": "bar"}
+while (x) { break; continue; }
+for (x in xs) {}
+const x = 1;
+x = x += x -= x *= x /= 2;
+macro(x, y) { x + y; };
+import "lib.monkey" as lib;
+export let x = lib.y;
+a -> b - > c;
 "#;
 
         let tests: Vec<(TokenType, String)> = vec![
@@ -264,6 +375,117 @@ if (5 < 10) {
             (TokenType::NotEq, "!=".to_string()),
             (TokenType::INT, "9".to_string()),
             (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IDENT, "a".to_string()),
+            (TokenType::LtEq, "<=".to_string()),
+            (TokenType::IDENT, "b".to_string()),
+            (TokenType::GtEq, ">=".to_string()),
+            (TokenType::IDENT, "c".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IDENT, "a".to_string()),
+            (TokenType::AND, "&&".to_string()),
+            (TokenType::IDENT, "b".to_string()),
+            (TokenType::OR, "||".to_string()),
+            (TokenType::IDENT, "c".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::INT, "10".to_string()),
+            (TokenType::PERCENT, "%".to_string()),
+            (TokenType::INT, "3".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IDENT, "a".to_string()),
+            (TokenType::AMPERSAND, "&".to_string()),
+            (TokenType::IDENT, "b".to_string()),
+            (TokenType::PIPE, "|".to_string()),
+            (TokenType::IDENT, "c".to_string()),
+            (TokenType::CARET, "^".to_string()),
+            (TokenType::IDENT, "d".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::INT, "1".to_string()),
+            (TokenType::LShift, "<<".to_string()),
+            (TokenType::INT, "2".to_string()),
+            (TokenType::RShift, ">>".to_string()),
+            (TokenType::INT, "3".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::STRING, "foobar".to_string()),
+            (TokenType::STRING, "foo bar".to_string()),
+            (TokenType::LBRACKET, "[".to_string()),
+            (TokenType::INT, "1".to_string()),
+            (TokenType::COMMA, ",".to_string()),
+            (TokenType::INT, "2".to_string()),
+            (TokenType::RBRACKET, "]".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::LBRACE, "{".to_string()),
+            (TokenType::STRING, "foo".to_string()),
+            (TokenType::COLON, ":".to_string()),
+            (TokenType::STRING, "bar".to_string()),
+            (TokenType::RBRACE, "}".to_string()),
+            (TokenType::WHILE, "while".to_string()),
+            (TokenType::LPAREN, "(".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::RPAREN, ")".to_string()),
+            (TokenType::LBRACE, "{".to_string()),
+            (TokenType::BREAK, "break".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::CONTINUE, "continue".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::RBRACE, "}".to_string()),
+            (TokenType::FOR, "for".to_string()),
+            (TokenType::LPAREN, "(".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::IN, "in".to_string()),
+            (TokenType::IDENT, "xs".to_string()),
+            (TokenType::RPAREN, ")".to_string()),
+            (TokenType::LBRACE, "{".to_string()),
+            (TokenType::RBRACE, "}".to_string()),
+            (TokenType::CONST, "const".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::ASSIGN, "=".to_string()),
+            (TokenType::INT, "1".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::ASSIGN, "=".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::PlusAssign, "+=".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::MinusAssign, "-=".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::AsteriskAssign, "*=".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::SlashAssign, "/=".to_string()),
+            (TokenType::INT, "2".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::MACRO, "macro".to_string()),
+            (TokenType::LPAREN, "(".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::COMMA, ",".to_string()),
+            (TokenType::IDENT, "y".to_string()),
+            (TokenType::RPAREN, ")".to_string()),
+            (TokenType::LBRACE, "{".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::PLUS, "+".to_string()),
+            (TokenType::IDENT, "y".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::RBRACE, "}".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IMPORT, "import".to_string()),
+            (TokenType::STRING, "lib.monkey".to_string()),
+            (TokenType::AS, "as".to_string()),
+            (TokenType::IDENT, "lib".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::EXPORT, "export".to_string()),
+            (TokenType::LET, "let".to_string()),
+            (TokenType::IDENT, "x".to_string()),
+            (TokenType::ASSIGN, "=".to_string()),
+            (TokenType::IDENT, "lib".to_string()),
+            (TokenType::DOT, ".".to_string()),
+            (TokenType::IDENT, "y".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
+            (TokenType::IDENT, "a".to_string()),
+            (TokenType::ARROW, "->".to_string()),
+            (TokenType::IDENT, "b".to_string()),
+            (TokenType::MINUS, "-".to_string()),
+            (TokenType::GT, ">".to_string()),
+            (TokenType::IDENT, "c".to_string()),
+            (TokenType::SEMICOLON, ";".to_string()),
             (TokenType::EOF, "".to_string()),
         ];
 
@@ -280,4 +502,67 @@ if (5 < 10) {
             assert_eq!(tok.literal, expected_literal);
         }
     }
+
+    #[test]
+    fn test_illegal_and_non_ascii_characters() {
+        let input = "let é = \"ü→\"; @ \0 → x";
+
+        let tests = vec![
+            (TokenType::LET, "let"),
+            (TokenType::IDENT, "é"),
+            (TokenType::ASSIGN, "="),
+            (TokenType::STRING, "ü→"),
+            (TokenType::SEMICOLON, ";"),
+            (TokenType::ILLEGAL, "@"),
+            (TokenType::ILLEGAL, "\0"),
+            (TokenType::ILLEGAL, "→"),
+            (TokenType::IDENT, "x"),
+            (TokenType::EOF, ""),
+            (TokenType::EOF, ""),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_literal) in tests {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, expected_type);
+            assert_eq!(tok.literal, expected_literal);
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "// leading\nlet x = 1; // trailing // nested\nx / 2 //";
+
+        let tests = vec![
+            (TokenType::LET, "let", 2),
+            (TokenType::IDENT, "x", 2),
+            (TokenType::ASSIGN, "=", 2),
+            (TokenType::INT, "1", 2),
+            (TokenType::SEMICOLON, ";", 2),
+            (TokenType::IDENT, "x", 3),
+            (TokenType::SLASH, "/", 3),
+            (TokenType::INT, "2", 3),
+            (TokenType::EOF, "", 3),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_literal, expected_line) in tests {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, expected_type);
+            assert_eq!(tok.literal, expected_literal);
+            assert_eq!(tok.span.start.line, expected_line);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut l = Lexer::new("\"ab");
+
+        let tok = l.next_token();
+        assert_eq!(tok.token_type, TokenType::STRING);
+        assert_eq!(tok.literal, "ab");
+        assert_eq!(l.next_token().token_type, TokenType::EOF);
+    }
 }
@@ -0,0 +1,49 @@
+//! An interpreter for the Monkey programming language.
+//!
+//! Host applications embed it through `Interpreter`, which evaluates source
+//! code, shares globals with it and registers Rust closures as Monkey
+//! functions. The other modules are the stages of the interpreter, for tools
+//! built on them.
+
+pub mod ast;
+mod backend;
+pub mod bigint;
+pub mod builtins;
+pub mod c;
+pub mod capi;
+pub mod check;
+pub mod convert;
+pub mod debugger;
+pub mod environment;
+pub mod evaluator;
+pub mod exception;
+pub mod fuzz;
+pub mod gc;
+#[cfg(test)]
+mod golden;
+pub mod interpreter;
+pub mod js;
+pub mod json;
+pub mod lexer;
+pub mod limits;
+pub mod macro_expansion;
+pub mod modify;
+pub mod module;
+pub mod object;
+pub mod optimizer;
+pub mod parser;
+pub mod printer;
+pub mod repl;
+pub mod stack_trace;
+pub mod tail_calls;
+pub mod token;
+pub mod types;
+pub mod visitor;
+pub mod wat;
+#[cfg(test)]
+mod wat_vm;
+
+pub use convert::{FromObject, HostFunction, IntoArgs, IntoObject};
+pub use interpreter::{Error, Interpreter};
+pub use limits::Limits;
+pub use object::Object;
@@ -0,0 +1,653 @@
+//! Bounds on the resources one evaluation may use, so that untrusted
+//! scripts cannot hang or crash the process running them.
+//!
+//! An `Evaluator` meters each top-level evaluation against its `Limits`:
+//! every statement and expression evaluated burns one unit of fuel, every
+//! call of a function goes one level deeper, unless it is in tail position
+//! and takes the place of its caller, and every string, array, hash or
+//! function created counts as an allocation of its size in bytes. The
+//! first limit exceeded stops the evaluation with an
+//! `Object::LimitExceeded`.
+//!
+//! Evaluating statements and expressions nested in each other takes stack
+//! as calls do, and how much of it each level takes depends on what it is
+//! and on how the crate was built, so the stack is measured as evaluation
+//! goes deeper: the first level that would take more than `max_stack` bytes
+//! of it stops the evaluation as well.
+//!
+//! Operations on big integers can take far longer and make far larger
+//! results than their operands suggest, and so can builtins copying arrays
+//! with all they hold, so their `Cost` is reserved before they run and they
+//! are refused when it does not fit. The syntax trees that quoting and the
+//! expansion of macros build count too: a step and an allocation for each
+//! of their nodes.
+
+use std::fmt;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use crate::ast::Expression;
+use crate::bigint::BigInt;
+use crate::object::*;
+
+// the stack left for the host, and for what runs between two measurements
+const STACK_RESERVE: usize = 512 << 10;
+
+/// The size of a thread's stack unless it is given another, in Rust's
+/// standard library.
+pub const EMBEDDED_STACK_SIZE: usize = 2 << 20;
+
+/// The stack evaluation may take by default, which a thread of
+/// `EMBEDDED_STACK_SIZE` bytes holds.
+pub const EMBEDDED_MAX_STACK: usize = max_stack_for(EMBEDDED_STACK_SIZE);
+
+/// The size of the stack the command line runs scripts on.
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// The stack evaluation may take on a thread of `stack_size` bytes.
+pub const fn max_stack_for(stack_size: usize) -> usize {
+    stack_size.saturating_sub(STACK_RESERVE)
+}
+
+// steps between two looks at the clock
+const CLOCK_INTERVAL: u64 = 1024;
+
+// operations on 32-bit digits taking about as long as a step
+const WORK_PER_STEP: u64 = 64;
+
+// operations on 32-bit digits a fast machine makes in a second
+const WORK_PER_SECOND: u64 = 1 << 30;
+
+/// Each limit is unbounded when `None`. The default only bounds the stack
+/// evaluation takes, to what a thread of Rust's standard library holds
+/// unless it is given a smaller one; threads with more stack can take more
+/// of it, see `Limits::for_stack`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// The number of statements and expressions evaluated.
+    pub fuel: Option<u64>,
+    /// The number of function calls in progress at once.
+    pub max_depth: Option<usize>,
+    /// The bytes of stack taken by the calls, statements and expressions
+    /// in progress at once.
+    pub max_stack: Option<usize>,
+    /// The number of strings, arrays, hashes and functions created.
+    pub max_allocations: Option<u64>,
+    /// The total size of the objects created.
+    pub max_bytes: Option<u64>,
+    /// How long an evaluation may run.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::for_stack(EMBEDDED_STACK_SIZE)
+    }
+}
+
+impl Limits {
+    /// Limits bounding nothing but the stack, to what a thread of
+    /// `stack_size` bytes holds.
+    pub fn for_stack(stack_size: usize) -> Limits {
+        Limits {
+            fuel: None,
+            max_depth: None,
+            max_stack: Some(max_stack_for(stack_size)),
+            max_allocations: None,
+            max_bytes: None,
+            timeout: None,
+        }
+    }
+}
+
+/// The limit an evaluation ran into, with its bound.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Fuel(u64),
+    Depth(usize),
+    Stack(usize),
+    Allocations(u64),
+    Bytes(u64),
+    Time(Duration),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "limit exceeded: ")?;
+        match self {
+            LimitExceeded::Fuel(fuel) => write!(f, "ran out of fuel after {} steps", fuel),
+            LimitExceeded::Depth(depth) => write!(f, "more than {} nested calls", depth),
+            LimitExceeded::Stack(stack) => write!(f, "more than {} bytes of stack taken", stack),
+            LimitExceeded::Allocations(allocations) => {
+                write!(f, "more than {} objects allocated", allocations)
+            }
+            LimitExceeded::Bytes(bytes) => write!(f, "more than {} bytes allocated", bytes),
+            LimitExceeded::Time(timeout) => write!(f, "ran for longer than {:?}", timeout),
+        }
+    }
+}
+
+/// What an operation on integers is estimated to use before it runs: the
+/// size of its result and the operations on digits it makes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cost {
+    pub bytes: u64,
+    pub work: u64,
+}
+
+impl Cost {
+    /// The cost of `left operator right` on integers beyond 64 bits, none
+    /// for anything else.
+    pub fn of_infix(operator: &str, left: &Object, right: &Object) -> Cost {
+        let (Some(left), Some(right)) = (digits(left), digits(right)) else {
+            return Cost::default();
+        };
+        if left.max(right) <= 2 {
+            return Cost::default();
+        }
+        match operator {
+            "*" => Cost {
+                bytes: (left + right) * size_of::<u32>() as u64,
+                work: left * right,
+            },
+            // long division goes through the dividend a bit at a time
+            "/" | "%" => Cost {
+                bytes: left * size_of::<u32>() as u64,
+                work: left * 32 * right,
+            },
+            _ => Cost {
+                bytes: (left.max(right) + 1) * size_of::<u32>() as u64,
+                work: left.max(right),
+            },
+        }
+    }
+
+    /// The cost of raising `base` to the power `exponent` by squaring, whose
+    /// last squaring takes about as long as all of the others.
+    pub fn of_pow(base: &BigInt, exponent: u64) -> Cost {
+        let digits = base.bits().saturating_mul(exponent).div_ceil(32);
+        Cost {
+            bytes: digits.saturating_mul(size_of::<u32>() as u64),
+            work: digits.saturating_mul(digits) / 2,
+        }
+    }
+}
+
+impl Cost {
+    /// The cost of copying `objects` with everything they hold, as builtins
+    /// do with the arrays they take.
+    pub fn of_copy<'a>(objects: impl IntoIterator<Item = &'a Object>) -> Cost {
+        let bytes: u64 = objects.into_iter().map(deep_size).sum();
+        Cost {
+            bytes,
+            work: bytes / size_of::<u32>() as u64,
+        }
+    }
+}
+
+fn digits(obj: &Object) -> Option<u64> {
+    match obj {
+        Object::Integer(_) => Some(2),
+        Object::BigInteger(i) => Some(i.digits() as u64),
+        _ => None,
+    }
+}
+
+/// Tracks what an evaluation has used so far.
+#[derive(Debug, Default)]
+pub struct Meter {
+    limits: Limits,
+    running: bool,
+    steps: u64,
+    depth: usize,
+    // where the stack stood when the evaluation started
+    stack_base: Option<usize>,
+    allocations: u64,
+    bytes: u64,
+    deadline: Option<Instant>,
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Meter {
+        Meter {
+            limits,
+            ..Meter::default()
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Starts metering an evaluation from scratch, unless one is running
+    /// already. Returns whether it started one, which must then be stopped.
+    pub fn start(&mut self) -> bool {
+        if self.running {
+            return false;
+        }
+
+        *self = Meter {
+            running: true,
+            stack_base: Some(stack_position()),
+            deadline: self.limits.timeout.map(|timeout| Instant::now() + timeout),
+            ..Meter::new(self.limits.clone())
+        };
+        true
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Burns one unit of fuel, and looks at the clock now and then.
+    pub fn step(&mut self) -> Result<(), LimitExceeded> {
+        self.steps += 1;
+
+        if let Some(fuel) = self.limits.fuel {
+            if self.steps > fuel {
+                return Err(LimitExceeded::Fuel(fuel));
+            }
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline {
+                return Err(LimitExceeded::Time(timeout));
+            }
+        }
+        Ok(())
+    }
+
+    /// Enters a function call, which must be left again with `leave` when
+    /// this succeeds.
+    pub fn enter(&mut self) -> Result<(), LimitExceeded> {
+        if let Some(max_depth) = self.limits.max_depth {
+            if self.depth >= max_depth {
+                return Err(LimitExceeded::Depth(max_depth));
+            }
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Makes sure the stack taken since the evaluation started is within
+    /// the limit, before it goes a level deeper.
+    pub fn check_stack(&self) -> Result<(), LimitExceeded> {
+        if let (Some(base), Some(max_stack)) = (self.stack_base, self.limits.max_stack) {
+            if base.abs_diff(stack_position()) > max_stack {
+                return Err(LimitExceeded::Stack(max_stack));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes sure an operation costing `cost` stays within the limits before
+    /// it runs, burning fuel for its work. Work that could not finish in the
+    /// time left even on a fast machine is refused right away.
+    pub fn reserve(&mut self, cost: Cost) -> Result<(), LimitExceeded> {
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if self.bytes.saturating_add(cost.bytes) > max_bytes {
+                return Err(LimitExceeded::Bytes(max_bytes));
+            }
+        }
+
+        let steps = cost.work / WORK_PER_STEP;
+        self.steps = self.steps.saturating_add(steps);
+        if let Some(fuel) = self.limits.fuel {
+            if self.steps > fuel {
+                return Err(LimitExceeded::Fuel(fuel));
+            }
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if steps >= CLOCK_INTERVAL {
+                let needed = Duration::from_secs_f64(cost.work as f64 / WORK_PER_SECOND as f64);
+                let left = deadline.saturating_duration_since(Instant::now());
+                if needed >= left {
+                    return Err(LimitExceeded::Time(timeout));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a syntax tree of `nodes` nodes as built, burning a unit of
+    /// fuel for each. Looks at the clock right away, since trees can double
+    /// in size with every macro expanded.
+    pub fn build(&mut self, nodes: u64) -> Result<(), LimitExceeded> {
+        self.steps = self.steps.saturating_add(nodes);
+
+        if let Some(fuel) = self.limits.fuel {
+            if self.steps > fuel {
+                return Err(LimitExceeded::Fuel(fuel));
+            }
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if Instant::now() >= deadline {
+                return Err(LimitExceeded::Time(timeout));
+            }
+        }
+        self.count(nodes.saturating_mul(size_of::<Expression>() as u64))
+    }
+
+    /// Counts `obj` as allocated if it lives on the heap, without what it
+    /// holds, which was counted as it was made.
+    pub fn allocate(&mut self, obj: &Object) -> Result<(), LimitExceeded> {
+        match size(obj) {
+            Some(size) => self.count(size),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts `obj` as allocated with everything it holds, as it is when it
+    /// was copied whole.
+    pub fn allocate_copy(&mut self, obj: &Object) -> Result<(), LimitExceeded> {
+        match size(obj) {
+            Some(_) => self.count(deep_size(obj)),
+            None => Ok(()),
+        }
+    }
+
+    fn count(&mut self, size: u64) -> Result<(), LimitExceeded> {
+        self.allocations += 1;
+        self.bytes = self.bytes.saturating_add(size);
+
+        if let Some(max_allocations) = self.limits.max_allocations {
+            if self.allocations > max_allocations {
+                return Err(LimitExceeded::Allocations(max_allocations));
+            }
+        }
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if self.bytes > max_bytes {
+                return Err(LimitExceeded::Bytes(max_bytes));
+            }
+        }
+        Ok(())
+    }
+}
+
+// how far the stack has grown, as the address of a local in a frame on top
+// of it; it grows down on most machines and up on a few
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// the size of `obj` on the heap, if it lives there
+fn size(obj: &Object) -> Option<u64> {
+    let size = match obj {
+        Object::String(s) => s.len(),
+        Object::BigInteger(i) => i.size(),
+        Object::Array(elements) => elements.len() * size_of::<Object>(),
+        Object::Hash(pairs) => pairs.len() * size_of::<(HashKey, HashPair)>(),
+        Object::Function(_) => size_of::<Function>(),
+        _ => return None,
+    };
+    Some(size as u64)
+}
+
+// the size of `obj` on the heap with everything it holds
+fn deep_size(obj: &Object) -> u64 {
+    let held: u64 = match obj {
+        Object::Array(elements) => elements.iter().map(deep_size).sum(),
+        Object::Hash(pairs) => pairs
+            .values()
+            .map(|pair| deep_size(&pair.key) + deep_size(&pair.value))
+            .sum(),
+        _ => 0,
+    };
+    size(obj).unwrap_or(0) + held
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+
+    use crate::parser::parse_ok;
+
+    fn eval(input: &str, evaluator: &mut Evaluator) -> Object {
+        evaluator.eval_program(&parse_ok(input), &Environment::new())
+    }
+
+    fn eval_with(input: &str, limits: Limits) -> Object {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(limits);
+        eval(input, &mut evaluator)
+    }
+
+    #[test]
+    fn test_limits_exceeded() {
+        let recurse = "let f = fn(n) { f(n + 1) + 1 }; f(0)";
+        let grow = "let xs = []; while (true) { xs = push(xs, xs); }";
+        let tests = [
+            (
+                "while (true) { }",
+                Limits {
+                    fuel: Some(100),
+                    ..Limits::default()
+                },
+                LimitExceeded::Fuel(100),
+            ),
+            (
+                recurse,
+                Limits {
+                    max_depth: Some(50),
+                    ..Limits::default()
+                },
+                LimitExceeded::Depth(50),
+            ),
+            (
+                grow,
+                Limits {
+                    max_allocations: Some(10),
+                    ..Limits::default()
+                },
+                LimitExceeded::Allocations(10),
+            ),
+            (
+                grow,
+                Limits {
+                    max_bytes: Some(10_000),
+                    ..Limits::default()
+                },
+                LimitExceeded::Bytes(10_000),
+            ),
+            (
+                r#"let s = "x"; while (true) { s = s + s; }"#,
+                Limits {
+                    max_bytes: Some(1 << 20),
+                    ..Limits::default()
+                },
+                LimitExceeded::Bytes(1 << 20),
+            ),
+            (
+                "let n = 1; while (true) { n = n * 2; }",
+                Limits {
+                    max_bytes: Some(1 << 20),
+                    ..Limits::default()
+                },
+                LimitExceeded::Bytes(1 << 20),
+            ),
+            (
+                "let i = 0; while (true) { i += 1; }",
+                Limits {
+                    timeout: Some(Duration::from_millis(20)),
+                    ..Limits::default()
+                },
+                LimitExceeded::Time(Duration::from_millis(20)),
+            ),
+            // big integers are refused before they are computed
+            (
+                "let x = pow(3, 4000000); 1",
+                Limits {
+                    max_bytes: Some(500_000),
+                    ..Limits::default()
+                },
+                LimitExceeded::Bytes(500_000),
+            ),
+            (
+                "let x = pow(3, 4000000); 1",
+                Limits {
+                    timeout: Some(Duration::from_secs(1)),
+                    ..Limits::default()
+                },
+                LimitExceeded::Time(Duration::from_secs(1)),
+            ),
+            (
+                "let x = 2; while (true) { x = x * x; }",
+                Limits {
+                    timeout: Some(Duration::from_millis(100)),
+                    ..Limits::default()
+                },
+                LimitExceeded::Time(Duration::from_millis(100)),
+            ),
+            (
+                "let x = 2; while (true) { x *= x; }",
+                Limits {
+                    fuel: Some(10_000),
+                    ..Limits::default()
+                },
+                LimitExceeded::Fuel(10_000),
+            ), // so are the copies builtins make, with all they hold
+            (
+                grow,
+                Limits {
+                    max_bytes: Some(1_000_000),
+                    ..Limits::default()
+                },
+                LimitExceeded::Bytes(1_000_000),
+            ),
+            (
+                grow,
+                Limits {
+                    fuel: Some(100_000),
+                    ..Limits::default()
+                },
+                LimitExceeded::Fuel(100_000),
+            ),
+            (
+                grow,
+                Limits {
+                    timeout: Some(Duration::from_millis(100)),
+                    ..Limits::default()
+                },
+                LimitExceeded::Time(Duration::from_millis(100)),
+            ),
+        ];
+
+        for (input, limits, expected) in tests {
+            match eval_with(input, limits) {
+                Object::LimitExceeded(limit) => assert_eq!(limit, expected, "input: {}", input),
+                obj => panic!("no limit exceeded by {}, got {:?}", input, obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits_are_not_reached() {
+        let limits = Limits {
+            fuel: Some(200),
+            max_depth: Some(11),
+            max_stack: Some(1 << 20),
+            max_allocations: Some(3),
+            max_bytes: Some(100),
+            timeout: Some(Duration::from_secs(60)),
+        };
+        let input = r#"let f = fn(n) { if (n > 0) { f(n - 1) } else { "done" } }; f(10)"#;
+
+        assert_eq!(eval_with(input, limits).inspect(), "done");
+    }
+
+    #[test]
+    fn test_each_evaluation_starts_afresh() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(Limits {
+            fuel: Some(50),
+            ..Limits::default()
+        });
+
+        let count = "let i = 0; while (i < 5) { i += 1; } i";
+        for _ in 0..3 {
+            assert_eq!(eval(count, &mut evaluator).inspect(), "5");
+        }
+        assert_eq!(
+            eval("while (true) { }", &mut evaluator).inspect(),
+            "ERROR: limit exceeded: ran out of fuel after 50 steps"
+        );
+        assert_eq!(eval(count, &mut evaluator).inspect(), "5");
+    }
+
+    #[test]
+    fn test_limits_for_a_stack_keep_it() {
+        let recurse = "let f = fn() { 1 + f() }; f()";
+        let nested = format!(
+            "let f = fn() {{ 1 + len({}f(){}) }}; f()",
+            "[".repeat(20),
+            "]".repeat(20)
+        );
+        for (stack_size, input) in [
+            (EMBEDDED_STACK_SIZE, recurse.to_string()),
+            (EMBEDDED_STACK_SIZE, nested),
+            (STACK_SIZE, recurse.to_string()),
+        ] {
+            let result = std::thread::Builder::new()
+                .stack_size(stack_size)
+                .spawn(move || {
+                    let mut evaluator = Evaluator::new();
+                    evaluator.set_limits(Limits::for_stack(stack_size));
+                    eval(&input, &mut evaluator).inspect()
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+            assert_eq!(
+                result,
+                format!(
+                    "ERROR: limit exceeded: more than {} bytes of stack taken",
+                    max_stack_for(stack_size)
+                )
+            );
+        }
+        assert_eq!(Limits::default(), Limits::for_stack(EMBEDDED_STACK_SIZE));
+    }
+
+    #[test]
+    fn test_nested_expressions_take_stack() {
+        let limits = Limits {
+            max_stack: Some(256 << 10),
+            ..Limits::default()
+        };
+        let shallow = "let g = fn(n) { if (n == 0) { 0 } else { 1 + len([g(n - 1)]) } }; g(5)";
+        assert_eq!(eval_with(shallow, limits.clone()).inspect(), "2");
+
+        // the same calls run out of stack when their bodies nest deeply
+        let deep = format!(
+            "let g = fn(n) {{ if (n == 0) {{ 0 }} else {{ 1 + len({}g(n - 1){}) }} }}; g(10)",
+            "[".repeat(100),
+            "]".repeat(100)
+        );
+        assert_eq!(
+            eval_with(&deep, limits).inspect(),
+            "ERROR: limit exceeded: more than 262144 bytes of stack taken"
+        );
+    }
+
+    #[test]
+    fn test_limit_exceeded_ends_loops_and_calls() {
+        let limits = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        // the error ends loops and functions like any other
+        let input = "let f = fn() { for (x in [1, 2]) { while (true) { } } }; f(); 5";
+
+        assert!(matches!(
+            eval_with(input, limits),
+            Object::LimitExceeded(LimitExceeded::Fuel(1000))
+        ));
+    }
+}
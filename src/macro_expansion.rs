@@ -0,0 +1,460 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::environment::*;
+use crate::evaluator::*;
+use crate::exception::*;
+use crate::object::*;
+use crate::token::Position;
+use crate::visitor::{fold, visit, Fold, Visitor};
+
+/// How many times a macro may expand into calls of macros, which are
+/// expanded in turn, before expansion gives up on a macro that never stops.
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Moves every top-level `let <name> = macro(...) { ... };` out of `program`
+/// and binds the macro in `env`. Macros anywhere else are refused, as
+/// expansion would never see them.
+pub fn define_macros(program: &mut Program, env: &Rc<RefCell<Environment>>) -> Result<(), String> {
+    program.statements.retain(|statement| {
+        let (name, ml) = match statement {
+            Statement::LetStatement(LetStatement {
+                name,
+                value: Some(Expression::MacroLiteral(ml)),
+                ..
+            }) => (name, ml),
+            _ => return true,
+        };
+
+        let mac = Object::Macro(Macro {
+            parameters: ml.parameters.clone(),
+            body: Rc::new(ml.body.clone()),
+            env: Rc::clone(env),
+        });
+        env.borrow_mut().set(name.value.clone(), mac);
+        false
+    });
+
+    let mut nested = NestedMacros(None);
+    nested.visit_program(program);
+    match nested.0 {
+        Some(Position { line, column }) => Err(format!(
+            "{}:{}: macros can only be defined by a top-level let",
+            line, column
+        )),
+        None => Ok(()),
+    }
+}
+
+// finds the first macro literal left in a program
+struct NestedMacros(Option<Position>);
+
+impl Visitor for NestedMacros {
+    fn visit_macro_literal(&mut self, ml: &MacroLiteral) {
+        self.0.get_or_insert(ml.span.start);
+    }
+}
+
+/// Replaces every call of a macro defined in `env` with the AST the macro
+/// returns for the quoted arguments, as evaluated by `evaluator`, and then
+/// the calls of macros in that AST in turn. The walk through the program
+/// takes stack as evaluation does, and is stopped by the same limit.
+pub fn expand_macros(
+    program: Program,
+    env: &Rc<RefCell<Environment>>,
+    evaluator: &mut Evaluator,
+) -> Result<Program, String> {
+    // without macros there is nothing to walk through the program for
+    if env.borrow().is_empty() {
+        return Ok(program);
+    }
+
+    let mut expander = Expander {
+        env,
+        evaluator,
+        depth: 0,
+        error: None,
+    };
+    let expanded = expander.fold_program(program);
+
+    match expander.error {
+        Some(msg) => Err(msg),
+        None => Ok(expanded),
+    }
+}
+
+// Folds the tree, expanding every call of a macro once its arguments have
+// been, `depth` expansions into the macro that first called it. The first
+// error stops the walk.
+struct Expander<'a> {
+    env: &'a Rc<RefCell<Environment>>,
+    evaluator: &'a mut Evaluator,
+    depth: usize,
+    error: Option<String>,
+}
+
+impl Fold for Expander<'_> {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        if self.error.is_some() {
+            return expression;
+        }
+        if let Err(limit) = self.evaluator.check_stack() {
+            self.error = Some(limit.to_string());
+            return expression;
+        }
+
+        let folded = fold::walk_expression(self, expression);
+        self.expand(folded)
+    }
+}
+
+impl Expander<'_> {
+    // expands `exp` if it calls a macro
+    fn expand(&mut self, exp: Expression) -> Expression {
+        if self.error.is_some() {
+            return exp;
+        }
+
+        let (mac, ce) = match &exp {
+            Expression::CallExpression(ce) => match is_macro_call(ce, self.env) {
+                Some(mac) => (mac, ce),
+                None => return exp,
+            },
+            _ => return exp,
+        };
+
+        if self.depth == MAX_EXPANSION_DEPTH {
+            self.error = Some(format!(
+                "macro {} expands into macros more than {} levels deep",
+                ce.function.to_string(),
+                MAX_EXPANSION_DEPTH
+            ));
+            return exp;
+        }
+
+        if mac.parameters.len() != ce.arguments.len() {
+            self.error = Some(format!(
+                "wrong number of arguments to macro {}: want={}, got={}",
+                ce.function.to_string(),
+                mac.parameters.len(),
+                ce.arguments.len()
+            ));
+            return exp;
+        }
+
+        // the arguments are quoted as copies
+        let nodes = ce.arguments.iter().map(count_nodes).sum();
+        if let Err(limit) = self.evaluator.build_nodes(nodes) {
+            self.error = Some(limit.to_string());
+            return exp;
+        }
+
+        let eval_env = Environment::new_enclosed(Rc::clone(&mac.env));
+        for (param, arg) in mac.parameters.iter().zip(&ce.arguments) {
+            let quoted = Object::Quote(Box::new(arg.clone()));
+            eval_env.borrow_mut().set(param.value.clone(), quoted);
+        }
+
+        let evaluated = match self.evaluator.eval_block_statement(&mac.body, &eval_env) {
+            Object::ReturnValue(rv) => *rv,
+            obj => obj,
+        };
+
+        let error = match evaluated {
+            // what the macro returns may call macros too
+            Object::Quote(node) => {
+                self.depth += 1;
+                let expanded = self.fold_expression(*node);
+                self.depth -= 1;
+                return expanded;
+            }
+            Object::Error(msg) => msg,
+            Object::LimitExceeded(limit) => limit.to_string(),
+            Object::Thrown(value) => uncaught_message(&value),
+            _ => format!("macro {} must return a quote", ce.function.to_string()),
+        };
+        self.error = Some(error);
+        exp
+    }
+}
+
+/// The number of expressions and statements in `expression`.
+pub fn count_nodes(expression: &Expression) -> u64 {
+    let mut nodes = Nodes(0);
+    nodes.visit_expression(expression);
+    nodes.0
+}
+
+struct Nodes(u64);
+
+impl Visitor for Nodes {
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.0 += 1;
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.0 += 1;
+        visit::walk_expression(self, expression);
+    }
+}
+
+fn is_macro_call(ce: &CallExpression, env: &Rc<RefCell<Environment>>) -> Option<Macro> {
+    let id = match ce.function.as_ref() {
+        Expression::Identifier(id) => id,
+        _ => return None,
+    };
+
+    match env.borrow().get(&id.value) {
+        Some(Object::Macro(mac)) => Some(mac),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{define_macros, expand_macros};
+    use crate::ast::Node;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::limits::{Limits, EMBEDDED_MAX_STACK, EMBEDDED_STACK_SIZE};
+    use crate::object::Object;
+    use crate::parser::{parse_ok, MAX_NESTING};
+
+    #[test]
+    fn test_define_macros() {
+        let input = r#"
+let number = 1;
+let function = fn(x, y) { x + y };
+let mymacro = macro(x, y) { x + y; };
+"#;
+
+        let env = Environment::new();
+        let mut program = parse_ok(input);
+
+        define_macros(&mut program, &env).unwrap();
+
+        assert_eq!(program.statements.len(), 2, "wrong number of statements");
+        assert!(
+            env.borrow().get("number").is_none(),
+            "number should not be defined"
+        );
+        assert!(
+            env.borrow().get("function").is_none(),
+            "function should not be defined"
+        );
+
+        let mymacro = env.borrow().get("mymacro");
+        match mymacro {
+            Some(Object::Macro(m)) => {
+                let params: Vec<String> = m.parameters.iter().map(|p| p.to_string()).collect();
+                assert_eq!(params, vec!["x", "y"]);
+                assert_eq!(m.body.to_string(), "{ x + y }");
+            }
+            obj => panic!("object is not Macro, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let tests = [
+            (
+                "let infixExpression = macro() { quote(1 + 2); }; infixExpression();",
+                "1 + 2",
+            ),
+            (
+                "let reverse = macro(a, b) { quote(unquote(b) - unquote(a)); }; reverse(2 + 2, 10 - 5);",
+                "10 - 5 - 2 + 2",
+            ),
+            (
+                r#"
+let unless = macro(condition, consequence, alternative) {
+    quote(if (!(unquote(condition))) {
+        unquote(consequence);
+    } else {
+        unquote(alternative);
+    });
+};
+
+unless(10 > 5, puts("not greater"), puts("greater"));
+"#,
+                r#"if (!10 > 5) { puts("not greater") } else { puts("greater") }"#,
+            ),
+            (
+                "let m = macro(x) { return quote(unquote(x) * 2); }; m(3);",
+                "3 * 2",
+            ),
+            // what a macro returns is expanded in turn
+            (
+                r#"
+let twice = macro(x) { quote(unquote(x) + unquote(x)) };
+let quad = macro(x) { quote(twice(unquote(x)) * 2) };
+quad(y);
+"#,
+                "y + y * 2",
+            ),
+            (
+                "let id = macro(x) { x }; id(id(id(1)));",
+                "1",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let env = Environment::new();
+            let mut program = parse_ok(input);
+
+            define_macros(&mut program, &env).unwrap();
+            let expanded = expand_macros(program, &env, &mut Evaluator::new()).unwrap();
+
+            assert_eq!(expanded.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_expand_macros_errors() {
+        let tests = [
+            (
+                "let m = macro(x) { quote(x) }; m(1, 2);",
+                "wrong number of arguments to macro m: want=1, got=2",
+            ),
+            ("let m = macro() { 1 }; m();", "macro m must return a quote"),
+            (
+                "let m = macro() { nope }; m();",
+                "identifier not found: nope",
+            ),
+            (
+                "let loop = macro(x) { quote(loop(unquote(x) + 1)) }; loop(0);",
+                "macro loop expands into macros more than 64 levels deep",
+            ),
+            (
+                "let f = fn() { let m = macro() { quote(1) }; m() };",
+                "1:24: macros can only be defined by a top-level let",
+            ),
+            (
+                "let m = macro() { quote(1) }; [macro() { quote(2) }];",
+                "1:32: macros can only be defined by a top-level let",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let env = Environment::new();
+            let mut program = parse_ok(input);
+
+            let expanded = define_macros(&mut program, &env)
+                .and_then(|()| expand_macros(program, &env, &mut Evaluator::new()));
+
+            assert_eq!(expanded.unwrap_err(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_expansion_is_limited() {
+        // every expansion doubles the tree, to 2^40 nodes in the end
+        let input = format!(
+            "let d = macro(x) {{ quote(unquote(x) + unquote(x)) }}; d({}1{});",
+            "d(".repeat(39),
+            ")".repeat(39)
+        );
+        let tests = [
+            (
+                Limits {
+                    max_bytes: Some(1000),
+                    ..Limits::default()
+                },
+                "limit exceeded: more than 1000 bytes allocated",
+            ),
+            (
+                Limits {
+                    timeout: Some(Duration::from_millis(100)),
+                    ..Limits::default()
+                },
+                "limit exceeded: ran for longer than 100ms",
+            ),
+            (
+                Limits {
+                    fuel: Some(10_000),
+                    ..Limits::default()
+                },
+                "limit exceeded: ran out of fuel after 10000 steps",
+            ),
+        ];
+
+        for (limits, expected) in tests {
+            let env = Environment::new();
+            let mut program = parse_ok(&input);
+            let mut evaluator = Evaluator::new();
+            evaluator.set_limits(limits);
+
+            define_macros(&mut program, &env).unwrap();
+            let expanded = evaluator.expand_macros(program, &env);
+
+            assert_eq!(expanded.unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_expansion_keeps_the_stack() {
+        let mac = "let m = macro(x) { x };";
+        // the statement, the outermost expression and the argument of the
+        // call take a level each
+        let deepest = MAX_NESTING - 3;
+        let tests = [
+            format!(
+                "{}{}m(1){}",
+                mac,
+                "if (true) { ".repeat(deepest / 2),
+                " }".repeat(deepest / 2)
+            ),
+            format!("{}{}m(1){}", mac, "[".repeat(deepest), "]".repeat(deepest)),
+        ];
+
+        for input in tests {
+            // the deepest programs the parser accepts stop as evaluation would
+            let result = std::thread::Builder::new()
+                .stack_size(EMBEDDED_STACK_SIZE)
+                .spawn(move || {
+                    let env = Environment::new();
+                    let mut program = parse_ok(&input);
+                    define_macros(&mut program, &env).unwrap();
+                    Evaluator::new().expand_macros(program, &env).map(|_| ())
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+            if let Err(msg) = result {
+                assert_eq!(
+                    msg,
+                    format!(
+                        "limit exceeded: more than {} bytes of stack taken",
+                        EMBEDDED_MAX_STACK
+                    )
+                );
+            }
+        }
+
+        let limits = Limits {
+            max_stack: Some(64 << 10),
+            ..Limits::default()
+        };
+        let deep = format!("{}{}m(1){}", mac, "[".repeat(deepest), "]".repeat(deepest));
+        let env = Environment::new();
+        let mut program = parse_ok(&deep);
+        define_macros(&mut program, &env).unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(limits.clone());
+        assert_eq!(
+            evaluator.expand_macros(program, &env).unwrap_err(),
+            "limit exceeded: more than 65536 bytes of stack taken"
+        );
+
+        // without macros the program is not walked through at all
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(limits);
+        assert!(evaluator
+            .expand_macros(parse_ok(&deep[mac.len()..]), &Environment::new())
+            .is_ok());
+    }
+}
@@ -1,12 +1,462 @@
-mod ast;
-mod lexer;
-mod parser;
-mod repl;
-mod token;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use repl::*;
-//use token::*;
+use rust_monkey::ast::*;
+use rust_monkey::debugger::*;
+use rust_monkey::environment::*;
+use rust_monkey::evaluator::*;
+use rust_monkey::json::*;
+use rust_monkey::lexer::*;
+use rust_monkey::limits::*;
+use rust_monkey::object::*;
+use rust_monkey::parser::*;
+use rust_monkey::repl::*;
+use rust_monkey::stack_trace::*;
+use rust_monkey::token::*;
+use rust_monkey::{c, capi, check, fuzz, gc, js, json, limits, optimizer, types, wat};
 
 fn main() {
-    start_repl();
+    // deep recursion is stopped by the stack limit before it overflows this
+    let main = std::thread::Builder::new()
+        .stack_size(limits::STACK_SIZE)
+        .spawn(run_main)
+        .unwrap_or_else(|err| fail(&format!("could not start: {}", err)));
+
+    if main.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run_main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("parse") => parse_command(&args[1..]),
+        Some("fuzz") => fuzz_command(&args[1..]),
+        Some("check") => check_command(&args[1..]),
+        Some("typecheck") => typecheck_command(&args[1..]),
+        Some("header") => print!("{}", capi::header()),
+        Some("compile") => compile_command(&args[1..]),
+        Some("debug") => debug_command(&args[1..]),
+        Some(_) => run_command(&args),
+        None => start_repl(),
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("ERROR: {}", msg);
+    process::exit(1);
+}
+
+/// `parse [--json] [--tokens] [--from-json] [file]` prints the syntax tree of
+/// `file`, or of standard input, as source or in the JSON encoding of the
+/// `json` module. With `--tokens` it prints the tokens instead, and with
+/// `--from-json` the input is a JSON encoded program rather than source.
+fn parse_command(args: &[String]) {
+    let mut as_json = false;
+    let mut tokens = false;
+    let mut from_json = false;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--json" => as_json = true,
+            "--tokens" => tokens = true,
+            "--from-json" => from_json = true,
+            flag if flag.starts_with("--") => fail(&format!("unknown option {}", flag)),
+            file => path = Some(file),
+        }
+    }
+
+    let mut input = String::new();
+    let read = match path {
+        Some(path) => std::fs::read_to_string(path).map(|s| input = s),
+        None => io::stdin().read_to_string(&mut input).map(|_| ()),
+    };
+    if let Err(err) = read {
+        fail(&format!(
+            "could not read {}: {}",
+            path.unwrap_or("stdin"),
+            err
+        ));
+    }
+
+    if tokens {
+        let all = if from_json {
+            match json::parse(&input).and_then(|json| decode_tokens(&json)) {
+                Ok(all) => all,
+                Err(msg) => fail(&msg),
+            }
+        } else {
+            lex_all(&input)
+        };
+
+        if as_json {
+            println!("{}", Json::Array(all.iter().map(encode_token).collect()));
+        } else {
+            for tok in all {
+                println!("{}", tok);
+            }
+        }
+        return;
+    }
+
+    let program = if from_json {
+        match json::parse(&input).and_then(|json| decode_program(&json)) {
+            Ok(program) => program,
+            Err(msg) => fail(&msg),
+        }
+    } else {
+        let mut p = Parser::new(Lexer::new(&input));
+        let program = p.parse_program();
+        if !p.errors().is_empty() {
+            fail(&format!(
+                "could not parse input:\n\t{}",
+                p.errors().join("\n\t")
+            ));
+        }
+        program
+    };
+
+    if as_json {
+        println!("{}", encode_program(&program));
+    } else {
+        println!("{}", program.to_string());
+    }
+}
+
+/// `check <file>...` prints the diagnostics of the `check` module for each
+/// file, and exits with status 1 if there are any.
+fn check_command(paths: &[String]) {
+    if paths.is_empty() {
+        fail("check expects at least one file");
+    }
+
+    let mut clean = true;
+
+    for path in paths {
+        let source = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| fail(&format!("could not read {}: {}", path, err)));
+
+        match check::check_source(&source) {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics {
+                    clean = false;
+                    println!("{}:{}", path, diagnostic);
+                }
+            }
+            Err(errors) => fail(&format!(
+                "could not check {}:\n\t{}",
+                path,
+                errors.join("\n\t")
+            )),
+        }
+    }
+
+    if !clean {
+        process::exit(1);
+    }
+}
+
+/// `typecheck <file>` infers the types of the `types` module for the file and
+/// prints those of its top-level bindings, or its type errors and exits with
+/// status 1.
+fn typecheck_command(args: &[String]) {
+    match args {
+        [path] => typecheck(Path::new(path), true),
+        _ => fail("typecheck expects a file"),
+    }
+}
+
+/// Type checks the file at `path`, exiting with status 1 on errors. With
+/// `verbose` the types of its top-level bindings are printed.
+fn typecheck(path: &Path, verbose: bool) {
+    let program = Evaluator::new()
+        .parse_file(path)
+        .unwrap_or_else(|msg| fail(&msg));
+
+    match types::infer_program(&program) {
+        Ok(bindings) => {
+            if verbose {
+                for (name, t) in bindings {
+                    println!("{}: {}", name, t);
+                }
+            }
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}:{}", path.display(), error);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// `compile --target T [-o out] <file>` compiles the file with the backend
+/// of the `wat`, the `js` or the `c` module and prints the code, or writes
+/// it to `out`, along with the source map `out.map` for JavaScript. The
+/// `exe` target builds the C code into the executable `out` with the
+/// system C compiler. On errors it prints them and exits with status 1.
+fn compile_command(args: &[String]) {
+    let mut target = None;
+    let mut out = None;
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => match args.next() {
+                Some(t) => target = Some(t.as_str()),
+                None => fail("--target expects a target"),
+            },
+            "-o" => match args.next() {
+                Some(o) => out = Some(Path::new(o)),
+                None => fail("-o expects a file"),
+            },
+            flag if flag.starts_with('-') => fail(&format!("unknown option {}", flag)),
+            file => path = Some(Path::new(file)),
+        }
+    }
+    let path = path.unwrap_or_else(|| fail("compile expects a file"));
+
+    let program = Evaluator::new()
+        .parse_file(path)
+        .unwrap_or_else(|msg| fail(&msg));
+    let compiled = match target {
+        Some("wat") => wat::compile(&program).map(|code| (code, None)),
+        Some("js") => js::compile(&program).map(|output| (output.code.clone(), Some(output))),
+        Some("c" | "exe") => c::compile(&program).map(|code| (code, None)),
+        Some(target) => fail(&format!(
+            "unknown target {}, expected wat, js, c or exe",
+            target
+        )),
+        None => fail("compile expects --target wat, js, c or exe"),
+    };
+
+    let (mut code, output) = match compiled {
+        Ok(compiled) => compiled,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}:{}", path.display(), error);
+            }
+            process::exit(1);
+        }
+    };
+
+    if target == Some("exe") {
+        let out = out.unwrap_or_else(|| fail("--target exe expects -o"));
+        build_executable(&code, out);
+        return;
+    }
+    let Some(out) = out else {
+        print!("{}", code);
+        return;
+    };
+    if let Some(output) = output {
+        let name = out
+            .file_name()
+            .unwrap_or_else(|| fail(&format!("-o expects a file, got {}", out.display())))
+            .to_string_lossy();
+        let map = out.with_file_name(format!("{}.map", name));
+        let source = relative_path(path, out.parent().unwrap_or(Path::new("")));
+        write_file(&map, &output.source_map(&name, &source));
+        code += &format!("//# sourceMappingURL={}.map\n", name);
+    }
+    write_file(out, &code);
+}
+
+/// `path` relative to the directory `dir` when it is inside it, and
+/// absolute otherwise.
+fn relative_path(path: &Path, dir: &Path) -> String {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    match dir.canonicalize() {
+        Ok(dir) => match path.strip_prefix(&dir) {
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path.display().to_string(),
+        },
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Compiles the C program `code` to the executable `out` with the C
+/// compiler in `CC`, or `cc`.
+fn build_executable(code: &str, out: &Path) {
+    let source = std::env::temp_dir().join(format!("monkey_{}.c", process::id()));
+    write_file(&source, code);
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = process::Command::new(&cc)
+        .args(["-std=c11", "-O2", "-o"])
+        .arg(out)
+        .arg(&source)
+        .status();
+    let _ = std::fs::remove_file(&source);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => fail(&format!("{} failed with {}", cc, status)),
+        Err(err) => fail(&format!("could not run {}: {}", cc, err)),
+    }
+}
+
+fn write_file(path: &Path, contents: &str) {
+    if let Err(err) = std::fs::write(path, contents) {
+        fail(&format!("could not write {}: {}", path.display(), err));
+    }
+}
+
+/// `fuzz [--iterations N] [--seed S]` runs the lexer and parser fuzz targets
+/// of the `fuzz` module, with a seed taken from the clock unless one is given.
+fn fuzz_command(args: &[String]) {
+    let mut iterations = 10_000;
+    let mut seed = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next().map(|v| v.parse()) {
+            Some(Ok(value)) => value,
+            _ => fail(&format!("{} expects a number", name)),
+        };
+        match arg.as_str() {
+            "--iterations" => iterations = value("--iterations") as usize,
+            "--seed" => seed = Some(value("--seed")),
+            arg => fail(&format!("unknown argument {}", arg)),
+        }
+    }
+
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+
+    // failures are reported below, not by the default hook on every panic
+    std::panic::set_hook(Box::new(|_| {}));
+
+    match fuzz::run(seed, iterations) {
+        Ok(()) => println!("{} iterations from seed {} passed", iterations, seed),
+        Err(failure) => fail(&format!(
+            "{} target failed with seed {}: {}\ninput: {:?}",
+            failure.target, seed, failure.message, failure.input
+        )),
+    }
+}
+
+fn lex_all(input: &str) -> Vec<Token> {
+    let mut l = Lexer::new(input);
+    let mut all = Vec::new();
+    loop {
+        let tok = l.next_token();
+        let eof = tok.token_type == TokenType::EOF;
+        all.push(tok);
+        if eof {
+            return all;
+        }
+    }
+}
+
+fn decode_tokens(json: &Json) -> Result<Vec<Token>, String> {
+    match json {
+        Json::Array(elements) => elements.iter().map(decode_token).collect(),
+        _ => Err("expected an array of tokens".to_string()),
+    }
+}
+
+/// `debug <file>` runs `file` in the step debugger of the `debugger`
+/// module, reading its commands from standard input.
+fn debug_command(args: &[String]) {
+    let path = match args {
+        [path] => Path::new(path),
+        _ => fail("debug expects a file"),
+    };
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_limits(Limits::for_stack(STACK_SIZE));
+    evaluator.set_debugger(Debugger::new(
+        path,
+        Box::new(io::stdin().lock()),
+        Box::new(io::stdout()),
+    ));
+    match evaluator.eval_file(path, &Environment::new()) {
+        Object::Quit => {}
+        Object::Error(msg) => fail(&msg),
+        Object::LimitExceeded(limit) => fail(&limit.to_string()),
+        _ => {}
+    }
+}
+
+/// `[--typecheck] [--gc-stats] [--opt-level N] [limits] <file>` runs the file, after
+/// checking its types with `--typecheck`, and optimized at level N of the
+/// `optimizer` module, 0 by default. The limits of the `limits` module are
+/// set with `--fuel N`, `--max-depth N`, `--max-allocations N`,
+/// `--max-bytes N` and `--timeout MILLISECONDS`. `--gc-stats` prints the
+/// counters of the `gc` module to stderr at the end.
+fn run_command(args: &[String]) {
+    let mut check_types = false;
+    let mut gc_stats = false;
+    let mut opt_level = 0;
+    let mut limits = Limits::for_stack(STACK_SIZE);
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next().map(|v| v.parse()) {
+            Some(Ok(value)) => value,
+            _ => fail(&format!("{} expects a number", name)),
+        };
+        match arg.as_str() {
+            "--typecheck" => check_types = true,
+            "--gc-stats" => gc_stats = true,
+            "--opt-level" => match value("--opt-level") {
+                level if level <= optimizer::MAX_OPT_LEVEL as u64 => opt_level = level as u8,
+                _ => fail(&format!(
+                    "--opt-level expects a level from 0 to {}",
+                    optimizer::MAX_OPT_LEVEL
+                )),
+            },
+            "--fuel" => limits.fuel = Some(value("--fuel")),
+            "--max-depth" => limits.max_depth = Some(value("--max-depth") as usize),
+            "--max-allocations" => limits.max_allocations = Some(value("--max-allocations")),
+            "--max-bytes" => limits.max_bytes = Some(value("--max-bytes")),
+            "--timeout" => limits.timeout = Some(Duration::from_millis(value("--timeout"))),
+            flag if flag.starts_with("--") => fail(&format!("unknown option {}", flag)),
+            file if path.is_none() => path = Some(Path::new(file)),
+            arg => fail(&format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| fail("expected a file to run"));
+    if check_types {
+        typecheck(path, false);
+    }
+
+    let mut evaluator = Evaluator::with_opt_level(opt_level);
+    evaluator.set_limits(limits);
+    let result = evaluator.eval_file(path, &Environment::new());
+    if gc_stats {
+        let stats = gc::stats();
+        eprintln!(
+            "gc: {} environments allocated, {} collected in {} collections, {} live",
+            stats.allocated, stats.collected, stats.collections, stats.live
+        );
+    }
+    match result {
+        Object::Error(msg) => {
+            let trace = format_stack_trace(&evaluator.stack_trace(), |file| {
+                std::fs::read_to_string(file?).ok()
+            });
+            eprintln!("ERROR: {}", msg);
+            if !trace.is_empty() {
+                eprint!("stack trace, innermost call first:\n{}", trace);
+            }
+            process::exit(1);
+        }
+        Object::LimitExceeded(limit) => fail(&limit.to_string()),
+        _ => {}
+    }
 }
@@ -0,0 +1,83 @@
+use crate::ast::*;
+use crate::visitor::{fold, Fold};
+
+pub type ModifierFunc<'a> = &'a mut dyn FnMut(Expression) -> Expression;
+
+/// Folds the tree, handing every expression to the modifier once its
+/// children have been rewritten.
+struct Modifier<'a>(ModifierFunc<'a>);
+
+impl Fold for Modifier<'_> {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        let modified = fold::walk_expression(self, expression);
+        (self.0)(modified)
+    }
+}
+
+pub fn modify_program(program: Program, modifier: ModifierFunc) -> Program {
+    Modifier(modifier).fold_program(program)
+}
+
+/// Rewrites the children of `expression` first and then hands the
+/// expression itself to `modifier`.
+pub fn modify_expression(expression: Expression, modifier: ModifierFunc) -> Expression {
+    Modifier(modifier).fold_expression(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bigint::BigInt;
+
+    use crate::parser::parse_ok;
+
+    #[test]
+    fn test_modify() {
+        let mut turn_one_into_two = |exp: Expression| match exp {
+            Expression::IntegerLiteral(mut il) if il.value == 1 => {
+                il.value = BigInt::from(2);
+                il.token.literal = "2".to_string();
+                Expression::IntegerLiteral(il)
+            }
+            exp => exp,
+        };
+
+        let tests = [
+            ("1", "2"),
+            ("1 + 2", "2 + 2"),
+            ("-1", "-2"),
+            ("1 / 1", "2 / 2"),
+            ("a[1]", "a[2]"),
+            ("a[1] = 1", "a[2] = 2"),
+            ("if (1 > 2) { 1 } else { 1 }", "if (2 > 2) { 2 } else { 2 }"),
+            ("return 1;", "return 2;"),
+            ("let x = 1;", "let x = 2;"),
+            ("fn() { 1 }", "fn() { 2 }"),
+            ("[1, 1]", "[2, 2]"),
+            ("{1: 1}", "{2: 2}"),
+            ("f(1, g(1))", "f(2, g(2))"),
+            ("while (1) { 1; }", "while (2) { 2 }"),
+            ("for (x in [1]) { 1; }", "for (x in [2]) { 2 }"),
+            ("macro(a) { 1 }", "macro(a) { 2 }"),
+            ("f(1).x", "f(2).x"),
+        ];
+
+        for (input, expected) in tests {
+            let modified = modify_program(parse_ok(input), &mut turn_one_into_two);
+            assert_eq!(modified.to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_modify_visits_children_before_parents() {
+        let mut visited = Vec::new();
+        let mut record = |exp: Expression| {
+            visited.push(exp.to_string());
+            exp
+        };
+
+        modify_program(parse_ok("f(a + b)"), &mut record);
+
+        assert_eq!(visited, vec!["f", "a", "b", "a + b", "f(a + b)"]);
+    }
+}
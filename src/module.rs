@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::object::*;
+
+/// The exported bindings of an evaluated source file.
+#[derive(Debug)]
+pub struct Module {
+    pub path: PathBuf,
+    pub exports: BTreeMap<String, Object>,
+}
+
+/// Keeps track of the files being evaluated so imports can be resolved
+/// relative to the importing file, evaluated at most once and checked for
+/// cycles.
+#[derive(Debug, Default)]
+pub struct ModuleLoader {
+    cache: HashMap<PathBuf, Rc<Module>>,
+    // canonical paths of the files currently being evaluated, innermost last
+    loading: Vec<PathBuf>,
+}
+
+impl ModuleLoader {
+    /// Resolves an import path against the directory of the file currently
+    /// being evaluated, or the working directory outside of any file.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        match self.loading.last().and_then(|current| current.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    pub fn cached(&self, path: &Path) -> Option<Rc<Module>> {
+        self.cache.get(path).cloned()
+    }
+
+    /// Marks `path` as being evaluated, failing if that would close an
+    /// import cycle.
+    pub fn enter(&mut self, path: PathBuf) -> Result<(), String> {
+        if let Some(start) = self.loading.iter().position(|p| *p == path) {
+            let chain: Vec<String> = self.loading[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| self.display_path(p))
+                .collect();
+            return Err(format!("import cycle detected: {}", chain.join(" -> ")));
+        }
+
+        self.loading.push(path);
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.loading.pop();
+    }
+
+    pub fn insert(&mut self, module: Rc<Module>) {
+        self.cache.insert(module.path.clone(), module);
+    }
+
+    /// Shows `path` relative to the directory of the outermost file being
+    /// evaluated when possible.
+    pub fn display_path(&self, path: &Path) -> String {
+        let root = self.loading.first().and_then(|entry| entry.parent());
+        match root.and_then(|root| path.strip_prefix(root).ok()) {
+            Some(relative) => relative.display().to_string(),
+            None => path.display().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::object::Object;
+
+    /// Writes `files` into a fresh directory under the system temp dir.
+    fn write_files(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust_monkey_{}_{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (name, contents) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn eval_file(path: &Path) -> Object {
+        Evaluator::new().eval_file(path, &Environment::new())
+    }
+
+    #[test]
+    fn test_import_exported_bindings() {
+        let dir = write_files(
+            "import_exports",
+            &[
+                (
+                    "main.monkey",
+                    r#"
+import "lib/math.monkey" as math;
+math.square(math.base) + math.offset
+"#,
+                ),
+                (
+                    "lib/math.monkey",
+                    r#"
+import "../consts.monkey" as consts;
+let helper = fn(x) { x * x };
+export let square = fn(x) { helper(x) };
+export const base = 4;
+export let offset = consts.one;
+"#,
+                ),
+                ("consts.monkey", "export let one = 1;"),
+            ],
+        );
+
+        match eval_file(&dir.join("main.monkey")) {
+            Object::Integer(i) => assert_eq!(i, 17),
+            obj => panic!("object is not Integer, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_modules_are_evaluated_once() {
+        let dir = write_files(
+            "module_cache",
+            &[
+                (
+                    "main.monkey",
+                    r#"
+import "counter.monkey" as a;
+import "counter.monkey" as b;
+import "./counter.monkey" as c;
+[a.items, b.items, c.items]
+"#,
+                ),
+                (
+                    "counter.monkey",
+                    r#"puts("loading"); export let items = [1];"#,
+                ),
+            ],
+        );
+
+        let env = Environment::new();
+        let result = Evaluator::new().eval_file(&dir.join("main.monkey"), &env);
+        assert_eq!(result.inspect(), "[[1], [1], [1]]");
+
+        let module = |name: &str| match env.borrow().get(name) {
+            Some(Object::Module(module)) => module,
+            obj => panic!("{} is not a Module, got {:?}", name, obj),
+        };
+        assert!(Rc::ptr_eq(&module("a"), &module("b")));
+        assert!(Rc::ptr_eq(&module("a"), &module("c")));
+    }
+
+    #[test]
+    fn test_unexported_bindings_are_private() {
+        let dir = write_files(
+            "private_bindings",
+            &[
+                ("main.monkey", r#"import "lib.monkey" as lib; lib.secret"#),
+                ("lib.monkey", "let secret = 42;"),
+            ],
+        );
+
+        match eval_file(&dir.join("main.monkey")) {
+            Object::Error(msg) => assert_eq!(msg, "module lib.monkey has no export named secret"),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let dir = write_files(
+            "import_cycle",
+            &[
+                ("main.monkey", r#"import "a.monkey" as a;"#),
+                ("a.monkey", r#"import "b/b.monkey" as b;"#),
+                ("b/b.monkey", r#"import "../a.monkey" as a;"#),
+            ],
+        );
+
+        match eval_file(&dir.join("main.monkey")) {
+            Object::Error(msg) => assert_eq!(
+                msg,
+                "import cycle detected: a.monkey -> b/b.monkey -> a.monkey"
+            ),
+            obj => panic!("no error object returned, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let dir = write_files(
+            "module_errors",
+            &[
+                ("missing.monkey", r#"import "nope.monkey" as nope;"#),
+                ("broken.monkey", r#"import "syntax.monkey" as s;"#),
+                ("syntax.monkey", "let = 1;"),
+                ("failing.monkey", r#"import "runtime.monkey" as r;"#),
+                ("runtime.monkey", "export let x = 1 + true;"),
+                ("member.monkey", "let x = 1; x.y"),
+                ("nested.monkey", r#"import "macros.monkey" as m;"#),
+                ("macros.monkey", "let f = fn() { let m = macro() { 1 }; };"),
+            ],
+        );
+
+        let tests = [
+            ("missing.monkey", "could not load module nope.monkey"),
+            (
+                "broken.monkey",
+                "could not parse syntax.monkey:\n\texpected next token to be IDENT, got ASSIGN instead",
+            ),
+            ("failing.monkey", "type mismatch: INTEGER + BOOLEAN"),
+            ("member.monkey", "member access not supported: INTEGER"),
+            (
+                "nested.monkey",
+                "could not parse macros.monkey:\n\t1:24: macros can only be defined by a top-level let",
+            ),
+        ];
+
+        for (file, expected) in tests {
+            match eval_file(&dir.join(file)) {
+                Object::Error(msg) => assert!(
+                    msg.starts_with(expected),
+                    "{}: expected {:?}, got {:?}",
+                    file,
+                    expected,
+                    msg
+                ),
+                obj => panic!("no error object returned for {}, got {:?}", file, obj),
+            }
+        }
+    }
+}
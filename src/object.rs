@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::bigint::BigInt;
+use crate::environment::*;
+use crate::exception::*;
+use crate::limits::*;
+use crate::module::*;
+
+pub const INTEGER_OBJ: &str = "INTEGER";
+pub const BOOLEAN_OBJ: &str = "BOOLEAN";
+pub const NULL_OBJ: &str = "NULL";
+pub const RETURN_VALUE_OBJ: &str = "RETURN_VALUE";
+pub const ERROR_OBJ: &str = "ERROR";
+pub const FUNCTION_OBJ: &str = "FUNCTION";
+pub const STRING_OBJ: &str = "STRING";
+pub const BUILTIN_OBJ: &str = "BUILTIN";
+pub const ARRAY_OBJ: &str = "ARRAY";
+pub const HASH_OBJ: &str = "HASH";
+pub const BREAK_OBJ: &str = "BREAK";
+pub const CONTINUE_OBJ: &str = "CONTINUE";
+pub const TAIL_CALL_OBJ: &str = "TAIL_CALL";
+pub const QUOTE_OBJ: &str = "QUOTE";
+pub const MACRO_OBJ: &str = "MACRO";
+pub const MODULE_OBJ: &str = "MODULE";
+
+#[derive(Debug, Clone)]
+pub enum Object {
+    Integer(i64),
+    /// An integer beyond the range of `i64`, as only arithmetic overflowing
+    /// it makes. See `Object::integer`.
+    BigInteger(BigInt),
+    Boolean(bool),
+    Null,
+    ReturnValue(Box<Object>),
+    Error(String),
+    LimitExceeded(LimitExceeded),
+    /// A value raised by `throw`, on its way to the `try` that catches it.
+    Thrown(Box<Object>),
+    /// The program was stopped from the debugger, which no `try` catches.
+    Quit,
+    Function(Function),
+    String(String),
+    Builtin(Builtin),
+    Array(Vec<Object>),
+    Hash(BTreeMap<HashKey, HashPair>),
+    Break,
+    Continue,
+    /// A call in tail position, with its function and arguments evaluated,
+    /// that the function making it leaves for its caller to run in its
+    /// frame, under the name a frame of its own would give it.
+    TailCall(Box<Object>, Vec<Object>, String),
+    Quote(Box<Expression>),
+    Macro(Macro),
+    Module(Rc<Module>),
+}
+
+impl Object {
+    /// The integer `value`, as an `Object::Integer` when it fits in one.
+    pub fn integer(value: BigInt) -> Object {
+        match value.to_i64() {
+            Some(i) => Object::Integer(i),
+            None => Object::BigInteger(value),
+        }
+    }
+
+    /// The value of an integer of either size.
+    pub fn to_big_int(&self) -> Option<BigInt> {
+        match self {
+            Object::Integer(i) => Some(BigInt::from(*i)),
+            Object::BigInteger(i) => Some(i.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn object_type(&self) -> &'static str {
+        match self {
+            Object::Integer(_) | Object::BigInteger(_) => INTEGER_OBJ,
+            Object::Boolean(_) => BOOLEAN_OBJ,
+            Object::Null => NULL_OBJ,
+            Object::ReturnValue(_) => RETURN_VALUE_OBJ,
+            Object::Error(_) | Object::LimitExceeded(_) | Object::Thrown(_) | Object::Quit => {
+                ERROR_OBJ
+            }
+            Object::Function(_) => FUNCTION_OBJ,
+            Object::String(_) => STRING_OBJ,
+            Object::Builtin(_) => BUILTIN_OBJ,
+            Object::Array(_) => ARRAY_OBJ,
+            Object::Hash(_) => HASH_OBJ,
+            Object::Break => BREAK_OBJ,
+            Object::Continue => CONTINUE_OBJ,
+            Object::TailCall(..) => TAIL_CALL_OBJ,
+            Object::Quote(_) => QUOTE_OBJ,
+            Object::Macro(_) => MACRO_OBJ,
+            Object::Module(_) => MODULE_OBJ,
+        }
+    }
+
+    pub fn inspect(&self) -> String {
+        match self {
+            Object::Integer(i) => i.to_string(),
+            Object::BigInteger(i) => i.to_string(),
+            Object::Boolean(b) => b.to_string(),
+            Object::Null => "null".to_string(),
+            Object::ReturnValue(rv) => rv.inspect(),
+            Object::Error(msg) => format!("ERROR: {}", msg),
+            Object::LimitExceeded(limit) => format!("ERROR: {}", limit),
+            Object::Thrown(value) => format!("ERROR: {}", uncaught_message(value)),
+            Object::Quit => "ERROR: stopped by the debugger".to_string(),
+            Object::Function(f) => {
+                let params: Vec<String> = f.parameters.iter().map(|p| p.to_string()).collect();
+                format!("fn({}) {}", params.join(", "), f.body.to_string())
+            }
+            Object::String(s) => s.clone(),
+            Object::Builtin(_) => "builtin function".to_string(),
+            Object::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.inspect()).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .values()
+                    .map(|pair| format!("{}: {}", pair.key.inspect(), pair.value.inspect()))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Object::Break => "break".to_string(),
+            Object::Continue => "continue".to_string(),
+            Object::TailCall(function, args, _) => {
+                let args: Vec<String> = args.iter().map(|a| a.inspect()).collect();
+                format!("tail call {}({})", function.inspect(), args.join(", "))
+            }
+            Object::Quote(node) => format!("QUOTE({})", node.to_string()),
+            Object::Macro(m) => {
+                let params: Vec<String> = m.parameters.iter().map(|p| p.to_string()).collect();
+                format!("macro({}) {}", params.join(", "), m.body.to_string())
+            }
+            Object::Module(m) => format!("module({})", m.path.display()),
+        }
+    }
+
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(i) => Some(HashKey::Integer(*i)),
+            Object::BigInteger(i) => Some(HashKey::BigInteger(i.clone())),
+            Object::Boolean(b) => Some(HashKey::Boolean(*b)),
+            Object::String(s) => Some(HashKey::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Object::Error(_) | Object::LimitExceeded(_) | Object::Thrown(_) | Object::Quit
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Function {
+    pub parameters: Vec<Identifier>,
+    pub body: Rc<BlockStatement>,
+    pub env: Rc<RefCell<Environment>>,
+    /// The name of the `let` the function literal was bound with, if any.
+    pub name: Option<String>,
+    /// The file the function is defined in, `None` outside of a file.
+    pub file: Option<Rc<Path>>,
+}
+
+// The captured environment usually contains the function itself, so it is
+// left out to keep debug output finite.
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct Macro {
+    pub parameters: Vec<Identifier>,
+    pub body: Rc<BlockStatement>,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for Macro {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Macro")
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+pub type BuiltinFunction = Rc<dyn Fn(Vec<Object>) -> Object>;
+
+/// Estimates the cost of a call from its arguments.
+pub type BuiltinCost = fn(&[Object]) -> Cost;
+
+/// A function implemented in Rust, either one of the `builtins` or one a
+/// host application registered.
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub func: BuiltinFunction,
+    /// For builtins whose calls may take long, the cost reserved before
+    /// each call.
+    pub cost: Option<BuiltinCost>,
+}
+
+impl std::fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builtin").field("name", &self.name).finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    BigInteger(BigInt),
+    Boolean(bool),
+    String(String),
+}
+
+// Integers of both sizes come first, in numeric order, then booleans and
+// strings.
+impl Ord for HashKey {
+    fn cmp(&self, other: &HashKey) -> std::cmp::Ordering {
+        fn rank(key: &HashKey) -> u8 {
+            match key {
+                HashKey::Integer(_) | HashKey::BigInteger(_) => 0,
+                HashKey::Boolean(_) => 1,
+                HashKey::String(_) => 2,
+            }
+        }
+        match (self, other) {
+            (HashKey::Integer(a), HashKey::Integer(b)) => a.cmp(b),
+            (HashKey::Integer(a), HashKey::BigInteger(b)) => BigInt::from(*a).cmp(b),
+            (HashKey::BigInteger(a), HashKey::Integer(b)) => a.cmp(&BigInt::from(*b)),
+            (HashKey::BigInteger(a), HashKey::BigInteger(b)) => a.cmp(b),
+            (HashKey::Boolean(a), HashKey::Boolean(b)) => a.cmp(b),
+            (HashKey::String(a), HashKey::String(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+impl PartialOrd for HashKey {
+    fn partial_cmp(&self, other: &HashKey) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HashPair {
+    pub key: Object,
+    pub value: Object,
+}
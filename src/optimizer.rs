@@ -0,0 +1,600 @@
+//! Optimization passes over a parsed, macro-expanded `Program`.
+//!
+//! Level 1 folds operators whose operands are literals, keeps only the
+//! branch of an `if` whose condition is a literal and drops statements that
+//! can never run or whose value is never used. Level 2 also inlines calls to
+//! small functions whose bodies are a single expression over their
+//! parameters and builtins.
+//!
+//! Every pass keeps what a program prints and evaluates to, including its
+//! errors: operators whose result is an error, such as `1 / 0`, are left for
+//! the evaluator to report, and a call is only inlined when evaluating its
+//! arguments cannot fail or have effects.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::builtins::lookup_builtin;
+use crate::evaluator::{
+    convert_object_to_ast_node, eval_infix_expression, eval_prefix_expression, is_truthy,
+};
+use crate::object::Object;
+use crate::tail_calls::{clear_tail_calls, function_body};
+use crate::token::Span;
+use crate::visitor::{fold, visit, Fold, Visitor};
+
+pub const MAX_OPT_LEVEL: u8 = 2;
+
+// the number of expressions in the largest function body that is inlined
+const MAX_INLINE_SIZE: usize = 16;
+
+/// Optimizes `program` at `level`, from 0 for none to `MAX_OPT_LEVEL`.
+pub fn optimize(program: Program, level: u8) -> Program {
+    if level == 0 {
+        return program;
+    }
+
+    let mut names = Names::default();
+    names.visit_program(&program);
+
+    let mut optimizer = Optimizer {
+        inline: level >= 2,
+        names,
+        functions: HashMap::new(),
+        bound: vec![Vec::new()],
+    };
+    optimizer.fold_program(program)
+}
+
+/// Counts how each name in a program is bound.
+#[derive(Default)]
+struct Names {
+    lets: HashMap<String, usize>,
+    // parameters, loop variables, import aliases, catch parameters and
+    // assignment targets
+    others: HashSet<String>,
+}
+
+impl Names {
+    /// Whether `name` only ever holds the value of its one `let`.
+    fn is_constant(&self, name: &str) -> bool {
+        self.lets.get(name) == Some(&1) && !self.others.contains(name)
+    }
+
+    /// Whether `name` always refers to a builtin.
+    fn is_builtin(&self, name: &str) -> bool {
+        lookup_builtin(name).is_some()
+            && !self.lets.contains_key(name)
+            && !self.others.contains(name)
+    }
+}
+
+impl Visitor for Names {
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        *self.lets.entry(ls.name.value.clone()).or_insert(0) += 1;
+        visit::walk_let_statement(self, ls);
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.others.insert(fs.variable.value.clone());
+        visit::walk_for_statement(self, fs);
+    }
+
+    fn visit_import_statement(&mut self, is: &ImportStatement) {
+        self.others.insert(is.alias.value.clone());
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        if let Some(catch) = &te.catch {
+            self.others.insert(catch.parameter.value.clone());
+        }
+        visit::walk_try_expression(self, te);
+    }
+
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        self.others
+            .extend(fl.parameters.iter().map(|p| p.value.clone()));
+        visit::walk_function_literal(self, fl);
+    }
+
+    fn visit_macro_literal(&mut self, ml: &MacroLiteral) {
+        self.others
+            .extend(ml.parameters.iter().map(|p| p.value.clone()));
+        visit::walk_macro_literal(self, ml);
+    }
+
+    fn visit_assign_expression(&mut self, ae: &AssignExpression) {
+        let mut target = ae.target.as_ref();
+        while let Expression::IndexExpression(ie) = target {
+            target = &ie.left;
+        }
+        if let Expression::Identifier(id) = target {
+            self.others.insert(id.value.clone());
+        }
+        visit::walk_assign_expression(self, ae);
+    }
+}
+
+/// A function that calls can be replaced by.
+struct Inlinable {
+    parameters: Vec<String>,
+    body: Expression,
+}
+
+struct Optimizer {
+    inline: bool,
+    names: Names,
+    functions: HashMap<String, Inlinable>,
+    // the names certainly bound at this point of the program, one frame per
+    // block that may not run
+    bound: Vec<Vec<String>>,
+}
+
+impl Optimizer {
+    fn is_bound(&self, name: &str) -> bool {
+        self.bound.iter().flatten().any(|bound| bound == name)
+    }
+
+    fn in_frame<T>(&mut self, names: Vec<String>, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.bound.push(names);
+        let result = f(self);
+        self.bound.pop();
+        result
+    }
+
+    /// Optimizes the statements of a block or program, whose value is that of
+    /// its last statement.
+    fn statements(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        let count = statements.len();
+        let mut out = Vec::new();
+
+        for (i, statement) in statements.into_iter().enumerate() {
+            let last = i + 1 == count;
+            let statement = self.fold_statement(statement);
+
+            if let Statement::LetStatement(ls) = &statement {
+                self.define(ls);
+            }
+
+            match statement {
+                Statement::ExpressionStatement(ExpressionStatement {
+                    expression: Some(Expression::IfExpression(ie)),
+                    ..
+                }) if constant_condition(&ie.condition) == Some(true)
+                    && ie.alternative.is_none()
+                    && !(last && ie.consequence.statements.is_empty()) =>
+                {
+                    // blocks share the scope they are in
+                    out.extend(ie.consequence.statements);
+                }
+                statement if !last && is_dead(&statement) => {}
+                statement => out.push(statement),
+            }
+
+            if out.last().is_some_and(|s| {
+                matches!(
+                    s,
+                    Statement::ReturnStatement(_)
+                        | Statement::BreakStatement(_)
+                        | Statement::ContinueStatement(_)
+                        | Statement::ThrowStatement(_)
+                )
+            }) {
+                break;
+            }
+        }
+
+        out
+    }
+
+    /// Records the binding of `ls`, which has just been evaluated.
+    fn define(&mut self, ls: &LetStatement) {
+        let name = &ls.name.value;
+        self.bound.last_mut().unwrap().push(name.clone());
+
+        if let Some(Expression::FunctionLiteral(fl)) = &ls.value {
+            if self.names.is_constant(name) {
+                if let Some(inlinable) = self.inlinable(fl) {
+                    self.functions.insert(name.clone(), inlinable);
+                }
+            }
+        }
+    }
+
+    fn inlinable(&self, fl: &FunctionLiteral) -> Option<Inlinable> {
+        let body = match fl.body.statements.as_slice() {
+            [Statement::ExpressionStatement(ExpressionStatement {
+                expression: Some(e),
+                ..
+            })] => e,
+            [Statement::ReturnStatement(ReturnStatement {
+                return_value: Some(rv),
+                ..
+            })] => rv.as_ref(),
+            _ => return None,
+        };
+
+        let parameters: Vec<String> = fl.parameters.iter().map(|p| p.value.clone()).collect();
+        let mut check = InlineCheck {
+            names: &self.names,
+            parameters: &parameters,
+            size: 0,
+            inlinable: true,
+        };
+        check.visit_expression(body);
+
+        (check.inlinable && check.size <= MAX_INLINE_SIZE).then(|| {
+            // the calls are in tail position no longer where the body goes
+            let mut body = body.clone();
+            clear_tail_calls(&mut body);
+            Inlinable { parameters, body }
+        })
+    }
+
+    /// The body of the function `ce` calls with its arguments in place of the
+    /// parameters, when evaluating the arguments first could not make a
+    /// difference.
+    fn inline_call(&self, ce: &CallExpression) -> Option<Expression> {
+        let name = match ce.function.as_ref() {
+            Expression::Identifier(id) if self.is_bound(&id.value) => &id.value,
+            _ => return None,
+        };
+        let function = self.functions.get(name)?;
+
+        let trivial = ce.arguments.iter().all(|a| match a {
+            Expression::Identifier(id) => self.is_bound(&id.value),
+            a => constant(a).is_some(),
+        });
+        if !trivial || ce.arguments.len() != function.parameters.len() {
+            return None;
+        }
+
+        let mut substitute = Substitute {
+            parameters: &function.parameters,
+            arguments: &ce.arguments,
+        };
+        Some(substitute.fold_expression(function.body.clone()))
+    }
+}
+
+impl Fold for Optimizer {
+    fn fold_program(&mut self, program: Program) -> Program {
+        Program {
+            statements: self.statements(program.statements),
+        }
+    }
+
+    fn fold_block_statement(&mut self, mut block: BlockStatement) -> BlockStatement {
+        block.statements = self.statements(block.statements);
+        block
+    }
+
+    fn fold_while_statement(&mut self, mut ws: WhileStatement) -> WhileStatement {
+        ws.condition = self.fold_expression(ws.condition);
+        ws.body = self.in_frame(Vec::new(), |o| o.fold_block_statement(ws.body));
+        ws
+    }
+
+    fn fold_for_statement(&mut self, mut fs: ForStatement) -> ForStatement {
+        fs.iterable = self.fold_expression(fs.iterable);
+        let variable = vec![fs.variable.value.clone()];
+        fs.body = self.in_frame(variable, |o| o.fold_block_statement(fs.body));
+        fs
+    }
+
+    fn fold_if_expression(&mut self, mut ie: IfExpression) -> IfExpression {
+        ie.condition = Box::new(self.fold_expression(*ie.condition));
+        ie.consequence = self.in_frame(Vec::new(), |o| o.fold_block_statement(ie.consequence));
+        ie.alternative = ie
+            .alternative
+            .map(|alt| self.in_frame(Vec::new(), |o| o.fold_block_statement(alt)));
+        ie
+    }
+
+    // any part of a try may be cut short
+    fn fold_try_expression(&mut self, mut te: TryExpression) -> TryExpression {
+        te.body = self.in_frame(Vec::new(), |o| o.fold_block_statement(te.body));
+        te.catch = te.catch.map(|mut catch| {
+            let (parameter, body) = (vec![catch.parameter.value.clone()], catch.body);
+            catch.body = self.in_frame(parameter, |o| o.fold_block_statement(body));
+            catch
+        });
+        te.finally = te
+            .finally
+            .map(|finally| self.in_frame(Vec::new(), |o| o.fold_block_statement(finally)));
+        te
+    }
+
+    fn fold_function_literal(&mut self, mut fl: FunctionLiteral) -> FunctionLiteral {
+        let parameters = fl.parameters.iter().map(|p| p.value.clone()).collect();
+        let body = Rc::unwrap_or_clone(fl.body);
+        fl.body = function_body(self.in_frame(parameters, |o| o.fold_block_statement(body)));
+        fl
+    }
+
+    // macro bodies are code to be quoted
+    fn fold_macro_literal(&mut self, ml: MacroLiteral) -> MacroLiteral {
+        ml
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        if let Expression::CallExpression(ce) = &expression {
+            if matches!(ce.function.as_ref(), Expression::Identifier(id) if id.value == "quote") {
+                return expression;
+            }
+        }
+
+        match fold::walk_expression(self, expression) {
+            Expression::PrefixExpression(pe) => match constant(&pe.right) {
+                Some(right) => {
+                    let folded = eval_prefix_expression(&pe.operator, right);
+                    literal(&folded, pe.span).unwrap_or(Expression::PrefixExpression(pe))
+                }
+                None => Expression::PrefixExpression(pe),
+            },
+            Expression::InfixExpression(ie) => fold_infix(ie),
+            Expression::IfExpression(ie) => simplify_if(ie),
+            Expression::CallExpression(ce) if self.inline => match self.inline_call(&ce) {
+                Some(inlined) => self.fold_expression(inlined),
+                None => Expression::CallExpression(ce),
+            },
+            expression => expression,
+        }
+    }
+}
+
+fn fold_infix(ie: InfixExpression) -> Expression {
+    let left = match constant(&ie.left) {
+        Some(left) => left,
+        None => return Expression::InfixExpression(ie),
+    };
+
+    let folded = match (ie.operator.as_str(), is_truthy(&left)) {
+        // the right operand is never evaluated
+        ("&&", false) => Object::Boolean(false),
+        ("||", true) => Object::Boolean(true),
+        (operator, _) => match constant(&ie.right) {
+            // the left operand does not decide the result
+            Some(right) if operator == "&&" || operator == "||" => {
+                Object::Boolean(is_truthy(&right))
+            }
+            Some(right) => eval_infix_expression(operator, left, right),
+            None => return Expression::InfixExpression(ie),
+        },
+    };
+
+    literal(&folded, ie.span).unwrap_or(Expression::InfixExpression(ie))
+}
+
+/// Keeps only the branch that runs when the condition of `ie` is a literal.
+fn simplify_if(mut ie: IfExpression) -> Expression {
+    let block = match constant_condition(&ie.condition) {
+        Some(true) => ie.consequence,
+        Some(false) => match ie.alternative.take() {
+            Some(alternative) => alternative,
+            None => {
+                ie.consequence.statements.clear();
+                return Expression::IfExpression(ie);
+            }
+        },
+        None => return Expression::IfExpression(ie),
+    };
+
+    match block.statements.as_slice() {
+        [Statement::ExpressionStatement(ExpressionStatement {
+            expression: Some(e),
+            ..
+        })] => e.clone(),
+        _ => Expression::IfExpression(IfExpression {
+            token: ie.token,
+            span: ie.span,
+            condition: Box::new(literal(&Object::Boolean(true), ie.condition.span()).unwrap()),
+            consequence: block,
+            alternative: None,
+        }),
+    }
+}
+
+/// Whether `statement` neither has an effect nor can fail, so that it only
+/// matters as the last statement of a block.
+fn is_dead(statement: &Statement) -> bool {
+    match statement {
+        Statement::ExpressionStatement(es) => match &es.expression {
+            Some(Expression::IfExpression(ie)) => {
+                constant_condition(&ie.condition) == Some(false) && ie.alternative.is_none()
+            }
+            Some(e) => constant(e).is_some(),
+            None => true,
+        },
+        Statement::WhileStatement(ws) => constant_condition(&ws.condition) == Some(false),
+        _ => false,
+    }
+}
+
+/// The value of a literal.
+fn constant(expression: &Expression) -> Option<Object> {
+    match expression {
+        Expression::IntegerLiteral(il) => Some(Object::integer(il.value.clone())),
+        Expression::Boolean(b) => Some(Object::Boolean(b.value)),
+        Expression::StringLiteral(sl) => Some(Object::String(sl.value.clone())),
+        _ => None,
+    }
+}
+
+fn constant_condition(condition: &Expression) -> Option<bool> {
+    constant(condition).map(|c| is_truthy(&c))
+}
+
+/// The literal for `obj`, unless it is an error or has none.
+fn literal(obj: &Object, span: Span) -> Option<Expression> {
+    match obj {
+        Object::Integer(_) | Object::BigInteger(_) | Object::Boolean(_) | Object::String(_) => {
+            convert_object_to_ast_node(obj, span)
+        }
+        _ => None,
+    }
+}
+
+/// Measures a function body and checks that it only uses its parameters and
+/// builtins, and does not bind or assign anything.
+struct InlineCheck<'a> {
+    names: &'a Names,
+    parameters: &'a [String],
+    size: usize,
+    inlinable: bool,
+}
+
+impl Visitor for InlineCheck<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.size += 1;
+        match expression {
+            Expression::IfExpression(_)
+            | Expression::TryExpression(_)
+            | Expression::FunctionLiteral(_)
+            | Expression::MacroLiteral(_)
+            | Expression::AssignExpression(_)
+            | Expression::MemberExpression(_) => self.inlinable = false,
+            _ => visit::walk_expression(self, expression),
+        }
+    }
+
+    fn visit_identifier(&mut self, id: &Identifier) {
+        if !self.parameters.contains(&id.value) && !self.names.is_builtin(&id.value) {
+            self.inlinable = false;
+        }
+    }
+}
+
+/// Replaces parameters by the arguments of a call.
+struct Substitute<'a> {
+    parameters: &'a [String],
+    arguments: &'a [Expression],
+}
+
+impl Fold for Substitute<'_> {
+    fn fold_identifier(&mut self, id: Identifier) -> Expression {
+        match self.parameters.iter().position(|p| *p == id.value) {
+            Some(i) => self.arguments[i].clone(),
+            None => Expression::Identifier(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::capture_output;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+
+    use crate::parser::parse_ok;
+    use crate::printer::parenthesize;
+
+    #[test]
+    fn test_optimized_programs() {
+        let tests = [
+            // level 1
+            ("1 + 2 * 3", 1, "7"),
+            ("-(2 - 5) * 2 == 6", 1, "true"),
+            (r#""a" + "b" + c"#, 1, "(\"ab\" + c)"),
+            ("x + 1 + 2", 1, "((x + 1) + 2)"),
+            ("!0 || f()", 1, "(false || f())"),
+            ("false && f()", 1, "false"),
+            ("true && f()", 1, "(true && f())"),
+            ("1 < 2 && 3", 1, "true"),
+            ("1 << 64; 10 / 0; 1 + true", 1, "(1 << 64);(10 / 0);(1 + true)"),
+            ("if (1 > 2) { a } else { b }", 1, "b"),
+            ("if (true) { a } else { b }", 1, "a"),
+            ("if (false) { a }", 1, "if (false) {  }"),
+            ("if (false) { a }; b", 1, "b"),
+            ("let x = if (true) { let y = 1; y };", 1, "let x = if (true) { let y = 1; y };"),
+            ("if (true) { let y = 1; y }", 1, "let y = 1;y"),
+            ("while (false) { f() } 1; x", 1, "x"),
+            (
+                "fn(x) { if (x) { return 1; f(); } return 2; g(); }",
+                1,
+                "fn(x) { if (x) { return 1; }; return 2; }",
+            ),
+            ("while (x) { break; x = 1; }", 1, "while (x) { break; }"),
+            ("try { throw 1 + 2; f(); } finally { g() }", 1, "try { throw 3; } finally { g() }"),
+            ("quote(1 + 2); let m = macro(a) { 1 + 2 };", 1, "quote((1 + 2));let m = macro(a) { (1 + 2) };"),
+            // level 2
+            ("let sq = fn(x) { x * x }; sq(3)", 1, "let sq = fn(x) { (x * x) };sq(3)"),
+            ("let sq = fn(x) { x * x }; sq(3)", 2, "let sq = fn(x) { (x * x) };9"),
+            (
+                "let f = fn(a, b) { return len(a) + b; }; let y = 2; f(\"ab\", y)",
+                2,
+                "let f = fn(a, b) { return (len(a) + b); };let y = 2;(len(\"ab\") + y)",
+            ),
+            // recursive, uses a free variable, or has a body with effects
+            (
+                "let f = fn(n) { f(n - 1) }; let k = 1; let g = fn(n) { n + k }; let h = fn(n) { n = 1 }; f(1); g(1); h(1)",
+                2,
+                "let f = fn(n) { f((n - 1)) };let k = 1;let g = fn(n) { (n + k) };let h = fn(n) { (n = 1) };f(1);g(1);h(1)",
+            ),
+            // arguments that could fail, or a function that may be rebound
+            (
+                "let f = fn(x) { x }; f(y); f(g()); let g = fn(x) { x }; g = 1; g(1)",
+                2,
+                "let f = fn(x) { x };f(y);f(g());let g = fn(x) { x };(g = 1);g(1)",
+            ),
+            // the binding may be cut short by an exception
+            (
+                "let f = fn(x) { x }; try { let y = g(); f(y) } catch (e) { f(e) }",
+                2,
+                "let f = fn(x) { x };try { let y = g(); y } catch (e) { e }",
+            ),
+            // called before it is bound, or with shadowed builtins
+            (
+                "f(1); let f = fn(x) { len(x) }; let len = 1; f(1)",
+                2,
+                "f(1);let f = fn(x) { len(x) };let len = 1;f(1)",
+            ),
+        ];
+
+        for (input, level, expected) in tests {
+            let program = optimize(parse_ok(input), level);
+            assert_eq!(parenthesize(&program), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_optimization_preserves_semantics() {
+        let tests = [
+            "let a = 2 * 3 + 4; puts(a); if (a > 5) { puts(\"big\"); } else { puts(\"small\"); }",
+            "let f = fn(x) { if (true) { return x * 2; } x }; puts(f(21)); f(1) + 1 * 2",
+            "let sq = fn(x) { x * x }; let total = 0; for (i in [1, 2, 3]) { total += sq(i); } total",
+            "let show = fn(x) { puts(x) }; show(1); show(\"two\"); let x = [3]; show(x)",
+            "let add = fn(a, b) { a + b }; add(1, true)",
+            "let div = fn(a, b) { a / b }; div(1, 0)",
+            "let head = fn(a) { first(a) }; head([1, 2])",
+            "let i = 0; while (true) { i += 1; if (i > 3) { break; } } i",
+            "let f = fn() { if (false) { 1 } }; f()",
+            "if (true) { let x = 5; } x",
+            "let f = fn(x) { x }; f(1, 2)",
+            "true || undefined",
+            "1 + 2; 3 * 4",
+        ];
+
+        let run = |input: &str, level: u8| {
+            let program = optimize(parse_ok(input), level);
+            let (result, output) =
+                capture_output(|| Evaluator::new().eval_program(&program, &Environment::new()));
+            (result.inspect(), output)
+        };
+
+        for input in tests {
+            let expected = run(input, 0);
+            for level in 1..=MAX_OPT_LEVEL {
+                assert_eq!(
+                    run(input, level),
+                    expected,
+                    "input: {} at level {}",
+                    input,
+                    level
+                );
+            }
+        }
+    }
+}
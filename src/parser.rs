@@ -1,16 +1,121 @@
 use crate::ast::*;
+use crate::bigint::BigInt;
 use crate::lexer::*;
+use crate::limits::{max_stack_for, EMBEDDED_MAX_STACK, STACK_SIZE};
+use crate::tail_calls::function_body;
 use crate::token::*;
 
-#[allow(dead_code)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub enum Precedence {
     LOWEST,
-    EQUALS,
-    LESSGREATER,
-    SUM,
-    PRODUCT,
-    PREFIX,
-    CALL,
+    ASSIGN,      // = or +=
+    LOGICALOR,   // ||
+    LOGICALAND,  // &&
+    BITOR,       // |
+    BITXOR,      // ^
+    BITAND,      // &
+    EQUALS,      // ==
+    LESSGREATER, // > or <
+    SHIFT,       // << or >>
+    SUM,         // +
+    PRODUCT,     // *
+    PREFIX,      // -X or !X
+    CALL,        // myFunction(X)
+    INDEX,       // array[index]
+}
+
+fn token_precedence(token_type: &TokenType) -> Precedence {
+    match token_type {
+        TokenType::ASSIGN
+        | TokenType::PlusAssign
+        | TokenType::MinusAssign
+        | TokenType::AsteriskAssign
+        | TokenType::SlashAssign => Precedence::ASSIGN,
+        TokenType::OR => Precedence::LOGICALOR,
+        TokenType::AND => Precedence::LOGICALAND,
+        TokenType::PIPE => Precedence::BITOR,
+        TokenType::CARET => Precedence::BITXOR,
+        TokenType::AMPERSAND => Precedence::BITAND,
+        TokenType::EQ | TokenType::NotEq => Precedence::EQUALS,
+        TokenType::LT | TokenType::GT | TokenType::LtEq | TokenType::GtEq => {
+            Precedence::LESSGREATER
+        }
+        TokenType::LShift | TokenType::RShift => Precedence::SHIFT,
+        TokenType::PLUS | TokenType::MINUS => Precedence::SUM,
+        TokenType::ASTERISK | TokenType::SLASH | TokenType::PERCENT => Precedence::PRODUCT,
+        TokenType::LPAREN => Precedence::CALL,
+        TokenType::LBRACKET | TokenType::DOT => Precedence::INDEX,
+        _ => Precedence::LOWEST,
+    }
+}
+
+// the stack parsing takes for each level of nesting at most, measured in a
+// debug build with some to spare
+const PARSE_STACK_PER_LEVEL: usize = 12 << 10;
+
+// the stack the passes over a syntax tree take for each of its levels at
+// most, measured likewise
+const PASS_STACK_PER_LEVEL: usize = 32 << 10;
+
+// the stack the walks over a syntax tree that do not measure what they take,
+// dropping it or looking for macros in it, take for each of its levels at
+// most, measured likewise
+const WALK_STACK_PER_LEVEL: usize = 512;
+
+/// How deeply statements and expressions may nest before parsing gives up.
+/// Hosts parse on the threads they evaluate on, so this keeps parsing within
+/// the stack evaluation may take by default, `limits::EMBEDDED_MAX_STACK`.
+pub const MAX_NESTING: usize = EMBEDDED_MAX_STACK / PARSE_STACK_PER_LEVEL;
+
+/// How deep the syntax tree of a program may be. Operators, calls and
+/// indexes chained onto an expression are parsed in a loop, which takes no
+/// more stack however long the chain is, but each of them makes the tree a
+/// level deeper. Hosts walk the tree on the threads they evaluate on, if only
+/// to drop it, so this keeps those walks within `limits::EMBEDDED_MAX_STACK`
+/// too, and checking, optimizing and compiling within the stack of the
+/// command line, `limits::STACK_SIZE`. Evaluation and the expansion of macros
+/// measure the stack they take instead.
+pub const MAX_TREE_DEPTH: usize = {
+    let walks = EMBEDDED_MAX_STACK / WALK_STACK_PER_LEVEL;
+    let passes = max_stack_for(STACK_SIZE) / PASS_STACK_PER_LEVEL;
+    if walks < passes {
+        walks
+    } else {
+        passes
+    }
+};
+
+/// Parses `input` as a type annotation, such as `fn(int) -> [int]`.
+pub fn parse_type_annotation(input: &str) -> Result<TypeAnnotation, String> {
+    let mut p = Parser::new(Lexer::new(input));
+    let annotation = p.parse_type();
+
+    if let (Some(annotation), true) = (annotation, p.peek_token_is(&TokenType::EOF)) {
+        return Ok(annotation);
+    }
+    if p.errors.is_empty() {
+        p.peek_error(&TokenType::EOF);
+    }
+    Err(p.errors.join("\n"))
+}
+
+/// Parses `input` as a program, for tests of the passes that take one,
+/// failing the test if it does not parse.
+#[cfg(test)]
+pub(crate) fn parse_ok(input: &str) -> Program {
+    let mut p = Parser::new(Lexer::new(input));
+    let program = p.parse_program();
+    assert!(p.errors().is_empty(), "parser errors: {:?}", p.errors());
+    program
+}
+
+fn is_assignable(target: &Expression) -> bool {
+    match target {
+        Expression::Identifier(_) => true,
+        Expression::IndexExpression(ie) => is_assignable(&ie.left),
+        _ => false,
+    }
 }
 
 pub struct Parser {
@@ -18,6 +123,16 @@ pub struct Parser {
     cur_token: Token,
     peek_token: Token,
     errors: Vec<String>,
+    // number of loops enclosing the statement being parsed, reset inside
+    // function bodies
+    loop_depth: usize,
+    // number of blocks enclosing the statement being parsed
+    block_depth: usize,
+    // number of statements and expressions being parsed, see MAX_NESTING
+    nesting: usize,
+    // the deepest level of the tree the expression being parsed reaches so
+    // far, see MAX_TREE_DEPTH
+    deepest: usize,
 }
 
 impl Parser {
@@ -27,12 +142,18 @@ impl Parser {
             cur_token: Token {
                 token_type: TokenType::ILLEGAL,
                 literal: "\0".to_string(),
+                ..Default::default()
             },
             peek_token: Token {
                 token_type: TokenType::ILLEGAL,
                 literal: "\0".to_string(),
+                ..Default::default()
             },
             errors: Vec::new(),
+            loop_depth: 0,
+            block_depth: 0,
+            nesting: 0,
+            deepest: 0,
         };
 
         p.next_token();
@@ -40,7 +161,6 @@ impl Parser {
         p
     }
 
-    #[allow(dead_code)]
     pub fn errors(&self) -> Vec<String> {
         self.errors.clone()
     }
@@ -53,24 +173,33 @@ impl Parser {
         self.errors.push(msg);
     }
 
+    fn no_prefix_parse_fn_error(&mut self, token_type: &TokenType) {
+        let msg = format!("no prefix parse function for {:?} found", token_type);
+        self.errors.push(msg);
+    }
+
     pub fn next_token(&mut self) {
         self.cur_token = self.peek_token.clone();
         self.peek_token = self.l.next_token()
     }
 
+    /// The span from `start` to the end of the current token, which is the
+    /// last token of the node being parsed.
+    fn span_from(&self, start: Position) -> Span {
+        Span {
+            start,
+            end: self.cur_token.span.end,
+        }
+    }
+
     pub fn parse_program(&mut self) -> Program {
         let mut program = Program {
             statements: Vec::new(),
         };
 
-        loop {
-            if self.cur_token.token_type == TokenType::EOF {
-                break;
-            }
-            let stmt = self.parse_statement();
-            match stmt {
-                Some(s) => program.statements.push(s),
-                None => {}
+        while !self.cur_token_is(TokenType::EOF) {
+            if let Some(s) = self.parse_statement() {
+                program.statements.push(s);
             }
             self.next_token()
         }
@@ -78,16 +207,50 @@ impl Parser {
         program
     }
 
+    /// Enters a nested statement or expression, failing once the input is
+    /// nested more than `MAX_NESTING` levels deep.
+    fn enter_nesting(&mut self) -> bool {
+        if self.nesting == MAX_NESTING {
+            self.too_deep(MAX_NESTING);
+            return false;
+        }
+        self.nesting += 1;
+        self.deepest = self.deepest.max(self.nesting);
+        true
+    }
+
+    fn too_deep(&mut self, limit: usize) {
+        let msg = format!("input is nested more than {} levels deep", limit);
+        if !self.errors.contains(&msg) {
+            self.errors.push(msg);
+        }
+    }
+
     pub fn parse_statement(&mut self) -> Option<Statement> {
+        if !self.enter_nesting() {
+            return None;
+        }
+        let stmt = self.parse_statement_kind();
+        self.nesting -= 1;
+        stmt
+    }
+
+    fn parse_statement_kind(&mut self) -> Option<Statement> {
         match self.cur_token.token_type {
-            TokenType::LET => self.parse_let_statement(),
+            TokenType::LET | TokenType::CONST => self.parse_let_statement(),
             TokenType::RETURN => self.parse_return_statement(),
+            TokenType::WHILE => self.parse_while_statement(),
+            TokenType::FOR => self.parse_for_statement(),
+            TokenType::BREAK | TokenType::CONTINUE => self.parse_loop_control_statement(),
+            TokenType::IMPORT => self.parse_import_statement(),
+            TokenType::EXPORT => self.parse_export_statement(),
+            TokenType::THROW => self.parse_throw_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
     pub fn parse_let_statement(&mut self) -> Option<Statement> {
-        // let <identifier> = <expression>;
+        // let <identifier>[: <type>] = <expression>; or the same with const
         let mut stmt = LetStatement {
             token: self.cur_token.clone(),
             value: None,
@@ -100,72 +263,318 @@ impl Parser {
 
         stmt.name = Identifier {
             token: self.cur_token.clone(),
+            span: self.cur_token.span,
             value: self.cur_token.literal.clone(),
         };
+        stmt.type_annotation = self.parse_optional_annotation(&TokenType::COLON)?;
 
         if !self.expect_peek(TokenType::ASSIGN) {
             return None;
         }
 
-        // TODO: skipping expression suntil we encounter semicolon
-        loop {
-            if self.cur_token_is(TokenType::SEMICOLON) {
-                self.next_token();
-            } else {
-                break;
-            }
+        self.next_token();
+        stmt.value = Some(self.parse_expression(Precedence::LOWEST)?);
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
         }
 
+        stmt.span = self.span_from(stmt.token.span.start);
         Some(Statement::LetStatement(stmt))
     }
 
+    pub fn parse_export_statement(&mut self) -> Option<Statement> {
+        // export let <identifier> = <expression>;
+        if !self.top_level_only("export") {
+            return None;
+        }
+        if !self.peek_token_is(&TokenType::LET) && !self.peek_token_is(&TokenType::CONST) {
+            self.peek_error(&TokenType::LET);
+            return None;
+        }
+
+        let start = self.cur_token.span.start;
+        self.next_token();
+        match self.parse_let_statement()? {
+            Statement::LetStatement(mut ls) => {
+                ls.exported = true;
+                ls.span.start = start;
+                Some(Statement::LetStatement(ls))
+            }
+            stmt => Some(stmt),
+        }
+    }
+
+    pub fn parse_import_statement(&mut self) -> Option<Statement> {
+        // import "<path>" as <identifier>;
+        let token = self.cur_token.clone();
+
+        // a misplaced import is parsed all the same, so the rest of it
+        // is not taken for statements of its own
+        let misplaced = !self.top_level_only("import");
+        if !self.expect_peek(TokenType::STRING) {
+            return None;
+        }
+
+        let path = self.cur_token.literal.clone();
+
+        if !self.expect_peek(TokenType::AS) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::IDENT) {
+            return None;
+        }
+
+        let alias = Identifier {
+            token: self.cur_token.clone(),
+            span: self.cur_token.span,
+            value: self.cur_token.literal.clone(),
+        };
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        if misplaced {
+            return None;
+        }
+
+        Some(Statement::ImportStatement(ImportStatement {
+            span: self.span_from(token.span.start),
+            token,
+            path,
+            alias,
+        }))
+    }
+
+    fn top_level_only(&mut self, keyword: &str) -> bool {
+        if self.block_depth > 0 {
+            let msg = format!("'{}' is only allowed at the top level", keyword);
+            self.errors.push(msg);
+            return false;
+        }
+        true
+    }
+
     pub fn parse_return_statement(&mut self) -> Option<Statement> {
         // return <expression>;
-        let stmt = ReturnStatement {
+        let mut stmt = ReturnStatement {
             token: self.cur_token.clone(),
             ..Default::default()
         };
 
         self.next_token();
+        stmt.return_value = Some(Box::new(self.parse_expression(Precedence::LOWEST)?));
 
-        // TODO: skipping expressions until encounter semicolon;
-
-        loop {
-            if self.cur_token_is(TokenType::SEMICOLON) {
-                break;
-            } else {
-                self.next_token();
-            }
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
         }
 
+        stmt.span = self.span_from(stmt.token.span.start);
         Some(Statement::ReturnStatement(stmt))
     }
 
+    pub fn parse_throw_statement(&mut self) -> Option<Statement> {
+        // throw <expression>;
+        let token = self.cur_token.clone();
+
+        self.next_token();
+        let value = self.parse_expression(Precedence::LOWEST)?;
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+
+        Some(Statement::ThrowStatement(ThrowStatement {
+            span: self.span_from(token.span.start),
+            token,
+            value,
+        }))
+    }
+
     pub fn parse_expression_statement(&mut self) -> Option<Statement> {
-        dbg!("parse_expression_statement");
-        let stmt = Statement::ExpressionStatement(ExpressionStatement {
+        let mut stmt = ExpressionStatement {
             token: self.cur_token.clone(),
             ..Default::default()
-        });
+        };
 
-        if let Statement::ExpressionStatement(mut es) = stmt.clone() {
-            es.expression = self.parse_expression(Precedence::LOWEST);
+        stmt.expression = Some(self.parse_expression(Precedence::LOWEST)?);
 
-            if self.peek_token_is(&TokenType::SEMICOLON) {
-                self.next_token();
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+
+        stmt.span = self.span_from(stmt.token.span.start);
+        Some(Statement::ExpressionStatement(stmt))
+    }
+
+    pub fn parse_while_statement(&mut self) -> Option<Statement> {
+        // while (<condition>) <block statement>
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(Precedence::LOWEST)?;
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        let body = self.parse_loop_body();
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+
+        Some(Statement::WhileStatement(WhileStatement {
+            span: self.span_from(token.span.start),
+            token,
+            condition,
+            body,
+        }))
+    }
+
+    pub fn parse_for_statement(&mut self) -> Option<Statement> {
+        // for (<identifier> in <expression>) <block statement>
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::IDENT) {
+            return None;
+        }
+
+        let variable = Identifier {
+            token: self.cur_token.clone(),
+            span: self.cur_token.span,
+            value: self.cur_token.literal.clone(),
+        };
+
+        if !self.expect_peek(TokenType::IN) {
+            return None;
+        }
+
+        self.next_token();
+        let iterable = self.parse_expression(Precedence::LOWEST)?;
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        let body = self.parse_loop_body();
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+
+        Some(Statement::ForStatement(ForStatement {
+            span: self.span_from(token.span.start),
+            token,
+            variable,
+            iterable,
+            body,
+        }))
+    }
+
+    fn parse_loop_body(&mut self) -> BlockStatement {
+        self.loop_depth += 1;
+        let body = self.parse_block_statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    pub fn parse_loop_control_statement(&mut self) -> Option<Statement> {
+        // break; or continue;
+        let token = self.cur_token.clone();
+
+        if self.peek_token_is(&TokenType::SEMICOLON) {
+            self.next_token();
+        }
+
+        if self.loop_depth == 0 {
+            let msg = format!("'{}' outside of a loop", token.literal);
+            self.errors.push(msg);
+            return None;
+        }
+
+        let span = self.span_from(token.span.start);
+        match token.token_type {
+            TokenType::BREAK => Some(Statement::BreakStatement(BreakStatement { token, span })),
+            _ => Some(Statement::ContinueStatement(ContinueStatement {
+                token,
+                span,
+            })),
+        }
+    }
+
+    pub fn parse_block_statement(&mut self) -> BlockStatement {
+        let mut block = BlockStatement {
+            token: self.cur_token.clone(),
+            statements: Vec::new(),
+            ..Default::default()
+        };
+
+        self.next_token();
+        self.block_depth += 1;
+
+        while !self.cur_token_is(TokenType::RBRACE) && !self.cur_token_is(TokenType::EOF) {
+            if let Some(s) = self.parse_statement() {
+                block.statements.push(s);
             }
+            self.next_token();
+        }
 
-            return Some(Statement::ExpressionStatement(es));
+        self.block_depth -= 1;
+        block.span = self.span_from(block.token.span.start);
+        block
+    }
+
+    pub fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if !self.enter_nesting() {
+            return None;
         }
-        None
+        let expression = self.parse_operators(precedence);
+        self.nesting -= 1;
+        expression
     }
 
-    pub fn parse_expression(&mut self, _precedence: Precedence) -> Option<Expression> {
-        dbg!("parse_expression");
-        let exp = self.prefix_parse_fn(&self.cur_token.token_type);
-        dbg!(&exp);
+    // Operators are parsed in a loop, each nesting the expression so far one
+    // level deeper, so how deep the result goes is tracked here rather than
+    // by the recursion: the first operand ends up a level deeper for every
+    // operator, and every later one for each operator after its own.
+    fn parse_operators(&mut self, precedence: Precedence) -> Option<Expression> {
+        let outer = std::mem::replace(&mut self.deepest, self.nesting);
+        let mut left = self.prefix_parse_fn();
+        let mut operands = self.deepest - self.nesting;
+        let mut chained = 0;
 
-        exp
+        while left.is_some()
+            && !self.peek_token_is(&TokenType::SEMICOLON)
+            && precedence < self.peek_precedence()
+        {
+            self.deepest = self.nesting;
+            self.next_token();
+            left = self.infix_parse_fn(left.unwrap());
+            chained += 1;
+            operands = operands.max((self.deepest - self.nesting).saturating_sub(chained));
+            if self.nesting + chained + operands > MAX_TREE_DEPTH {
+                self.too_deep(MAX_TREE_DEPTH);
+                left = None;
+                break;
+            }
+        }
+
+        self.deepest = outer.max(self.nesting + chained + operands);
+        left
     }
 
     fn expect_peek(&mut self, token_type: TokenType) -> bool {
@@ -186,103 +595,656 @@ impl Parser {
         self.peek_token.token_type == *token_type
     }
 
-    fn prefix_parse_fn(&self, token_type: &TokenType) -> Option<Expression> {
-        dbg!("prefix_parse_fn");
-        match token_type {
+    fn peek_precedence(&self) -> Precedence {
+        token_precedence(&self.peek_token.token_type)
+    }
+
+    fn cur_precedence(&self) -> Precedence {
+        token_precedence(&self.cur_token.token_type)
+    }
+
+    fn prefix_parse_fn(&mut self) -> Option<Expression> {
+        match self.cur_token.token_type {
             TokenType::IDENT => Some(self.parse_identifier()),
-            _ => None,
+            TokenType::INT => self.parse_integer_literal(),
+            TokenType::STRING => Some(self.parse_string_literal()),
+            TokenType::LBRACKET => self.parse_array_literal(),
+            TokenType::LBRACE => self.parse_hash_literal(),
+            TokenType::TRUE | TokenType::FALSE => Some(self.parse_boolean()),
+            TokenType::BANG | TokenType::MINUS => self.parse_prefix_expression(),
+            TokenType::LPAREN => self.parse_grouped_expression(),
+            TokenType::IF => self.parse_if_expression(),
+            TokenType::TRY => self.parse_try_expression(),
+            TokenType::FUNCTION => self.parse_function_literal(),
+            TokenType::MACRO => self.parse_macro_literal(),
+            _ => {
+                let token_type = self.cur_token.token_type.clone();
+                self.no_prefix_parse_fn_error(&token_type);
+                None
+            }
         }
     }
 
-    #[allow(dead_code)]
-    fn infix_parse_fn(&self, _token_type: TokenType) {}
+    fn infix_parse_fn(&mut self, left: Expression) -> Option<Expression> {
+        match self.cur_token.token_type {
+            TokenType::LPAREN => self.parse_call_expression(left),
+            TokenType::LBRACKET => self.parse_index_expression(left),
+            TokenType::DOT => self.parse_member_expression(left),
+            TokenType::ASSIGN
+            | TokenType::PlusAssign
+            | TokenType::MinusAssign
+            | TokenType::AsteriskAssign
+            | TokenType::SlashAssign => self.parse_assign_expression(left),
+            _ => self.parse_infix_expression(left),
+        }
+    }
 
     fn parse_identifier(&self) -> Expression {
-        dbg!("parse_identifier");
-        return Expression::Identifier(Identifier {
+        Expression::Identifier(Identifier {
             token: self.cur_token.clone(),
+            span: self.cur_token.span,
             value: self.cur_token.literal.clone(),
-        });
+        })
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{Lexer, Node, Parser,Statement};
-    use crate::{
-        ast::Expression,
-        lexer,
-    };
 
-    fn check_parser_errors(p: &Parser) {
-        let error_count = p.errors.len();
-        if error_count == 0 {
-            return;
+    fn parse_integer_literal(&mut self) -> Option<Expression> {
+        match self.cur_token.literal.parse::<BigInt>() {
+            Ok(value) => Some(Expression::IntegerLiteral(IntegerLiteral {
+                token: self.cur_token.clone(),
+                span: self.cur_token.span,
+                value,
+            })),
+            Err(_) => {
+                let msg = format!("could not parse {:?} as integer", self.cur_token.literal);
+                self.errors.push(msg);
+                None
+            }
         }
+    }
 
-        println!("parser has {} errors", error_count);
-        for msg in p.errors() {
-            println!("parser error: {}", msg);
-        }
-        assert!(false);
+    fn parse_boolean(&self) -> Expression {
+        Expression::Boolean(Boolean {
+            token: self.cur_token.clone(),
+            span: self.cur_token.span,
+            value: self.cur_token_is(TokenType::TRUE),
+        })
     }
-    #[test]
-    fn test_let_statements() {
-        let input = r#"
-let x = 5;
-let y = 10;
-let foo = 838383;
-"#;
 
-        let l = lexer::Lexer::new(input);
-        let mut p = Parser::new(l);
+    fn parse_prefix_expression(&mut self) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
 
-        let program = p.parse_program();
-        check_parser_errors(&p);
+        self.next_token();
+        let right = self.parse_expression(Precedence::PREFIX)?;
 
-        if program.statements.len() != 3 {
-            println!(
-                "program statements does not container 3 statements, got {}",
-                program.statements.len()
-            );
+        Some(Expression::PrefixExpression(PrefixExpression {
+            span: self.span_from(token.span.start),
+            token,
+            operator,
+            right: Box::new(right),
+        }))
+    }
+
+    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
+        let precedence = self.cur_precedence();
+
+        self.next_token();
+        let right = self.parse_expression(precedence)?;
+
+        Some(Expression::InfixExpression(InfixExpression {
+            span: self.span_from(left.span().start),
+            token,
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        }))
+    }
+
+    fn parse_assign_expression(&mut self, target: Expression) -> Option<Expression> {
+        if !is_assignable(&target) {
+            let msg = format!("invalid assignment target: {}", target.to_string());
+            self.errors.push(msg);
+            return None;
         }
 
-        let tests = vec!["x", "y", "foobar"];
+        let token = self.cur_token.clone();
+        let operator = self.cur_token.literal.clone();
 
-        for (i, item) in tests.iter().enumerate() {
-            let stmt = program.statements.get(i).unwrap();
-            if !test_let_statement(stmt, item) {
-                return;
-            }
+        // assignment is right-associative: a = b = c is a = (b = c)
+        self.next_token();
+        let value = self.parse_expression(Precedence::LOWEST)?;
+
+        Some(Expression::AssignExpression(AssignExpression {
+            span: self.span_from(target.span().start),
+            token,
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+        }))
+    }
+
+    fn parse_grouped_expression(&mut self) -> Option<Expression> {
+        self.next_token();
+        let exp = self.parse_expression(Precedence::LOWEST);
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
         }
+
+        exp
     }
 
-    fn test_let_statement(stmt: &Statement, name: &str) -> bool {
-        if stmt.token_literal() == "let" {
-            println!("TokenLiteral not 'let', got {}", stmt.token_literal());
-            return false;
+    fn parse_if_expression(&mut self) -> Option<Expression> {
+        // if (<condition>) <consequence> else <alternative>
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
         }
 
-        if let Statement::LetStatement(ls) = stmt {
-            if ls.name.value != name {
-                println!(
-                    "LetStatement.Name.Value not {}, got {}",
-                    name, ls.name.value
-                );
-                return false;
-            }
-            if ls.name.token_literal() != name {
-                println!(
-                    "LetStatement.Name.TokenLiteral not {} got {}",
-                    name,
-                    ls.name.token_literal()
-                );
-                return false;
+        self.next_token();
+        let condition = self.parse_expression(Precedence::LOWEST)?;
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        let consequence = self.parse_block_statement();
+        let mut alternative = None;
+
+        if self.peek_token_is(&TokenType::ELSE) {
+            self.next_token();
+
+            if !self.expect_peek(TokenType::LBRACE) {
+                return None;
             }
-            return true;
-        } else {
-            println!("statement is not LetStatement");
-            return false;
+            alternative = Some(self.parse_block_statement());
+        }
+
+        Some(Expression::IfExpression(IfExpression {
+            span: self.span_from(token.span.start),
+            token,
+            condition: Box::new(condition),
+            consequence,
+            alternative,
+        }))
+    }
+
+    fn parse_try_expression(&mut self) -> Option<Expression> {
+        // try <block> catch (<identifier>) <block> finally <block>, with the
+        // catch or the finally left out but not both
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_statement();
+
+        let mut catch = None;
+        if self.peek_token_is(&TokenType::CATCH) {
+            self.next_token();
+
+            if !self.expect_peek(TokenType::LPAREN) {
+                return None;
+            }
+            if !self.expect_peek(TokenType::IDENT) {
+                return None;
+            }
+            let parameter = Identifier {
+                token: self.cur_token.clone(),
+                span: self.cur_token.span,
+                value: self.cur_token.literal.clone(),
+            };
+            if !self.expect_peek(TokenType::RPAREN) {
+                return None;
+            }
+            if !self.expect_peek(TokenType::LBRACE) {
+                return None;
+            }
+            catch = Some(Box::new(CatchClause {
+                parameter,
+                body: self.parse_block_statement(),
+            }));
+        }
+
+        let mut finally = None;
+        if self.peek_token_is(&TokenType::FINALLY) {
+            self.next_token();
+
+            if !self.expect_peek(TokenType::LBRACE) {
+                return None;
+            }
+            finally = Some(self.parse_block_statement());
+        }
+
+        if catch.is_none() && finally.is_none() {
+            let msg = format!(
+                "expected next token to be CATCH or FINALLY, got {:?} instead",
+                self.peek_token.token_type
+            );
+            self.errors.push(msg);
+            return None;
+        }
+
+        Some(Expression::TryExpression(TryExpression {
+            span: self.span_from(token.span.start),
+            token,
+            body,
+            catch,
+            finally,
+        }))
+    }
+
+    fn parse_function_literal(&mut self) -> Option<Expression> {
+        // fn(<parameters>)[ -> <type>] <block statement>, where parameters
+        // may be annotated as <identifier>: <type>
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+
+        let (parameters, parameter_types) = self.parse_function_parameters(true)?;
+        let return_type = self.parse_optional_annotation(&TokenType::ARROW)?;
+
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        // a function body starts a new loop context: `break` inside it cannot
+        // refer to a loop around the literal
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.parse_block_statement();
+        self.loop_depth = loop_depth;
+
+        Some(Expression::FunctionLiteral(FunctionLiteral {
+            span: self.span_from(token.span.start),
+            token,
+            parameters,
+            parameter_types,
+            return_type,
+            body: function_body(body),
+        }))
+    }
+
+    fn parse_macro_literal(&mut self) -> Option<Expression> {
+        // macro(<parameters>) <block statement>
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::LPAREN) {
+            return None;
+        }
+
+        let (parameters, _) = self.parse_function_parameters(false)?;
+
+        if !self.expect_peek(TokenType::LBRACE) {
+            return None;
+        }
+
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.parse_block_statement();
+        self.loop_depth = loop_depth;
+
+        Some(Expression::MacroLiteral(MacroLiteral {
+            span: self.span_from(token.span.start),
+            token,
+            parameters,
+            body,
+        }))
+    }
+
+    /// Parses the parameters up to the closing parenthesis, with their type
+    /// annotations if `annotated`.
+    fn parse_function_parameters(
+        &mut self,
+        annotated: bool,
+    ) -> Option<(Vec<Identifier>, Vec<Option<TypeAnnotation>>)> {
+        let mut identifiers = Vec::new();
+        let mut types = Vec::new();
+
+        if self.peek_token_is(&TokenType::RPAREN) {
+            self.next_token();
+            return Some((identifiers, types));
+        }
+
+        loop {
+            if !self.expect_peek(TokenType::IDENT) {
+                return None;
+            }
+            identifiers.push(Identifier {
+                token: self.cur_token.clone(),
+                span: self.cur_token.span,
+                value: self.cur_token.literal.clone(),
+            });
+            types.push(match annotated {
+                true => self.parse_optional_annotation(&TokenType::COLON)?,
+                false => None,
+            });
+
+            if !self.peek_token_is(&TokenType::COMMA) {
+                break;
+            }
+            self.next_token();
+        }
+
+        if !self.expect_peek(TokenType::RPAREN) {
+            return None;
+        }
+
+        Some((identifiers, types))
+    }
+
+    /// Parses `<separator> <type>` if the next token is `separator`.
+    fn parse_optional_annotation(
+        &mut self,
+        separator: &TokenType,
+    ) -> Option<Option<TypeAnnotation>> {
+        if !self.peek_token_is(separator) {
+            return Some(None);
+        }
+        self.next_token();
+        self.next_token();
+        self.parse_type().map(Some)
+    }
+
+    /// Parses the type starting at the current token.
+    fn parse_type(&mut self) -> Option<TypeAnnotation> {
+        if !self.enter_nesting() {
+            return None;
+        }
+        let annotation = self.parse_type_kind();
+        self.nesting -= 1;
+        annotation
+    }
+
+    fn parse_type_kind(&mut self) -> Option<TypeAnnotation> {
+        match self.cur_token.token_type {
+            TokenType::IDENT => match self.cur_token.literal.as_str() {
+                "int" | "bool" | "string" | "null" => {
+                    Some(TypeAnnotation::Named(self.cur_token.literal.clone()))
+                }
+                name => {
+                    let msg = format!("unknown type {}", name);
+                    self.errors.push(msg);
+                    None
+                }
+            },
+            TokenType::LBRACKET => {
+                self.next_token();
+                let element = self.parse_type()?;
+                if !self.expect_peek(TokenType::RBRACKET) {
+                    return None;
+                }
+                Some(TypeAnnotation::Array(Box::new(element)))
+            }
+            TokenType::LBRACE => {
+                self.next_token();
+                let key = self.parse_type()?;
+                if !self.expect_peek(TokenType::COLON) {
+                    return None;
+                }
+                self.next_token();
+                let value = self.parse_type()?;
+                if !self.expect_peek(TokenType::RBRACE) {
+                    return None;
+                }
+                Some(TypeAnnotation::Hash(Box::new(key), Box::new(value)))
+            }
+            TokenType::FUNCTION => {
+                if !self.expect_peek(TokenType::LPAREN) {
+                    return None;
+                }
+                let mut parameters = Vec::new();
+                if self.peek_token_is(&TokenType::RPAREN) {
+                    self.next_token();
+                } else {
+                    loop {
+                        self.next_token();
+                        parameters.push(self.parse_type()?);
+                        if !self.peek_token_is(&TokenType::COMMA) {
+                            break;
+                        }
+                        self.next_token();
+                    }
+                    if !self.expect_peek(TokenType::RPAREN) {
+                        return None;
+                    }
+                }
+                if !self.expect_peek(TokenType::ARROW) {
+                    return None;
+                }
+                self.next_token();
+                let result = self.parse_type()?;
+                Some(TypeAnnotation::Function(parameters, Box::new(result)))
+            }
+            _ => {
+                let msg = format!("expected a type, got {:?}", self.cur_token.token_type);
+                self.errors.push(msg);
+                None
+            }
+        }
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let arguments = self.parse_expression_list(TokenType::RPAREN)?;
+
+        Some(Expression::CallExpression(CallExpression {
+            span: self.span_from(function.span().start),
+            token,
+            function: Box::new(function),
+            arguments,
+            tail: false,
+        }))
+    }
+
+    fn parse_string_literal(&self) -> Expression {
+        Expression::StringLiteral(StringLiteral {
+            token: self.cur_token.clone(),
+            span: self.cur_token.span,
+            value: self.cur_token.literal.clone(),
+        })
+    }
+
+    fn parse_array_literal(&mut self) -> Option<Expression> {
+        let token = self.cur_token.clone();
+        let elements = self.parse_expression_list(TokenType::RBRACKET)?;
+
+        Some(Expression::ArrayLiteral(ArrayLiteral {
+            span: self.span_from(token.span.start),
+            token,
+            elements,
+        }))
+    }
+
+    fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        let token = self.cur_token.clone();
+
+        self.next_token();
+        let index = self.parse_expression(Precedence::LOWEST)?;
+
+        if !self.expect_peek(TokenType::RBRACKET) {
+            return None;
+        }
+
+        Some(Expression::IndexExpression(IndexExpression {
+            span: self.span_from(left.span().start),
+            token,
+            left: Box::new(left),
+            index: Box::new(index),
+        }))
+    }
+
+    fn parse_member_expression(&mut self, object: Expression) -> Option<Expression> {
+        let token = self.cur_token.clone();
+
+        if !self.expect_peek(TokenType::IDENT) {
+            return None;
+        }
+
+        Some(Expression::MemberExpression(MemberExpression {
+            span: self.span_from(object.span().start),
+            token,
+            object: Box::new(object),
+            property: Identifier {
+                token: self.cur_token.clone(),
+                span: self.cur_token.span,
+                value: self.cur_token.literal.clone(),
+            },
+        }))
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expression> {
+        // {<expression>: <expression>, ...}
+        let token = self.cur_token.clone();
+        let mut pairs = Vec::new();
+
+        while !self.peek_token_is(&TokenType::RBRACE) {
+            self.next_token();
+            let key = self.parse_expression(Precedence::LOWEST)?;
+
+            if !self.expect_peek(TokenType::COLON) {
+                return None;
+            }
+
+            self.next_token();
+            let value = self.parse_expression(Precedence::LOWEST)?;
+            pairs.push((key, value));
+
+            if !self.peek_token_is(&TokenType::RBRACE) && !self.expect_peek(TokenType::COMMA) {
+                return None;
+            }
+        }
+
+        if !self.expect_peek(TokenType::RBRACE) {
+            return None;
+        }
+
+        Some(Expression::HashLiteral(HashLiteral {
+            span: self.span_from(token.span.start),
+            token,
+            pairs,
+        }))
+    }
+
+    fn parse_expression_list(&mut self, end: TokenType) -> Option<Vec<Expression>> {
+        let mut list = Vec::new();
+
+        if self.peek_token_is(&end) {
+            self.next_token();
+            return Some(list);
+        }
+
+        self.next_token();
+        list.push(self.parse_expression(Precedence::LOWEST)?);
+
+        while self.peek_token_is(&TokenType::COMMA) {
+            self.next_token();
+            self.next_token();
+            list.push(self.parse_expression(Precedence::LOWEST)?);
+        }
+
+        if !self.expect_peek(end) {
+            return None;
+        }
+
+        Some(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ok, Lexer, Node, Parser, Statement, MAX_NESTING, MAX_TREE_DEPTH};
+    use crate::limits::EMBEDDED_STACK_SIZE;
+    use crate::printer::parenthesize;
+    use crate::{ast::Expression, lexer};
+
+    fn parse_single_expression(input: &str) -> Expression {
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(
+            1,
+            program.statements.len(),
+            "program has not enough statements, got {}",
+            program.statements.len()
+        );
+        match program.statements.into_iter().next().unwrap() {
+            Statement::ExpressionStatement(es) => es.expression.unwrap(),
+            stmt => panic!("statement is not ExpressionStatement, got {:?}", stmt),
+        }
+    }
+
+    fn test_integer_literal(exp: &Expression, value: i64) {
+        match exp {
+            Expression::IntegerLiteral(il) => {
+                assert_eq!(il.value, value, "integer value not {}", value);
+                assert_eq!(il.token_literal(), value.to_string());
+            }
+            _ => panic!("exp not IntegerLiteral, got {:?}", exp),
+        }
+    }
+
+    fn check_parser_errors(p: &Parser) {
+        let error_count = p.errors.len();
+        if error_count == 0 {
+            return;
+        }
+
+        println!("parser has {} errors", error_count);
+        for msg in p.errors() {
+            println!("parser error: {}", msg);
+        }
+        panic!("parser has {} errors", error_count);
+    }
+    #[test]
+    fn test_let_statements() {
+        let input = r#"
+let x = 5;
+let y = 10;
+let foo = 838383;
+"#;
+
+        let l = lexer::Lexer::new(input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(
+            program.statements.len(),
+            3,
+            "program.statements does not contain 3 statements"
+        );
+
+        let tests = ["x", "y", "foo"];
+
+        for (stmt, name) in program.statements.iter().zip(tests) {
+            test_let_statement(stmt, name);
+        }
+    }
+
+    fn test_let_statement(stmt: &Statement, name: &str) {
+        assert_eq!(stmt.token_literal(), "let", "token literal not 'let'");
+
+        match stmt {
+            Statement::LetStatement(ls) => {
+                assert_eq!(ls.name.value, name, "LetStatement.name.value not {}", name);
+                assert_eq!(
+                    ls.name.token_literal(),
+                    name,
+                    "LetStatement.name.token_literal() not {}",
+                    name
+                );
+            }
+            stmt => panic!("statement is not LetStatement, got {:?}", stmt),
         }
     }
 
@@ -311,17 +1273,14 @@ return 993322;
             let returnstmt = match stmt {
                 Statement::ReturnStatement(rs) => rs,
                 _ => {
-                    assert!(false, "statement is not a return statement");
-                    continue;
+                    panic!("statement is not a return statement");
                 }
             };
             if returnstmt.token_literal() != "return" {
-                assert!(
-                    false,
+                panic!(
                     "return statement literal not 'return', got {}",
                     returnstmt.token_literal()
                 );
-                continue;
             }
         }
     }
@@ -341,8 +1300,8 @@ return 993322;
             program.statements.len()
         );
 
-
-        if let Statement::ExpressionStatement(mut es) = program.statements.get(0).unwrap().clone() {
+        if let Statement::ExpressionStatement(mut es) = program.statements.first().unwrap().clone()
+        {
             dbg!(&es);
             #[allow(irrefutable_let_patterns)]
             if let Expression::Identifier(id) = es.expression.as_mut().unwrap() {
@@ -359,10 +1318,577 @@ return 993322;
                     &id.token_literal()
                 );
             } else {
-                assert!(false, "exp not Identifier");
+                panic!("exp not Identifier");
             }
         } else {
-            assert!(false, "program statements [0] is not ExpressionStatement");
+            panic!("program statements [0] is not ExpressionStatement");
+        }
+    }
+
+    #[test]
+    fn test_huge_integer_literal() {
+        let digits = "123456789012345678901234567890";
+        match parse_single_expression(digits) {
+            Expression::IntegerLiteral(il) => {
+                assert_eq!(il.value.to_string(), digits);
+                assert_eq!(il.value.to_i64(), None);
+            }
+            exp => panic!("exp not IntegerLiteral, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_parsing_prefix_expressions() {
+        let tests = [("!5;", "!", 5), ("-15;", "-", 15)];
+
+        for (input, operator, value) in tests {
+            match parse_single_expression(input) {
+                Expression::PrefixExpression(pe) => {
+                    assert_eq!(pe.operator, operator);
+                    test_integer_literal(&pe.right, value);
+                }
+                exp => panic!("exp not PrefixExpression, got {:?}", exp),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsing_infix_expressions() {
+        let operators = [
+            "+", "-", "*", "/", "%", ">", "<", ">=", "<=", "==", "!=", "&&", "||", "&", "|", "^",
+            "<<", ">>",
+        ];
+
+        for operator in operators {
+            let input = format!("5 {} 6;", operator);
+            match parse_single_expression(&input) {
+                Expression::InfixExpression(ie) => {
+                    test_integer_literal(&ie.left, 5);
+                    assert_eq!(ie.operator, operator);
+                    test_integer_literal(&ie.right, 6);
+                }
+                exp => panic!("exp not InfixExpression, got {:?}", exp),
+            }
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence_parsing() {
+        let tests = [
+            ("-a * b", "((-a) * b)"),
+            ("!-a", "(!(-a))"),
+            ("a + b + c", "((a + b) + c)"),
+            ("a + b - c", "((a + b) - c)"),
+            ("a * b * c", "((a * b) * c)"),
+            ("a * b / c", "((a * b) / c)"),
+            ("a + b / c", "(a + (b / c))"),
+            ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+            ("3 + 4; -5 * 5", "(3 + 4);((-5) * 5)"),
+            ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+            ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            (
+                "3 + 4 * 5 == 3 * 1 + 4 * 5",
+                "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
+            ),
+            ("true", "true"),
+            ("3 > 5 == false", "((3 > 5) == false)"),
+            ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2", "((5 + 5) * 2)"),
+            ("2 / (5 + 5)", "(2 / (5 + 5))"),
+            ("-(5 + 5)", "(-(5 + 5))"),
+            ("!(true == true)", "(!(true == true))"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+            (
+                "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+                "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+            ),
+            (
+                "add(a + b + c * d / f + g)",
+                "add((((a + b) + ((c * d) / f)) + g))",
+            ),
+            (
+                "a * [1, 2, 3, 4][b * c] * d",
+                "((a * ([1, 2, 3, 4][(b * c)])) * d)",
+            ),
+            (
+                "add(a * b[2], b[1], 2 * [1, 2][1])",
+                "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
+            ),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c", "((a && b) || c)"),
+            ("a && b | c", "(a && (b | c))"),
+            ("a | b ^ c", "(a | (b ^ c))"),
+            ("a ^ b & c", "(a ^ (b & c))"),
+            ("a & b == c", "(a & (b == c))"),
+            ("a == b <= c", "(a == (b <= c))"),
+            ("a >= b << c", "(a >= (b << c))"),
+            ("a << b + c", "(a << (b + c))"),
+            ("a - b % c", "(a - (b % c))"),
+            ("a % b * c", "((a % b) * c)"),
+            ("x = y = a || b", "(x = (y = (a || b)))"),
+            ("x[i + 1] += -y.z", "((x[(i + 1)]) += (-(y.z)))"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(&p);
+
+            assert_eq!(parenthesize(&program), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_expression() {
+        match parse_single_expression("if (x <= y) { x } else { y }") {
+            Expression::IfExpression(ie) => {
+                assert_eq!(ie.condition.to_string(), "x <= y");
+                assert_eq!(ie.consequence.statements.len(), 1);
+                assert_eq!(ie.alternative.unwrap().statements.len(), 1);
+            }
+            exp => panic!("exp not IfExpression, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_function_literal_and_call_parsing() {
+        match parse_single_expression("fn(x, y) { x + y; }(1, 2 * 3)") {
+            Expression::CallExpression(ce) => {
+                assert_eq!(ce.function.to_string(), "fn(x, y) { x + y }");
+                assert_eq!(ce.arguments.len(), 2);
+                test_integer_literal(&ce.arguments[0], 1);
+                assert_eq!(ce.arguments[1].to_string(), "2 * 3");
+            }
+            exp => panic!("exp not CallExpression, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_parsing_collection_literals() {
+        match parse_single_expression(r#"[1, 2 * 2, "three"][1]"#) {
+            Expression::IndexExpression(ie) => {
+                match *ie.left {
+                    Expression::ArrayLiteral(al) => {
+                        assert_eq!(al.elements.len(), 3);
+                        test_integer_literal(&al.elements[0], 1);
+                        assert_eq!(al.elements[2].to_string(), r#""three""#);
+                    }
+                    exp => panic!("exp not ArrayLiteral, got {:?}", exp),
+                }
+                test_integer_literal(&ie.index, 1);
+            }
+            exp => panic!("exp not IndexExpression, got {:?}", exp),
+        }
+
+        match parse_single_expression(r#"{"one": 1, true: 2, 3: 1 + 2}"#) {
+            Expression::HashLiteral(hl) => {
+                let pairs: Vec<String> = hl
+                    .pairs
+                    .iter()
+                    .map(|(k, v)| format!("{} => {}", k.to_string(), v.to_string()))
+                    .collect();
+                assert_eq!(pairs, vec![r#""one" => 1"#, "true => 2", "3 => 1 + 2"]);
+            }
+            exp => panic!("exp not HashLiteral, got {:?}", exp),
+        }
+
+        match parse_single_expression("{}") {
+            Expression::HashLiteral(hl) => assert!(hl.pairs.is_empty()),
+            exp => panic!("exp not HashLiteral, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_loop_statements() {
+        let input = r#"
+while (x < 10) { if (x == 5) { break; } continue; }
+for (item in [1, 2, 3]) { item; }
+"#;
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(program.statements.len(), 2);
+        match &program.statements[0] {
+            Statement::WhileStatement(ws) => {
+                assert_eq!(ws.condition.to_string(), "x < 10");
+                assert_eq!(ws.body.to_string(), "{ if (x == 5) { break; } continue; }");
+            }
+            stmt => panic!("statement is not WhileStatement, got {:?}", stmt),
+        }
+        match &program.statements[1] {
+            Statement::ForStatement(fs) => {
+                assert_eq!(fs.variable.value, "item");
+                assert_eq!(fs.iterable.to_string(), "[1, 2, 3]");
+                assert_eq!(fs.body.statements.len(), 1);
+            }
+            stmt => panic!("statement is not ForStatement, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_loop_statements_with_semicolons() {
+        let input = "while (false) { }; for (x in []) { }; puts(1);";
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(program.statements.len(), 3);
+        assert_eq!(program.statements[2].to_string(), "puts(1)");
+    }
+
+    #[test]
+    fn test_loop_control_outside_of_loop() {
+        let tests = [
+            ("break;", "'break' outside of a loop"),
+            ("if (true) { continue; }", "'continue' outside of a loop"),
+            (
+                "while (true) { let f = fn() { break; }; }",
+                "'break' outside of a loop",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            assert_eq!(p.errors(), vec![expected.to_string()], "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_throw_and_try() {
+        let input = r#"throw "oops"; try { f() } finally { done() };"#;
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(program.statements.len(), 2);
+        match &program.statements[0] {
+            Statement::ThrowStatement(ts) => assert_eq!(ts.value.to_string(), r#""oops""#),
+            stmt => panic!("statement is not ThrowStatement, got {:?}", stmt),
+        }
+        assert_eq!(
+            program.statements[1].to_string(),
+            "try { f() } finally { done() }"
+        );
+
+        match parse_single_expression("try { f() } catch (e) { e.message } finally { done() }") {
+            Expression::TryExpression(te) => {
+                assert_eq!(te.body.to_string(), "{ f() }");
+                let catch = te.catch.unwrap();
+                assert_eq!(catch.parameter.value, "e");
+                assert_eq!(catch.body.to_string(), "{ e.message }");
+                assert_eq!(te.finally.unwrap().to_string(), "{ done() }");
+            }
+            exp => panic!("exp not TryExpression, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_try_errors() {
+        let tests = [
+            (
+                "try { f() }",
+                "expected next token to be CATCH or FINALLY, got EOF instead",
+            ),
+            (
+                "try { f() } catch { g() }",
+                "expected next token to be LPAREN, got LBRACE instead",
+            ),
+            (
+                "try { f() } catch (1) { g() }",
+                "expected next token to be IDENT, got INT instead",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            assert_eq!(p.errors()[0], expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_assign_expressions() {
+        let tests = [
+            ("x = 5;", "x", "=", "5"),
+            ("x += y * 2;", "x", "+=", "y * 2"),
+            ("arr[0] -= 1;", "arr[0]", "-=", "1"),
+            ("m[\"a\"][1] *= 3;", "m[\"a\"][1]", "*=", "3"),
+            ("x /= 2;", "x", "/=", "2"),
+            ("x = y = 1;", "x", "=", "y = 1"),
+            ("x = a || b;", "x", "=", "a || b"),
+        ];
+
+        for (input, target, operator, value) in tests {
+            match parse_single_expression(input) {
+                Expression::AssignExpression(ae) => {
+                    assert_eq!(ae.target.to_string(), target);
+                    assert_eq!(ae.operator, operator);
+                    assert_eq!(ae.value.to_string(), value);
+                }
+                exp => panic!("exp not AssignExpression, got {:?}", exp),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_assign_targets() {
+        let tests = [
+            ("5 = 1;", "invalid assignment target: 5"),
+            ("f() = 1;", "invalid assignment target: f()"),
+            ("f()[0] += 1;", "invalid assignment target: f()[0]"),
+            ("a + b = 1;", "invalid assignment target: a + b"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            assert_eq!(p.errors()[0], expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_const_statements() {
+        let l = Lexer::new("const answer = 42;");
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        match &program.statements[0] {
+            Statement::LetStatement(ls) => {
+                assert!(ls.is_const());
+                assert_eq!(ls.name.value, "answer");
+                assert_eq!(program.to_string(), "const answer = 42;");
+            }
+            stmt => panic!("statement is not LetStatement, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_macro_literal_parsing() {
+        match parse_single_expression("macro(x, y) { x + y; }") {
+            Expression::MacroLiteral(ml) => {
+                let params: Vec<String> = ml.parameters.iter().map(|p| p.to_string()).collect();
+                assert_eq!(params, vec!["x", "y"]);
+                assert_eq!(ml.body.to_string(), "{ x + y }");
+            }
+            exp => panic!("exp not MacroLiteral, got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn test_import_and_export_statements() {
+        let input = r#"
+import "lib/math.monkey" as math;
+export let answer = math.square(6) + 6;
+export const name = "deep thought";
+"#;
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(&p);
+
+        assert_eq!(program.statements.len(), 3);
+        match &program.statements[0] {
+            Statement::ImportStatement(is) => {
+                assert_eq!(is.path, "lib/math.monkey");
+                assert_eq!(is.alias.value, "math");
+            }
+            stmt => panic!("statement is not ImportStatement, got {:?}", stmt),
+        }
+        match &program.statements[1] {
+            Statement::LetStatement(ls) => {
+                assert!(ls.exported);
+                match ls.value.as_ref().unwrap() {
+                    Expression::InfixExpression(ie) => match ie.left.as_ref() {
+                        Expression::CallExpression(ce) => {
+                            assert_eq!(ce.function.to_string(), "math.square")
+                        }
+                        exp => panic!("exp not CallExpression, got {:?}", exp),
+                    },
+                    exp => panic!("exp not InfixExpression, got {:?}", exp),
+                }
+            }
+            stmt => panic!("statement is not LetStatement, got {:?}", stmt),
+        }
+        assert_eq!(
+            program.statements[2].to_string(),
+            r#"export const name = "deep thought";"#
+        );
+    }
+
+    #[test]
+    fn test_module_statements_outside_top_level() {
+        let tests = [
+            (
+                r#"if (true) { import "a.monkey" as a; }"#,
+                "'import' is only allowed at the top level",
+            ),
+            (
+                r#"let f = fn() { import "x.monkey" as q; q.f };"#,
+                "'import' is only allowed at the top level",
+            ),
+            (
+                "let f = fn() { export let x = 1; };",
+                "'export' is only allowed at the top level",
+            ),
+            (
+                "export x;",
+                "expected next token to be LET, got IDENT instead",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            // and nothing else
+            assert_eq!(p.errors(), vec![expected], "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_deeply_nested_input() {
+        std::thread::Builder::new()
+            .stack_size(EMBEDDED_STACK_SIZE)
+            .spawn(check_deeply_nested_input)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn check_deeply_nested_input() {
+        // the statement and the outermost expression take a level each
+        let deepest = MAX_NESTING - 2;
+        for shallow in [
+            format!("{}1{}", "(".repeat(deepest), ")".repeat(deepest)),
+            format!("{}1{}", "f(".repeat(deepest), ")".repeat(deepest)),
+            format!("{}1{}", "[".repeat(deepest), "]".repeat(deepest)),
+            format!(
+                "1{}",
+                " + (1".repeat(deepest / 2) + &")".repeat(deepest / 2)
+            ),
+            format!(
+                "{}1{}",
+                "if (true) { ".repeat(deepest / 2),
+                " }".repeat(deepest / 2)
+            ),
+            // chains are parsed in a loop, so they may go far deeper
+            vec!["1"; MAX_TREE_DEPTH - 2].join(" + "),
+            format!("f{}", "()".repeat(MAX_TREE_DEPTH - 2)),
+            format!("a{}", "[0]".repeat(MAX_TREE_DEPTH - 2)),
+        ] {
+            parse_ok(&shallow);
+        }
+
+        for input in [
+            "(".repeat(1000),
+            "-".repeat(1000),
+            "[".repeat(1000),
+            "{a: ".repeat(1000),
+            "f(".repeat(1000),
+            "a = ".repeat(1000),
+            "while (a) { ".repeat(1000),
+        ] {
+            let mut p = Parser::new(Lexer::new(&input));
+            p.parse_program();
+
+            assert_eq!(
+                p.errors()[0],
+                format!("input is nested more than {} levels deep", MAX_NESTING),
+                "input: {}",
+                &input[..20]
+            );
+        }
+
+        for input in [
+            vec!["1"; MAX_TREE_DEPTH].join(" + "),
+            format!("f{}", "()".repeat(MAX_TREE_DEPTH)),
+            format!("a{}", "[0]".repeat(MAX_TREE_DEPTH)),
+            // a chain in an argument goes on from the depth of the call
+            format!("1 + f({})", vec!["1"; MAX_TREE_DEPTH - 2].join(" + ")),
+            // and so does one on the right of an operator, below those after it
+            format!(
+                "1 + ({}){}",
+                vec!["1"; MAX_TREE_DEPTH / 2].join(" + "),
+                " + 1".repeat(MAX_TREE_DEPTH / 2)
+            ),
+        ] {
+            let mut p = Parser::new(Lexer::new(&input));
+            p.parse_program();
+
+            assert_eq!(
+                p.errors()[0],
+                format!("input is nested more than {} levels deep", MAX_TREE_DEPTH),
+                "input: {}",
+                &input[..20]
+            );
+        }
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let tests = [
+            ("let x: int = 5;", "let x: int = 5;"),
+            ("const xs: [string] = [];", "const xs: [string] = [];"),
+            (
+                "let f = fn(a: int, b, c: {string: [bool]}) -> null { a };",
+                "let f = fn(a: int, b, c: {string: [bool]}) -> null { a };",
+            ),
+            (
+                "let g: fn(fn() -> int, bool) -> [int] = fn(h) -> [int] { [h()] };",
+                "let g: fn(fn() -> int, bool) -> [int] = fn(h) -> [int] { [h()] };",
+            ),
+            ("fn() -> int { 1 }", "fn() -> int { 1 }"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(&p);
+
+            assert_eq!(program.to_string(), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_type_annotation_errors() {
+        let tests = [
+            ("let x: float = 1;", "unknown type float"),
+            ("fn(a:) { a }", "expected a type, got RPAREN"),
+            (
+                "let f: fn(int) = 1;",
+                "expected next token to be ARROW, got ASSIGN instead",
+            ),
+            (
+                "let x int = 1;",
+                "expected next token to be ASSIGN, got IDENT instead",
+            ),
+            (
+                "macro(a: int) { a }",
+                "expected next token to be RPAREN, got COLON instead",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            assert_eq!(p.errors()[0], expected, "input: {}", input);
         }
     }
 }
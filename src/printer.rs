@@ -0,0 +1,605 @@
+//! Renderings of the AST that, unlike `Node::to_string`, show its shape.
+
+use crate::ast::*;
+use crate::visitor::{visit, Visitable, Visitor};
+
+/// Renders `node` like `Node::to_string` does, but with every prefix, infix,
+/// assignment, index and member expression wrapped in parentheses:
+/// `a + b * c` becomes `(a + (b * c))`. Expression statements followed by
+/// another statement end in `;`, so the output parses back into the same tree.
+pub fn parenthesize<N: Visitable>(node: &N) -> String {
+    let mut printer = Parenthesizer::default();
+    node.accept(&mut printer);
+    printer.out
+}
+
+/// Renders `node` as an S-expression such as `(let x (+ a (* b c)))`, with
+/// the statements of a program on separate lines.
+pub fn sexp<N: Visitable>(node: &N) -> String {
+    let mut printer = SexpPrinter::default();
+    node.accept(&mut printer);
+    printer.out
+}
+
+/// `name` followed by `separator` and its type, if it has one.
+fn annotated(name: &Identifier, annotation: Option<&TypeAnnotation>, separator: &str) -> String {
+    match annotation {
+        Some(t) => format!("{}{}{}", name.value, separator, t),
+        None => name.value.clone(),
+    }
+}
+
+#[derive(Default)]
+struct Parenthesizer {
+    out: String,
+}
+
+impl Parenthesizer {
+    fn expressions(&mut self, expressions: &[Expression]) {
+        for (i, e) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.visit_expression(e);
+        }
+    }
+
+    fn parameters(&mut self, parameters: &[Identifier]) {
+        let params: Vec<&str> = parameters.iter().map(|p| p.value.as_str()).collect();
+        self.out.push_str(&params.join(", "));
+    }
+
+    fn statements(&mut self, statements: &[Statement], separator: &str) {
+        for (i, s) in statements.iter().enumerate() {
+            if i > 0 {
+                // without the `;`, `a` followed by `(b)` would read as a call
+                if let Statement::ExpressionStatement(_) = statements[i - 1] {
+                    self.out.push(';');
+                }
+                self.out.push_str(separator);
+            }
+            self.visit_statement(s);
+        }
+    }
+}
+
+impl Visitor for Parenthesizer {
+    fn visit_program(&mut self, program: &Program) {
+        self.statements(&program.statements, "");
+    }
+
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        if ls.exported {
+            self.out.push_str("export ");
+        }
+        self.out.push_str(&format!(
+            "{} {} = ",
+            ls.token_literal(),
+            annotated(&ls.name, ls.type_annotation.as_ref(), ": ")
+        ));
+        visit::walk_let_statement(self, ls);
+        self.out.push(';');
+    }
+
+    fn visit_return_statement(&mut self, rs: &ReturnStatement) {
+        self.out.push_str("return ");
+        visit::walk_return_statement(self, rs);
+        self.out.push(';');
+    }
+
+    fn visit_while_statement(&mut self, ws: &WhileStatement) {
+        self.out.push_str("while (");
+        self.visit_expression(&ws.condition);
+        self.out.push_str(") ");
+        self.visit_block_statement(&ws.body);
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.out
+            .push_str(&format!("for ({} in ", fs.variable.value));
+        self.visit_expression(&fs.iterable);
+        self.out.push_str(") ");
+        self.visit_block_statement(&fs.body);
+    }
+
+    fn visit_break_statement(&mut self, bs: &BreakStatement) {
+        self.out.push_str(&bs.to_string());
+    }
+
+    fn visit_continue_statement(&mut self, cs: &ContinueStatement) {
+        self.out.push_str(&cs.to_string());
+    }
+
+    fn visit_import_statement(&mut self, is: &ImportStatement) {
+        self.out.push_str(&is.to_string());
+    }
+
+    fn visit_throw_statement(&mut self, ts: &ThrowStatement) {
+        self.out.push_str("throw ");
+        self.visit_expression(&ts.value);
+        self.out.push(';');
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.out.push_str("{ ");
+        self.statements(&block.statements, " ");
+        self.out.push_str(" }");
+    }
+
+    fn visit_identifier(&mut self, id: &Identifier) {
+        self.out.push_str(&id.to_string());
+    }
+
+    fn visit_integer_literal(&mut self, il: &IntegerLiteral) {
+        self.out.push_str(&il.to_string());
+    }
+
+    fn visit_boolean(&mut self, b: &Boolean) {
+        self.out.push_str(&b.to_string());
+    }
+
+    fn visit_string_literal(&mut self, sl: &StringLiteral) {
+        self.out.push_str(&sl.to_string());
+    }
+
+    fn visit_prefix_expression(&mut self, pe: &PrefixExpression) {
+        self.out.push('(');
+        self.out.push_str(&pe.operator);
+        self.visit_expression(&pe.right);
+        self.out.push(')');
+    }
+
+    fn visit_infix_expression(&mut self, ie: &InfixExpression) {
+        self.out.push('(');
+        self.visit_expression(&ie.left);
+        self.out.push_str(&format!(" {} ", ie.operator));
+        self.visit_expression(&ie.right);
+        self.out.push(')');
+    }
+
+    fn visit_assign_expression(&mut self, ae: &AssignExpression) {
+        self.out.push('(');
+        self.visit_expression(&ae.target);
+        self.out.push_str(&format!(" {} ", ae.operator));
+        self.visit_expression(&ae.value);
+        self.out.push(')');
+    }
+
+    fn visit_if_expression(&mut self, ie: &IfExpression) {
+        self.out.push_str("if (");
+        self.visit_expression(&ie.condition);
+        self.out.push_str(") ");
+        self.visit_block_statement(&ie.consequence);
+        if let Some(alternative) = &ie.alternative {
+            self.out.push_str(" else ");
+            self.visit_block_statement(alternative);
+        }
+    }
+
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        self.out.push_str(&format!(
+            "fn({}){} ",
+            fl.parameters_to_string(),
+            fl.return_type_to_string()
+        ));
+        self.visit_block_statement(&fl.body);
+    }
+
+    fn visit_macro_literal(&mut self, ml: &MacroLiteral) {
+        self.out.push_str("macro(");
+        self.parameters(&ml.parameters);
+        self.out.push_str(") ");
+        self.visit_block_statement(&ml.body);
+    }
+
+    fn visit_call_expression(&mut self, ce: &CallExpression) {
+        self.visit_expression(&ce.function);
+        self.out.push('(');
+        self.expressions(&ce.arguments);
+        self.out.push(')');
+    }
+
+    fn visit_array_literal(&mut self, al: &ArrayLiteral) {
+        self.out.push('[');
+        self.expressions(&al.elements);
+        self.out.push(']');
+    }
+
+    fn visit_index_expression(&mut self, ie: &IndexExpression) {
+        self.out.push('(');
+        self.visit_expression(&ie.left);
+        self.out.push('[');
+        self.visit_expression(&ie.index);
+        self.out.push_str("])");
+    }
+
+    fn visit_hash_literal(&mut self, hl: &HashLiteral) {
+        self.out.push('{');
+        for (i, (key, value)) in hl.pairs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.visit_expression(key);
+            self.out.push_str(": ");
+            self.visit_expression(value);
+        }
+        self.out.push('}');
+    }
+
+    fn visit_member_expression(&mut self, me: &MemberExpression) {
+        self.out.push('(');
+        self.visit_expression(&me.object);
+        self.out.push_str(&format!(".{})", me.property.value));
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        self.out.push_str("try ");
+        self.visit_block_statement(&te.body);
+        if let Some(catch) = &te.catch {
+            self.out
+                .push_str(&format!(" catch ({}) ", catch.parameter.value));
+            self.visit_block_statement(&catch.body);
+        }
+        if let Some(finally) = &te.finally {
+            self.out.push_str(" finally ");
+            self.visit_block_statement(finally);
+        }
+    }
+}
+
+#[derive(Default)]
+struct SexpPrinter {
+    out: String,
+}
+
+impl SexpPrinter {
+    // every list is written as `(head`, then ` child` per child, then `)`
+    fn open(&mut self, head: &str) {
+        self.out.push('(');
+        self.out.push_str(head);
+    }
+
+    fn atom(&mut self, atom: &str) {
+        self.out.push(' ');
+        self.out.push_str(atom);
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.out.push(' ');
+        self.visit_expression(expression);
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        self.out.push(' ');
+        self.visit_block_statement(block);
+    }
+
+    fn close(&mut self) {
+        self.out.push(')');
+    }
+
+    fn parameters(&mut self, parameters: &[Identifier]) {
+        let params: Vec<&str> = parameters.iter().map(|p| p.value.as_str()).collect();
+        self.atom(&format!("({})", params.join(" ")));
+    }
+}
+
+impl Visitor for SexpPrinter {
+    fn visit_program(&mut self, program: &Program) {
+        for (i, s) in program.statements.iter().enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+            }
+            self.visit_statement(s);
+        }
+    }
+
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        if ls.exported {
+            self.out.push_str("(export ");
+        }
+        self.open(&ls.token_literal());
+        self.atom(&annotated(&ls.name, ls.type_annotation.as_ref(), ":"));
+        if let Some(value) = &ls.value {
+            self.expression(value);
+        }
+        self.close();
+        if ls.exported {
+            self.close();
+        }
+    }
+
+    fn visit_return_statement(&mut self, rs: &ReturnStatement) {
+        self.open("return");
+        if let Some(value) = &rs.return_value {
+            self.expression(value);
+        }
+        self.close();
+    }
+
+    fn visit_while_statement(&mut self, ws: &WhileStatement) {
+        self.open("while");
+        self.expression(&ws.condition);
+        self.block(&ws.body);
+        self.close();
+    }
+
+    fn visit_for_statement(&mut self, fs: &ForStatement) {
+        self.open("for");
+        self.atom(&fs.variable.value);
+        self.expression(&fs.iterable);
+        self.block(&fs.body);
+        self.close();
+    }
+
+    fn visit_break_statement(&mut self, _bs: &BreakStatement) {
+        self.out.push_str("(break)");
+    }
+
+    fn visit_continue_statement(&mut self, _cs: &ContinueStatement) {
+        self.out.push_str("(continue)");
+    }
+
+    fn visit_import_statement(&mut self, is: &ImportStatement) {
+        self.open("import");
+        self.atom(&format!("\"{}\"", is.path));
+        self.atom(&is.alias.value);
+        self.close();
+    }
+
+    fn visit_throw_statement(&mut self, ts: &ThrowStatement) {
+        self.open("throw");
+        self.expression(&ts.value);
+        self.close();
+    }
+
+    fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.open("block");
+        for s in &block.statements {
+            self.out.push(' ');
+            self.visit_statement(s);
+        }
+        self.close();
+    }
+
+    fn visit_identifier(&mut self, id: &Identifier) {
+        self.out.push_str(&id.to_string());
+    }
+
+    fn visit_integer_literal(&mut self, il: &IntegerLiteral) {
+        self.out.push_str(&il.to_string());
+    }
+
+    fn visit_boolean(&mut self, b: &Boolean) {
+        self.out.push_str(&b.to_string());
+    }
+
+    fn visit_string_literal(&mut self, sl: &StringLiteral) {
+        self.out.push_str(&sl.to_string());
+    }
+
+    fn visit_prefix_expression(&mut self, pe: &PrefixExpression) {
+        self.open(&pe.operator);
+        self.expression(&pe.right);
+        self.close();
+    }
+
+    fn visit_infix_expression(&mut self, ie: &InfixExpression) {
+        self.open(&ie.operator);
+        self.expression(&ie.left);
+        self.expression(&ie.right);
+        self.close();
+    }
+
+    fn visit_assign_expression(&mut self, ae: &AssignExpression) {
+        self.open(&ae.operator);
+        self.expression(&ae.target);
+        self.expression(&ae.value);
+        self.close();
+    }
+
+    fn visit_if_expression(&mut self, ie: &IfExpression) {
+        self.open("if");
+        self.expression(&ie.condition);
+        self.block(&ie.consequence);
+        if let Some(alternative) = &ie.alternative {
+            self.block(alternative);
+        }
+        self.close();
+    }
+
+    fn visit_function_literal(&mut self, fl: &FunctionLiteral) {
+        self.open("fn");
+        let params: Vec<String> = fl
+            .parameters
+            .iter()
+            .zip(&fl.parameter_types)
+            .map(|(p, t)| annotated(p, t.as_ref(), ":"))
+            .collect();
+        let result = match &fl.return_type {
+            Some(t) => format!(":{}", t),
+            None => "".to_string(),
+        };
+        self.atom(&format!("({}){}", params.join(" "), result));
+        self.block(&fl.body);
+        self.close();
+    }
+
+    fn visit_macro_literal(&mut self, ml: &MacroLiteral) {
+        self.open("macro");
+        self.parameters(&ml.parameters);
+        self.block(&ml.body);
+        self.close();
+    }
+
+    fn visit_call_expression(&mut self, ce: &CallExpression) {
+        self.open("call");
+        self.expression(&ce.function);
+        for argument in &ce.arguments {
+            self.expression(argument);
+        }
+        self.close();
+    }
+
+    fn visit_array_literal(&mut self, al: &ArrayLiteral) {
+        self.open("array");
+        for element in &al.elements {
+            self.expression(element);
+        }
+        self.close();
+    }
+
+    fn visit_index_expression(&mut self, ie: &IndexExpression) {
+        self.open("index");
+        self.expression(&ie.left);
+        self.expression(&ie.index);
+        self.close();
+    }
+
+    fn visit_hash_literal(&mut self, hl: &HashLiteral) {
+        self.open("hash");
+        for (key, value) in &hl.pairs {
+            self.out.push_str(" (");
+            self.visit_expression(key);
+            self.expression(value);
+            self.close();
+        }
+        self.close();
+    }
+
+    fn visit_member_expression(&mut self, me: &MemberExpression) {
+        self.open("member");
+        self.expression(&me.object);
+        self.atom(&me.property.value);
+        self.close();
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        self.open("try");
+        self.block(&te.body);
+        if let Some(catch) = &te.catch {
+            self.out.push_str(" (catch");
+            self.atom(&catch.parameter.value);
+            self.block(&catch.body);
+            self.close();
+        }
+        if let Some(finally) = &te.finally {
+            self.out.push_str(" (finally");
+            self.block(finally);
+            self.close();
+        }
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parenthesize, sexp};
+    use crate::ast::Statement;
+
+    use crate::parser::parse_ok;
+
+    #[test]
+    fn test_parenthesize() {
+        let tests = [
+            ("a + b * c", "(a + (b * c))"),
+            ("(a + b) * c", "((a + b) * c)"),
+            ("-a[0].b", "(-((a[0]).b))"),
+            ("x = y += f(1 + 2, [3])", "(x = (y += f((1 + 2), [3])))"),
+            (
+                "let f = fn(a, b) { return a < b; };",
+                "let f = fn(a, b) { return (a < b); };",
+            ),
+            (
+                r#"export const h = {"a": !b, 1: 2};"#,
+                r#"export const h = {"a": (!b), 1: 2};"#,
+            ),
+            (
+                "if (a && b) { c } else { d }",
+                "if ((a && b)) { c } else { d }",
+            ),
+            (
+                "while (i < 3) { i += 1; break; }",
+                "while ((i < 3)) { (i += 1); break; }",
+            ),
+            (
+                r#"import "m.monkey" as m; for (x in m.xs) { continue; }"#,
+                r#"import "m.monkey" as m;for (x in (m.xs)) { continue; }"#,
+            ),
+            ("macro(q) { quote(q) }", "macro(q) { quote(q) }"),
+            (
+                "try { throw a + 1; } catch (e) { e } finally { f() }",
+                "try { throw (a + 1); } catch (e) { e } finally { f() }",
+            ),
+            (
+                "let f: fn(int) -> [int] = fn(a: int, b) -> [int] { [a + b] };",
+                "let f: fn(int) -> [int] = fn(a: int, b) -> [int] { [(a + b)] };",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parenthesize(&parse_ok(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_sexp() {
+        let tests = [
+            ("let x = a + b * c;", "(let x (+ a (* b c)))"),
+            ("(a + b) * -c", "(* (+ a b) (- c))"),
+            (
+                "x = y += f(1, [2, 3])[0]",
+                "(= x (+= y (index (call f 1 (array 2 3)) 0)))",
+            ),
+            (
+                "let f = fn(a, b) { return a < b; };",
+                "(let f (fn (a b) (block (return (< a b)))))",
+            ),
+            (
+                r#"export const h = {"a": !b, 1: 2};"#,
+                r#"(export (const h (hash ("a" (! b)) (1 2))))"#,
+            ),
+            (
+                "if (a && b) { c } else { }",
+                "(if (&& a b) (block c) (block))",
+            ),
+            (
+                "while (true) { break; continue; }",
+                "(while true (block (break) (continue)))",
+            ),
+            (
+                r#"import "m.monkey" as m; for (x in m.xs) { macro() { x } }"#,
+                "(import \"m.monkey\" m)\n(for x (member m xs) (block (macro () (block x))))",
+            ),
+            (
+                "let h: {string: bool} = fn(a: int, b) -> int { a };",
+                "(let h:{string: bool} (fn (a:int b):int (block a)))",
+            ),
+            (
+                "try { throw 1; } catch (e) { e } finally { }",
+                "(try (block (throw 1)) (catch e (block e)) (finally (block)))",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(sexp(&parse_ok(input)), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_print_single_nodes() {
+        let program = parse_ok("let x = 1 + 2 * 3;");
+        let ls = match &program.statements[0] {
+            Statement::LetStatement(ls) => ls,
+            stmt => panic!("statement is not LetStatement, got {:?}", stmt),
+        };
+
+        assert_eq!(sexp(ls), "(let x (+ 1 (* 2 3)))");
+        assert_eq!(sexp(ls.value.as_ref().unwrap()), "(+ 1 (* 2 3))");
+        assert_eq!(parenthesize(&ls.name), "x");
+        assert_eq!(
+            parenthesize(&program.statements[0]),
+            "let x = (1 + (2 * 3));"
+        );
+    }
+}
@@ -1,11 +1,21 @@
 use std::io::{self, Write};
 
-use crate::{lexer::*, parser::*};
+use crate::{
+    ast::*, environment::*, evaluator::*, lexer::*, limits::*, macro_expansion::*, object::*,
+    parser::*, printer::*, stack_trace::*,
+};
 
 const PROMPT: &str = ">> ";
 
+type Printer = fn(&Program) -> String;
+
 pub fn start_repl() {
     let mut buffer = String::new();
+    let env = Environment::new();
+    let macro_env = Environment::new();
+    // the command line starts the REPL on a stack of its size
+    let mut evaluator = Evaluator::new();
+    evaluator.set_limits(Limits::for_stack(STACK_SIZE));
 
     loop {
         let _ = io::stdout().write_all(PROMPT.as_bytes());
@@ -22,22 +32,56 @@ pub fn start_repl() {
                 panic!("somethings gone wrong {:?}", err)
             }
         }
-        buffer = buffer.trim().to_string();
-        println!("{}", buffer);
-
-        let lexer = Lexer::new(buffer.as_str());
-        // loop {
-        //     let tok = lexer.next_token();
-        //     if tok.token_type != TokenType::EOF {
-        //         println!("{:?}", tok);
-        //     } else {
-        //         println!("{:?}", tok);
-        //         break;
-        //     }
-        // }
-        println!("created lexer");
+
+        // `:parens <input>` and `:sexp <input>` show how the input parses
+        // instead of evaluating it
+        let (printer, input): (Option<Printer>, &str) = match buffer.trim().split_once(' ') {
+            Some((":parens", rest)) => (Some(parenthesize), rest),
+            Some((":sexp", rest)) => (Some(sexp), rest),
+            _ => (None, buffer.trim()),
+        };
+
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
-        let p = parser.parse_program();
-        dbg!(p);
+        let mut program = parser.parse_program();
+
+        if !parser.errors().is_empty() {
+            print_parser_errors(&parser.errors());
+            continue;
+        }
+
+        if let Some(print) = printer {
+            println!("{}", print(&program));
+            continue;
+        }
+
+        let expanded = define_macros(&mut program, &macro_env)
+            .and_then(|()| evaluator.expand_macros(program, &macro_env));
+        let program = match expanded {
+            Ok(program) => program,
+            Err(msg) => {
+                println!("ERROR: {}", msg);
+                continue;
+            }
+        };
+
+        let evaluated = evaluator.eval_program(&program, &env);
+        println!("{}", evaluated.inspect());
+        if let Object::Error(_) = evaluated {
+            // the calls of the input line, or of files it imported
+            print!(
+                "{}",
+                format_stack_trace(&evaluator.stack_trace(), |file| match file {
+                    Some(path) => std::fs::read_to_string(path).ok(),
+                    None => Some(input.to_string()),
+                })
+            );
+        }
+    }
+}
+
+fn print_parser_errors(errors: &[String]) {
+    for msg in errors {
+        println!("\t{}", msg);
     }
 }
@@ -0,0 +1,312 @@
+//! The calls a runtime error unwound, and how to show them.
+//!
+//! Every call the evaluator makes pushes a `Frame` naming the function
+//! called, by the `let` it was bound with if it was, and where the call is:
+//! the file and the position of the call expression's first token. A call in
+//! tail position takes over the frame of the function making it, renaming
+//! it, since that function has nothing left to do once it returns. An error
+//! leaving functions behind keeps their frames and where it arose, which
+//! `Evaluator::stack_trace` turns into a `backtrace` once it goes uncaught,
+//! and `format_stack_trace` prints along with the source line of each
+//! entry.
+
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::token::Position;
+
+/// A call in progress: the function called and where the call is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    /// The file of the call, `None` for code evaluated from a string.
+    pub file: Option<Rc<Path>>,
+    pub position: Position,
+}
+
+// `f at lib.monkey:3:5`, `f at 3:5` outside of a file, or only the name for
+// calls from outside of a program
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.position.line == 0 {
+            return write!(f, "{}", self.function);
+        }
+        write!(f, "{} at ", self.function)?;
+        if let Some(file) = &self.file {
+            write!(f, "{}:", display_path(file))?;
+        }
+        write!(f, "{}:{}", self.position.line, self.position.column)
+    }
+}
+
+/// Shows `path` relative to the working directory when it is inside of it.
+pub fn display_path(path: &Path) -> String {
+    let cwd = std::env::current_dir().ok();
+    match cwd.as_deref().and_then(|cwd| path.strip_prefix(cwd).ok()) {
+        Some(relative) => relative.display().to_string(),
+        None => path.display().to_string(),
+    }
+}
+
+/// The functions in progress, innermost first, each with where it had got
+/// to: `position` in `file` for the innermost, and for the others the call
+/// of the function they were waiting on. `calls` are the frames of the
+/// calls, innermost last as the evaluator keeps them; the code outside of
+/// them is `<top level>`, unless a host application made the outermost.
+pub fn backtrace(calls: &[Frame], file: Option<Rc<Path>>, position: Position) -> Vec<Frame> {
+    let function = |depth: usize| match depth {
+        0 => "<top level>".to_string(),
+        depth => calls[depth - 1].function.clone(),
+    };
+
+    let mut entries = vec![Frame {
+        function: function(calls.len()),
+        file,
+        position,
+    }];
+    for (depth, call) in calls.iter().enumerate().rev() {
+        // a call from outside of a program is not in any code to show
+        if call.position.line == 0 {
+            continue;
+        }
+        entries.push(Frame {
+            function: function(depth),
+            file: call.file.clone(),
+            position: call.position,
+        });
+    }
+    entries
+}
+
+/// Formats the entries of a `backtrace`, each followed by its line with a
+/// caret under the position. `source` gives the text of a file, or of the
+/// code evaluated from a string for `None`; entries whose source it has not
+/// are listed alone.
+pub fn format_stack_trace(
+    frames: &[Frame],
+    mut source: impl FnMut(Option<&Path>) -> Option<String>,
+) -> String {
+    let width = frames
+        .iter()
+        .map(|frame| frame.position.line.to_string().len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for frame in frames {
+        out.push_str(&format!("  {}\n", frame));
+
+        let Position { line, column } = frame.position;
+        let text = match source(frame.file.as_deref()) {
+            Some(text) if line > 0 => text,
+            _ => continue,
+        };
+        if let Some(text) = text.lines().nth(line - 1) {
+            // tabs are kept so that the caret lines up however they show
+            let indent: String = text
+                .chars()
+                .take(column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out.push_str(&format!("  {:>width$} | {}\n", line, text));
+            out.push_str(&format!("  {:>width$} | {}^\n", "", indent));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+    use crate::parser::parse_ok;
+
+    fn frame(function: &str, file: Option<&str>, line: usize, column: usize) -> Frame {
+        Frame {
+            function: function.to_string(),
+            file: file.map(|file| Rc::from(Path::new(file))),
+            position: Position { line, column },
+        }
+    }
+
+    #[test]
+    fn test_frame_display() {
+        let tests = [
+            (frame("<host>", None, 0, 0), "<host>"),
+            (frame("f", None, 3, 5), "f at 3:5"),
+            (
+                frame("f", Some("/lib.monkey"), 3, 5),
+                "f at /lib.monkey:3:5",
+            ),
+        ];
+
+        for (frame, expected) in tests {
+            assert_eq!(frame.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let calls = [
+            frame("f", Some("/main.monkey"), 9, 1),
+            frame("g", Some("/lib.monkey"), 2, 3),
+        ];
+        let file = Some(Rc::from(Path::new("/lib.monkey")));
+        assert_eq!(
+            backtrace(&calls, file.clone(), Position { line: 5, column: 7 }),
+            [
+                frame("g", Some("/lib.monkey"), 5, 7),
+                frame("f", Some("/lib.monkey"), 2, 3),
+                frame("<top level>", Some("/main.monkey"), 9, 1),
+            ]
+        );
+
+        // the function a host application called is not called from any
+        // code
+        let calls = [frame("<host>", None, 0, 0), frame("g", None, 2, 3)];
+        assert_eq!(
+            backtrace(&calls, None, Position { line: 1, column: 1 }),
+            [frame("g", None, 1, 1), frame("<host>", None, 2, 3)]
+        );
+        assert_eq!(
+            backtrace(&[], file, Position { line: 4, column: 2 }),
+            [frame("<top level>", Some("/lib.monkey"), 4, 2)]
+        );
+    }
+
+    #[test]
+    fn test_format_stack_trace() {
+        let source = "let f = fn() {\n\tg(1)\n};\nf();\n\n\n\n\n\n\nf()";
+        let frames = [
+            frame("h", Some("/missing.monkey"), 7, 3),
+            frame("g", None, 2, 2),
+            frame("f", None, 11, 1),
+            frame("<host>", None, 0, 0),
+        ];
+
+        let trace = format_stack_trace(&frames, |file| match file {
+            None => Some(source.to_string()),
+            Some(_) => None,
+        });
+
+        assert_eq!(
+            trace,
+            "  h at /missing.monkey:7:3
+  g at 2:2
+   2 | \tg(1)
+     | \t^
+  f at 11:1
+  11 | f()
+     | ^
+  <host>
+"
+        );
+    }
+
+    #[test]
+    fn test_stack_trace_of_uncaught_errors() {
+        let dir = std::env::temp_dir().join(format!("rust_monkey_trace_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lib = dir.join("lib.monkey");
+        let main = dir.join("main.monkey");
+        fs::write(
+            &lib,
+            "let half = fn(x) { x / 0 };\nexport let check = fn(x) {\n  half(x) + 1\n};\n",
+        )
+        .unwrap();
+        fs::write(
+            &main,
+            "import \"lib.monkey\" as lib;\nlet run = fn(f) { f(2) + 1 };\nrun(lib.check)\n",
+        )
+        .unwrap();
+
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.eval_file(&main, &Environment::new());
+        assert_eq!(result.inspect(), "ERROR: division by zero");
+
+        // the function passed as `f` is named by its `let`, and the
+        // innermost entry is where the error arose
+        let files: Vec<PathBuf> = evaluator
+            .stack_trace()
+            .iter()
+            .map(|frame| frame.file.as_deref().unwrap().to_path_buf())
+            .collect();
+        let (lib, main) = (
+            fs::canonicalize(&lib).unwrap(),
+            fs::canonicalize(&main).unwrap(),
+        );
+        assert_eq!(
+            files,
+            [lib.clone(), lib.clone(), main.clone(), main.clone()]
+        );
+        let trace = format_stack_trace(&evaluator.stack_trace(), |file| {
+            fs::read_to_string(file?).ok()
+        });
+        assert_eq!(
+            trace,
+            format!(
+                "  half at {lib}:1:20
+  1 | let half = fn(x) {{ x / 0 }};
+    |                    ^
+  check at {lib}:3:3
+  3 |   half(x) + 1
+    |   ^
+  run at {main}:2:19
+  2 | let run = fn(f) {{ f(2) + 1 }};
+    |                   ^
+  <top level> at {main}:3:1
+  3 | run(lib.check)
+    | ^
+",
+                lib = display_path(&lib),
+                main = display_path(&main),
+            )
+        );
+
+        // an error outside of any function is where it arose, and one in a
+        // builtin at its call
+        let tests = [
+            (
+                "let x = 1;\nx + true",
+                vec![frame("<top level>", None, 2, 1)],
+            ),
+            (
+                "puts(1);\nlet xs = [len(1)];",
+                vec![frame("len", None, 2, 11), frame("<top level>", None, 2, 11)],
+            ),
+            (
+                "try { 1 } finally {\n  throw 2;\n}",
+                vec![frame("<top level>", None, 2, 3)],
+            ),
+        ];
+        for (input, expected) in tests {
+            evaluator.eval_program(&parse_ok(input), &Environment::new());
+            assert_eq!(evaluator.stack_trace(), expected, "input: {}", input);
+        }
+
+        // a function making a call in tail position leaves its frame to the
+        // call, which its caller is still waiting on
+        let program = parse_ok(
+            "let b = fn() { 1 + true };\nlet c = fn() { b() };\nlet d = fn() {\n  let x = c();\n  x\n};\nd();",
+        );
+        evaluator.eval_program(&program, &Environment::new());
+        assert_eq!(
+            evaluator.stack_trace(),
+            vec![
+                frame("b", None, 1, 16),
+                frame("d", None, 4, 11),
+                frame("<top level>", None, 7, 1),
+            ]
+        );
+
+        // a caught error leaves no trace
+        let program = parse_ok("let f = fn() { 1 / 0 }; try { f() } catch (e) { 1 }");
+        evaluator.eval_program(&program, &Environment::new());
+        assert!(evaluator.stack_trace().is_empty());
+    }
+}
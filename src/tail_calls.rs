@@ -0,0 +1,190 @@
+//! Finding the calls in tail position of a function.
+//!
+//! A call is in tail position when the function returns its result as it
+//! is: the value of the last expression statement of the body, of a
+//! `return` anywhere in it, and the same positions again in the branches of
+//! an `if` in one of those. The evaluator runs such calls in place of the
+//! function that makes them rather than on top of it, so that recursion in
+//! tail position takes no stack.
+//!
+//! A `return` counts as long as the blocks around it are statements, such as
+//! loop bodies and `if` expressions used as statements. One inside an `if`
+//! that is the operand of something else is left alone, like any other
+//! call in an expression. Calls inside a `try` never are, since the `try`
+//! may still have to catch what they raise or run its `finally`.
+//!
+//! The parser marks the body of every function literal as it builds it, and
+//! so does every `Fold` rebuilding one, which may have moved calls in or out
+//! of tail position.
+
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::visitor::{visit_mut, VisitorMut};
+
+/// The body of a function literal, marked, to be shared by the functions the
+/// literal evaluates to.
+pub fn function_body(mut body: BlockStatement) -> Rc<BlockStatement> {
+    mark_tail_calls(&mut body);
+    Rc::new(body)
+}
+
+/// Sets `tail` on the calls in tail position in `body`, the body of a
+/// function, and clears it on the others. Nested functions are marked when
+/// they are built.
+pub fn mark_tail_calls(body: &mut BlockStatement) {
+    Unmark.visit_block_statement(body);
+    mark_block(body, true);
+}
+
+/// Clears `tail` on the calls in `expression`, taken out of the function it
+/// was in, outside of the functions within it.
+pub fn clear_tail_calls(expression: &mut Expression) {
+    Unmark.visit_expression(expression);
+}
+
+struct Unmark;
+
+impl VisitorMut for Unmark {
+    fn visit_call_expression(&mut self, ce: &mut CallExpression) {
+        ce.tail = false;
+        visit_mut::walk_call_expression(self, ce);
+    }
+
+    fn visit_function_literal(&mut self, _fl: &mut FunctionLiteral) {}
+}
+
+// `tail` is whether the value of the block is the result of the function
+fn mark_block(block: &mut BlockStatement, tail: bool) {
+    let last = block.statements.len().saturating_sub(1);
+    for (i, statement) in block.statements.iter_mut().enumerate() {
+        match statement {
+            Statement::ReturnStatement(ReturnStatement {
+                return_value: Some(value),
+                ..
+            }) => mark_expression(value, true),
+            Statement::ExpressionStatement(ExpressionStatement {
+                expression: Some(expression),
+                ..
+            }) => mark_expression(expression, tail && i == last),
+            Statement::WhileStatement(ws) => mark_block(&mut ws.body, false),
+            Statement::ForStatement(fs) => mark_block(&mut fs.body, false),
+            _ => {}
+        }
+    }
+}
+
+// an expression used as a statement, in tail position or not
+fn mark_expression(expression: &mut Expression, tail: bool) {
+    match expression {
+        Expression::CallExpression(ce) => ce.tail = tail,
+        Expression::IfExpression(ie) => {
+            mark_block(&mut ie.consequence, tail);
+            if let Some(alternative) = &mut ie.alternative {
+                mark_block(alternative, tail);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::optimizer::{optimize, MAX_OPT_LEVEL};
+    use crate::parser::parse_ok;
+    use crate::visitor::{visit, Visitor};
+
+    // the calls of the function `statement` is or binds, outside of the
+    // functions within it, with whether they are in tail position
+    fn tail_calls(statement: &Statement) -> Vec<(String, bool)> {
+        struct Calls(Vec<(String, bool)>);
+
+        impl Visitor for Calls {
+            fn visit_call_expression(&mut self, ce: &CallExpression) {
+                self.0.push((ce.function.to_string(), ce.tail));
+                visit::walk_call_expression(self, ce);
+            }
+
+            fn visit_function_literal(&mut self, _fl: &FunctionLiteral) {}
+        }
+
+        let mut calls = Calls(Vec::new());
+        match statement {
+            Statement::ExpressionStatement(ExpressionStatement {
+                expression: Some(Expression::FunctionLiteral(fl)),
+                ..
+            })
+            | Statement::LetStatement(LetStatement {
+                value: Some(Expression::FunctionLiteral(fl)),
+                ..
+            }) => calls.visit_block_statement(&fl.body),
+            statement => panic!("not a function: {:?}", statement),
+        }
+        calls.0
+    }
+
+    #[test]
+    fn test_mark_tail_calls() {
+        let tests = [
+            ("fn() { a(); b() }", vec![("a", false), ("b", true)]),
+            ("fn() { a(b()) }", vec![("a", true), ("b", false)]),
+            ("fn() { 1 + a() }", vec![("a", false)]),
+            ("fn() { a(); }", vec![("a", true)]),
+            ("fn() { let x = a(); x }", vec![("a", false)]),
+            (
+                "fn(n) { if (n == 0) { a() } else { b(); c() } }",
+                vec![("a", true), ("b", false), ("c", true)],
+            ),
+            (
+                "fn(n) { if (n) { return a(); } b(); 1 }",
+                vec![("a", true), ("b", false)],
+            ),
+            (
+                "fn(n) { while (n) { if (n) { return a(); } b() } c() }",
+                vec![("a", true), ("b", false), ("c", true)],
+            ),
+            (
+                "fn() { return if (true) { a() } else { if (false) { b() } }; }",
+                vec![("a", true), ("b", true)],
+            ),
+            (
+                "fn() { let x = if (true) { return a(); }; fn() { b() } }",
+                vec![("a", false)],
+            ),
+            ("fn() { a() || b() }", vec![("a", false), ("b", false)]),
+            ("fn() { a()() }", vec![("a()", true), ("a", false)]),
+            (
+                "fn() { try { return a(); } catch (e) { b() } }",
+                vec![("a", false), ("b", false)],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let expected: Vec<(String, bool)> = expected
+                .into_iter()
+                .map(|(name, tail)| (name.to_string(), tail))
+                .collect();
+            let program = parse_ok(input);
+            assert_eq!(
+                tail_calls(&program.statements[0]),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_rebuilt_functions_are_marked_anew() {
+        // inlining `f` takes its call out of tail position in `g`, and
+        // leaves it there in `h`
+        let input = "let f = fn(x) { len(x) }; let g = fn(x) { f(x) + 1 }; let h = fn(x) { f(x) };";
+        let program = optimize(parse_ok(input), MAX_OPT_LEVEL);
+
+        let call = |tail| vec![("len".to_string(), tail)];
+        assert_eq!(tail_calls(&program.statements[1]), call(false));
+        assert_eq!(tail_calls(&program.statements[2]), call(true));
+    }
+}
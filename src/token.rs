@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use std::fmt;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub enum TokenType {
     #[default]
@@ -9,64 +11,141 @@ pub enum TokenType {
     // Identifiers + literals
     IDENT,
     INT,
+    STRING,
 
     // Operators
     ASSIGN,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
     PLUS,
     MINUS,
     BANG,
     ASTERISK,
     SLASH,
+    PERCENT,
 
     LT,
     GT,
+    LtEq,
+    GtEq,
 
     EQ,
     NotEq,
 
+    AND,
+    OR,
+
+    AMPERSAND,
+    PIPE,
+    CARET,
+    LShift,
+    RShift,
+
     // Delimiters
     COMMA,
     SEMICOLON,
+    COLON,
+    DOT,
+    ARROW,
 
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
 
     // Keywords
     FUNCTION,
     LET,
+    CONST,
     TRUE,
     FALSE,
     IF,
     ELSE,
     RETURN,
+    WHILE,
+    FOR,
+    IN,
+    BREAK,
+    CONTINUE,
+    MACRO,
+    IMPORT,
+    EXPORT,
+    AS,
+    THROW,
+    TRY,
+    CATCH,
+    FINALLY,
 }
 
 fn keywords(literal: &str) -> Option<TokenType> {
     let keywords: HashMap<&str, TokenType> = HashMap::from([
         ("fn", TokenType::FUNCTION),
         ("let", TokenType::LET),
+        ("const", TokenType::CONST),
         ("true", TokenType::TRUE),
         ("false", TokenType::FALSE),
         ("if", TokenType::IF),
         ("else", TokenType::ELSE),
         ("return", TokenType::RETURN),
+        ("while", TokenType::WHILE),
+        ("for", TokenType::FOR),
+        ("in", TokenType::IN),
+        ("break", TokenType::BREAK),
+        ("continue", TokenType::CONTINUE),
+        ("macro", TokenType::MACRO),
+        ("import", TokenType::IMPORT),
+        ("export", TokenType::EXPORT),
+        ("as", TokenType::AS),
+        ("throw", TokenType::THROW),
+        ("try", TokenType::TRY),
+        ("catch", TokenType::CATCH),
+        ("finally", TokenType::FINALLY),
     ]);
 
     keywords.get(literal).cloned()
 }
 
 pub fn lookup_ident(ident: String) -> TokenType {
-    let tok = match keywords(ident.as_str()) {
-        Some(tt) => tt.clone(),
+    match keywords(ident.as_str()) {
+        Some(tt) => tt,
         None => TokenType::IDENT,
-    };
-    return tok;
+    }
+}
+
+/// A place in the source; both fields start at 1 and columns count
+/// characters, not bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The source range of a token or node, `end` being the position just past
+/// its last character.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Token {
     pub token_type: TokenType,
     pub literal: String,
+    pub span: Span,
+}
+
+// `line:column`, type and literal, separated by tabs
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}\t{:?}\t{:?}",
+            self.span.start.line, self.span.start.column, self.token_type, self.literal
+        )
+    }
 }
@@ -0,0 +1,1016 @@
+//! Optional Hindley–Milner type inference, run by the `typecheck`
+//! subcommand and by `--typecheck` before a file is run.
+//!
+//! The types are `int`, `bool`, `string`, `null`, arrays `[T]`, hashes
+//! `{K: V}` and functions `fn(A, B) -> R`, plus type variables printed as
+//! `'a`, `'b` and so on. A `let` binding of a function literal is
+//! generalized, so after `let id = fn(x) { x };` the type of `id` is
+//! `fn('a) -> 'a` and it applies to values of any type. Annotations such as
+//! `let x: int = 5;` and `fn(a: int) -> int` must agree with what is
+//! inferred.
+//!
+//! The checker is stricter than the evaluator in places: arrays and hashes
+//! hold values of one type, and `==` compares values of one type. It is
+//! lenient in others: any value may be a condition or an operand of `!`,
+//! `&&` and `||`, an `if` without `else` has the type of its consequence
+//! and indexing a hash gives its value type, although both can give `null`.
+//! Module members, macros and quoted code are of unknown type. As in the
+//! evaluator, only functions and catch bodies introduce scopes, and a
+//! function may use a `let` binding that comes after it.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::token::Span;
+use crate::visitor::{visit, Visitor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    String,
+    Null,
+    Array(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    Var(usize),
+}
+
+impl Type {
+    fn from_annotation(annotation: &TypeAnnotation) -> Type {
+        match annotation {
+            TypeAnnotation::Named(name) => match name.as_str() {
+                "int" => Type::Int,
+                "bool" => Type::Bool,
+                "string" => Type::String,
+                // the parser accepts no other names
+                _ => Type::Null,
+            },
+            TypeAnnotation::Array(element) => Type::Array(Box::new(Type::from_annotation(element))),
+            TypeAnnotation::Hash(key, value) => Type::Hash(
+                Box::new(Type::from_annotation(key)),
+                Box::new(Type::from_annotation(value)),
+            ),
+            TypeAnnotation::Function(parameters, result) => Type::Function(
+                parameters.iter().map(Type::from_annotation).collect(),
+                Box::new(Type::from_annotation(result)),
+            ),
+        }
+    }
+
+    fn free_variables(&self, out: &mut Vec<usize>) {
+        match self {
+            Type::Var(v) if !out.contains(v) => out.push(*v),
+            Type::Array(element) => element.free_variables(out),
+            Type::Hash(key, value) => {
+                key.free_variables(out);
+                value.free_variables(out);
+            }
+            Type::Function(parameters, result) => {
+                for p in parameters {
+                    p.free_variables(out);
+                }
+                result.free_variables(out);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", TypeNames::default().render(self))
+    }
+}
+
+/// Names type variables `'a`, `'b` and so on in the order they are first
+/// rendered, so that the types of one message share their names.
+#[derive(Default)]
+struct TypeNames {
+    variables: Vec<usize>,
+}
+
+impl TypeNames {
+    fn render(&mut self, t: &Type) -> String {
+        match t {
+            Type::Int => "int".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::String => "string".to_string(),
+            Type::Null => "null".to_string(),
+            Type::Array(element) => format!("[{}]", self.render(element)),
+            Type::Hash(key, value) => {
+                format!("{{{}: {}}}", self.render(key), self.render(value))
+            }
+            Type::Function(parameters, result) => {
+                let params: Vec<String> = parameters.iter().map(|p| self.render(p)).collect();
+                format!("fn({}) -> {}", params.join(", "), self.render(result))
+            }
+            Type::Var(v) => {
+                let i = match self.variables.iter().position(|w| w == v) {
+                    Some(i) => i,
+                    None => {
+                        self.variables.push(*v);
+                        self.variables.len() - 1
+                    }
+                };
+                let letter = (b'a' + (i % 26) as u8) as char;
+                match i / 26 {
+                    0 => format!("'{}", letter),
+                    n => format!("'{}{}", letter, n),
+                }
+            }
+        }
+    }
+}
+
+/// A type whose `variables` can stand for any type, as that of `id` above.
+#[derive(Debug, Clone)]
+struct Scheme {
+    variables: Vec<usize>,
+    t: Type,
+}
+
+impl Scheme {
+    fn monomorphic(t: Type) -> Scheme {
+        Scheme {
+            variables: Vec::new(),
+            t,
+        }
+    }
+}
+
+struct Binding {
+    scheme: Scheme,
+    // declared ahead of its `let`, which has yet to be checked
+    pending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.start.line, self.span.start.column, self.message
+        )
+    }
+}
+
+/// Infers the types of `program`, returning those of its top-level `let`
+/// bindings in the order they are first bound, or every error found.
+pub fn infer_program(program: &Program) -> Result<Vec<(String, String)>, Vec<TypeError>> {
+    let inferer = infer(program)?;
+
+    let mut names: Vec<&str> = Vec::new();
+    for statement in &program.statements {
+        if let Statement::LetStatement(ls) = statement {
+            if !names.contains(&ls.name.value.as_str()) {
+                names.push(&ls.name.value);
+            }
+        }
+    }
+
+    let scope = inferer.scopes.last().unwrap();
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let t = inferer.apply(&scope[name].scheme.t);
+            (name.to_string(), t.to_string())
+        })
+        .collect())
+}
+
+/// The types inferred for the expressions of a program, for the backends
+/// that compile it.
+#[derive(Debug)]
+pub struct Types {
+    expressions: HashMap<Span, Type>,
+}
+
+impl Types {
+    /// The type of `expression`, which is a variable where the program
+    /// leaves it open, as in the body of `fn(x) { x }`.
+    pub fn of(&self, expression: &Expression) -> Type {
+        self.expressions
+            .get(&expression.span())
+            .cloned()
+            .unwrap_or(Type::Var(0))
+    }
+}
+
+/// Infers the types of every expression in `program`, or returns every
+/// error found.
+pub fn infer_types(program: &Program) -> Result<Types, Vec<TypeError>> {
+    let inferer = infer(program)?;
+
+    let expressions = inferer
+        .expressions
+        .iter()
+        .map(|(span, t)| (*span, inferer.apply(t)))
+        .collect();
+    Ok(Types { expressions })
+}
+
+fn infer(program: &Program) -> Result<Inferer, Vec<TypeError>> {
+    let mut inferer = Inferer::new();
+
+    let scope = inferer.declarations(|declarations| declarations.visit_program(program));
+    inferer.scopes.push(scope);
+    let result = inferer.fresh();
+    inferer.returns.push(result);
+    for statement in &program.statements {
+        inferer.statement(statement);
+    }
+
+    match inferer.errors.is_empty() {
+        true => Ok(inferer),
+        false => Err(inferer.errors),
+    }
+}
+
+/// Collects the `let` bindings of a function body, without descending into
+/// nested functions.
+#[derive(Default)]
+struct Declarations {
+    names: Vec<String>,
+}
+
+impl Visitor for Declarations {
+    fn visit_let_statement(&mut self, ls: &LetStatement) {
+        self.names.push(ls.name.value.clone());
+        visit::walk_let_statement(self, ls);
+    }
+
+    fn visit_try_expression(&mut self, te: &TryExpression) {
+        // the catch body declares into a scope of its own
+        self.visit_block_statement(&te.body);
+        if let Some(finally) = &te.finally {
+            self.visit_block_statement(finally);
+        }
+    }
+
+    fn visit_function_literal(&mut self, _fl: &FunctionLiteral) {}
+
+    fn visit_macro_literal(&mut self, _ml: &MacroLiteral) {}
+}
+
+struct Inferer {
+    // what each type variable stands for, once known
+    substitution: Vec<Option<Type>>,
+    // the builtins, the program, then one scope per function or catch body
+    // being checked
+    scopes: Vec<HashMap<String, Binding>>,
+    // the return types of the functions being checked
+    returns: Vec<Type>,
+    // the type of each expression checked, by its span
+    expressions: Vec<(Span, Type)>,
+    errors: Vec<TypeError>,
+}
+
+impl Inferer {
+    fn new() -> Inferer {
+        let mut inferer = Inferer {
+            substitution: Vec::new(),
+            scopes: Vec::new(),
+            returns: Vec::new(),
+            expressions: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let a = inferer.fresh();
+        let array = Type::Array(Box::new(a.clone()));
+        let function = |parameters: Vec<Type>, result: Type| Scheme {
+            variables: vec![0],
+            t: Type::Function(parameters, Box::new(result)),
+        };
+        let builtins = [
+            ("len", function(vec![a.clone()], Type::Int)),
+            ("first", function(vec![array.clone()], a.clone())),
+            ("last", function(vec![array.clone()], a.clone())),
+            ("rest", function(vec![array.clone()], array.clone())),
+            ("push", function(vec![array.clone(), a.clone()], array)),
+            // calls to puts take any number of arguments, see `call`
+            ("puts", function(vec![a], Type::Null)),
+            (
+                "gc",
+                function(
+                    vec![],
+                    Type::Hash(Box::new(Type::String), Box::new(Type::Int)),
+                ),
+            ),
+            ("pow", function(vec![Type::Int, Type::Int], Type::Int)),
+        ];
+        inferer.scopes.push(
+            builtins
+                .into_iter()
+                .map(|(name, scheme)| {
+                    let binding = Binding {
+                        scheme,
+                        pending: false,
+                    };
+                    (name.to_string(), binding)
+                })
+                .collect(),
+        );
+        inferer
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() - 1)
+    }
+
+    /// Follows the substitution until `t` is not a known variable.
+    fn resolve(&self, t: &Type) -> Type {
+        match t {
+            Type::Var(v) => match &self.substitution[*v] {
+                Some(t) => self.resolve(t),
+                None => t.clone(),
+            },
+            t => t.clone(),
+        }
+    }
+
+    /// Replaces every known variable in `t`.
+    fn apply(&self, t: &Type) -> Type {
+        match self.resolve(t) {
+            Type::Array(element) => Type::Array(Box::new(self.apply(&element))),
+            Type::Hash(key, value) => {
+                Type::Hash(Box::new(self.apply(&key)), Box::new(self.apply(&value)))
+            }
+            Type::Function(parameters, result) => Type::Function(
+                parameters.iter().map(|p| self.apply(p)).collect(),
+                Box::new(self.apply(&result)),
+            ),
+            t => t,
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(v), Type::Var(w)) if v == w => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => {
+                let mut variables = Vec::new();
+                self.apply(&t).free_variables(&mut variables);
+                // a type cannot contain itself
+                if variables.contains(&v) {
+                    return Err(());
+                }
+                self.substitution[v] = Some(t);
+                Ok(())
+            }
+            (Type::Array(a), Type::Array(b)) => self.unify(&a, &b),
+            (Type::Hash(ka, va), Type::Hash(kb, vb)) => {
+                self.unify(&ka, &kb)?;
+                self.unify(&va, &vb)
+            }
+            (Type::Function(pa, ra), Type::Function(pb, rb)) if pa.len() == pb.len() => {
+                for (a, b) in pa.iter().zip(&pb) {
+                    self.unify(a, b)?;
+                }
+                self.unify(&ra, &rb)
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// Unifies the `actual` type of the code at `span` with the type the
+    /// context `expected`, reporting when they differ.
+    fn expect(&mut self, span: Span, expected: &Type, actual: &Type) {
+        if self.unify(expected, actual).is_err() {
+            let mut names = TypeNames::default();
+            let message = format!(
+                "type mismatch: expected {}, got {}",
+                names.render(&self.apply(expected)),
+                names.render(&self.apply(actual))
+            );
+            self.report(span, message);
+        }
+    }
+
+    fn report(&mut self, span: Span, message: String) {
+        self.errors.push(TypeError { span, message });
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: Vec<(usize, Type)> = scheme
+            .variables
+            .iter()
+            .map(|v| (*v, self.fresh()))
+            .collect();
+        substitute(&self.apply(&scheme.t), &fresh)
+    }
+
+    /// Turns the variables of `t` that no binding in scope refers to into
+    /// variables of the scheme.
+    fn generalize(&self, t: &Type) -> Scheme {
+        let t = self.apply(t);
+        let mut in_scope = Vec::new();
+        for scope in &self.scopes[1..] {
+            for binding in scope.values() {
+                let mut variables = Vec::new();
+                self.apply(&binding.scheme.t).free_variables(&mut variables);
+                in_scope.extend(
+                    variables
+                        .into_iter()
+                        .filter(|v| !binding.scheme.variables.contains(v)),
+                );
+            }
+        }
+
+        let mut variables = Vec::new();
+        t.free_variables(&mut variables);
+        variables.retain(|v| !in_scope.contains(v));
+        Scheme { variables, t }
+    }
+
+    /// A scope holding a pending binding for each `let` that `declare`
+    /// finds.
+    fn declarations(
+        &mut self,
+        declare: impl FnOnce(&mut Declarations),
+    ) -> HashMap<String, Binding> {
+        let mut declarations = Declarations::default();
+        declare(&mut declarations);
+
+        let mut scope = HashMap::new();
+        for name in declarations.names {
+            scope.entry(name).or_insert_with(|| Binding {
+                scheme: Scheme::monomorphic(self.fresh()),
+                pending: true,
+            });
+        }
+        scope
+    }
+
+    fn bind(&mut self, name: &str, scheme: Scheme) {
+        let binding = Binding {
+            scheme,
+            pending: false,
+        };
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), binding);
+    }
+
+    /// The scope `name` is bound in, counting from the builtins, and its
+    /// binding.
+    fn lookup(&self, name: &str) -> Option<(usize, &Binding)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, scope)| scope.get(name).map(|binding| (i, binding)))
+    }
+
+    /// Checks `statement`, returning the type of its value when it ends a
+    /// block.
+    fn statement(&mut self, statement: &Statement) -> Type {
+        match statement {
+            Statement::LetStatement(ls) => {
+                self.let_statement(ls);
+                Type::Null
+            }
+            Statement::ReturnStatement(rs) => {
+                if let Some(value) = &rs.return_value {
+                    let t = self.expression(value);
+                    let result = self.returns.last().unwrap().clone();
+                    self.expect(value.span(), &result, &t);
+                }
+                // the block never ends with the value of a return
+                self.fresh()
+            }
+            Statement::ExpressionStatement(es) => match &es.expression {
+                Some(e) => self.expression(e),
+                None => Type::Null,
+            },
+            Statement::WhileStatement(ws) => {
+                self.expression(&ws.condition);
+                self.block(&ws.body);
+                Type::Null
+            }
+            Statement::ForStatement(fs) => {
+                let iterable = self.expression(&fs.iterable);
+                let element = match self.resolve(&iterable) {
+                    Type::String => Type::String,
+                    Type::Hash(key, _) => *key,
+                    _ => {
+                        let element = self.fresh();
+                        let array = Type::Array(Box::new(element.clone()));
+                        self.expect(fs.iterable.span(), &array, &iterable);
+                        element
+                    }
+                };
+                self.bind(&fs.variable.value, Scheme::monomorphic(element));
+                self.block(&fs.body);
+                Type::Null
+            }
+            Statement::BreakStatement(_) | Statement::ContinueStatement(_) => self.fresh(),
+            Statement::ImportStatement(is) => {
+                let module = self.fresh();
+                self.bind(&is.alias.value, Scheme::monomorphic(module));
+                Type::Null
+            }
+            Statement::ThrowStatement(ts) => {
+                self.expression(&ts.value);
+                // like a return, the block never ends with it
+                self.fresh()
+            }
+        }
+    }
+
+    fn let_statement(&mut self, ls: &LetStatement) {
+        let name = &ls.name.value;
+        let declared = match self.scopes.last().unwrap().get(name) {
+            Some(binding) if binding.pending => Some(binding.scheme.t.clone()),
+            _ => None,
+        };
+
+        let (t, span) = match &ls.value {
+            Some(value) => (self.expression(value), value.span()),
+            None => (Type::Null, ls.span),
+        };
+        if let Some(annotation) = &ls.type_annotation {
+            self.expect(span, &Type::from_annotation(annotation), &t);
+        }
+        // functions before the `let` may have used the name already
+        if let Some(declared) = declared {
+            self.expect(span, &declared, &t);
+        }
+
+        self.scopes.last_mut().unwrap().remove(name);
+        let scheme = match &ls.value {
+            Some(Expression::FunctionLiteral(_)) => self.generalize(&t),
+            _ => Scheme::monomorphic(t),
+        };
+        self.bind(name, scheme);
+    }
+
+    /// Checks the statements of `block`, returning the type of its value.
+    fn block(&mut self, block: &BlockStatement) -> Type {
+        let mut t = Type::Null;
+        for statement in &block.statements {
+            t = self.statement(statement);
+        }
+        t
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Type {
+        let t = self.expression_type(expression);
+        self.expressions.push((expression.span(), t.clone()));
+        t
+    }
+
+    fn expression_type(&mut self, expression: &Expression) -> Type {
+        match expression {
+            Expression::Identifier(id) => match self.lookup(&id.value) {
+                Some((_, binding)) => {
+                    let scheme = binding.scheme.clone();
+                    self.instantiate(&scheme)
+                }
+                None => {
+                    self.report(id.span, format!("identifier not found: {}", id.value));
+                    self.fresh()
+                }
+            },
+            Expression::IntegerLiteral(_) => Type::Int,
+            Expression::Boolean(_) => Type::Bool,
+            Expression::StringLiteral(_) => Type::String,
+            Expression::PrefixExpression(pe) => {
+                let right = self.expression(&pe.right);
+                match pe.operator.as_str() {
+                    "-" => {
+                        self.expect(pe.right.span(), &Type::Int, &right);
+                        Type::Int
+                    }
+                    _ => Type::Bool,
+                }
+            }
+            Expression::InfixExpression(ie) => {
+                let left = self.expression(&ie.left);
+                let right = self.expression(&ie.right);
+                self.operator(
+                    &ie.operator,
+                    (&left, ie.left.span()),
+                    (&right, ie.right.span()),
+                )
+            }
+            Expression::AssignExpression(ae) => {
+                let target = self.expression(&ae.target);
+                let value = self.expression(&ae.value);
+                let value = match ae.operator.strip_suffix('=') {
+                    Some(operator) if !operator.is_empty() => self.operator(
+                        operator,
+                        (&target, ae.target.span()),
+                        (&value, ae.value.span()),
+                    ),
+                    _ => value,
+                };
+                self.expect(ae.value.span(), &target, &value);
+                target
+            }
+            Expression::IfExpression(ie) => {
+                self.expression(&ie.condition);
+                let consequence = self.block(&ie.consequence);
+                if let Some(alternative) = &ie.alternative {
+                    let t = self.block(alternative);
+                    let span = alternative
+                        .statements
+                        .last()
+                        .map_or(alternative.span, |s| s.span());
+                    self.expect(span, &consequence, &t);
+                }
+                consequence
+            }
+            Expression::TryExpression(te) => {
+                let body = self.block(&te.body);
+                if let Some(catch) = &te.catch {
+                    // anything may be thrown
+                    let caught = self.fresh();
+                    let scope = self.declarations(|declarations| {
+                        declarations.visit_block_statement(&catch.body)
+                    });
+                    self.scopes.push(scope);
+                    self.bind(&catch.parameter.value, Scheme::monomorphic(caught));
+                    let t = self.block(&catch.body);
+                    self.scopes.pop();
+                    let span = catch
+                        .body
+                        .statements
+                        .last()
+                        .map_or(catch.body.span, |s| s.span());
+                    self.expect(span, &body, &t);
+                }
+                if let Some(finally) = &te.finally {
+                    self.block(finally);
+                }
+                body
+            }
+            Expression::FunctionLiteral(fl) => self.function(fl),
+            Expression::MacroLiteral(_) => self.fresh(),
+            Expression::CallExpression(ce) => self.call(ce),
+            Expression::ArrayLiteral(al) => {
+                let element = self.fresh();
+                for e in &al.elements {
+                    let t = self.expression(e);
+                    self.expect(e.span(), &element, &t);
+                }
+                Type::Array(Box::new(element))
+            }
+            Expression::IndexExpression(ie) => {
+                let left = self.expression(&ie.left);
+                let index = self.expression(&ie.index);
+                match self.resolve(&left) {
+                    Type::Hash(key, value) => {
+                        self.expect(ie.index.span(), &key, &index);
+                        *value
+                    }
+                    _ => {
+                        let element = self.fresh();
+                        let array = Type::Array(Box::new(element.clone()));
+                        self.expect(ie.left.span(), &array, &left);
+                        self.expect(ie.index.span(), &Type::Int, &index);
+                        element
+                    }
+                }
+            }
+            Expression::HashLiteral(hl) => {
+                let (key, value) = (self.fresh(), self.fresh());
+                for (k, v) in &hl.pairs {
+                    let t = self.expression(k);
+                    self.expect(k.span(), &key, &t);
+                    let t = self.expression(v);
+                    self.expect(v.span(), &value, &t);
+                }
+                Type::Hash(Box::new(key), Box::new(value))
+            }
+            Expression::MemberExpression(me) => {
+                self.expression(&me.object);
+                self.fresh()
+            }
+        }
+    }
+
+    /// The type of `left <operator> right`.
+    fn operator(&mut self, operator: &str, left: (&Type, Span), right: (&Type, Span)) -> Type {
+        let (operands, result) = match operator {
+            "+" if [left.0, right.0]
+                .iter()
+                .any(|t| self.resolve(t) == Type::String) =>
+            {
+                (Some(Type::String), Type::String)
+            }
+            "==" | "!=" => {
+                self.expect(right.1, left.0, right.0);
+                (None, Type::Bool)
+            }
+            "<" | ">" | "<=" | ">=" => (Some(Type::Int), Type::Bool),
+            "&&" | "||" => (None, Type::Bool),
+            _ => (Some(Type::Int), Type::Int),
+        };
+
+        if let Some(operands) = operands {
+            self.expect(left.1, &operands, left.0);
+            self.expect(right.1, &operands, right.0);
+        }
+        result
+    }
+
+    fn function(&mut self, fl: &FunctionLiteral) -> Type {
+        let mut scope =
+            self.declarations(|declarations| declarations.visit_block_statement(&fl.body));
+
+        let mut parameters = Vec::new();
+        for (p, annotation) in fl.parameters.iter().zip(&fl.parameter_types) {
+            let t = match annotation {
+                Some(annotation) => Type::from_annotation(annotation),
+                None => self.fresh(),
+            };
+            let binding = Binding {
+                scheme: Scheme::monomorphic(t.clone()),
+                pending: false,
+            };
+            scope.insert(p.value.clone(), binding);
+            parameters.push(t);
+        }
+
+        let result = match &fl.return_type {
+            Some(annotation) => Type::from_annotation(annotation),
+            None => self.fresh(),
+        };
+
+        self.scopes.push(scope);
+        self.returns.push(result.clone());
+        let t = self.block(&fl.body);
+        let span = fl.body.statements.last().map_or(fl.body.span, |s| s.span());
+        self.expect(span, &result, &t);
+        self.returns.pop();
+        self.scopes.pop();
+
+        Type::Function(parameters, Box::new(result))
+    }
+
+    fn call(&mut self, ce: &CallExpression) -> Type {
+        if let Expression::Identifier(id) = ce.function.as_ref() {
+            match (id.value.as_str(), self.lookup(&id.value)) {
+                // quoted code is data
+                ("quote" | "unquote", None) => return self.fresh(),
+                ("puts", Some((0, _))) => {
+                    for argument in &ce.arguments {
+                        self.expression(argument);
+                    }
+                    return Type::Null;
+                }
+                _ => {}
+            }
+        }
+
+        let function = self.expression(&ce.function);
+        let arguments: Vec<Type> = ce.arguments.iter().map(|a| self.expression(a)).collect();
+
+        match self.resolve(&function) {
+            Type::Function(parameters, result) => {
+                if parameters.len() != arguments.len() {
+                    let message = format!(
+                        "wrong number of arguments: expected {}, got {}",
+                        parameters.len(),
+                        arguments.len()
+                    );
+                    self.report(ce.span, message);
+                    return self.fresh();
+                }
+                for ((p, a), argument) in parameters.iter().zip(&arguments).zip(&ce.arguments) {
+                    self.expect(argument.span(), p, a);
+                }
+                *result
+            }
+            _ => {
+                let result = self.fresh();
+                let expected = Type::Function(arguments, Box::new(result.clone()));
+                self.expect(ce.function.span(), &expected, &function);
+                result
+            }
+        }
+    }
+}
+
+/// Replaces the variables of `t` listed in `fresh` by their new types.
+fn substitute(t: &Type, fresh: &[(usize, Type)]) -> Type {
+    match t {
+        Type::Var(v) => match fresh.iter().find(|(w, _)| w == v) {
+            Some((_, t)) => t.clone(),
+            None => t.clone(),
+        },
+        Type::Array(element) => Type::Array(Box::new(substitute(element, fresh))),
+        Type::Hash(key, value) => Type::Hash(
+            Box::new(substitute(key, fresh)),
+            Box::new(substitute(value, fresh)),
+        ),
+        Type::Function(parameters, result) => Type::Function(
+            parameters.iter().map(|p| substitute(p, fresh)).collect(),
+            Box::new(substitute(result, fresh)),
+        ),
+        t => t.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::{parse_ok, Parser};
+
+    fn infer(input: &str) -> Result<Vec<(String, String)>, Vec<String>> {
+        infer_program(&parse_ok(input))
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn test_inferred_types() {
+        let tests = [
+            ("let x = 5;", "x", "int"),
+            (r#"let s = "a" + "b";"#, "s", "string"),
+            ("let b = 1 < 2 && !true;", "b", "bool"),
+            ("let xs = [1, 2, 3];", "xs", "[int]"),
+            (r#"let h = {"a": [true]};"#, "h", "{string: [bool]}"),
+            ("let add = fn(a, b) { a + b };", "add", "fn(int, int) -> int"),
+            ("let id = fn(x) { x };", "id", "fn('a) -> 'a"),
+            (
+                "let compose = fn(f, g) { fn(x) { g(f(x)) } };",
+                "compose",
+                "fn(fn('a) -> 'b, fn('b) -> 'c) -> fn('a) -> 'c",
+            ),
+            (
+                "let map = fn(xs, f) { let out = []; for (x in xs) { out = push(out, f(x)); } out };",
+                "map",
+                "fn(['a], fn('a) -> 'b) -> ['b]",
+            ),
+            (
+                "let fact = fn(n) { if (n < 2) { return 1; } n * fact(n - 1) };",
+                "fact",
+                "fn(int) -> int",
+            ),
+            (
+                r#"let parse = fn(s) { if (s == "") { throw "empty"; } try { len(s) } catch (e) { 0 } };"#,
+                "parse",
+                "fn(string) -> int",
+            ),
+            // polymorphic bindings instantiate anew at every use
+            (
+                r#"let id = fn(x) { x }; let pair = [id(1), len(id("s"))];"#,
+                "pair",
+                "[int]",
+            ),
+            // a function may call one defined after it
+            (
+                "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } }; let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };",
+                "even",
+                "fn(int) -> bool",
+            ),
+            ("let r = rest(first([[1]]));", "r", "[int]"),
+            ("let n = puts(1, true, \"s\");", "n", "null"),
+            ("let f = fn(x: string) -> [string] { [x] };", "f", "fn(string) -> [string]"),
+            ("let e: [int] = [];", "e", "[int]"),
+            ("let x = 1; let x = \"one\";", "x", "string"),
+        ];
+
+        for (input, name, expected) in tests {
+            let bindings = infer(input)
+                .unwrap_or_else(|errors| panic!("type errors for {}: {:?}", input, errors));
+            let (_, t) = bindings
+                .iter()
+                .find(|(n, _)| n == name)
+                .unwrap_or_else(|| panic!("{} is not bound by {}", name, input));
+            assert_eq!(t, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let tests = [
+            ("1 + true", "1:5: type mismatch: expected int, got bool"),
+            (
+                r#""a" - "b""#,
+                "1:1: type mismatch: expected int, got string",
+            ),
+            (
+                "[1, \"two\"]",
+                "1:5: type mismatch: expected int, got string",
+            ),
+            ("1 == \"1\"", "1:6: type mismatch: expected int, got string"),
+            (
+                "try { 1 } catch (e) { true }",
+                "1:23: type mismatch: expected int, got bool",
+            ),
+            (
+                "let x: int = true;",
+                "1:14: type mismatch: expected int, got bool",
+            ),
+            (
+                "let f = fn(a: int) -> string { a };",
+                "1:32: type mismatch: expected string, got int",
+            ),
+            (
+                "let f = fn(a) { a + 1 }; f(\"s\");",
+                "1:28: type mismatch: expected int, got string",
+            ),
+            (
+                "let f = fn(a) { a }; f(1, 2);",
+                "1:22: wrong number of arguments: expected 1, got 2",
+            ),
+            (
+                "5(1)",
+                "1:1: type mismatch: expected fn(int) -> 'a, got int",
+            ),
+            (
+                "let f = fn(x) { x(x) };",
+                "1:17: type mismatch: expected fn('a) -> 'b, got 'a",
+            ),
+            (
+                "if (true) { 1 } else { \"one\" }",
+                "1:24: type mismatch: expected int, got string",
+            ),
+            (
+                "let f = fn() { return 1; true };",
+                "1:26: type mismatch: expected int, got bool",
+            ),
+            (
+                "let x = 1; x = [x];",
+                "1:16: type mismatch: expected int, got [int]",
+            ),
+            (
+                "let x = 1; x += \"s\";",
+                "1:12: type mismatch: expected string, got int",
+            ),
+            (
+                "let h = {1: 2}; h[\"k\"]",
+                "1:19: type mismatch: expected int, got string",
+            ),
+            (
+                "for (x in 5) { x }",
+                "1:11: type mismatch: expected ['a], got int",
+            ),
+            ("y + 1", "1:1: identifier not found: y"),
+        ];
+
+        for (input, expected) in tests {
+            match infer(input) {
+                Ok(bindings) => panic!("no type errors for {}, got {:?}", input, bindings),
+                Err(errors) => assert_eq!(errors[0], expected, "input: {}", input),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unchecked_code() {
+        let tests = [
+            r#"import "lib.monkey" as lib; let x = lib.f(1) + lib.g("s");"#,
+            "let m = macro(a) { quote(unquote(a) + b) }; quote(1 + true);",
+            "let x = if (false) { 1 }; let y = {\"a\": 1}[\"b\"] + 1;",
+            "let f = fn(x) { if (x) { puts(x); } }; f(1); f(\"s\");",
+        ];
+
+        for input in tests {
+            if let Err(errors) = infer(input) {
+                panic!("type errors for {}: {:?}", input, errors);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expression_types() {
+        let input = "let f = fn(x) { x > 1 }; let g = fn(y) { y }; if (f(2)) { g(3) } else { 4 }";
+        let mut p = Parser::new(Lexer::new(input));
+        let program = p.parse_program();
+        let types = infer_types(&program).unwrap();
+
+        let (f, ie) = match &program.statements[..] {
+            [Statement::LetStatement(f), _, Statement::ExpressionStatement(es)] => {
+                match &es.expression {
+                    Some(Expression::IfExpression(ie)) => (f, ie),
+                    e => panic!("not an if expression: {:?}", e),
+                }
+            }
+            statements => panic!("unexpected statements: {:?}", statements),
+        };
+
+        let f = f.value.as_ref().unwrap();
+        assert_eq!(types.of(f).to_string(), "fn(int) -> bool");
+        assert_eq!(types.of(&ie.condition), Type::Bool);
+        // instances of generic functions get the types of their arguments
+        match &ie.consequence.statements[0] {
+            Statement::ExpressionStatement(es) => {
+                assert_eq!(types.of(es.expression.as_ref().unwrap()), Type::Int)
+            }
+            statement => panic!("not an expression: {:?}", statement),
+        }
+    }
+}
@@ -0,0 +1,861 @@
+//! Traversals over the AST.
+//!
+//! `Visitor` and `VisitorMut` walk a tree by reference; every method has a
+//! default that visits the children of its node through the matching
+//! `walk_*` function, so an implementation only overrides the nodes it is
+//! interested in and calls the walk function itself to keep descending.
+//! `Fold` takes the tree by value and rebuilds it the same way.
+//!
+//! `visit_identifier` and `fold_identifier` are only called for identifiers
+//! used as expressions. Names introduced by `let`, parameters, `for`,
+//! `import` and `catch`, and the property of a member expression, are part
+//! of their nodes.
+
+use std::rc::Rc;
+
+use crate::ast::*;
+
+// the body of a function literal as the walks get at it: shared, or made
+// their own for a `VisitorMut` to change
+trait FunctionBody<'a> {
+    type Body;
+    fn get(self) -> Self::Body;
+}
+
+impl<'a> FunctionBody<'a> for &'a Rc<BlockStatement> {
+    type Body = &'a BlockStatement;
+    fn get(self) -> &'a BlockStatement {
+        self
+    }
+}
+
+impl<'a> FunctionBody<'a> for &'a mut Rc<BlockStatement> {
+    type Body = &'a mut BlockStatement;
+    fn get(self) -> &'a mut BlockStatement {
+        Rc::make_mut(self)
+    }
+}
+
+macro_rules! make_visitor {
+    ($visitor:ident, $walk:ident, $($mutability:ident)?) => {
+        pub trait $visitor {
+            fn visit_program(&mut self, program: &$($mutability)? Program) {
+                $walk::walk_program(self, program)
+            }
+
+            fn visit_statement(&mut self, statement: &$($mutability)? Statement) {
+                $walk::walk_statement(self, statement)
+            }
+
+            fn visit_let_statement(&mut self, ls: &$($mutability)? LetStatement) {
+                $walk::walk_let_statement(self, ls)
+            }
+
+            fn visit_return_statement(&mut self, rs: &$($mutability)? ReturnStatement) {
+                $walk::walk_return_statement(self, rs)
+            }
+
+            fn visit_expression_statement(&mut self, es: &$($mutability)? ExpressionStatement) {
+                $walk::walk_expression_statement(self, es)
+            }
+
+            fn visit_while_statement(&mut self, ws: &$($mutability)? WhileStatement) {
+                $walk::walk_while_statement(self, ws)
+            }
+
+            fn visit_for_statement(&mut self, fs: &$($mutability)? ForStatement) {
+                $walk::walk_for_statement(self, fs)
+            }
+
+            fn visit_break_statement(&mut self, _bs: &$($mutability)? BreakStatement) {}
+
+            fn visit_continue_statement(&mut self, _cs: &$($mutability)? ContinueStatement) {}
+
+            fn visit_import_statement(&mut self, _is: &$($mutability)? ImportStatement) {}
+
+            fn visit_throw_statement(&mut self, ts: &$($mutability)? ThrowStatement) {
+                $walk::walk_throw_statement(self, ts)
+            }
+
+            fn visit_block_statement(&mut self, block: &$($mutability)? BlockStatement) {
+                $walk::walk_block_statement(self, block)
+            }
+
+            fn visit_expression(&mut self, expression: &$($mutability)? Expression) {
+                $walk::walk_expression(self, expression)
+            }
+
+            fn visit_identifier(&mut self, _id: &$($mutability)? Identifier) {}
+
+            fn visit_integer_literal(&mut self, _il: &$($mutability)? IntegerLiteral) {}
+
+            fn visit_boolean(&mut self, _b: &$($mutability)? Boolean) {}
+
+            fn visit_string_literal(&mut self, _sl: &$($mutability)? StringLiteral) {}
+
+            fn visit_prefix_expression(&mut self, pe: &$($mutability)? PrefixExpression) {
+                $walk::walk_prefix_expression(self, pe)
+            }
+
+            fn visit_infix_expression(&mut self, ie: &$($mutability)? InfixExpression) {
+                $walk::walk_infix_expression(self, ie)
+            }
+
+            fn visit_if_expression(&mut self, ie: &$($mutability)? IfExpression) {
+                $walk::walk_if_expression(self, ie)
+            }
+
+            fn visit_function_literal(&mut self, fl: &$($mutability)? FunctionLiteral) {
+                $walk::walk_function_literal(self, fl)
+            }
+
+            fn visit_macro_literal(&mut self, ml: &$($mutability)? MacroLiteral) {
+                $walk::walk_macro_literal(self, ml)
+            }
+
+            fn visit_call_expression(&mut self, ce: &$($mutability)? CallExpression) {
+                $walk::walk_call_expression(self, ce)
+            }
+
+            fn visit_array_literal(&mut self, al: &$($mutability)? ArrayLiteral) {
+                $walk::walk_array_literal(self, al)
+            }
+
+            fn visit_index_expression(&mut self, ie: &$($mutability)? IndexExpression) {
+                $walk::walk_index_expression(self, ie)
+            }
+
+            fn visit_hash_literal(&mut self, hl: &$($mutability)? HashLiteral) {
+                $walk::walk_hash_literal(self, hl)
+            }
+
+            fn visit_assign_expression(&mut self, ae: &$($mutability)? AssignExpression) {
+                $walk::walk_assign_expression(self, ae)
+            }
+
+            fn visit_member_expression(&mut self, me: &$($mutability)? MemberExpression) {
+                $walk::walk_member_expression(self, me)
+            }
+
+            fn visit_try_expression(&mut self, te: &$($mutability)? TryExpression) {
+                $walk::walk_try_expression(self, te)
+            }
+        }
+
+        pub mod $walk {
+            use super::{$visitor, FunctionBody};
+            use crate::ast::*;
+
+            pub fn walk_program<V: $visitor + ?Sized>(visitor: &mut V, program: &$($mutability)? Program) {
+                for statement in &$($mutability)? program.statements {
+                    visitor.visit_statement(statement);
+                }
+            }
+
+            pub fn walk_statement<V: $visitor + ?Sized>(visitor: &mut V, statement: &$($mutability)? Statement) {
+                match statement {
+                    Statement::LetStatement(ls) => visitor.visit_let_statement(ls),
+                    Statement::ReturnStatement(rs) => visitor.visit_return_statement(rs),
+                    Statement::ExpressionStatement(es) => visitor.visit_expression_statement(es),
+                    Statement::WhileStatement(ws) => visitor.visit_while_statement(ws),
+                    Statement::ForStatement(fs) => visitor.visit_for_statement(fs),
+                    Statement::BreakStatement(bs) => visitor.visit_break_statement(bs),
+                    Statement::ContinueStatement(cs) => visitor.visit_continue_statement(cs),
+                    Statement::ImportStatement(is) => visitor.visit_import_statement(is),
+                    Statement::ThrowStatement(ts) => visitor.visit_throw_statement(ts),
+                }
+            }
+
+            pub fn walk_let_statement<V: $visitor + ?Sized>(visitor: &mut V, ls: &$($mutability)? LetStatement) {
+                if let Some(value) = &$($mutability)? ls.value {
+                    visitor.visit_expression(value);
+                }
+            }
+
+            pub fn walk_return_statement<V: $visitor + ?Sized>(visitor: &mut V, rs: &$($mutability)? ReturnStatement) {
+                if let Some(value) = &$($mutability)? rs.return_value {
+                    visitor.visit_expression(value);
+                }
+            }
+
+            pub fn walk_expression_statement<V: $visitor + ?Sized>(visitor: &mut V, es: &$($mutability)? ExpressionStatement) {
+                if let Some(expression) = &$($mutability)? es.expression {
+                    visitor.visit_expression(expression);
+                }
+            }
+
+            pub fn walk_while_statement<V: $visitor + ?Sized>(visitor: &mut V, ws: &$($mutability)? WhileStatement) {
+                visitor.visit_expression(&$($mutability)? ws.condition);
+                visitor.visit_block_statement(&$($mutability)? ws.body);
+            }
+
+            pub fn walk_for_statement<V: $visitor + ?Sized>(visitor: &mut V, fs: &$($mutability)? ForStatement) {
+                visitor.visit_expression(&$($mutability)? fs.iterable);
+                visitor.visit_block_statement(&$($mutability)? fs.body);
+            }
+
+            pub fn walk_throw_statement<V: $visitor + ?Sized>(visitor: &mut V, ts: &$($mutability)? ThrowStatement) {
+                visitor.visit_expression(&$($mutability)? ts.value);
+            }
+
+            pub fn walk_block_statement<V: $visitor + ?Sized>(visitor: &mut V, block: &$($mutability)? BlockStatement) {
+                for statement in &$($mutability)? block.statements {
+                    visitor.visit_statement(statement);
+                }
+            }
+
+            pub fn walk_expression<V: $visitor + ?Sized>(visitor: &mut V, expression: &$($mutability)? Expression) {
+                match expression {
+                    Expression::Identifier(id) => visitor.visit_identifier(id),
+                    Expression::IntegerLiteral(il) => visitor.visit_integer_literal(il),
+                    Expression::Boolean(b) => visitor.visit_boolean(b),
+                    Expression::StringLiteral(sl) => visitor.visit_string_literal(sl),
+                    Expression::PrefixExpression(pe) => visitor.visit_prefix_expression(pe),
+                    Expression::InfixExpression(ie) => visitor.visit_infix_expression(ie),
+                    Expression::IfExpression(ie) => visitor.visit_if_expression(ie),
+                    Expression::FunctionLiteral(fl) => visitor.visit_function_literal(fl),
+                    Expression::MacroLiteral(ml) => visitor.visit_macro_literal(ml),
+                    Expression::CallExpression(ce) => visitor.visit_call_expression(ce),
+                    Expression::ArrayLiteral(al) => visitor.visit_array_literal(al),
+                    Expression::IndexExpression(ie) => visitor.visit_index_expression(ie),
+                    Expression::HashLiteral(hl) => visitor.visit_hash_literal(hl),
+                    Expression::AssignExpression(ae) => visitor.visit_assign_expression(ae),
+                    Expression::MemberExpression(me) => visitor.visit_member_expression(me),
+                    Expression::TryExpression(te) => visitor.visit_try_expression(te),
+                }
+            }
+
+            pub fn walk_prefix_expression<V: $visitor + ?Sized>(visitor: &mut V, pe: &$($mutability)? PrefixExpression) {
+                visitor.visit_expression(&$($mutability)? pe.right);
+            }
+
+            pub fn walk_infix_expression<V: $visitor + ?Sized>(visitor: &mut V, ie: &$($mutability)? InfixExpression) {
+                visitor.visit_expression(&$($mutability)? ie.left);
+                visitor.visit_expression(&$($mutability)? ie.right);
+            }
+
+            pub fn walk_if_expression<V: $visitor + ?Sized>(visitor: &mut V, ie: &$($mutability)? IfExpression) {
+                visitor.visit_expression(&$($mutability)? ie.condition);
+                visitor.visit_block_statement(&$($mutability)? ie.consequence);
+                if let Some(alternative) = &$($mutability)? ie.alternative {
+                    visitor.visit_block_statement(alternative);
+                }
+            }
+
+            pub fn walk_function_literal<V: $visitor + ?Sized>(visitor: &mut V, fl: &$($mutability)? FunctionLiteral) {
+                visitor.visit_block_statement((&$($mutability)? fl.body).get());
+            }
+
+            pub fn walk_macro_literal<V: $visitor + ?Sized>(visitor: &mut V, ml: &$($mutability)? MacroLiteral) {
+                visitor.visit_block_statement(&$($mutability)? ml.body);
+            }
+
+            pub fn walk_call_expression<V: $visitor + ?Sized>(visitor: &mut V, ce: &$($mutability)? CallExpression) {
+                visitor.visit_expression(&$($mutability)? ce.function);
+                for argument in &$($mutability)? ce.arguments {
+                    visitor.visit_expression(argument);
+                }
+            }
+
+            pub fn walk_array_literal<V: $visitor + ?Sized>(visitor: &mut V, al: &$($mutability)? ArrayLiteral) {
+                for element in &$($mutability)? al.elements {
+                    visitor.visit_expression(element);
+                }
+            }
+
+            pub fn walk_index_expression<V: $visitor + ?Sized>(visitor: &mut V, ie: &$($mutability)? IndexExpression) {
+                visitor.visit_expression(&$($mutability)? ie.left);
+                visitor.visit_expression(&$($mutability)? ie.index);
+            }
+
+            pub fn walk_hash_literal<V: $visitor + ?Sized>(visitor: &mut V, hl: &$($mutability)? HashLiteral) {
+                for (key, value) in &$($mutability)? hl.pairs {
+                    visitor.visit_expression(key);
+                    visitor.visit_expression(value);
+                }
+            }
+
+            pub fn walk_assign_expression<V: $visitor + ?Sized>(visitor: &mut V, ae: &$($mutability)? AssignExpression) {
+                visitor.visit_expression(&$($mutability)? ae.target);
+                visitor.visit_expression(&$($mutability)? ae.value);
+            }
+
+            pub fn walk_member_expression<V: $visitor + ?Sized>(visitor: &mut V, me: &$($mutability)? MemberExpression) {
+                visitor.visit_expression(&$($mutability)? me.object);
+            }
+
+            pub fn walk_try_expression<V: $visitor + ?Sized>(visitor: &mut V, te: &$($mutability)? TryExpression) {
+                visitor.visit_block_statement(&$($mutability)? te.body);
+                if let Some(catch) = &$($mutability)? te.catch {
+                    visitor.visit_block_statement(&$($mutability)? catch.body);
+                }
+                if let Some(finally) = &$($mutability)? te.finally {
+                    visitor.visit_block_statement(finally);
+                }
+            }
+        }
+    };
+}
+
+make_visitor!(Visitor, visit,);
+make_visitor!(VisitorMut, visit_mut, mut);
+
+/// A node that can be handed to the `Visitor` method for its type.
+pub trait Visitable {
+    fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V);
+}
+
+macro_rules! impl_visitable {
+    ($($node:ident => $method:ident,)*) => {
+        $(
+            impl Visitable for $node {
+                fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+                    visitor.$method(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_visitable! {
+    Program => visit_program,
+    Statement => visit_statement,
+    LetStatement => visit_let_statement,
+    ReturnStatement => visit_return_statement,
+    ExpressionStatement => visit_expression_statement,
+    WhileStatement => visit_while_statement,
+    ForStatement => visit_for_statement,
+    BreakStatement => visit_break_statement,
+    ContinueStatement => visit_continue_statement,
+    ImportStatement => visit_import_statement,
+    ThrowStatement => visit_throw_statement,
+    BlockStatement => visit_block_statement,
+    Expression => visit_expression,
+    Identifier => visit_identifier,
+    IntegerLiteral => visit_integer_literal,
+    Boolean => visit_boolean,
+    StringLiteral => visit_string_literal,
+    PrefixExpression => visit_prefix_expression,
+    InfixExpression => visit_infix_expression,
+    IfExpression => visit_if_expression,
+    FunctionLiteral => visit_function_literal,
+    MacroLiteral => visit_macro_literal,
+    CallExpression => visit_call_expression,
+    ArrayLiteral => visit_array_literal,
+    IndexExpression => visit_index_expression,
+    HashLiteral => visit_hash_literal,
+    AssignExpression => visit_assign_expression,
+    MemberExpression => visit_member_expression,
+    TryExpression => visit_try_expression,
+}
+
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold::walk_program(self, program)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold::walk_statement(self, statement)
+    }
+
+    fn fold_let_statement(&mut self, ls: LetStatement) -> LetStatement {
+        fold::walk_let_statement(self, ls)
+    }
+
+    fn fold_return_statement(&mut self, rs: ReturnStatement) -> ReturnStatement {
+        fold::walk_return_statement(self, rs)
+    }
+
+    fn fold_expression_statement(&mut self, es: ExpressionStatement) -> ExpressionStatement {
+        fold::walk_expression_statement(self, es)
+    }
+
+    fn fold_while_statement(&mut self, ws: WhileStatement) -> WhileStatement {
+        fold::walk_while_statement(self, ws)
+    }
+
+    fn fold_for_statement(&mut self, fs: ForStatement) -> ForStatement {
+        fold::walk_for_statement(self, fs)
+    }
+
+    fn fold_throw_statement(&mut self, ts: ThrowStatement) -> ThrowStatement {
+        fold::walk_throw_statement(self, ts)
+    }
+
+    fn fold_block_statement(&mut self, block: BlockStatement) -> BlockStatement {
+        fold::walk_block_statement(self, block)
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold::walk_expression(self, expression)
+    }
+
+    fn fold_identifier(&mut self, id: Identifier) -> Expression {
+        Expression::Identifier(id)
+    }
+
+    fn fold_prefix_expression(&mut self, pe: PrefixExpression) -> PrefixExpression {
+        fold::walk_prefix_expression(self, pe)
+    }
+
+    fn fold_infix_expression(&mut self, ie: InfixExpression) -> InfixExpression {
+        fold::walk_infix_expression(self, ie)
+    }
+
+    fn fold_if_expression(&mut self, ie: IfExpression) -> IfExpression {
+        fold::walk_if_expression(self, ie)
+    }
+
+    fn fold_function_literal(&mut self, fl: FunctionLiteral) -> FunctionLiteral {
+        fold::walk_function_literal(self, fl)
+    }
+
+    fn fold_macro_literal(&mut self, ml: MacroLiteral) -> MacroLiteral {
+        fold::walk_macro_literal(self, ml)
+    }
+
+    fn fold_call_expression(&mut self, ce: CallExpression) -> CallExpression {
+        fold::walk_call_expression(self, ce)
+    }
+
+    fn fold_array_literal(&mut self, al: ArrayLiteral) -> ArrayLiteral {
+        fold::walk_array_literal(self, al)
+    }
+
+    fn fold_index_expression(&mut self, ie: IndexExpression) -> IndexExpression {
+        fold::walk_index_expression(self, ie)
+    }
+
+    fn fold_hash_literal(&mut self, hl: HashLiteral) -> HashLiteral {
+        fold::walk_hash_literal(self, hl)
+    }
+
+    fn fold_assign_expression(&mut self, ae: AssignExpression) -> AssignExpression {
+        fold::walk_assign_expression(self, ae)
+    }
+
+    fn fold_member_expression(&mut self, me: MemberExpression) -> MemberExpression {
+        fold::walk_member_expression(self, me)
+    }
+
+    fn fold_try_expression(&mut self, te: TryExpression) -> TryExpression {
+        fold::walk_try_expression(self, te)
+    }
+}
+
+pub mod fold {
+    use std::rc::Rc;
+
+    use super::Fold;
+    use crate::ast::*;
+    use crate::tail_calls::function_body;
+
+    pub fn walk_program<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+        Program {
+            statements: program
+                .statements
+                .into_iter()
+                .map(|s| folder.fold_statement(s))
+                .collect(),
+        }
+    }
+
+    pub fn walk_statement<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+        match statement {
+            Statement::LetStatement(ls) => Statement::LetStatement(folder.fold_let_statement(ls)),
+            Statement::ReturnStatement(rs) => {
+                Statement::ReturnStatement(folder.fold_return_statement(rs))
+            }
+            Statement::ExpressionStatement(es) => {
+                Statement::ExpressionStatement(folder.fold_expression_statement(es))
+            }
+            Statement::WhileStatement(ws) => {
+                Statement::WhileStatement(folder.fold_while_statement(ws))
+            }
+            Statement::ForStatement(fs) => Statement::ForStatement(folder.fold_for_statement(fs)),
+            Statement::ThrowStatement(ts) => {
+                Statement::ThrowStatement(folder.fold_throw_statement(ts))
+            }
+            Statement::BreakStatement(_)
+            | Statement::ContinueStatement(_)
+            | Statement::ImportStatement(_) => statement,
+        }
+    }
+
+    pub fn walk_let_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ls: LetStatement,
+    ) -> LetStatement {
+        ls.value = ls.value.map(|v| folder.fold_expression(v));
+        ls
+    }
+
+    pub fn walk_return_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut rs: ReturnStatement,
+    ) -> ReturnStatement {
+        rs.return_value = rs
+            .return_value
+            .map(|rv| Box::new(folder.fold_expression(*rv)));
+        rs
+    }
+
+    pub fn walk_expression_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut es: ExpressionStatement,
+    ) -> ExpressionStatement {
+        es.expression = es.expression.map(|e| folder.fold_expression(e));
+        es
+    }
+
+    pub fn walk_while_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ws: WhileStatement,
+    ) -> WhileStatement {
+        ws.condition = folder.fold_expression(ws.condition);
+        ws.body = folder.fold_block_statement(ws.body);
+        ws
+    }
+
+    pub fn walk_for_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut fs: ForStatement,
+    ) -> ForStatement {
+        fs.iterable = folder.fold_expression(fs.iterable);
+        fs.body = folder.fold_block_statement(fs.body);
+        fs
+    }
+
+    pub fn walk_throw_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ts: ThrowStatement,
+    ) -> ThrowStatement {
+        ts.value = folder.fold_expression(ts.value);
+        ts
+    }
+
+    pub fn walk_block_statement<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut block: BlockStatement,
+    ) -> BlockStatement {
+        block.statements = block
+            .statements
+            .into_iter()
+            .map(|s| folder.fold_statement(s))
+            .collect();
+        block
+    }
+
+    pub fn walk_expression<F: Fold + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+        match expression {
+            Expression::Identifier(id) => folder.fold_identifier(id),
+            Expression::IntegerLiteral(_)
+            | Expression::Boolean(_)
+            | Expression::StringLiteral(_) => expression,
+            Expression::PrefixExpression(pe) => {
+                Expression::PrefixExpression(folder.fold_prefix_expression(pe))
+            }
+            Expression::InfixExpression(ie) => {
+                Expression::InfixExpression(folder.fold_infix_expression(ie))
+            }
+            Expression::IfExpression(ie) => Expression::IfExpression(folder.fold_if_expression(ie)),
+            Expression::FunctionLiteral(fl) => {
+                Expression::FunctionLiteral(folder.fold_function_literal(fl))
+            }
+            Expression::MacroLiteral(ml) => Expression::MacroLiteral(folder.fold_macro_literal(ml)),
+            Expression::CallExpression(ce) => {
+                Expression::CallExpression(folder.fold_call_expression(ce))
+            }
+            Expression::ArrayLiteral(al) => Expression::ArrayLiteral(folder.fold_array_literal(al)),
+            Expression::IndexExpression(ie) => {
+                Expression::IndexExpression(folder.fold_index_expression(ie))
+            }
+            Expression::HashLiteral(hl) => Expression::HashLiteral(folder.fold_hash_literal(hl)),
+            Expression::AssignExpression(ae) => {
+                Expression::AssignExpression(folder.fold_assign_expression(ae))
+            }
+            Expression::MemberExpression(me) => {
+                Expression::MemberExpression(folder.fold_member_expression(me))
+            }
+            Expression::TryExpression(te) => {
+                Expression::TryExpression(folder.fold_try_expression(te))
+            }
+        }
+    }
+
+    pub fn walk_prefix_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut pe: PrefixExpression,
+    ) -> PrefixExpression {
+        pe.right = Box::new(folder.fold_expression(*pe.right));
+        pe
+    }
+
+    pub fn walk_infix_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ie: InfixExpression,
+    ) -> InfixExpression {
+        ie.left = Box::new(folder.fold_expression(*ie.left));
+        ie.right = Box::new(folder.fold_expression(*ie.right));
+        ie
+    }
+
+    pub fn walk_if_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ie: IfExpression,
+    ) -> IfExpression {
+        ie.condition = Box::new(folder.fold_expression(*ie.condition));
+        ie.consequence = folder.fold_block_statement(ie.consequence);
+        ie.alternative = ie.alternative.map(|alt| folder.fold_block_statement(alt));
+        ie
+    }
+
+    pub fn walk_function_literal<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut fl: FunctionLiteral,
+    ) -> FunctionLiteral {
+        let body = folder.fold_block_statement(Rc::unwrap_or_clone(fl.body));
+        fl.body = function_body(body);
+        fl
+    }
+
+    pub fn walk_macro_literal<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ml: MacroLiteral,
+    ) -> MacroLiteral {
+        ml.body = folder.fold_block_statement(ml.body);
+        ml
+    }
+
+    pub fn walk_call_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ce: CallExpression,
+    ) -> CallExpression {
+        ce.function = Box::new(folder.fold_expression(*ce.function));
+        ce.arguments = ce
+            .arguments
+            .into_iter()
+            .map(|a| folder.fold_expression(a))
+            .collect();
+        ce
+    }
+
+    pub fn walk_array_literal<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut al: ArrayLiteral,
+    ) -> ArrayLiteral {
+        al.elements = al
+            .elements
+            .into_iter()
+            .map(|e| folder.fold_expression(e))
+            .collect();
+        al
+    }
+
+    pub fn walk_index_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ie: IndexExpression,
+    ) -> IndexExpression {
+        ie.left = Box::new(folder.fold_expression(*ie.left));
+        ie.index = Box::new(folder.fold_expression(*ie.index));
+        ie
+    }
+
+    pub fn walk_hash_literal<F: Fold + ?Sized>(folder: &mut F, mut hl: HashLiteral) -> HashLiteral {
+        hl.pairs = hl
+            .pairs
+            .into_iter()
+            .map(|(k, v)| {
+                let k = folder.fold_expression(k);
+                (k, folder.fold_expression(v))
+            })
+            .collect();
+        hl
+    }
+
+    pub fn walk_assign_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut ae: AssignExpression,
+    ) -> AssignExpression {
+        ae.target = Box::new(folder.fold_expression(*ae.target));
+        ae.value = Box::new(folder.fold_expression(*ae.value));
+        ae
+    }
+
+    pub fn walk_member_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut me: MemberExpression,
+    ) -> MemberExpression {
+        me.object = Box::new(folder.fold_expression(*me.object));
+        me
+    }
+
+    pub fn walk_try_expression<F: Fold + ?Sized>(
+        folder: &mut F,
+        mut te: TryExpression,
+    ) -> TryExpression {
+        te.body = folder.fold_block_statement(te.body);
+        te.catch = te.catch.map(|mut catch| {
+            catch.body = folder.fold_block_statement(catch.body);
+            catch
+        });
+        te.finally = te
+            .finally
+            .map(|finally| folder.fold_block_statement(finally));
+        te
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fold, visit, visit_mut, Fold, Visitor, VisitorMut};
+    use crate::ast::*;
+    use crate::bigint::BigInt;
+
+    use crate::parser::parse_ok;
+
+    // touches every statement and expression variant
+    const EVERYTHING: &str = r#"
+import "lib.monkey" as lib;
+export let a = -1 + 2 * lib.three;
+const b = [3, {4: "five"}][5][6];
+let f = fn(x) { if (x > 7) { return x; } else { x = 8; } };
+let m = macro(y) { quote(unquote(y) + 9) };
+while (a < 10) { a += 11; if (a == 12) { break; } continue; }
+for (item in [13]) { f(item); }
+try { throw 14; } catch (e) { e } finally { 15 };
+"#;
+
+    #[derive(Default)]
+    struct Collector {
+        identifiers: Vec<String>,
+        integers: Vec<i64>,
+        statements: usize,
+    }
+
+    impl Visitor for Collector {
+        fn visit_statement(&mut self, statement: &Statement) {
+            self.statements += 1;
+            visit::walk_statement(self, statement);
+        }
+
+        fn visit_identifier(&mut self, id: &Identifier) {
+            self.identifiers.push(id.value.clone());
+        }
+
+        fn visit_integer_literal(&mut self, il: &IntegerLiteral) {
+            self.integers.push(il.value.to_i64().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_every_node() {
+        let mut collector = Collector::default();
+        collector.visit_program(&parse_ok(EVERYTHING));
+
+        assert_eq!(collector.integers, (1..=15).collect::<Vec<i64>>());
+        assert_eq!(
+            collector.identifiers,
+            vec!["lib", "x", "x", "x", "quote", "unquote", "y", "a", "a", "a", "f", "item", "e"]
+        );
+        assert_eq!(collector.statements, 20);
+    }
+
+    #[test]
+    fn test_visitor_skips_children_when_not_walking() {
+        struct TopLevelCalls(usize);
+
+        impl Visitor for TopLevelCalls {
+            fn visit_function_literal(&mut self, _fl: &FunctionLiteral) {}
+
+            fn visit_call_expression(&mut self, ce: &CallExpression) {
+                self.0 += 1;
+                visit::walk_call_expression(self, ce);
+            }
+        }
+
+        let mut calls = TopLevelCalls(0);
+        calls.visit_program(&parse_ok("f(g(1)); let h = fn() { f(2) };"));
+
+        assert_eq!(calls.0, 2);
+    }
+
+    #[test]
+    fn test_visitor_mut_rewrites_in_place() {
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            fn visit_identifier(&mut self, id: &mut Identifier) {
+                id.value = id.value.to_uppercase();
+                id.token.literal = id.value.clone();
+            }
+
+            fn visit_integer_literal(&mut self, il: &mut IntegerLiteral) {
+                il.value = &il.value * &BigInt::from(10);
+                il.token.literal = il.value.to_string();
+            }
+
+            fn visit_let_statement(&mut self, ls: &mut LetStatement) {
+                ls.name.value = ls.name.value.to_uppercase();
+                visit_mut::walk_let_statement(self, ls);
+            }
+        }
+
+        let mut program = parse_ok("let x = y + 1; while (x) { x[2] = z; }");
+        Rename.visit_program(&mut program);
+
+        assert_eq!(
+            program.to_string(),
+            "let X = Y + 10;while (X) { X[20] = Z }"
+        );
+    }
+
+    #[test]
+    fn test_fold_rebuilds_tree() {
+        // replaces `x + 0` with `x` and identifiers bound in `constants`
+        // with their value
+        struct Simplify;
+
+        impl Fold for Simplify {
+            fn fold_expression(&mut self, expression: Expression) -> Expression {
+                match fold::walk_expression(self, expression) {
+                    Expression::InfixExpression(ie)
+                        if ie.operator == "+" && ie.right.to_string() == "0" =>
+                    {
+                        *ie.left
+                    }
+                    expression => expression,
+                }
+            }
+
+            fn fold_identifier(&mut self, id: Identifier) -> Expression {
+                match id.value.as_str() {
+                    "zero" => parse_ok("0").statements[0].clone().into_expression(),
+                    _ => Expression::Identifier(id),
+                }
+            }
+        }
+
+        let program = Simplify.fold_program(parse_ok(
+            "let a = b + zero; fn(x) { [x + 0 + 0, f(y + zero)] }; for (i in c + 0) { i }",
+        ));
+
+        assert_eq!(
+            program.to_string(),
+            "let a = b;fn(x) { [x, f(y)] }for (i in c) { i }"
+        );
+    }
+
+    trait IntoExpression {
+        fn into_expression(self) -> Expression;
+    }
+
+    impl IntoExpression for Statement {
+        fn into_expression(self) -> Expression {
+            match self {
+                Statement::ExpressionStatement(es) => es.expression.unwrap(),
+                stmt => panic!("statement is not ExpressionStatement, got {:?}", stmt),
+            }
+        }
+    }
+}
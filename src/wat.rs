@@ -0,0 +1,974 @@
+//! A backend compiling Monkey programs to WebAssembly in its text format,
+//! run by `compile --target wat`.
+//!
+//! It covers integers, booleans, functions, conditionals and calls, along
+//! with `let`, assignments, `return` and `while` loops. Every value is an
+//! `i64`: integers stand for themselves, booleans are 0 or 1, `null` is 0 and
+//! a function is its index in the table of the module. Only top-level `let`
+//! bindings may be functions; they use their parameters, their own `let`
+//! bindings and the top-level ones, which become globals.
+//!
+//! Since `0` is true in Monkey, how a condition is tested depends on its
+//! type, so a program must pass the checks of the `types` module, and a
+//! condition of a type those leave open, such as a parameter used only as
+//! one, must be annotated. Integers are 64 bits wide: arithmetic that would
+//! grow beyond them, as it does in the evaluator, traps: by `unreachable`,
+//! or by the integer overflow of `i64.div_s` when dividing the minimum by
+//! -1. Dividing by zero traps too, and shift amounts are taken modulo 64.
+//!
+//! The module exports each top-level function under its name, and `main`,
+//! which runs the other top-level statements and returns the value of the
+//! program.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::ast::*;
+use crate::backend::Declarations;
+use crate::builtins::lookup_builtin;
+use crate::token::Span;
+use crate::types::*;
+use crate::visitor::Visitor;
+
+// the column up to which code stays on one line
+const WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.start.line, self.span.start.column, self.message
+        )
+    }
+}
+
+impl From<TypeError> for CompileError {
+    fn from(error: TypeError) -> CompileError {
+        CompileError {
+            span: error.span,
+            message: error.message,
+        }
+    }
+}
+
+/// Compiles `program` to a WAT module, or returns every error found.
+pub fn compile(program: &Program) -> Result<String, Vec<CompileError>> {
+    let types = infer_types(program).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(CompileError::from)
+            .collect::<Vec<_>>()
+    })?;
+
+    let mut compiler = Compiler::new(&types);
+    let module = compiler.module(program);
+    if !compiler.errors.is_empty() {
+        compiler.errors.sort_by_key(|error| error.span.start);
+        return Err(compiler.errors);
+    }
+
+    let mut out = String::from("(module");
+    for field in module {
+        out += "\n  ";
+        field.write(&mut out, 2);
+    }
+    Ok(out + ")\n")
+}
+
+/// WAT code in the folded form, written on one line where it fits.
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn flat(&self) -> String {
+        match self {
+            Sexp::Atom(atom) => atom.clone(),
+            Sexp::List(items) => {
+                let items: Vec<String> = items.iter().map(Sexp::flat).collect();
+                format!("({})", items.join(" "))
+            }
+        }
+    }
+
+    // the leading atoms of a list stay on its first line, and every other
+    // item goes on a line of its own
+    fn write(&self, out: &mut String, indent: usize) {
+        let flat = self.flat();
+        match self {
+            Sexp::List(items) if indent + flat.len() > WIDTH => {
+                let head = items
+                    .iter()
+                    .take_while(|item| matches!(item, Sexp::Atom(_)))
+                    .count();
+                let head: Vec<String> = items[..head].iter().map(Sexp::flat).collect();
+                out.push('(');
+                out.push_str(&head.join(" "));
+                for item in &items[head.len()..] {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent + 2));
+                    item.write(out, indent + 2);
+                }
+                out.push(')');
+            }
+            _ => out.push_str(&flat),
+        }
+    }
+}
+
+fn atom(atom: impl Into<String>) -> Sexp {
+    Sexp::Atom(atom.into())
+}
+
+fn op(name: impl Into<String>, operands: Vec<Sexp>) -> Sexp {
+    let mut items = vec![atom(name)];
+    items.extend(operands);
+    Sexp::List(items)
+}
+
+fn i64_const(value: i64) -> Sexp {
+    op("i64.const", vec![atom(value.to_string())])
+}
+
+fn comparison(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "<" => "i64.lt_s",
+        ">" => "i64.gt_s",
+        "<=" => "i64.le_s",
+        ">=" => "i64.ge_s",
+        "==" => "i64.eq",
+        "!=" => "i64.ne",
+        _ => return None,
+    })
+}
+
+fn arithmetic(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "+" => "i64.add",
+        "-" => "i64.sub",
+        "*" => "i64.mul",
+        "/" => "i64.div_s",
+        "%" => "i64.rem_s",
+        "&" => "i64.and",
+        "|" => "i64.or",
+        "^" => "i64.xor",
+        "<<" => "i64.shl",
+        ">>" => "i64.shr_s",
+        _ => return None,
+    })
+}
+
+/// The functions of the module checking arithmetic for overflow, by the
+/// operators they apply. Their names cannot clash with those of a program.
+fn checked(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "+" => "$rt.add",
+        "-" => "$rt.sub",
+        "*" => "$rt.mul",
+        "<<" => "$rt.shl",
+        _ => return None,
+    })
+}
+
+/// The function named `name` by `checked`, which applies its operator to
+/// `$a` and `$b` and traps when the result does not fit.
+fn checked_function(name: &str) -> Sexp {
+    let get = |local: &str| op("local.get", vec![atom(local)]);
+    let zero = || i64_const(0);
+    let (result, overflowed) = match name {
+        // the sign of the result differs from the ones of both operands
+        "$rt.add" => (
+            op("i64.add", vec![get("$a"), get("$b")]),
+            op(
+                "i64.lt_s",
+                vec![
+                    op(
+                        "i64.and",
+                        vec![
+                            op("i64.xor", vec![get("$a"), get("$r")]),
+                            op("i64.xor", vec![get("$b"), get("$r")]),
+                        ],
+                    ),
+                    zero(),
+                ],
+            ),
+        ),
+        // the operands differ in sign and the result has the one of `$b`
+        "$rt.sub" => (
+            op("i64.sub", vec![get("$a"), get("$b")]),
+            op(
+                "i64.lt_s",
+                vec![
+                    op(
+                        "i64.and",
+                        vec![
+                            op("i64.xor", vec![get("$a"), get("$b")]),
+                            op("i64.xor", vec![get("$a"), get("$r")]),
+                        ],
+                    ),
+                    zero(),
+                ],
+            ),
+        ),
+        // dividing the product by `$a` does not give `$b` back, unless `$a`
+        // is 0, or -1, which the division traps on for the minimum and which
+        // overflows only times the minimum
+        "$rt.mul" => (
+            op("i64.mul", vec![get("$a"), get("$b")]),
+            op(
+                "if",
+                vec![
+                    atom("(result i32)"),
+                    op("i64.eqz", vec![get("$a")]),
+                    op("then", vec![op("i32.const", vec![atom("0")])]),
+                    op(
+                        "else",
+                        vec![op(
+                            "if",
+                            vec![
+                                atom("(result i32)"),
+                                op("i64.eq", vec![get("$a"), i64_const(-1)]),
+                                op(
+                                    "then",
+                                    vec![op("i64.eq", vec![get("$b"), i64_const(i64::MIN)])],
+                                ),
+                                op(
+                                    "else",
+                                    vec![op(
+                                        "i64.ne",
+                                        vec![
+                                            op("i64.div_s", vec![get("$r"), get("$a")]),
+                                            get("$b"),
+                                        ],
+                                    )],
+                                ),
+                            ],
+                        )],
+                    ),
+                ],
+            ),
+        ),
+        // shifting back does not give `$a` back
+        _ => (
+            op("i64.shl", vec![get("$a"), get("$b")]),
+            op(
+                "i64.ne",
+                vec![op("i64.shr_s", vec![get("$r"), get("$b")]), get("$a")],
+            ),
+        ),
+    };
+
+    op(
+        format!(
+            "func {} (param $a i64) (param $b i64) (result i64) (local $r i64)",
+            name
+        ),
+        vec![
+            op("local.set", vec![atom("$r"), result]),
+            op(
+                "if",
+                vec![overflowed, op("then", vec![atom("unreachable")])],
+            ),
+            get("$r"),
+        ],
+    )
+}
+
+struct Compiler<'a> {
+    types: &'a Types,
+    // the top-level functions, in the order of the table
+    functions: Vec<String>,
+    globals: Vec<String>,
+    // the parameters and `let` bindings of the function being compiled
+    locals: Vec<String>,
+    // the labels of the loops being compiled, innermost last
+    loops: Vec<usize>,
+    labels: usize,
+    // the numbers of arguments of calls through the table
+    signatures: BTreeSet<usize>,
+    // the functions checking arithmetic that are called, see `checked`
+    checks: BTreeSet<&'static str>,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(types: &'a Types) -> Compiler<'a> {
+        Compiler {
+            types,
+            functions: Vec::new(),
+            globals: Vec::new(),
+            locals: Vec::new(),
+            loops: Vec::new(),
+            labels: 0,
+            signatures: BTreeSet::new(),
+            checks: BTreeSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) -> Sexp {
+        self.errors.push(CompileError {
+            span,
+            message: message.into(),
+        });
+        atom("unreachable")
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str) -> Sexp {
+        let message = format!("{} not supported by the wat target", what);
+        self.error(span, message)
+    }
+
+    /// The fields of the module compiled from `program`.
+    fn module(&mut self, program: &Program) -> Vec<Sexp> {
+        let mut literals = Vec::new();
+        for statement in &program.statements {
+            if let Statement::LetStatement(LetStatement {
+                name,
+                value: Some(Expression::FunctionLiteral(fl)),
+                ..
+            }) = statement
+            {
+                if name.value == "main" {
+                    self.error(name.span, "main is reserved for the program");
+                } else if self.functions.contains(&name.value) {
+                    let message = format!("function {} is defined twice", name.value);
+                    self.error(name.span, message);
+                } else {
+                    self.functions.push(name.value.clone());
+                    literals.push((name, fl));
+                }
+            }
+        }
+
+        let mut declarations = Declarations::default();
+        declarations.visit_program(program);
+        for name in declarations.names {
+            if !self.functions.contains(&name) && !self.globals.contains(&name) {
+                self.globals.push(name);
+            }
+        }
+
+        let mut functions: Vec<Sexp> = literals
+            .into_iter()
+            .map(|(name, fl)| self.function(name, fl))
+            .collect();
+
+        self.locals = Vec::new();
+        let mut main = vec![atom("func $main (export \"main\") (result i64)")];
+        for (i, statement) in program.statements.iter().enumerate() {
+            let value = i == program.statements.len() - 1;
+            match statement {
+                // compiled as functions of the module
+                Statement::LetStatement(LetStatement {
+                    value: Some(Expression::FunctionLiteral(_)),
+                    ..
+                }) if value => main.push(i64_const(0)),
+                Statement::LetStatement(LetStatement {
+                    value: Some(Expression::FunctionLiteral(_)),
+                    ..
+                }) => {}
+                statement => main.extend(self.statement(statement, value)),
+            }
+        }
+        if program.statements.is_empty() {
+            main.push(i64_const(0));
+        }
+        functions.push(Sexp::List(main));
+
+        let mut fields: Vec<Sexp> = self
+            .signatures
+            .iter()
+            .map(|arguments| {
+                let params = " (param i64)".repeat(*arguments);
+                atom(format!(
+                    "(type $fn{} (func{} (result i64)))",
+                    arguments, params
+                ))
+            })
+            .collect();
+        fields.push(atom(format!(
+            "(table $functions {} funcref)",
+            self.functions.len()
+        )));
+        if !self.functions.is_empty() {
+            let names: Vec<String> = self.functions.iter().map(|f| format!("${}", f)).collect();
+            fields.push(atom(format!("(elem (i32.const 0) {})", names.join(" "))));
+        }
+        for global in &self.globals {
+            fields.push(atom(format!(
+                "(global ${} (mut i64) (i64.const 0))",
+                global
+            )));
+        }
+        fields.extend(functions);
+        fields.extend(self.checks.iter().map(|name| checked_function(name)));
+        fields
+    }
+
+    /// `left operator right` for an arithmetic `operator`, checked for
+    /// overflow where it can grow beyond 64 bits.
+    fn arithmetic(&mut self, operator: &str, left: Sexp, right: Sexp) -> Option<Sexp> {
+        if let Some(name) = checked(operator) {
+            self.checks.insert(name);
+            return Some(op("call", vec![atom(name), left, right]));
+        }
+        arithmetic(operator).map(|instruction| op(instruction, vec![left, right]))
+    }
+
+    fn function(&mut self, name: &Identifier, fl: &FunctionLiteral) -> Sexp {
+        let mut header = format!("func ${} (export \"{}\")", name.value, name.value);
+        self.locals = Vec::new();
+        for p in &fl.parameters {
+            header += &format!(" (param ${} i64)", p.value);
+            self.locals.push(p.value.clone());
+        }
+        header += " (result i64)";
+
+        let mut declarations = Declarations::default();
+        declarations.visit_block_statement(&fl.body);
+        for name in declarations.names {
+            if !self.locals.contains(&name) {
+                header += &format!(" (local ${} i64)", name);
+                self.locals.push(name);
+            }
+        }
+
+        let mut function = vec![atom(header)];
+        function.extend(self.block(&fl.body.statements, true));
+        Sexp::List(function)
+    }
+
+    /// The code of `statements`, leaving the value of the last one when
+    /// `value` is set, or nothing otherwise.
+    fn block(&mut self, statements: &[Statement], value: bool) -> Vec<Sexp> {
+        let mut code = Vec::new();
+        for (i, statement) in statements.iter().enumerate() {
+            code.extend(self.statement(statement, value && i == statements.len() - 1));
+        }
+        if value && statements.is_empty() {
+            code.push(i64_const(0));
+        }
+        code
+    }
+
+    fn statement(&mut self, statement: &Statement, value: bool) -> Vec<Sexp> {
+        let mut code = match statement {
+            Statement::LetStatement(ls) => {
+                let value = match &ls.value {
+                    Some(value) => self.expression(value),
+                    None => i64_const(0),
+                };
+                vec![self.set(&ls.name, value)]
+            }
+            Statement::ReturnStatement(rs) => {
+                let value = match &rs.return_value {
+                    Some(value) => self.expression(value),
+                    None => i64_const(0),
+                };
+                // nothing after a return runs, so it leaves any value
+                return vec![op("return", vec![value])];
+            }
+            Statement::ExpressionStatement(es) => match &es.expression {
+                Some(e) if value => return vec![self.expression(e)],
+                Some(e) => vec![op("drop", vec![self.expression(e)])],
+                None => vec![],
+            },
+            Statement::WhileStatement(ws) => vec![self.while_statement(ws)],
+            Statement::BreakStatement(BreakStatement { span, .. })
+            | Statement::ContinueStatement(ContinueStatement { span, .. }) => {
+                let (keyword, label) = match statement {
+                    Statement::BreakStatement(_) => ("break", "break"),
+                    _ => ("continue", "continue"),
+                };
+                return match self.loops.last() {
+                    Some(l) => vec![op("br", vec![atom(format!("${}{}", label, l))])],
+                    None => vec![self.error(*span, format!("'{}' outside of a loop", keyword))],
+                };
+            }
+            Statement::ForStatement(fs) => vec![self.unsupported(fs.span, "for loops are")],
+            Statement::ImportStatement(is) => vec![self.unsupported(is.span, "imports are")],
+            Statement::ThrowStatement(ts) => vec![self.unsupported(ts.span, "exceptions are")],
+        };
+
+        if value {
+            code.push(i64_const(0));
+        }
+        code
+    }
+
+    fn while_statement(&mut self, ws: &WhileStatement) -> Sexp {
+        let label = self.labels;
+        self.labels += 1;
+
+        let condition = self.condition(&ws.condition);
+        let mut body = vec![
+            atom(format!("$continue{}", label)),
+            op(
+                "br_if",
+                vec![
+                    atom(format!("$break{}", label)),
+                    op("i32.eqz", vec![condition]),
+                ],
+            ),
+        ];
+        self.loops.push(label);
+        body.extend(self.block(&ws.body.statements, false));
+        self.loops.pop();
+        body.push(op("br", vec![atom(format!("$continue{}", label))]));
+
+        op(
+            "block",
+            vec![atom(format!("$break{}", label)), op("loop", body)],
+        )
+    }
+
+    /// Stores `value` in the variable `name`.
+    fn set(&mut self, name: &Identifier, value: Sexp) -> Sexp {
+        let variable = atom(format!("${}", name.value));
+        if self.locals.contains(&name.value) {
+            op("local.set", vec![variable, value])
+        } else if self.globals.contains(&name.value) {
+            op("global.set", vec![variable, value])
+        } else {
+            let message = format!("cannot assign to function {}", name.value);
+            self.error(name.span, message)
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Sexp {
+        match expression {
+            Expression::Identifier(id) => self.identifier(id),
+            Expression::IntegerLiteral(il) => match il.value.to_i64() {
+                Some(i) => i64_const(i),
+                None => self.unsupported(il.span, "integers beyond 64 bits are"),
+            },
+            Expression::Boolean(b) => i64_const(b.value as i64),
+            Expression::PrefixExpression(pe) => match pe.operator.as_str() {
+                "-" => {
+                    let right = self.expression(&pe.right);
+                    self.arithmetic("-", i64_const(0), right).unwrap()
+                }
+                _ => op(
+                    "i64.extend_i32_u",
+                    vec![op("i32.eqz", vec![self.condition(&pe.right)])],
+                ),
+            },
+            Expression::InfixExpression(ie) => self.infix_expression(ie),
+            Expression::IfExpression(ie) => {
+                let condition = self.condition(&ie.condition);
+                let consequence = self.block(&ie.consequence.statements, true);
+                let alternative = match &ie.alternative {
+                    Some(alternative) => self.block(&alternative.statements, true),
+                    None => vec![i64_const(0)],
+                };
+                op(
+                    "if",
+                    vec![
+                        atom("(result i64)"),
+                        condition,
+                        op("then", consequence),
+                        op("else", alternative),
+                    ],
+                )
+            }
+            Expression::CallExpression(ce) => self.call(ce),
+            Expression::AssignExpression(ae) => self.assign_expression(ae),
+            Expression::FunctionLiteral(fl) => self.error(
+                fl.span,
+                "functions are only supported as top-level let bindings by the wat target",
+            ),
+            Expression::StringLiteral(sl) => self.unsupported(sl.span, "strings are"),
+            Expression::ArrayLiteral(al) => self.unsupported(al.span, "arrays are"),
+            Expression::HashLiteral(hl) => self.unsupported(hl.span, "hashes are"),
+            Expression::IndexExpression(ie) => self.unsupported(ie.span, "index expressions are"),
+            Expression::MacroLiteral(ml) => self.unsupported(ml.span, "macros are"),
+            Expression::MemberExpression(me) => self.unsupported(me.span, "modules are"),
+            Expression::TryExpression(te) => self.unsupported(te.span, "exceptions are"),
+        }
+    }
+
+    fn identifier(&mut self, id: &Identifier) -> Sexp {
+        let variable = atom(format!("${}", id.value));
+        if self.locals.contains(&id.value) {
+            op("local.get", vec![variable])
+        } else if let Some(index) = self.functions.iter().position(|f| *f == id.value) {
+            i64_const(index as i64)
+        } else if self.globals.contains(&id.value) {
+            op("global.get", vec![variable])
+        } else if lookup_builtin(&id.value).is_some() {
+            let what = format!("builtin function {} is", id.value);
+            self.unsupported(id.span, &what)
+        } else {
+            self.error(id.span, format!("identifier not found: {}", id.value))
+        }
+    }
+
+    fn infix_expression(&mut self, ie: &InfixExpression) -> Sexp {
+        let operator = ie.operator.as_str();
+        if operator == "&&" || operator == "||" {
+            let left = self.condition(&ie.left);
+            let right = op("i64.extend_i32_u", vec![self.condition(&ie.right)]);
+            let (then, otherwise) = match operator {
+                "&&" => (right, i64_const(0)),
+                _ => (i64_const(1), right),
+            };
+            return op(
+                "if",
+                vec![
+                    atom("(result i64)"),
+                    left,
+                    op("then", vec![then]),
+                    op("else", vec![otherwise]),
+                ],
+            );
+        }
+
+        let left = self.expression(&ie.left);
+        let right = self.expression(&ie.right);
+        if let Some(instruction) = comparison(operator) {
+            op("i64.extend_i32_u", vec![op(instruction, vec![left, right])])
+        } else if let Some(operation) = self.arithmetic(operator, left, right) {
+            operation
+        } else {
+            self.error(ie.span, format!("unknown operator: {}", operator))
+        }
+    }
+
+    /// `expression` as an `i32` that is 1 when it is true, that is neither
+    /// `false` nor `null`.
+    fn condition(&mut self, expression: &Expression) -> Sexp {
+        let truth = match self.types.of(expression) {
+            Type::Bool => None,
+            Type::Null => Some(0),
+            Type::Var(_) => {
+                return self.error(
+                    expression.span(),
+                    "the type of this condition is unknown, annotate it",
+                )
+            }
+            _ => Some(1),
+        };
+
+        match (truth, expression) {
+            (Some(truth), Expression::Identifier(_) | Expression::IntegerLiteral(_)) => {
+                op("i32.const", vec![atom(truth.to_string())])
+            }
+            (Some(truth), _) => op(
+                "block",
+                vec![
+                    atom("(result i32)"),
+                    op("drop", vec![self.expression(expression)]),
+                    op("i32.const", vec![atom(truth.to_string())]),
+                ],
+            ),
+            (None, Expression::Boolean(b)) => {
+                op("i32.const", vec![atom((b.value as i32).to_string())])
+            }
+            (None, Expression::PrefixExpression(pe)) if pe.operator == "!" => {
+                op("i32.eqz", vec![self.condition(&pe.right)])
+            }
+            (None, Expression::InfixExpression(ie)) if comparison(&ie.operator).is_some() => {
+                let left = self.expression(&ie.left);
+                let right = self.expression(&ie.right);
+                op(comparison(&ie.operator).unwrap(), vec![left, right])
+            }
+            (None, _) => op("i32.wrap_i64", vec![self.expression(expression)]),
+        }
+    }
+
+    fn call(&mut self, ce: &CallExpression) -> Sexp {
+        let mut operands: Vec<Sexp> = ce.arguments.iter().map(|a| self.expression(a)).collect();
+
+        if let Expression::Identifier(id) = ce.function.as_ref() {
+            if !self.locals.contains(&id.value) && self.functions.contains(&id.value) {
+                operands.insert(0, atom(format!("${}", id.value)));
+                return op("call", operands);
+            }
+        }
+
+        // any other function is an index into the table
+        self.signatures.insert(ce.arguments.len());
+        let function = self.expression(&ce.function);
+        operands.insert(0, atom(format!("(type $fn{})", ce.arguments.len())));
+        operands.push(op("i32.wrap_i64", vec![function]));
+        op("call_indirect", operands)
+    }
+
+    fn assign_expression(&mut self, ae: &AssignExpression) -> Sexp {
+        let id = match ae.target.as_ref() {
+            Expression::Identifier(id) => id,
+            target => return self.unsupported(target.span(), "assignments to elements are"),
+        };
+
+        let mut value = self.expression(&ae.value);
+        if let Some(operator) = ae.operator.strip_suffix('=').filter(|o| !o.is_empty()) {
+            let current = self.identifier(id);
+            match self.arithmetic(operator, current, value) {
+                Some(operation) => value = operation,
+                None => return self.error(ae.span, format!("unknown operator: {}", ae.operator)),
+            }
+        }
+
+        // the value of an assignment is the value assigned
+        let variable = atom(format!("${}", id.value));
+        if self.locals.contains(&id.value) {
+            op("local.tee", vec![variable, value])
+        } else if self.globals.contains(&id.value) {
+            op(
+                "block",
+                vec![
+                    atom("(result i64)"),
+                    op("global.set", vec![variable, value]),
+                    op("global.get", vec![atom(format!("${}", id.value))]),
+                ],
+            )
+        } else {
+            self.set(id, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::evaluator::Evaluator;
+
+    use crate::object::Object;
+    use crate::parser::parse_ok;
+    use crate::wat_vm;
+
+    #[test]
+    fn test_compiled_module() {
+        let input = "
+let double = fn(x) { x * 2 };
+let n = 3;
+if (n > 2) { double(n) } else { 0 }
+";
+        let expected = r#"(module
+  (table $functions 1 funcref)
+  (elem (i32.const 0) $double)
+  (global $n (mut i64) (i64.const 0))
+  (func $double (export "double") (param $x i64) (result i64)
+    (call $rt.mul (local.get $x) (i64.const 2)))
+  (func $main (export "main") (result i64)
+    (global.set $n (i64.const 3))
+    (if (result i64)
+      (i64.gt_s (global.get $n) (i64.const 2))
+      (then (call $double (global.get $n)))
+      (else (i64.const 0))))
+  (func $rt.mul (param $a i64) (param $b i64) (result i64) (local $r i64)
+    (local.set $r (i64.mul (local.get $a) (local.get $b)))
+    (if
+      (if (result i32)
+        (i64.eqz (local.get $a))
+        (then (i32.const 0))
+        (else
+          (if (result i32)
+            (i64.eq (local.get $a) (i64.const -1))
+            (then (i64.eq (local.get $b) (i64.const -9223372036854775808)))
+            (else
+              (i64.ne (i64.div_s (local.get $r) (local.get $a)) (local.get $b))))))
+      (then unreachable))
+    (local.get $r)))
+"#;
+
+        assert_eq!(compile(&parse_ok(input)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_compiled_programs() {
+        let tests = [
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+                610,
+            ),
+            (
+                "let even = fn(n) { n % 2 == 0 }; !even(3) && (even(4) || false)",
+                1,
+            ),
+            ("if (0) { 1 } else { 2 }", 1),
+            ("if (!0) { 1 } else { 2 }", 2),
+            (
+                "let twice = fn(f, x) { f(f(x)) }; let inc = fn(x) { x + 1 }; twice(inc, 5)",
+                7,
+            ),
+            (
+                "let apply = fn(f) { f(3, 4) }; let sub = fn(a, b) { a - b };
+                 let mul = fn(a, b) { a * b }; apply(sub) + apply(mul)",
+                11,
+            ),
+            (
+                "let total = 0; let i = 0;
+                 while (true) {
+                     i += 1;
+                     if (i > 10) { break; }
+                     if (i % 2 == 0) { continue; }
+                     total += i;
+                 }
+                 total",
+                25,
+            ),
+            (
+                "let abs = fn(n) { if (n < 0) { return -n; } n }; abs(-5) + abs(5)",
+                10,
+            ),
+            (
+                "let pick = fn(c: bool, a, b) { if (c) { a } else { b } }; pick(false, 1, 2)",
+                2,
+            ),
+            ("let f = fn(x) { if (x > 0) { x } }; f(-1)", 0),
+            ("-9223372036854775807 - 1", i64::MIN),
+            ("let m = -9223372036854775807; m -= 1; -1 << 63 == m", 1),
+            ("4294967296 * -2147483648", i64::MIN),
+            (
+                "let f = fn(x, y) { x * y }; f(-1, -9223372036854775807)",
+                i64::MAX,
+            ),
+            (
+                "let count = 0; let tick = fn() { count += 1 }; tick(); tick(); count",
+                2,
+            ),
+            ("let x = 1; if (true) { let y = 2; x = x + y; } x", 3),
+            ("let f = fn(n) { let m = n * 2; m = m + 1; m }; f(4)", 9),
+            ("let x = 5; return x * 2; x", 10),
+            (
+                "let f = fn() { let a = 1; while (a < 100) { a *= 3; } a }; f()",
+                243,
+            ),
+            ("1 < 2 == true", 1),
+            ("let x = 7;", 0),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse_ok(input);
+            let wat = compile(&program).unwrap_or_else(|errors| {
+                panic!("could not compile {}: {:?}", input, errors);
+            });
+            let mut instance =
+                wat_vm::instantiate(&wat).unwrap_or_else(|err| panic!("{}\n{}", err, wat));
+            let result = instance.invoke("main", &[]);
+            assert_eq!(result, Ok(expected), "input: {}\n{}", input, wat);
+
+            // the evaluator agrees
+            let value = match Evaluator::new().eval_program(&program, &Environment::new()) {
+                Object::Integer(i) => i,
+                Object::Boolean(b) => b as i64,
+                Object::Null => 0,
+                obj => panic!("unexpected value {:?} of {}", obj, input),
+            };
+            assert_eq!(value, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let tests = [
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "let x = -9223372036854775807 - 1; -x",
+            "let square = fn(x) { x * x }; square(4294967296)",
+            "(-9223372036854775807 - 1) * -1",
+            "let min = -9223372036854775807 - 1; -1 * min",
+            "1 << 63",
+            "let x = 9223372036854775807; x += 1",
+            "(-9223372036854775807 - 1) / -1",
+        ];
+
+        for input in tests {
+            let program = parse_ok(input);
+            let wat = compile(&program).unwrap();
+            let mut instance = wat_vm::instantiate(&wat).unwrap();
+            let trap = match input.contains('/') {
+                true => "integer overflow",
+                false => "unreachable",
+            };
+            assert_eq!(
+                instance.invoke("main", &[]),
+                Err(trap.to_string()),
+                "input: {}\n{}",
+                input,
+                wat
+            );
+
+            // where the evaluator goes on beyond 64 bits
+            let value = Evaluator::new().eval_program(&program, &Environment::new());
+            assert!(
+                matches!(value, Object::BigInteger(_)),
+                "input: {}, got {:?}",
+                input,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_exported_functions() {
+        let input = "let add = fn(a, b) { a + b }; let div = fn(a, b) { a / b };";
+        let wat = compile(&parse_ok(input)).unwrap();
+        let mut instance = wat_vm::instantiate(&wat).unwrap();
+
+        assert_eq!(instance.invoke("add", &[2, 3]), Ok(5));
+        assert_eq!(
+            instance.invoke("div", &[1, 0]),
+            Err("integer divide by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let tests = [
+            (
+                r#""a" + "b""#,
+                "1:1: strings are not supported by the wat target",
+            ),
+            (
+                "let f = fn(x) { fn(y) { x + y } };",
+                "1:17: functions are only supported as top-level let bindings by the wat target",
+            ),
+            (
+                "puts(1)",
+                "1:1: builtin function puts is not supported by the wat target",
+            ),
+            (
+                "let f = fn(c) { if (c) { 1 } else { 2 } };",
+                "1:21: the type of this condition is unknown, annotate it",
+            ),
+            (
+                "let main = fn() { 1 };",
+                "1:5: main is reserved for the program",
+            ),
+            ("1 + true", "1:5: type mismatch: expected int, got bool"),
+            (
+                "for (x in [1]) { }",
+                "1:1: for loops are not supported by the wat target",
+            ),
+            (
+                "9223372036854775808",
+                "1:1: integers beyond 64 bits are not supported by the wat target",
+            ),
+            (
+                "try { 1 } finally { 2 }",
+                "1:1: exceptions are not supported by the wat target",
+            ),
+            (
+                "let f = fn() { 1 }; let g = fn() { 2 }; f = g;",
+                "1:41: cannot assign to function f",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let errors = compile(&parse_ok(input)).unwrap_err();
+            assert_eq!(errors[0].to_string(), expected, "input: {}", input);
+        }
+    }
+}
@@ -0,0 +1,907 @@
+//! A small interpreter for the WebAssembly text the `wat` module emits, so
+//! its tests can check and run the modules without an engine.
+//!
+//! It reads the folded form of the instructions only, and the parts of the
+//! format the backend uses: types, a table with its elements, mutable `i64`
+//! globals and functions over `i32` and `i64`. Loading a module validates
+//! it: names must resolve, instructions must get operands of their types,
+//! and each instruction of a sequence but the last must leave nothing.
+
+use std::collections::HashMap;
+
+const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    I32(i32),
+    I64(i64),
+}
+
+impl Value {
+    fn i32(self) -> i32 {
+        match self {
+            Value::I32(v) => v,
+            Value::I64(v) => v as i32,
+        }
+    }
+
+    fn i64(self) -> i64 {
+        match self {
+            Value::I32(v) => v as i64,
+            Value::I64(v) => v,
+        }
+    }
+}
+
+// what an instruction leaves: nothing, a value, or anything at all when it
+// never completes, as a branch
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Empty,
+    Val(ValType),
+    Any,
+}
+
+// something with the `$name` it may have
+type Named<T> = (Option<String>, T);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+fn parse(text: &str) -> Result<Sexp, String> {
+    let mut stack: Vec<Vec<Sexp>> = vec![vec![]];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' if chars.peek() == Some(&';') => {
+                while let Some(c) = chars.next() {
+                    if c == ';' && chars.peek() == Some(&')') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            ';' if chars.peek() == Some(&';') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => stack.push(vec![]),
+            ')' => {
+                let list = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.push(Sexp::List(list)),
+                    None => return Err("unbalanced )".to_string()),
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                let quoted = c == '"';
+                while let Some(&c) = chars.peek() {
+                    let ends = match quoted {
+                        true => atom.len() > 1 && atom.ends_with('"'),
+                        false => c.is_whitespace() || c == '(' || c == ')',
+                    };
+                    if ends {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                stack.last_mut().unwrap().push(Sexp::Atom(atom));
+            }
+        }
+    }
+
+    match (stack.pop(), stack.is_empty()) {
+        (Some(mut items), true) if items.len() == 1 => Ok(items.pop().unwrap()),
+        (Some(_), true) => Err("expected a single module".to_string()),
+        _ => Err("unbalanced (".to_string()),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Instr {
+    Const(Value),
+    Unary(String, Box<Instr>),
+    Binary(String, Box<Instr>, Box<Instr>),
+    LocalGet(usize),
+    LocalSet(usize, Box<Instr>),
+    LocalTee(usize, Box<Instr>),
+    GlobalGet(usize),
+    GlobalSet(usize, Box<Instr>),
+    Call(usize, Vec<Instr>),
+    CallIndirect(Signature, Vec<Instr>, Box<Instr>),
+    If(Box<Instr>, Vec<Instr>, Vec<Instr>),
+    Block(Vec<Instr>),
+    Loop(Vec<Instr>),
+    Br(usize),
+    BrIf(usize, Box<Instr>),
+    Return(Option<Box<Instr>>),
+    Drop(Box<Instr>),
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Signature {
+    params: Vec<ValType>,
+    result: Option<ValType>,
+}
+
+#[derive(Debug)]
+struct Function {
+    signature: Signature,
+    // the types of the parameters, then of the locals
+    locals: Vec<ValType>,
+    body: Vec<Instr>,
+}
+
+// a parsed function before its body is read
+struct Declaration<'a> {
+    signature: Signature,
+    locals: Vec<Named<ValType>>,
+    body: &'a [Sexp],
+}
+
+pub struct Instance {
+    functions: Vec<Function>,
+    table: Vec<Option<usize>>,
+    globals: Vec<Value>,
+    exports: HashMap<String, usize>,
+    depth: usize,
+}
+
+/// Loads and validates the module in `text`.
+pub fn instantiate(text: &str) -> Result<Instance, String> {
+    let module = parse(text)?;
+    let fields = match &module {
+        Sexp::List(items) if items.first() == Some(&Sexp::Atom("module".to_string())) => {
+            &items[1..]
+        }
+        _ => return Err("expected (module ...)".to_string()),
+    };
+    Loader::default().load(fields)
+}
+
+#[derive(Default)]
+struct Loader {
+    types: Vec<Named<Signature>>,
+    functions: Vec<Named<Signature>>,
+    globals: Vec<Named<ValType>>,
+    table: Option<Named<usize>>,
+}
+
+fn atom(sexp: &Sexp) -> Result<&str, String> {
+    match sexp {
+        Sexp::Atom(atom) => Ok(atom),
+        Sexp::List(_) => Err("expected an atom, got a list".to_string()),
+    }
+}
+
+fn list(sexp: &Sexp) -> Result<(&str, &[Sexp]), String> {
+    match sexp {
+        Sexp::List(items) if !items.is_empty() => Ok((atom(&items[0])?, &items[1..])),
+        _ => Err(format!("expected a list, got {:?}", sexp)),
+    }
+}
+
+fn val_type(sexp: &Sexp) -> Result<ValType, String> {
+    match atom(sexp)? {
+        "i32" => Ok(ValType::I32),
+        "i64" => Ok(ValType::I64),
+        t => Err(format!("unsupported type {}", t)),
+    }
+}
+
+fn is_id(sexp: Option<&Sexp>) -> bool {
+    matches!(sexp, Some(Sexp::Atom(atom)) if atom.starts_with('$'))
+}
+
+fn resolve(names: &[Option<String>], name: &str, what: &str) -> Result<usize, String> {
+    let found = match name.strip_prefix('$') {
+        Some(_) => names.iter().position(|n| n.as_deref() == Some(name)),
+        None => name.parse().ok().filter(|i| *i < names.len()),
+    };
+    found.ok_or_else(|| format!("unknown {} {}", what, name))
+}
+
+// the `(param ..)`, `(result ..)` and `(local ..)` items at the start of
+// `items`, and how many there are
+fn signature(items: &[Sexp]) -> Result<(Signature, Vec<Named<ValType>>, usize), String> {
+    let mut signature = Signature {
+        params: vec![],
+        result: None,
+    };
+    let mut locals = vec![];
+    let mut count = 0;
+
+    for item in items {
+        let (keyword, rest) = match item {
+            Sexp::List(_) => list(item)?,
+            Sexp::Atom(_) => break,
+        };
+        match keyword {
+            "param" | "local" => {
+                let named: Vec<Named<ValType>> = match is_id(rest.first()) {
+                    true => vec![(Some(atom(&rest[0])?.to_string()), val_type(&rest[1])?)],
+                    false => rest
+                        .iter()
+                        .map(|t| Ok((None, val_type(t)?)))
+                        .collect::<Result<_, String>>()?,
+                };
+                if keyword == "param" {
+                    if locals.len() != signature.params.len() {
+                        return Err("param after local".to_string());
+                    }
+                    signature.params.extend(named.iter().map(|(_, t)| *t));
+                }
+                locals.extend(named);
+            }
+            "result" => signature.result = Some(val_type(&rest[0])?),
+            "export" => {}
+            _ => break,
+        }
+        count += 1;
+    }
+    Ok((signature, locals, count))
+}
+
+impl Loader {
+    fn load(mut self, fields: &[Sexp]) -> Result<Instance, String> {
+        let mut declarations = vec![];
+        let mut exports = HashMap::new();
+        let mut globals = vec![];
+        let mut elements = vec![];
+
+        for field in fields {
+            let (keyword, rest) = list(field)?;
+            let (id, rest) = match is_id(rest.first()) {
+                true => (Some(atom(&rest[0])?.to_string()), &rest[1..]),
+                false => (None, rest),
+            };
+            match keyword {
+                "type" => {
+                    let (func, items) = list(&rest[0])?;
+                    if func != "func" {
+                        return Err(format!("unsupported type {}", func));
+                    }
+                    self.types.push((id, signature(items)?.0));
+                }
+                "table" => {
+                    let size = atom(&rest[0])?.parse().map_err(|_| "bad table size")?;
+                    self.table = Some((id, size));
+                }
+                "elem" => {
+                    let offset = match list(&rest[0])? {
+                        ("i32.const", [offset]) => atom(offset)?.parse::<usize>(),
+                        _ => return Err("unsupported elem offset".to_string()),
+                    };
+                    let offset = offset.map_err(|_| "bad elem offset")?;
+                    elements.push((offset, &rest[1..]));
+                }
+                "global" => {
+                    let t = match list(&rest[0])? {
+                        ("mut", [t]) => val_type(t)?,
+                        _ => return Err("globals must be mutable".to_string()),
+                    };
+                    let init = match list(&rest[1])? {
+                        ("i64.const", [v]) => Value::I64(atom(v)?.parse().map_err(|_| "bad i64")?),
+                        ("i32.const", [v]) => Value::I32(atom(v)?.parse().map_err(|_| "bad i32")?),
+                        _ => return Err("globals must start as constants".to_string()),
+                    };
+                    if t != val_type_of(&init) {
+                        return Err("global initialized with a value of another type".to_string());
+                    }
+                    self.globals.push((id, t));
+                    globals.push(init);
+                }
+                "func" => {
+                    for item in rest {
+                        if let Sexp::List(_) = item {
+                            if let ("export", [name]) = list(item)? {
+                                let name = atom(name)?.trim_matches('"').to_string();
+                                exports.insert(name, self.functions.len());
+                            }
+                        }
+                    }
+                    let (signature, locals, count) = signature(rest)?;
+                    self.functions.push((id, signature.clone()));
+                    declarations.push(Declaration {
+                        signature,
+                        locals,
+                        body: &rest[count..],
+                    });
+                }
+                keyword => return Err(format!("unsupported field {}", keyword)),
+            }
+        }
+
+        let mut table = vec![None; self.table.as_ref().map_or(0, |(_, size)| *size)];
+        for (offset, names) in elements {
+            for (i, name) in names.iter().enumerate() {
+                let function = self.function(atom(name)?)?;
+                match table.get_mut(offset + i) {
+                    Some(slot) => *slot = Some(function),
+                    None => return Err("elements out of the table".to_string()),
+                }
+            }
+        }
+
+        let mut functions = vec![];
+        for declaration in declarations {
+            let mut body = Body {
+                loader: &self,
+                locals: declaration.locals.clone(),
+                labels: vec![],
+                result: declaration.signature.result,
+            };
+            let code = body.sequence(declaration.body, declaration.signature.result)?;
+            functions.push(Function {
+                signature: declaration.signature,
+                locals: declaration.locals.iter().map(|(_, t)| *t).collect(),
+                body: code,
+            });
+        }
+
+        Ok(Instance {
+            functions,
+            table,
+            globals,
+            exports,
+            depth: 0,
+        })
+    }
+
+    fn function(&self, name: &str) -> Result<usize, String> {
+        let names: Vec<Option<String>> = self.functions.iter().map(|(n, _)| n.clone()).collect();
+        resolve(&names, name, "function")
+    }
+}
+
+fn val_type_of(value: &Value) -> ValType {
+    match value {
+        Value::I32(_) => ValType::I32,
+        Value::I64(_) => ValType::I64,
+    }
+}
+
+// the instructions over one type, with the types of their operands and of
+// their result
+fn numeric(name: &str) -> Option<(Vec<ValType>, ValType)> {
+    use ValType::*;
+    Some(match name {
+        "i64.add" | "i64.sub" | "i64.mul" | "i64.div_s" | "i64.rem_s" | "i64.and" | "i64.or"
+        | "i64.xor" | "i64.shl" | "i64.shr_s" => (vec![I64, I64], I64),
+        "i64.eq" | "i64.ne" | "i64.lt_s" | "i64.gt_s" | "i64.le_s" | "i64.ge_s" => {
+            (vec![I64, I64], I32)
+        }
+        "i64.eqz" | "i32.wrap_i64" => (vec![I64], I32),
+        "i32.eqz" => (vec![I32], I32),
+        "i64.extend_i32_u" => (vec![I32], I64),
+        _ => return None,
+    })
+}
+
+// reads and validates the body of a function
+struct Body<'a> {
+    loader: &'a Loader,
+    locals: Vec<Named<ValType>>,
+    // the labels in scope, innermost last, and whether a branch to them
+    // takes a value
+    labels: Vec<(Option<String>, bool)>,
+    result: Option<ValType>,
+}
+
+impl Body<'_> {
+    fn sequence(&mut self, items: &[Sexp], result: Option<ValType>) -> Result<Vec<Instr>, String> {
+        let mut code = vec![];
+        for (i, item) in items.iter().enumerate() {
+            let (instr, ty) = self.instr(item)?;
+            let expected = match (i == items.len() - 1, result) {
+                (true, Some(t)) => Ty::Val(t),
+                _ => Ty::Empty,
+            };
+            if ty != expected && ty != Ty::Any {
+                return Err(format!(
+                    "expected {:?}, got {:?} from {:?}",
+                    expected, ty, item
+                ));
+            }
+            code.push(instr);
+        }
+        if items.is_empty() && result.is_some() {
+            return Err("empty sequence for a result".to_string());
+        }
+        Ok(code)
+    }
+
+    fn operand(&mut self, item: Option<&Sexp>, t: ValType) -> Result<Instr, String> {
+        let item = item.ok_or("missing operand")?;
+        match self.instr(item)? {
+            (instr, Ty::Val(u)) if u == t => Ok(instr),
+            (instr, Ty::Any) => Ok(instr),
+            (_, ty) => Err(format!(
+                "expected {:?} operand, got {:?} from {:?}",
+                t, ty, item
+            )),
+        }
+    }
+
+    fn operands(&mut self, items: &[Sexp], types: &[ValType]) -> Result<Vec<Instr>, String> {
+        if items.len() != types.len() {
+            return Err(format!(
+                "expected {} operands, got {}",
+                types.len(),
+                items.len()
+            ));
+        }
+        items
+            .iter()
+            .zip(types)
+            .map(|(item, t)| self.operand(Some(item), *t))
+            .collect()
+    }
+
+    fn local(&self, name: &Sexp) -> Result<(usize, ValType), String> {
+        let names: Vec<Option<String>> = self.locals.iter().map(|(n, _)| n.clone()).collect();
+        let i = resolve(&names, atom(name)?, "local")?;
+        Ok((i, self.locals[i].1))
+    }
+
+    fn global(&self, name: &Sexp) -> Result<(usize, ValType), String> {
+        let names: Vec<Option<String>> =
+            self.loader.globals.iter().map(|(n, _)| n.clone()).collect();
+        let i = resolve(&names, atom(name)?, "global")?;
+        Ok((i, self.loader.globals[i].1))
+    }
+
+    // the relative depth of the label `name`
+    fn label(&self, name: &Sexp) -> Result<usize, String> {
+        let name = atom(name)?;
+        let found = match name.strip_prefix('$') {
+            Some(_) => self
+                .labels
+                .iter()
+                .rev()
+                .position(|(l, _)| l.as_deref() == Some(name)),
+            None => name.parse().ok().filter(|d| *d < self.labels.len()),
+        };
+        let depth = found.ok_or_else(|| format!("unknown label {}", name))?;
+        match self.labels[self.labels.len() - 1 - depth] {
+            (_, true) => Err("branches with values are not supported".to_string()),
+            (_, false) => Ok(depth),
+        }
+    }
+
+    // the optional label and `(result T)` of a block, and the rest of it
+    fn block_type<'s>(
+        &self,
+        rest: &'s [Sexp],
+    ) -> Result<(Named<Option<ValType>>, &'s [Sexp]), String> {
+        let (label, rest) = match is_id(rest.first()) {
+            true => (Some(atom(&rest[0])?.to_string()), &rest[1..]),
+            false => (None, rest),
+        };
+        match rest.first().map(list) {
+            Some(Ok(("result", [t]))) => Ok(((label, Some(val_type(t)?)), &rest[1..])),
+            _ => Ok(((label, None), rest)),
+        }
+    }
+
+    fn instr(&mut self, item: &Sexp) -> Result<(Instr, Ty), String> {
+        let (name, rest) = match item {
+            Sexp::Atom(atom) => (atom.as_str(), &[][..]),
+            Sexp::List(_) => list(item)?,
+        };
+
+        if let Some((params, result)) = numeric(name) {
+            let mut operands = self.operands(rest, &params)?.into_iter();
+            let instr = match params.len() {
+                1 => Instr::Unary(name.to_string(), Box::new(operands.next().unwrap())),
+                _ => Instr::Binary(
+                    name.to_string(),
+                    Box::new(operands.next().unwrap()),
+                    Box::new(operands.next().unwrap()),
+                ),
+            };
+            return Ok((instr, Ty::Val(result)));
+        }
+
+        Ok(match name {
+            "i64.const" => {
+                let v = atom(rest.first().ok_or("missing constant")?)?;
+                (
+                    Instr::Const(Value::I64(v.parse().map_err(|_| "bad i64")?)),
+                    Ty::Val(ValType::I64),
+                )
+            }
+            "i32.const" => {
+                let v = atom(rest.first().ok_or("missing constant")?)?;
+                (
+                    Instr::Const(Value::I32(v.parse().map_err(|_| "bad i32")?)),
+                    Ty::Val(ValType::I32),
+                )
+            }
+            "local.get" => {
+                let (i, t) = self.local(&rest[0])?;
+                (Instr::LocalGet(i), Ty::Val(t))
+            }
+            "local.set" | "local.tee" => {
+                let (i, t) = self.local(&rest[0])?;
+                let value = Box::new(self.operand(rest.get(1), t)?);
+                match name {
+                    "local.set" => (Instr::LocalSet(i, value), Ty::Empty),
+                    _ => (Instr::LocalTee(i, value), Ty::Val(t)),
+                }
+            }
+            "global.get" => {
+                let (i, t) = self.global(&rest[0])?;
+                (Instr::GlobalGet(i), Ty::Val(t))
+            }
+            "global.set" => {
+                let (i, t) = self.global(&rest[0])?;
+                let value = Box::new(self.operand(rest.get(1), t)?);
+                (Instr::GlobalSet(i, value), Ty::Empty)
+            }
+            "call" => {
+                let f = self.loader.function(atom(&rest[0])?)?;
+                let signature = self.loader.functions[f].1.clone();
+                let arguments = self.operands(&rest[1..], &signature.params)?;
+                (Instr::Call(f, arguments), ty(signature.result))
+            }
+            "call_indirect" => {
+                if self.loader.table.is_none() {
+                    return Err("call_indirect without a table".to_string());
+                }
+                let t = match list(&rest[0])? {
+                    ("type", [t]) => {
+                        let names: Vec<Option<String>> =
+                            self.loader.types.iter().map(|(n, _)| n.clone()).collect();
+                        resolve(&names, atom(t)?, "type")?
+                    }
+                    _ => return Err("call_indirect needs a type".to_string()),
+                };
+                let signature = self.loader.types[t].1.clone();
+                let (arguments, index) = rest[1..].split_at(rest.len().saturating_sub(2));
+                let arguments = self.operands(arguments, &signature.params)?;
+                let index = self.operand(index.first(), ValType::I32)?;
+                let result = ty(signature.result);
+                (
+                    Instr::CallIndirect(signature, arguments, Box::new(index)),
+                    result,
+                )
+            }
+            "if" => {
+                let ((label, result), rest) = self.block_type(rest)?;
+                let condition = self.operand(rest.first(), ValType::I32)?;
+                self.labels.push((label, result.is_some()));
+                let mut branches = vec![];
+                for (i, keyword) in ["then", "else"].iter().enumerate() {
+                    match rest.get(i + 1).map(list) {
+                        Some(Ok((k, body))) if k == *keyword => {
+                            branches.push(self.sequence(body, result)?)
+                        }
+                        _ if i == 1 && result.is_none() => branches.push(vec![]),
+                        _ => {
+                            self.labels.pop();
+                            return Err(format!("if without {}", keyword));
+                        }
+                    }
+                }
+                self.labels.pop();
+                let otherwise = branches.pop().unwrap();
+                let then = branches.pop().unwrap();
+                (Instr::If(Box::new(condition), then, otherwise), ty(result))
+            }
+            "block" | "loop" => {
+                let ((label, result), rest) = self.block_type(rest)?;
+                if name == "loop" && result.is_some() {
+                    return Err("loops with results are not supported".to_string());
+                }
+                self.labels
+                    .push((label, name == "block" && result.is_some()));
+                let body = self.sequence(rest, result);
+                self.labels.pop();
+                match name {
+                    "block" => (Instr::Block(body?), ty(result)),
+                    _ => (Instr::Loop(body?), Ty::Empty),
+                }
+            }
+            "br" => (Instr::Br(self.label(&rest[0])?), Ty::Any),
+            "br_if" => {
+                let depth = self.label(&rest[0])?;
+                let condition = self.operand(rest.get(1), ValType::I32)?;
+                (Instr::BrIf(depth, Box::new(condition)), Ty::Empty)
+            }
+            "return" => {
+                let value = match self.result {
+                    Some(t) => Some(Box::new(self.operand(rest.first(), t)?)),
+                    None => None,
+                };
+                (Instr::Return(value), Ty::Any)
+            }
+            "drop" => {
+                let value = match self.instr(rest.first().ok_or("missing operand")?)? {
+                    (instr, Ty::Val(_) | Ty::Any) => instr,
+                    _ => return Err("drop of nothing".to_string()),
+                };
+                (Instr::Drop(Box::new(value)), Ty::Empty)
+            }
+            "unreachable" => (Instr::Unreachable, Ty::Any),
+            name => return Err(format!("unknown instruction {}", name)),
+        })
+    }
+}
+
+fn ty(result: Option<ValType>) -> Ty {
+    match result {
+        Some(t) => Ty::Val(t),
+        None => Ty::Empty,
+    }
+}
+
+// how execution leaves an instruction other than by completing
+enum Flow {
+    Branch(usize),
+    Return(Option<Value>),
+    Trap(String),
+}
+
+fn trap<T>(message: &str) -> Result<T, Flow> {
+    Err(Flow::Trap(message.to_string()))
+}
+
+impl Instance {
+    /// Calls the exported function `name` with `args`, returning its result
+    /// or the message of the trap that ended it.
+    pub fn invoke(&mut self, name: &str, args: &[i64]) -> Result<i64, String> {
+        let f = *self
+            .exports
+            .get(name)
+            .ok_or_else(|| format!("no export {}", name))?;
+        let signature = &self.functions[f].signature;
+        if signature.params != vec![ValType::I64; args.len()] {
+            return Err(format!("wrong arguments for {}", name));
+        }
+
+        let args = args.iter().map(|a| Value::I64(*a)).collect();
+        match self.call(f, args) {
+            Ok(Some(value)) => Ok(value.i64()),
+            Ok(None) => Ok(0),
+            Err(Flow::Trap(message)) => Err(message),
+            Err(_) => unreachable!("branches and returns end within functions"),
+        }
+    }
+
+    fn call(&mut self, f: usize, args: Vec<Value>) -> Result<Option<Value>, Flow> {
+        if self.depth == MAX_DEPTH {
+            return trap("call stack exhausted");
+        }
+
+        let function = &self.functions[f];
+        let mut locals = args;
+        for t in &function.locals[locals.len()..] {
+            locals.push(match t {
+                ValType::I32 => Value::I32(0),
+                ValType::I64 => Value::I64(0),
+            });
+        }
+        // the instance is borrowed while the body runs
+        let body = function.body.clone();
+
+        self.depth += 1;
+        let result = self.sequence(&body, &mut locals);
+        self.depth -= 1;
+        match result {
+            Ok(value) => Ok(value),
+            Err(Flow::Return(value)) => Ok(value),
+            Err(flow) => Err(flow),
+        }
+    }
+
+    fn sequence(&mut self, code: &[Instr], locals: &mut Vec<Value>) -> Result<Option<Value>, Flow> {
+        let mut result = None;
+        for instr in code {
+            result = self.exec(instr, locals)?;
+        }
+        Ok(result)
+    }
+
+    fn value(&mut self, instr: &Instr, locals: &mut Vec<Value>) -> Result<Value, Flow> {
+        Ok(self
+            .exec(instr, locals)?
+            .expect("validated to leave a value"))
+    }
+
+    // the block a branch of `depth` leaves, or the branch to pass on
+    fn leave(flow: Flow) -> Result<Option<Value>, Flow> {
+        match flow {
+            Flow::Branch(0) => Ok(None),
+            Flow::Branch(depth) => Err(Flow::Branch(depth - 1)),
+            flow => Err(flow),
+        }
+    }
+
+    fn exec(&mut self, instr: &Instr, locals: &mut Vec<Value>) -> Result<Option<Value>, Flow> {
+        let value = match instr {
+            Instr::Const(v) => *v,
+            Instr::Unary(name, operand) => {
+                let v = self.value(operand, locals)?;
+                match name.as_str() {
+                    "i64.eqz" => Value::I32((v.i64() == 0) as i32),
+                    "i32.eqz" => Value::I32((v.i32() == 0) as i32),
+                    "i32.wrap_i64" => Value::I32(v.i64() as i32),
+                    "i64.extend_i32_u" => Value::I64(v.i32() as u32 as i64),
+                    _ => unreachable!("validated"),
+                }
+            }
+            Instr::Binary(name, left, right) => {
+                let l = self.value(left, locals)?.i64();
+                let r = self.value(right, locals)?.i64();
+                let bool = |b: bool| Value::I32(b as i32);
+                match name.as_str() {
+                    "i64.add" => Value::I64(l.wrapping_add(r)),
+                    "i64.sub" => Value::I64(l.wrapping_sub(r)),
+                    "i64.mul" => Value::I64(l.wrapping_mul(r)),
+                    "i64.div_s" | "i64.rem_s" if r == 0 => return trap("integer divide by zero"),
+                    "i64.div_s" if l == i64::MIN && r == -1 => return trap("integer overflow"),
+                    "i64.div_s" => Value::I64(l / r),
+                    "i64.rem_s" => Value::I64(l.wrapping_rem(r)),
+                    "i64.and" => Value::I64(l & r),
+                    "i64.or" => Value::I64(l | r),
+                    "i64.xor" => Value::I64(l ^ r),
+                    "i64.shl" => Value::I64(l.wrapping_shl(r as u32)),
+                    "i64.shr_s" => Value::I64(l.wrapping_shr(r as u32)),
+                    "i64.eq" => bool(l == r),
+                    "i64.ne" => bool(l != r),
+                    "i64.lt_s" => bool(l < r),
+                    "i64.gt_s" => bool(l > r),
+                    "i64.le_s" => bool(l <= r),
+                    "i64.ge_s" => bool(l >= r),
+                    _ => unreachable!("validated"),
+                }
+            }
+            Instr::LocalGet(i) => locals[*i],
+            Instr::LocalSet(i, value) => {
+                locals[*i] = self.value(value, locals)?;
+                return Ok(None);
+            }
+            Instr::LocalTee(i, value) => {
+                locals[*i] = self.value(value, locals)?;
+                locals[*i]
+            }
+            Instr::GlobalGet(i) => self.globals[*i],
+            Instr::GlobalSet(i, value) => {
+                self.globals[*i] = self.value(value, locals)?;
+                return Ok(None);
+            }
+            Instr::Call(f, arguments) => {
+                let args = self.values(arguments, locals)?;
+                return self.call(*f, args);
+            }
+            Instr::CallIndirect(signature, arguments, index) => {
+                let args = self.values(arguments, locals)?;
+                let index = self.value(index, locals)?.i32() as usize;
+                let f = match self.table.get(index) {
+                    Some(Some(f)) => *f,
+                    _ => return trap("undefined element"),
+                };
+                if self.functions[f].signature != *signature {
+                    return trap("indirect call type mismatch");
+                }
+                return self.call(f, args);
+            }
+            Instr::If(condition, then, otherwise) => {
+                let branch = match self.value(condition, locals)?.i32() {
+                    0 => otherwise,
+                    _ => then,
+                };
+                return self.sequence(branch, locals).or_else(Instance::leave);
+            }
+            Instr::Block(body) => return self.sequence(body, locals).or_else(Instance::leave),
+            Instr::Loop(body) => loop {
+                match self.sequence(body, locals) {
+                    Err(Flow::Branch(0)) => continue,
+                    Err(Flow::Branch(depth)) => return Err(Flow::Branch(depth - 1)),
+                    result => return result,
+                }
+            },
+            Instr::Br(depth) => return Err(Flow::Branch(*depth)),
+            Instr::BrIf(depth, condition) => {
+                if self.value(condition, locals)?.i32() != 0 {
+                    return Err(Flow::Branch(*depth));
+                }
+                return Ok(None);
+            }
+            Instr::Return(value) => {
+                let value = match value {
+                    Some(value) => Some(self.value(value, locals)?),
+                    None => None,
+                };
+                return Err(Flow::Return(value));
+            }
+            Instr::Drop(value) => {
+                self.exec(value, locals)?;
+                return Ok(None);
+            }
+            Instr::Unreachable => return trap("unreachable"),
+        };
+        Ok(Some(value))
+    }
+
+    fn values(&mut self, code: &[Instr], locals: &mut Vec<Value>) -> Result<Vec<Value>, Flow> {
+        code.iter().map(|instr| self.value(instr, locals)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_modules() {
+        let tests = [
+            ("(module", "unbalanced ("),
+            ("(func)", "expected (module ...)"),
+            (
+                "(module (func $f (result i64) (local.get $x)))",
+                "unknown local $x",
+            ),
+            (
+                "(module (func $f (result i64) (i32.const 1)))",
+                "expected Val(I64), got Val(I32) from List([Atom(\"i32.const\"), Atom(\"1\")])",
+            ),
+            (
+                "(module (func $f (param $x i64) (result i64) (i64.add (local.get $x))))",
+                "expected 2 operands, got 1",
+            ),
+            ("(module (func $f (br $out)))", "unknown label $out"),
+            ("(module (func $f (call $g)))", "unknown function $g"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                instantiate(input).err().as_deref(),
+                Some(expected),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_handwritten_module() {
+        let input = r#"
+(module
+  ;; the sum of 1 to n
+  (func $sum (export "sum") (param $n i64) (result i64) (local $total i64)
+    (block $done
+      (loop $next
+        (br_if $done (i64.eqz (local.get $n)))
+        (local.set $total (i64.add (local.get $total) (local.get $n)))
+        (local.set $n (i64.sub (local.get $n) (i64.const 1)))
+        (br $next)))
+    (local.get $total)))
+"#;
+        let mut instance = instantiate(input).unwrap();
+
+        assert_eq!(instance.invoke("sum", &[100]), Ok(5050));
+        assert_eq!(
+            instance.invoke("sum", &[]),
+            Err("wrong arguments for sum".to_string())
+        );
+    }
+}
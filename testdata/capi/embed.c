@@ -0,0 +1,88 @@
+/*
+ * Embeds the interpreter through the C interface of include/monkey.h,
+ * printing what it reads back. The capi tests compare the output with
+ * embed.out.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "monkey.h"
+
+static MonkeyValue *eval(MonkeyInterpreter *interpreter, const char *source)
+{
+    return monkey_eval(interpreter, source, strlen(source));
+}
+
+static void print(const char *label, MonkeyValue *value)
+{
+    char *s = monkey_value_is_error(value) ? monkey_value_error(value)
+                                           : monkey_value_inspect(value);
+    printf("%s: %s%s\n", label, monkey_value_is_error(value) ? "error: " : "", s);
+    monkey_string_free(s);
+    monkey_value_free(value);
+}
+
+int main(void)
+{
+    MonkeyInterpreter *interpreter = monkey_interpreter_new();
+
+    /* globals live from one evaluation to the next */
+    monkey_value_free(eval(interpreter, "let double = fn(x) { x * 2 };"));
+
+    MonkeyValue *value = eval(interpreter, "double(21)");
+    int64_t i = 0;
+    bool b = false;
+    if (!monkey_value_as_int(value, &i) || monkey_value_as_bool(value, &b)) {
+        return 1;
+    }
+    printf("int: %lld\n", (long long)i);
+    monkey_value_free(value);
+
+    value = eval(interpreter, "double(2) == 4");
+    if (!monkey_value_as_bool(value, &b) || monkey_value_as_int(value, &i)) {
+        return 1;
+    }
+    printf("bool: %s\n", b ? "true" : "false");
+    monkey_value_free(value);
+
+    value = eval(interpreter, "\"hello\" + \" world\"");
+    char *s = monkey_value_as_string(value);
+    printf("string: %s\n", s);
+    monkey_string_free(s);
+    monkey_value_free(value);
+
+    /* only part of the buffer is evaluated */
+    const char *source = "double(5); this is not evaluated";
+    print("prefix", monkey_eval(interpreter, source, strlen("double(5)")));
+
+    print("array", eval(interpreter, "[1, \"two\", double]"));
+    print("nothing", monkey_eval(interpreter, NULL, 0));
+
+    value = eval(interpreter, "1 + true");
+    if (monkey_value_inspect(value) != NULL || monkey_value_as_string(value) != NULL) {
+        return 1;
+    }
+    print("runtime", value);
+    print("parse", eval(interpreter, "let = 1;"));
+    print("utf8", eval(interpreter, "\"\xff\""));
+
+    /* no depth at all is refused */
+    if (monkey_interpreter_set_max_depth(interpreter, 0) ||
+        !monkey_interpreter_set_max_depth(interpreter, 100)) {
+        return 1;
+    }
+    print("depth", eval(interpreter, "let f = fn(n) { f(n + 1) + 1 }; f(0)"));
+
+    /* nor is no stack */
+    if (monkey_interpreter_set_max_stack(interpreter, 0) ||
+        !monkey_interpreter_set_max_depth(interpreter, 1000000) ||
+        !monkey_interpreter_set_max_stack(interpreter, 65536)) {
+        return 1;
+    }
+    print("stack", eval(interpreter, "let f = fn(n) { f(n + 1) + 1 }; f(0)"));
+
+    monkey_interpreter_free(interpreter);
+    monkey_interpreter_free(NULL);
+    return 0;
+}